@@ -7,7 +7,10 @@ async fn main() {
     let player = Player::default();
 
     player
-        .play("https://www2.cs.uic.edu/~i101/SoundFiles/gettysburg10.wav")
+        .play(
+            "https://www2.cs.uic.edu/~i101/SoundFiles/gettysburg10.wav",
+            1.0,
+        )
         .await
         .unwrap();
 
@@ -19,7 +22,10 @@ async fn main() {
             Ok(PlayerMessage::EndOfStream) => {
                 println!("END OF STREAM");
                 player
-                    .play("https://www2.cs.uic.edu/~i101/SoundFiles/preamble10.wav")
+                    .play(
+                        "https://www2.cs.uic.edu/~i101/SoundFiles/preamble10.wav",
+                        1.0,
+                    )
                     .await
                     .unwrap();
                 break;
@@ -0,0 +1,280 @@
+//! Crossfades between tracks. The beginning of the next track is mixed into the end of the
+//! playing one with equal-power curves, and the sink goes on with the next track where the mix
+//! stopped, so both share their decoder.
+use std::f32::consts::FRAC_PI_2;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::time::Duration;
+
+use rodio::Source;
+
+use crate::decoder::SymphoniaDecoder;
+
+/// A decoder and the gain its samples are scaled with, e.g. to normalize loudness
+#[derive(Clone)]
+pub struct SharedDecoder {
+    decoder: Arc<Mutex<SymphoniaDecoder>>,
+    gain: f32,
+}
+
+impl SharedDecoder {
+    pub fn new(decoder: SymphoniaDecoder, gain: f32) -> Self {
+        Self {
+            decoder: Arc::new(Mutex::new(decoder)),
+            gain,
+        }
+    }
+
+    pub fn lock(&self) -> MutexGuard<'_, SymphoniaDecoder> {
+        self.decoder.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+// frames taken from the decoders at a time, their locks are taken once for all of them
+const CHUNK_FRAMES: usize = 512;
+
+struct Follower {
+    decoder: SharedDecoder,
+    duration: Duration,
+}
+
+/// Where the engine puts the track that is faded in at the end of the playing one
+#[derive(Clone, Default)]
+pub struct FollowerSlot(Arc<Mutex<Option<Follower>>>);
+
+impl FollowerSlot {
+    pub fn set(&self, decoder: SharedDecoder, duration: Duration) {
+        *self.lock() = Some(Follower { decoder, duration });
+    }
+
+    pub fn clear(&self) {
+        *self.lock() = None;
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Option<Follower>> {
+        self.0.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Takes the follower with the number of frames its fade lasts once the playing track is
+    /// near enough to its end, otherwise tells in how many frames that is
+    fn take_due(&self, decoder: &SymphoniaDecoder) -> Result<(SharedDecoder, u64), Option<u64>> {
+        let mut next = self.lock();
+        let (Some(follower), Some(remaining)) = (next.as_ref(), decoder.remaining_frames()) else {
+            return Err(None);
+        };
+        let frames = (follower.duration.as_secs_f64() * decoder.sample_rate() as f64) as u64;
+        if frames < remaining {
+            return Err(Some(remaining - frames));
+        }
+        // a track preloaded late is faded in over the rest of the playing one
+        next.take()
+            .map(|follower| (follower.decoder, remaining.max(1)))
+            .ok_or(None)
+    }
+}
+
+pub struct Crossfade {
+    decoder: SharedDecoder,
+    next: FollowerSlot,
+    // the track faded in and the number of frames the fade lasts, once it started
+    fading: Option<(SharedDecoder, u64)>,
+    // samples taken from the decoders and mixed, in the format of the playing track when they
+    // were taken
+    buffer: Vec<i16>,
+    position: usize,
+    channels: u16,
+    sample_rate: u32,
+}
+
+impl Crossfade {
+    pub fn new(decoder: SharedDecoder, next: FollowerSlot) -> Self {
+        Self {
+            decoder,
+            next,
+            fading: None,
+            buffer: Vec::new(),
+            position: 0,
+            channels: 0,
+            sample_rate: 0,
+        }
+    }
+
+    pub fn decoder(&self) -> &SharedDecoder {
+        &self.decoder
+    }
+
+    fn unread(&self) -> usize {
+        self.buffer.len() - self.position
+    }
+
+    /// Takes the next chunk of frames from the playing track, mixed with the next one once it
+    /// is faded in
+    fn fill(&mut self) {
+        self.buffer.clear();
+        self.position = 0;
+        let mut decoder = self.decoder.lock();
+        self.channels = decoder.channels();
+        self.sample_rate = decoder.sample_rate();
+        let mut frames = CHUNK_FRAMES;
+        if self.fading.is_none() {
+            match self.next.take_due(&decoder) {
+                Ok(fading) => self.fading = Some(fading),
+                // fades start with a chunk
+                Err(Some(due)) => frames = frames.min(due as usize),
+                Err(None) => {}
+            }
+        }
+        let mut incoming = self
+            .fading
+            .as_ref()
+            .map(|(next, fade)| (next.lock(), next.gain, *fade));
+        for _ in 0..frames {
+            // seeking back leaves the next track where it was until the end is near again
+            let progress = incoming.as_ref().and_then(|(_, _, fade)| {
+                // the frames left once this one is played
+                let remaining = decoder.remaining_frames().unwrap_or(0).saturating_sub(1);
+                (remaining <= *fade).then(|| (1.0 - remaining as f32 / *fade as f32) * FRAC_PI_2)
+            });
+            for _ in 0..self.channels.max(1) {
+                let Some(sample) = decoder.next() else {
+                    return;
+                };
+                let mut mixed = sample as f32 * self.decoder.gain;
+                if let (Some((next, gain, _)), Some(progress)) = (&mut incoming, progress) {
+                    let sample = next.next().unwrap_or(0) as f32 * *gain;
+                    mixed = mixed * progress.cos() + sample * progress.sin();
+                }
+                self.buffer.push(to_sample(mixed));
+            }
+        }
+    }
+}
+
+impl Source for Crossfade {
+    #[inline]
+    fn current_frame_len(&self) -> Option<usize> {
+        match self.unread() {
+            0 => self.decoder.lock().current_frame_len(),
+            unread => Some(unread),
+        }
+    }
+
+    #[inline]
+    fn channels(&self) -> u16 {
+        match self.unread() {
+            0 => self.decoder.lock().channels(),
+            _ => self.channels,
+        }
+    }
+
+    #[inline]
+    fn sample_rate(&self) -> u32 {
+        match self.unread() {
+            0 => self.decoder.lock().sample_rate(),
+            _ => self.sample_rate,
+        }
+    }
+
+    #[inline]
+    fn total_duration(&self) -> Option<Duration> {
+        self.decoder.lock().total_duration()
+    }
+}
+
+impl Iterator for Crossfade {
+    type Item = i16;
+
+    #[inline]
+    fn next(&mut self) -> Option<i16> {
+        if self.unread() == 0 {
+            self.fill();
+        }
+        let sample = *self.buffer.get(self.position)?;
+        self.position += 1;
+        Some(sample)
+    }
+}
+
+fn to_sample(value: f32) -> i16 {
+    value.clamp(i16::MIN as f32, i16::MAX as f32) as i16
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+    use symphonia::core::{
+        io::{MediaSourceStream, MediaSourceStreamOptions},
+        probe::Hint,
+    };
+
+    // A mono wav file of 1000 frames at 1000 Hz with every sample set to `value`
+    fn track(value: i16) -> SharedDecoder {
+        track_with_gain(value, 1.0)
+    }
+
+    fn track_with_gain(value: i16, gain: f32) -> SharedDecoder {
+        let data: Vec<u8> = (0..1000).flat_map(|_| value.to_le_bytes()).collect();
+        let mut wav = Vec::new();
+        wav.extend_from_slice(b"RIFF");
+        wav.extend_from_slice(&(36 + data.len() as u32).to_le_bytes());
+        wav.extend_from_slice(b"WAVEfmt ");
+        wav.extend_from_slice(&16u32.to_le_bytes());
+        wav.extend_from_slice(&1u16.to_le_bytes());
+        wav.extend_from_slice(&1u16.to_le_bytes());
+        wav.extend_from_slice(&1000u32.to_le_bytes());
+        wav.extend_from_slice(&2000u32.to_le_bytes());
+        wav.extend_from_slice(&2u16.to_le_bytes());
+        wav.extend_from_slice(&16u16.to_le_bytes());
+        wav.extend_from_slice(b"data");
+        wav.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        wav.extend_from_slice(&data);
+        let mss = MediaSourceStream::new(
+            Box::new(Cursor::new(wav)),
+            MediaSourceStreamOptions::default(),
+        );
+        let mut hint = Hint::new();
+        hint.with_extension("wav");
+        let (tx, _rx) = flume::unbounded();
+        SharedDecoder::new(SymphoniaDecoder::new(mss, hint, tx).ok().unwrap(), gain)
+    }
+
+    #[test]
+    fn the_next_track_is_faded_in_at_the_end() {
+        let slot = FollowerSlot::default();
+        let next = track(-1000);
+        slot.set(next.clone(), Duration::from_millis(100));
+        let playing: Vec<i16> = Crossfade::new(track(1000), slot).collect();
+        assert_eq!(playing.len(), 1000);
+        assert_eq!(playing[800], 1000);
+        // both are about as loud in the middle of the fade, which cancels out here
+        assert!(playing[950].abs() < 50);
+        assert!(playing[999] < -990);
+
+        // the next track goes on where the fade stopped
+        let rest: Vec<i16> = Crossfade::new(next, FollowerSlot::default()).collect();
+        assert_eq!(rest.len(), 900);
+    }
+
+    #[test]
+    fn samples_are_taken_in_chunks() {
+        let mut playing = Crossfade::new(track(1000), FollowerSlot::default());
+        playing.next();
+        assert_eq!(playing.current_frame_len(), Some(CHUNK_FRAMES - 1));
+        assert_eq!((playing.channels(), playing.sample_rate()), (1, 1000));
+    }
+
+    #[test]
+    fn tracks_follow_each_other_without_a_slot() {
+        let playing: Vec<i16> = Crossfade::new(track(1000), FollowerSlot::default()).collect();
+        assert!(playing.iter().all(|sample| *sample == 1000));
+    }
+
+    #[test]
+    fn samples_are_scaled_by_the_gain() {
+        let slot = FollowerSlot::default();
+        slot.set(track_with_gain(-1000, 2.0), Duration::from_millis(100));
+        let playing: Vec<i16> = Crossfade::new(track_with_gain(1000, 0.5), slot).collect();
+        assert_eq!(playing[0], 500);
+        assert!(playing[999] < -1990);
+    }
+}
@@ -40,7 +40,8 @@ pub struct SymphoniaDecoder {
     buffer: SampleBuffer<i16>,
     spec: SignalSpec,
     time_base: Option<TimeBase>,
-    duration: u64,
+    // unknown for live streams
+    duration: Option<u64>,
     elapsed: u64,
     metadata: Option<MetadataRevision>,
     track: Track,
@@ -92,8 +93,7 @@ impl SymphoniaDecoder {
         let duration = track
             .codec_params
             .n_frames
-            .map(|frames| track.codec_params.start_ts + frames)
-            .unwrap_or_default();
+            .map(|frames| track.codec_params.start_ts + frames);
 
         let mut _elapsed = 0;
 
@@ -165,6 +165,17 @@ impl SymphoniaDecoder {
         Duration::default()
     }
 
+    /// Frames left until the end of the track, unknown for live streams
+    pub fn remaining_frames(&self) -> Option<u64> {
+        let time = self
+            .time_base?
+            .calc_time(self.duration?.saturating_sub(self.elapsed));
+        let frames = (time.seconds as f64 + time.frac) * self.spec.rate as f64;
+        // the samples of the current packet that were played already
+        let played = self.current_frame_offset / self.spec.channels.count().max(1);
+        Some((frames.round() as u64).saturating_sub(played as u64))
+    }
+
     #[inline]
     pub fn seek(&mut self, time: Duration) -> Option<Duration> {
         let nanos_per_sec = 1_000_000_000.0;
@@ -179,6 +190,7 @@ impl SymphoniaDecoder {
             },
         ) {
             Ok(seeked_to) => {
+                self.elapsed = seeked_to.actual_ts;
                 let base = TimeBase::new(1, self.sample_rate());
                 let time = base.calc_time(seeked_to.actual_ts);
 
@@ -217,12 +229,12 @@ impl Source for SymphoniaDecoder {
 
     #[inline]
     fn total_duration(&self) -> Option<Duration> {
-        match self.time_base {
-            Some(tb) => {
-                let time = tb.calc_time(self.duration);
+        match (self.time_base, self.duration) {
+            (Some(tb), Some(duration)) => {
+                let time = tb.calc_time(duration);
                 Some(Duration::from_secs_f64(time.seconds as f64 + time.frac))
             }
-            None => None,
+            _ => None,
         }
     }
 }
@@ -0,0 +1,530 @@
+//! Filters the samples pass through between the decoder and the sink, e.g. an equalizer to
+//! correct the response of headphones. The chain can be switched while playing, every source
+//! picks it up with its next frame.
+use std::f64::consts::PI;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::Duration;
+
+use rodio::Source;
+use serde::{Deserialize, Serialize};
+
+// The bands of a graphic equalizer are an octave apart, starting at 31.25 Hz
+const GRAPHIC_FIRST_BAND: f64 = 31.25;
+const GRAPHIC_Q: f32 = std::f32::consts::SQRT_2;
+
+fn default_q() -> f32 {
+    std::f32::consts::FRAC_1_SQRT_2
+}
+
+/// A filter of the chain, gains are in dB and frequencies in Hz
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Filter {
+    /// Applied to all channels, usually negative to make room for the bands that are boosted
+    Preamp { gain: f32 },
+    /// Boosts or cuts the frequencies around `frequency`, a higher `q` narrows the band
+    Peaking {
+        frequency: f32,
+        gain: f32,
+        #[serde(default = "default_q")]
+        q: f32,
+    },
+    LowShelf {
+        frequency: f32,
+        gain: f32,
+        #[serde(default = "default_q")]
+        q: f32,
+    },
+    HighShelf {
+        frequency: f32,
+        gain: f32,
+        #[serde(default = "default_q")]
+        q: f32,
+    },
+    LowPass {
+        frequency: f32,
+        #[serde(default = "default_q")]
+        q: f32,
+    },
+    HighPass {
+        frequency: f32,
+        #[serde(default = "default_q")]
+        q: f32,
+    },
+    /// The gains of the bands of a graphic equalizer, from 31.25 Hz on an octave apart
+    Graphic { gains: Vec<f32> },
+    /// From -1.0, only the left channel, to 1.0, only the right one
+    Balance { balance: f32 },
+    /// Mixes all channels down to one, which is played on all of them
+    Mono,
+}
+
+/// The chain the sources are filtered with, shared between the engine and its sources
+#[derive(Clone, Default)]
+pub struct Dsp {
+    filters: Arc<Mutex<Arc<Vec<Filter>>>>,
+    // changes with every new chain, so the sources only lock it when it changed
+    version: Arc<AtomicUsize>,
+}
+
+impl Dsp {
+    pub fn set(&self, filters: Vec<Filter>) {
+        *self.filters.lock().unwrap_or_else(PoisonError::into_inner) = Arc::new(filters);
+        self.version.fetch_add(1, Ordering::SeqCst);
+    }
+
+    fn version(&self) -> usize {
+        self.version.load(Ordering::SeqCst)
+    }
+
+    fn filters(&self) -> Arc<Vec<Filter>> {
+        self.filters
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+}
+
+// The coefficients of a biquad from the Audio EQ Cookbook, normalized by a0
+#[derive(Clone, Copy, Debug)]
+struct Coefficients {
+    b0: f64,
+    b1: f64,
+    b2: f64,
+    a1: f64,
+    a2: f64,
+}
+
+impl Coefficients {
+    fn new(b0: f64, b1: f64, b2: f64, a0: f64, a1: f64, a2: f64) -> Self {
+        Self {
+            b0: b0 / a0,
+            b1: b1 / a0,
+            b2: b2 / a0,
+            a1: a1 / a0,
+            a2: a2 / a0,
+        }
+    }
+}
+
+enum Stage {
+    Gain(f32),
+    // with the state of the filter for every channel
+    Biquad(Coefficients, Vec<[f64; 2]>),
+    Balance(f32),
+    Mono,
+}
+
+impl Stage {
+    /// The stages of the filter for the format, filters of frequencies the sample rate can not
+    /// represent are left out
+    fn build(filter: &Filter, channels: u16, sample_rate: u32) -> Vec<Stage> {
+        let rate = sample_rate as f64;
+        let biquad = |kind: Biquad, frequency: f64, gain: f32, q: f32| {
+            if frequency <= 0.0 || frequency >= rate / 2.0 || q <= 0.0 {
+                return None;
+            }
+            let coefficients = kind.coefficients(frequency / rate, gain as f64, q as f64);
+            Some(Stage::Biquad(
+                coefficients,
+                vec![[0.0; 2]; channels as usize],
+            ))
+        };
+        let stage = match filter {
+            Filter::Preamp { gain } => Some(Stage::Gain(db_to_factor(*gain))),
+            Filter::Peaking { frequency, gain, q } => {
+                biquad(Biquad::Peaking, *frequency as f64, *gain, *q)
+            }
+            Filter::LowShelf { frequency, gain, q } => {
+                biquad(Biquad::LowShelf, *frequency as f64, *gain, *q)
+            }
+            Filter::HighShelf { frequency, gain, q } => {
+                biquad(Biquad::HighShelf, *frequency as f64, *gain, *q)
+            }
+            Filter::LowPass { frequency, q } => biquad(Biquad::LowPass, *frequency as f64, 0.0, *q),
+            Filter::HighPass { frequency, q } => {
+                biquad(Biquad::HighPass, *frequency as f64, 0.0, *q)
+            }
+            Filter::Graphic { gains } => {
+                return gains
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, gain)| **gain != 0.0)
+                    .filter_map(|(band, gain)| {
+                        let frequency = GRAPHIC_FIRST_BAND * 2f64.powi(band as i32);
+                        biquad(Biquad::Peaking, frequency, *gain, GRAPHIC_Q)
+                    })
+                    .collect()
+            }
+            Filter::Balance { balance } => Some(Stage::Balance(balance.clamp(-1.0, 1.0))),
+            Filter::Mono => Some(Stage::Mono),
+        };
+        stage.into_iter().collect()
+    }
+
+    fn process(&mut self, frame: &mut [f32]) {
+        match self {
+            Stage::Gain(factor) => frame.iter_mut().for_each(|sample| *sample *= *factor),
+            Stage::Biquad(c, state) => {
+                // transposed direct form II
+                for (sample, [z1, z2]) in frame.iter_mut().zip(state.iter_mut()) {
+                    let x = *sample as f64;
+                    let y = c.b0 * x + *z1;
+                    *z1 = c.b1 * x - c.a1 * y + *z2;
+                    *z2 = c.b2 * x - c.a2 * y;
+                    *sample = y as f32;
+                }
+            }
+            // only stereo sources have a left and a right channel
+            Stage::Balance(balance) => {
+                if let [left, right] = frame {
+                    *left *= 1.0 - balance.max(0.0);
+                    *right *= 1.0 + balance.min(0.0);
+                }
+            }
+            Stage::Mono => {
+                let mixed = frame.iter().sum::<f32>() / frame.len().max(1) as f32;
+                frame.iter_mut().for_each(|sample| *sample = mixed);
+            }
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Biquad {
+    Peaking,
+    LowShelf,
+    HighShelf,
+    LowPass,
+    HighPass,
+}
+
+impl Biquad {
+    /// `frequency` is relative to the sample rate
+    fn coefficients(self, frequency: f64, gain: f64, q: f64) -> Coefficients {
+        let a = 10f64.powf(gain / 40.0);
+        let w0 = 2.0 * PI * frequency;
+        let (sin, cos) = w0.sin_cos();
+        let alpha = sin / (2.0 * q);
+        let shelf = 2.0 * a.sqrt() * alpha;
+        match self {
+            Biquad::Peaking => Coefficients::new(
+                1.0 + alpha * a,
+                -2.0 * cos,
+                1.0 - alpha * a,
+                1.0 + alpha / a,
+                -2.0 * cos,
+                1.0 - alpha / a,
+            ),
+            Biquad::LowShelf => Coefficients::new(
+                a * ((a + 1.0) - (a - 1.0) * cos + shelf),
+                2.0 * a * ((a - 1.0) - (a + 1.0) * cos),
+                a * ((a + 1.0) - (a - 1.0) * cos - shelf),
+                (a + 1.0) + (a - 1.0) * cos + shelf,
+                -2.0 * ((a - 1.0) + (a + 1.0) * cos),
+                (a + 1.0) + (a - 1.0) * cos - shelf,
+            ),
+            Biquad::HighShelf => Coefficients::new(
+                a * ((a + 1.0) + (a - 1.0) * cos + shelf),
+                -2.0 * a * ((a - 1.0) + (a + 1.0) * cos),
+                a * ((a + 1.0) + (a - 1.0) * cos - shelf),
+                (a + 1.0) - (a - 1.0) * cos + shelf,
+                2.0 * ((a - 1.0) - (a + 1.0) * cos),
+                (a + 1.0) - (a - 1.0) * cos - shelf,
+            ),
+            Biquad::LowPass => Coefficients::new(
+                (1.0 - cos) / 2.0,
+                1.0 - cos,
+                (1.0 - cos) / 2.0,
+                1.0 + alpha,
+                -2.0 * cos,
+                1.0 - alpha,
+            ),
+            Biquad::HighPass => Coefficients::new(
+                (1.0 + cos) / 2.0,
+                -(1.0 + cos),
+                (1.0 + cos) / 2.0,
+                1.0 + alpha,
+                -2.0 * cos,
+                1.0 - alpha,
+            ),
+        }
+    }
+}
+
+fn db_to_factor(gain: f32) -> f32 {
+    10f32.powf(gain / 20.0)
+}
+
+/// Passes the samples of the source through the chain frame by frame
+pub struct DspSource<S> {
+    inner: S,
+    dsp: Dsp,
+    // the chain and format the stages were built for
+    version: Option<usize>,
+    format: (u16, u32),
+    stages: Vec<Stage>,
+    frame: Vec<f32>,
+    // of the next sample of the frame to pass on
+    position: usize,
+}
+
+impl<S> DspSource<S>
+where
+    S: Source<Item = i16>,
+{
+    pub fn new(inner: S, dsp: Dsp) -> Self {
+        Self {
+            inner,
+            dsp,
+            version: None,
+            format: (0, 0),
+            stages: Vec::new(),
+            frame: Vec::new(),
+            position: 0,
+        }
+    }
+
+    pub fn inner(&self) -> &S {
+        &self.inner
+    }
+
+    /// Reads and filters the next frame, false once the source ended
+    fn next_frame(&mut self) -> bool {
+        let format = (self.inner.channels().max(1), self.inner.sample_rate());
+        self.frame.clear();
+        self.position = 0;
+        self.frame
+            .extend(self.inner.by_ref().take(format.0 as usize).map(f32::from));
+        if self.frame.is_empty() {
+            return false;
+        }
+        let version = self.dsp.version();
+        if self.version != Some(version) || self.format != format {
+            self.version = Some(version);
+            self.format = format;
+            self.stages = self
+                .dsp
+                .filters()
+                .iter()
+                .flat_map(|filter| Stage::build(filter, format.0, format.1))
+                .collect();
+        }
+        for stage in &mut self.stages {
+            stage.process(&mut self.frame);
+        }
+        true
+    }
+}
+
+impl<S> Source for DspSource<S>
+where
+    S: Source<Item = i16>,
+{
+    // the samples left of the filtered frame are in its format, the inner source may have moved
+    // on to another one
+    #[inline]
+    fn current_frame_len(&self) -> Option<usize> {
+        let unread = self.frame.len() - self.position;
+        if unread == 0 {
+            return self.inner.current_frame_len();
+        }
+        if (self.inner.channels().max(1), self.inner.sample_rate()) != self.format {
+            return Some(unread);
+        }
+        self.inner.current_frame_len().map(|len| len + unread)
+    }
+
+    #[inline]
+    fn channels(&self) -> u16 {
+        match self.frame.len() - self.position {
+            0 => self.inner.channels(),
+            _ => self.format.0,
+        }
+    }
+
+    #[inline]
+    fn sample_rate(&self) -> u32 {
+        match self.frame.len() - self.position {
+            0 => self.inner.sample_rate(),
+            _ => self.format.1,
+        }
+    }
+
+    #[inline]
+    fn total_duration(&self) -> Option<Duration> {
+        self.inner.total_duration()
+    }
+}
+
+impl<S> Iterator for DspSource<S>
+where
+    S: Source<Item = i16>,
+{
+    type Item = i16;
+
+    #[inline]
+    fn next(&mut self) -> Option<i16> {
+        if self.position == self.frame.len() && !self.next_frame() {
+            return None;
+        }
+        let sample = self.frame[self.position];
+        self.position += 1;
+        Some(sample.clamp(i16::MIN as f32, i16::MAX as f32) as i16)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rodio::buffer::SamplesBuffer;
+
+    // A mono sine of a second at 48 kHz
+    fn sine(frequency: f64) -> SamplesBuffer<i16> {
+        let samples = (0..48000)
+            .map(|i| ((2.0 * PI * frequency * i as f64 / 48000.0).sin() * 10000.0) as i16)
+            .collect::<Vec<_>>();
+        SamplesBuffer::new(1, 48000, samples)
+    }
+
+    // The peak of the second half, once the filters settled
+    fn peak(samples: &[i16]) -> f32 {
+        let settled = &samples[samples.len() / 2..];
+        settled.iter().map(|s| s.unsigned_abs()).max().unwrap_or(0) as f32 / 10000.0
+    }
+
+    fn filtered<S: Source<Item = i16>>(source: S, filters: Vec<Filter>) -> Vec<i16> {
+        let dsp = Dsp::default();
+        dsp.set(filters);
+        DspSource::new(source, dsp).collect()
+    }
+
+    #[test]
+    fn peaking_filters_boost_their_band_only() {
+        let boost = vec![Filter::Peaking {
+            frequency: 1000.0,
+            gain: 6.0,
+            q: 1.0,
+        }];
+        assert!((peak(&filtered(sine(1000.0), boost.clone())) - 2.0).abs() < 0.05);
+        assert!((peak(&filtered(sine(50.0), boost)) - 1.0).abs() < 0.05);
+
+        let graphic = vec![Filter::Graphic {
+            gains: vec![0.0, 0.0, 0.0, 0.0, 0.0, -6.0],
+        }];
+        assert!((peak(&filtered(sine(1000.0), graphic)) - 0.5).abs() < 0.05);
+    }
+
+    #[test]
+    fn shelves_and_passes_keep_the_other_end() {
+        let low_cut = vec![Filter::HighPass {
+            frequency: 200.0,
+            q: default_q(),
+        }];
+        assert!(peak(&filtered(sine(20.0), low_cut.clone())) < 0.05);
+        assert!((peak(&filtered(sine(5000.0), low_cut)) - 1.0).abs() < 0.05);
+
+        let treble = vec![Filter::HighShelf {
+            frequency: 4000.0,
+            gain: -6.0,
+            q: default_q(),
+        }];
+        assert!((peak(&filtered(sine(15000.0), treble.clone())) - 0.5).abs() < 0.05);
+        assert!((peak(&filtered(sine(100.0), treble)) - 1.0).abs() < 0.05);
+    }
+
+    #[test]
+    fn channels_are_balanced_and_mixed_down() {
+        let stereo = || SamplesBuffer::new(2, 48000, vec![1000i16, 3000, 1000, 3000]);
+        let preamp = Filter::Preamp { gain: -6.0206 };
+        assert_eq!(
+            filtered(stereo(), vec![preamp, Filter::Balance { balance: 0.5 }]),
+            vec![250, 1500, 250, 1500]
+        );
+        assert_eq!(
+            filtered(stereo(), vec![Filter::Mono]),
+            vec![2000, 2000, 2000, 2000]
+        );
+        // mono sources have no balance
+        let mono = SamplesBuffer::new(1, 48000, vec![1000i16]);
+        assert_eq!(
+            filtered(mono, vec![Filter::Balance { balance: 1.0 }]),
+            vec![1000]
+        );
+    }
+
+    // Plays `first` and then `second`, with the format of `second` once `first` is read like a
+    // crossfade or gapless switch between tracks
+    struct Switch {
+        first: SamplesBuffer<i16>,
+        second: SamplesBuffer<i16>,
+    }
+
+    impl Switch {
+        fn current(&self) -> &SamplesBuffer<i16> {
+            match self.first.size_hint() {
+                (0, _) => &self.second,
+                _ => &self.first,
+            }
+        }
+    }
+
+    impl Iterator for Switch {
+        type Item = i16;
+
+        fn next(&mut self) -> Option<i16> {
+            self.first.next().or_else(|| self.second.next())
+        }
+    }
+
+    impl Source for Switch {
+        fn current_frame_len(&self) -> Option<usize> {
+            Some(self.current().size_hint().0)
+        }
+
+        fn channels(&self) -> u16 {
+            self.current().channels()
+        }
+
+        fn sample_rate(&self) -> u32 {
+            self.current().sample_rate()
+        }
+
+        fn total_duration(&self) -> Option<Duration> {
+            None
+        }
+    }
+
+    #[test]
+    fn filtered_samples_keep_their_format() {
+        let mut source = DspSource::new(
+            Switch {
+                first: SamplesBuffer::new(2, 48000, vec![1000i16, 3000, 1000, 3000]),
+                second: SamplesBuffer::new(1, 44100, vec![2000i16, 2000]),
+            },
+            Dsp::default(),
+        );
+        assert_eq!(source.next(), Some(1000));
+        assert_eq!(source.current_frame_len(), Some(3));
+        assert_eq!(source.next(), Some(3000));
+        assert_eq!(source.next(), Some(1000));
+        // the last frame of the first one is read already
+        assert_eq!(source.current_frame_len(), Some(1));
+        assert_eq!((source.channels(), source.sample_rate()), (2, 48000));
+        assert_eq!(source.next(), Some(3000));
+        assert_eq!((source.channels(), source.sample_rate()), (1, 44100));
+        assert_eq!(source.current_frame_len(), Some(2));
+    }
+
+    #[test]
+    fn the_chain_is_switched_with_the_next_frame() {
+        let dsp = Dsp::default();
+        let stereo = SamplesBuffer::new(2, 48000, vec![1000i16, 3000, 1000, 3000]);
+        let mut source = DspSource::new(stereo, dsp.clone());
+        assert_eq!(source.next(), Some(1000));
+        dsp.set(vec![Filter::Mono]);
+        assert_eq!(source.next(), Some(3000));
+        assert_eq!(source.collect::<Vec<_>>(), vec![2000, 2000]);
+    }
+}
@@ -1,7 +1,10 @@
+mod crossfade;
 mod decoder;
+mod dsp;
 mod player;
 mod player_engine;
 
 pub use decoder::MediaInfo;
+pub use dsp::Filter;
 pub use player::{Player, PlayerError};
 pub use player_engine::PlayerMessage;
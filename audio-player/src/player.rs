@@ -6,6 +6,7 @@ use flume::{Receiver, Sender};
 use tracing::{error, warn};
 
 use crate::decoder::MediaInfo;
+use crate::dsp::Filter;
 use crate::player_engine::{PlayerEngine, PlayerEngineCommand, PlayerMessage};
 
 // TODO:
@@ -37,8 +38,8 @@ impl Default for Player {
 
             loop {
                 match rx_engine.recv() {
-                    Ok(PlayerEngineCommand::Play(source_str, tx)) => {
-                        tx.send(player.play(&source_str))
+                    Ok(PlayerEngineCommand::Play(source_str, gain, tx)) => {
+                        tx.send(player.play(&source_str, gain))
                             .unwrap_or_else(|e| warn!("Send error {}", e));
                     }
                     Ok(PlayerEngineCommand::Pause(tx)) => {
@@ -91,6 +92,15 @@ impl Default for Player {
                     Ok(PlayerEngineCommand::Eos) => {
                         player.handle_eos();
                     }
+                    Ok(PlayerEngineCommand::Preload(source_str, crossfade, gain)) => {
+                        player.preload(source_str, crossfade, gain);
+                    }
+                    Ok(PlayerEngineCommand::Preloaded(source_str, decoder)) => {
+                        player.handle_preloaded(source_str, decoder);
+                    }
+                    Ok(PlayerEngineCommand::SetDsp(filters)) => {
+                        player.set_dsp(filters);
+                    }
                     Err(e) => {
                         warn!("Recv error {}", e);
                     }
@@ -106,20 +116,40 @@ impl Default for Player {
 }
 
 impl Player {
-    pub async fn play(&self, source_str: &str) -> Result<MediaInfo> {
+    /// Plays the source with its samples scaled by `gain`, 1.0 plays it as it is
+    pub async fn play(&self, source_str: &str, gain: f32) -> Result<MediaInfo> {
         let (tx, rx) = flume::bounded(1);
         self.tx_engine
-            .send(PlayerEngineCommand::Play(source_str.to_string(), tx))?;
+            .send(PlayerEngineCommand::Play(source_str.to_string(), gain, tx))?;
         rx.recv_async().await?
     }
 
+    /// Lets the player open the source that follows the playing one, so it starts without a gap
+    /// when the playing one ends, or is crossfaded into it for `crossfade` unless that is zero.
+    /// `None` drops a source preloaded before.
+    pub async fn preload(
+        &self,
+        source_str: Option<&str>,
+        crossfade: Duration,
+        gain: f32,
+    ) -> Result<()> {
+        self.tx_engine
+            .send_async(PlayerEngineCommand::Preload(
+                source_str.map(String::from),
+                crossfade,
+                gain,
+            ))
+            .await?;
+        Ok(())
+    }
+
     pub async fn restart(&self) -> Result<MediaInfo> {
         let (tx, rx) = flume::bounded(1);
         self.tx_engine.send(PlayerEngineCommand::Restart(tx))?;
         rx.recv_async().await?
     }
 
-    pub async fn elpased(&self) -> Result<Duration> {
+    pub async fn elapsed(&self) -> Result<Duration> {
         let (tx, rx) = flume::bounded(1);
         self.tx_engine.send(PlayerEngineCommand::GetElapsed(tx))?;
         rx.recv_async().await?
@@ -156,6 +186,14 @@ impl Player {
         Ok(rx.recv_async().await?)
     }
 
+    /// Lets all audio pass through the filters, in order, an empty chain turns them off
+    pub async fn set_dsp(&self, filters: Vec<Filter>) -> Result<()> {
+        self.tx_engine
+            .send_async(PlayerEngineCommand::SetDsp(filters))
+            .await?;
+        Ok(())
+    }
+
     pub async fn pause(&self) -> Result<()> {
         let (tx, rx) = flume::bounded(1);
         self.tx_engine.send(PlayerEngineCommand::Pause(tx))?;
@@ -1,22 +1,25 @@
 use flume::Sender;
 use std::path::Path;
-use std::sync::atomic::AtomicU64;
+use std::sync::atomic::{AtomicBool, AtomicU64};
+use std::sync::Arc;
 use std::thread;
 use std::time::Duration;
 use std::{fs::File, sync::atomic::Ordering};
+use stream_download::StreamDownload;
 use symphonia::core::probe::Hint;
 use tracing::{debug, warn};
 use url::Url;
 
+use crate::crossfade::{Crossfade, FollowerSlot, SharedDecoder};
 use crate::decoder::{MediaInfo, SymphoniaDecoder};
+use crate::dsp::{Dsp, DspSource, Filter};
 use anyhow::{anyhow, Result};
 use rodio::{OutputStream, OutputStreamHandle, Sink, Source};
-use stream_download::StreamDownload;
 use symphonia::core::io::{MediaSource, MediaSourceStream, MediaSourceStreamOptions};
 use thiserror::Error;
 
 pub enum PlayerEngineCommand {
-    Play(String, Sender<Result<MediaInfo>>),
+    Play(String, f32, Sender<Result<MediaInfo>>),
     SetVolume(f32, Sender<f32>),
     Pause(Sender<Result<()>>),
     Unpause(Sender<Result<()>>),
@@ -30,6 +33,9 @@ pub enum PlayerEngineCommand {
     GetPaused(Sender<Result<bool>>),
     Eos,
     SetElapsed(Duration),
+    Preload(Option<String>, Duration, f32),
+    Preloaded(String, Result<Box<SymphoniaDecoder>>),
+    SetDsp(Vec<Filter>),
 }
 
 pub enum PlayerMessage {
@@ -44,6 +50,12 @@ pub enum PlayerMessage {
     Paused,
     Playing,
     EndOfStream,
+    /// The preloaded source took over from the one that ended, without a gap
+    NextStarted,
+    /// A live stream announced the title that is playing now, e.g. `Artist - Title`
+    StreamTitle {
+        title: String,
+    },
 }
 
 // TODO:
@@ -53,15 +65,42 @@ pub enum PlayerMessage {
 pub enum PlayerEngineError {
     #[error("Sink is not playing")]
     NotPlaying,
+    #[error("Live streams can not be seeked")]
+    NotSeekable,
 }
 
-// Used for seeking in the stream
-static SEEK_TO: AtomicU64 = AtomicU64::new(0);
+// Used for seeking in the stream, the position in milliseconds or `NO_SEEK`
+const NO_SEEK: u64 = u64::MAX;
+static SEEK_TO: AtomicU64 = AtomicU64::new(NO_SEEK);
+
+// A source opened ahead of time and appended to the sink, which goes on with it sample-accurately
+// when the playing one ends
+struct Preloaded {
+    source: String,
+    media_info: MediaInfo,
+    // ends the source as soon as the sink gets to it
+    cancelled: Arc<AtomicBool>,
+    next_slot: FollowerSlot,
+    format: (u16, u32),
+    gain: f32,
+}
 
 pub struct PlayerEngine {
     elapsed: Duration,
     current_source: Option<String>,
     media_info: Option<MediaInfo>,
+    // the gain the samples of the playing source are scaled with, kept to restart it
+    gain: f32,
+    // the source being opened to follow the playing one, how long to crossfade into it and its
+    // gain
+    preloading: Option<(String, Duration, f32)>,
+    next: Option<Preloaded>,
+    // where the source crossfaded into at the end of the playing one goes
+    next_slot: FollowerSlot,
+    // the channels and sample rate of the playing source, only the same are crossfaded
+    format: (u16, u32),
+    // the filters all sources pass through
+    dsp: Dsp,
     sink: Sink,
     // We need to keep the stream around as it will stop playing when it's dropped
     _stream: OutputStream,
@@ -80,6 +119,12 @@ impl PlayerEngine {
         Ok(Self {
             current_source: None,
             media_info: None,
+            gain: 1.0,
+            preloading: None,
+            next: None,
+            next_slot: FollowerSlot::default(),
+            format: (0, 0),
+            dsp: Dsp::default(),
             elapsed: Duration::default(),
             sink,
             _stream,
@@ -89,15 +134,11 @@ impl PlayerEngine {
         })
     }
 
-    pub fn play(&mut self, source_str: &str) -> Result<MediaInfo> {
-        let tx_player = self.tx_player.clone();
-        let tx_engine = self.tx_engine.clone();
-
+    /// Plays the source with its samples scaled by `gain`
+    pub fn play(&mut self, source_str: &str, gain: f32) -> Result<MediaInfo> {
         self.reset();
 
-        let (source, hint) = self.get_source(source_str)?;
-        let mss = MediaSourceStream::new(source, MediaSourceStreamOptions::default());
-        let decoder = SymphoniaDecoder::new(mss, hint, self.tx_engine.clone())?;
+        let decoder = Self::open(source_str, self.tx_engine.clone(), &self.tx_player)?;
 
         let media_info = decoder.media_info();
         let media_info_copy = media_info.clone();
@@ -105,28 +146,14 @@ impl PlayerEngine {
 
         self.media_info = Some(media_info);
         self.current_source = Some(source_str.to_string());
+        self.gain = gain;
 
-        tx_player
+        self.tx_player
             .send(PlayerMessage::Duration { duration })
             .unwrap_or_else(|e| warn!("Send error {}", e));
 
-        // FIXME: regularly update metadata revision
-        let decoder = decoder.periodic_access(Duration::from_millis(250), move |src| {
-            let seek = SEEK_TO.load(Ordering::SeqCst);
-            if seek > 0 {
-                src.seek(Duration::from_secs(seek));
-                SEEK_TO.store(0, Ordering::SeqCst);
-            }
-            let elapsed = src.elapsed();
-            tx_engine
-                .send(PlayerEngineCommand::SetElapsed(elapsed))
-                .unwrap_or_else(|e| warn!("Send error {}", e));
-            tx_player
-                .send(PlayerMessage::Elapsed { elapsed, duration })
-                .unwrap_or_else(|e| warn!("Send error {}", e));
-        });
-
-        self.sink.append(decoder);
+        self.format = (decoder.channels(), decoder.sample_rate());
+        (_, self.next_slot) = self.append(decoder, gain, Arc::default());
         self.sink.play();
 
         self.tx_player
@@ -136,9 +163,67 @@ impl PlayerEngine {
         Ok(media_info_copy)
     }
 
+    /// Starts opening the source that follows the playing one, `None` drops the one preloaded
+    /// before. A `crossfade` of zero appends it without a gap.
+    pub fn preload(&mut self, source_str: Option<String>, crossfade: Duration, gain: f32) {
+        if let Some(next) = self.next.take() {
+            next.cancelled.store(true, Ordering::SeqCst);
+        }
+        self.next_slot.clear();
+        self.preloading = source_str
+            .clone()
+            .map(|source_str| (source_str, crossfade, gain));
+        let Some(source_str) = source_str else {
+            return;
+        };
+        debug!("Preloading {}", source_str);
+        // opening streams takes a while, the engine keeps handling commands meanwhile
+        let tx_engine = self.tx_engine.clone();
+        let tx_player = self.tx_player.clone();
+        thread::spawn(move || {
+            let decoder = Self::open(&source_str, tx_engine.clone(), &tx_player).map(Box::new);
+            tx_engine
+                .send(PlayerEngineCommand::Preloaded(source_str, decoder))
+                .unwrap_or_else(|e| warn!("Send error {}", e));
+        });
+    }
+
+    pub fn handle_preloaded(&mut self, source_str: String, decoder: Result<Box<SymphoniaDecoder>>) {
+        // something else was played or preloaded in the meantime
+        let (crossfade, gain) = match &self.preloading {
+            Some((preloading, crossfade, gain)) if *preloading == source_str => (*crossfade, *gain),
+            _ => return,
+        };
+        if self.current_source.is_none() {
+            return;
+        }
+        self.preloading = None;
+        match decoder {
+            Ok(decoder) => {
+                let media_info = decoder.media_info();
+                let format = (decoder.channels(), decoder.sample_rate());
+                let cancelled = Arc::new(AtomicBool::new(false));
+                let (shared, next_slot) = self.append(*decoder, gain, cancelled.clone());
+                // mixing needs the samples of both in the same format
+                if !crossfade.is_zero() && format == self.format {
+                    self.next_slot.set(shared, crossfade);
+                }
+                self.next = Some(Preloaded {
+                    source: source_str,
+                    media_info,
+                    cancelled,
+                    next_slot,
+                    format,
+                    gain,
+                });
+            }
+            Err(e) => warn!("Could not preload {}: {}", source_str, e),
+        }
+    }
+
     pub fn restart(&mut self) -> Result<MediaInfo> {
         if let Some(source) = self.current_source.clone() {
-            return self.play(&source);
+            return self.play(&source, self.gain);
         }
         Err(PlayerEngineError::NotPlaying.into())
     }
@@ -215,11 +300,19 @@ impl PlayerEngine {
         Ok(self.elapsed)
     }
 
-    pub fn seek_to(&self, time: Duration) -> Result<Duration> {
-        // We can seek between 1 second and the total duration of the track
-        let duration = self.duration().unwrap_or(self.elapsed);
-        let time = time.clamp(Duration::from_secs(1), duration);
-        SEEK_TO.store(time.as_secs(), Ordering::SeqCst);
+    pub fn seek_to(&mut self, time: Duration) -> Result<Duration> {
+        let Some(media_info) = &self.media_info else {
+            return Err(PlayerEngineError::NotPlaying.into());
+        };
+        // Live streams have no duration
+        let Some(duration) = media_info.duration else {
+            return Err(PlayerEngineError::NotSeekable.into());
+        };
+        // We can seek up to the total duration of the track
+        let time = time.min(duration);
+        SEEK_TO.store(time.as_millis() as u64, Ordering::SeqCst);
+        // seeking again right away goes on from here
+        self.elapsed = time;
         // FIXME: ideally we would like to return once the seeking is successful
         // then return the current elapsed time
         // Cond-var might be needed to sleep this (seeking takes time)
@@ -235,7 +328,29 @@ impl PlayerEngine {
         self.sink.volume()
     }
 
+    /// Replaces the filters, the playing source passes through the new ones right away
+    pub fn set_dsp(&mut self, filters: Vec<Filter>) {
+        self.dsp.set(filters);
+    }
+
     pub fn handle_eos(&mut self) {
+        // the sink already went on with the preloaded source
+        if let Some(next) = self.next.take() {
+            let duration = next.media_info.duration.unwrap_or_default();
+            self.elapsed = Duration::default();
+            self.current_source = Some(next.source);
+            self.media_info = Some(next.media_info);
+            self.next_slot = next.next_slot;
+            self.format = next.format;
+            self.gain = next.gain;
+            self.tx_player
+                .send(PlayerMessage::Duration { duration })
+                .unwrap_or_else(|e| warn!("Send error {}", e));
+            self.tx_player
+                .send(PlayerMessage::NextStarted)
+                .unwrap_or_else(|e| warn!("Send error {}", e));
+            return;
+        }
         self.reset();
         self.tx_player
             .send(PlayerMessage::EndOfStream)
@@ -249,17 +364,92 @@ impl PlayerEngine {
     fn reset(&mut self) {
         self.elapsed = Duration::default();
         self.current_source = None;
+        // stopping the sink drops the preloaded source as well
+        self.preloading = None;
+        self.next = None;
+        self.next_slot.clear();
+        // a seek the source did not get to does not apply to the next one
+        SEEK_TO.store(NO_SEEK, Ordering::SeqCst);
         self.sink.pause();
         self.sink.stop();
     }
 
-    fn get_source(&self, source_str: &str) -> Result<(Box<dyn MediaSource>, Hint)> {
+    /// Appends the source to the sink, where it reports its position while it is playing. Returns
+    /// its decoder, to crossfade into it, and the slot for the source crossfaded into at its end.
+    fn append(
+        &self,
+        decoder: SymphoniaDecoder,
+        gain: f32,
+        cancelled: Arc<AtomicBool>,
+    ) -> (SharedDecoder, FollowerSlot) {
+        let tx_player = self.tx_player.clone();
+        let tx_engine = self.tx_engine.clone();
+        let duration = decoder.media_info().duration.unwrap_or_default();
+        let decoder = SharedDecoder::new(decoder, gain);
+        let next_slot = FollowerSlot::default();
+
+        // FIXME: regularly update metadata revision
+        let crossfade = Crossfade::new(decoder.clone(), next_slot.clone());
+        let source = DspSource::new(crossfade, self.dsp.clone())
+            .stoppable()
+            .periodic_access(Duration::from_millis(250), move |src| {
+                if cancelled.load(Ordering::SeqCst) {
+                    src.stop();
+                    return;
+                }
+                let mut src = src.inner_mut().inner().decoder().lock();
+                let seek = SEEK_TO.swap(NO_SEEK, Ordering::SeqCst);
+                if seek != NO_SEEK {
+                    src.seek(Duration::from_millis(seek));
+                }
+                let elapsed = src.elapsed();
+                tx_engine
+                    .send(PlayerEngineCommand::SetElapsed(elapsed))
+                    .unwrap_or_else(|e| warn!("Send error {}", e));
+                tx_player
+                    .send(PlayerMessage::Elapsed { elapsed, duration })
+                    .unwrap_or_else(|e| warn!("Send error {}", e));
+            });
+
+        self.sink.append(source);
+        (decoder, next_slot)
+    }
+
+    /// Opens the source and probes its format, which starts downloading streams
+    fn open(
+        source_str: &str,
+        tx_engine: Sender<PlayerEngineCommand>,
+        tx_player: &Sender<PlayerMessage>,
+    ) -> Result<SymphoniaDecoder> {
+        let (source, hint) = Self::get_source(source_str, tx_player)?;
+        let mss = MediaSourceStream::new(source, MediaSourceStreamOptions::default());
+        Ok(SymphoniaDecoder::new(mss, hint, tx_engine)?)
+    }
+
+    fn get_source(
+        source_str: &str,
+        tx_player: &Sender<PlayerMessage>,
+    ) -> Result<(Box<dyn MediaSource>, Hint)> {
         match Url::parse(source_str) {
             Ok(url) => {
                 if let "http" | "https" = url.scheme() {
-                    let reader = StreamDownload::new_http(source_str.parse().unwrap());
+                    let tx_player = tx_player.clone();
+                    let reader = stream_download::open_http(
+                        source_str.parse()?,
+                        Box::new(move |title| {
+                            tx_player
+                                .send(PlayerMessage::StreamTitle { title })
+                                .unwrap_or_else(|e| warn!("Send error {}", e));
+                        }),
+                    )?;
                     let path = Path::new(url.path());
-                    let hint = self.get_hint(path);
+                    let hint = Self::get_hint(path);
+
+                    Ok((reader, hint))
+                } else if let Some(segments) = stream_download::segmented::uri_list(source_str) {
+                    // the initialization segment tells the format
+                    let hint = Self::get_hint(Path::new(segments[0].path()));
+                    let reader = StreamDownload::new_segmented(segments);
 
                     Ok((Box::new(reader), hint))
                 } else {
@@ -268,13 +458,13 @@ impl PlayerEngine {
             }
             Err(_) => {
                 let path = Path::new(source_str);
-                let hint = self.get_hint(path);
+                let hint = Self::get_hint(path);
                 Ok((Box::new(File::open(path)?), hint))
             }
         }
     }
 
-    fn get_hint(&self, path: &Path) -> Hint {
+    fn get_hint(path: &Path) -> Hint {
         // Create a hint to help the format registry guess what format reader is appropriate.
         let mut hint = Hint::new();
         // Provide the file extension as a hint.
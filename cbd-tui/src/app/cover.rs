@@ -0,0 +1,264 @@
+//! The cover of the track that is playing. Terminals that can show images get the image itself,
+//! all others a rough version of it drawn with half blocks.
+use std::io::{self, Cursor, Write};
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use crossterm::{cursor::MoveTo, queue};
+use image::{imageops::FilterType, ImageReader, ImageResult, Limits, RgbImage};
+use ratatui::{
+    backend::Backend, buffer::Buffer, layout::Rect, style::Color, widgets::Widget, Frame,
+};
+
+// kitty takes the image data in chunks of at most this size
+const KITTY_CHUNK: usize = 4096;
+const KITTY_IMAGE_ID: u32 = 4242;
+// images claiming to be larger are not decoded, the header alone must not make us allocate
+// gigabytes
+const MAX_SIDE: u32 = 4096;
+const MAX_ALLOC: u64 = 128 * 1024 * 1024;
+// decoded covers are scaled down to this size, plenty for the cells they are drawn in
+const BITMAP_SIDE: u32 = 320;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Protocol {
+    Kitty,
+    Iterm,
+    HalfBlocks,
+}
+
+impl Protocol {
+    /// The image protocol of the terminal, as far as its environment tells
+    pub fn detect() -> Self {
+        let var = |name| std::env::var(name).unwrap_or_default();
+        if !var("KITTY_WINDOW_ID").is_empty() || var("TERM").contains("kitty") {
+            Protocol::Kitty
+        } else if matches!(var("TERM_PROGRAM").as_str(), "iTerm.app" | "WezTerm")
+            || var("LC_TERMINAL") == "iTerm2"
+        {
+            Protocol::Iterm
+        } else {
+            Protocol::HalfBlocks
+        }
+    }
+}
+
+pub struct Cover {
+    protocol: Protocol,
+    url: Option<String>,
+    // as fetched, for terminals that decode the image themselves
+    data: Option<Vec<u8>>,
+    bitmap: Option<RgbImage>,
+    area: Rect,
+    // the cover and the area the image was written to last, the escape sequences are only
+    // written again when one of them changes
+    drawn: Option<(String, Rect)>,
+}
+
+impl Cover {
+    pub fn new(protocol: Protocol) -> Self {
+        Cover {
+            protocol,
+            url: None,
+            data: None,
+            bitmap: None,
+            area: Rect::default(),
+            drawn: None,
+        }
+    }
+
+    /// Switches to the cover of the url, returns whether it has to be fetched
+    pub fn request(&mut self, url: Option<String>) -> bool {
+        if url == self.url {
+            return false;
+        }
+        self.data = None;
+        self.bitmap = None;
+        self.url = url;
+        self.url.is_some()
+    }
+
+    pub fn update(&mut self, url: &str, data: Vec<u8>) {
+        if self.url.as_deref() != Some(url) {
+            return;
+        }
+        // iTerm2 decodes the image itself
+        if self.protocol != Protocol::Iterm {
+            self.bitmap = decode(&data).ok();
+        }
+        self.data = Some(data);
+    }
+
+    pub fn is_shown(&self) -> bool {
+        match self.protocol {
+            Protocol::Iterm => self.data.is_some(),
+            _ => self.bitmap.is_some(),
+        }
+    }
+
+    pub fn render<B: Backend>(&mut self, f: &mut Frame<B>, area: Rect) {
+        self.area = area;
+        if let (Protocol::HalfBlocks, Some(bitmap)) = (self.protocol, &self.bitmap) {
+            f.render_widget(HalfBlocks(bitmap), area);
+        }
+    }
+
+    /// Writes the image for terminals that show them, after the rest of the screen is drawn
+    pub fn draw(&mut self, out: &mut impl Write) -> io::Result<()> {
+        let current = self
+            .url
+            .clone()
+            .filter(|_| self.is_shown() && 0 < self.area.area())
+            .map(|url| (url, self.area));
+        if self.protocol == Protocol::HalfBlocks || current == self.drawn {
+            return Ok(());
+        }
+        if self.protocol == Protocol::Kitty && self.drawn.is_some() {
+            write!(out, "\x1b_Ga=d,d=I,i={},q=2\x1b\\", KITTY_IMAGE_ID)?;
+        }
+        self.drawn = current;
+        let area = self.area;
+        match (&self.drawn, self.protocol, &self.data, &self.bitmap) {
+            (Some(_), Protocol::Kitty, _, Some(bitmap)) => {
+                queue!(out, MoveTo(area.x, area.y))?;
+                write_kitty(out, bitmap, area)?;
+            }
+            (Some(_), Protocol::Iterm, Some(data), _) => {
+                queue!(out, MoveTo(area.x, area.y))?;
+                write!(
+                    out,
+                    "\x1b]1337;File=inline=1;size={};width={};height={};preserveAspectRatio=1:{}\x07",
+                    data.len(),
+                    area.width,
+                    area.height,
+                    STANDARD.encode(data)
+                )?;
+            }
+            _ => {}
+        }
+        out.flush()
+    }
+}
+
+/// The cover as JPEG, PNG or WebP decoded and scaled down to at most `BITMAP_SIDE` pixels
+fn decode(data: &[u8]) -> ImageResult<RgbImage> {
+    let mut reader = ImageReader::new(Cursor::new(data)).with_guessed_format()?;
+    let mut limits = Limits::default();
+    limits.max_image_width = Some(MAX_SIDE);
+    limits.max_image_height = Some(MAX_SIDE);
+    limits.max_alloc = Some(MAX_ALLOC);
+    reader.limits(limits);
+    let image = reader.decode()?;
+    if image.width() <= BITMAP_SIDE && image.height() <= BITMAP_SIDE {
+        return Ok(image.to_rgb8());
+    }
+    Ok(image.thumbnail(BITMAP_SIDE, BITMAP_SIDE).to_rgb8())
+}
+
+fn write_kitty(out: &mut impl Write, bitmap: &RgbImage, area: Rect) -> io::Result<()> {
+    let data = STANDARD.encode(bitmap.as_raw());
+    let chunks: Vec<&[u8]> = data.as_bytes().chunks(KITTY_CHUNK).collect();
+    for (i, chunk) in chunks.iter().enumerate() {
+        let more = u8::from(i + 1 < chunks.len());
+        if i == 0 {
+            // scaled to the cells of the area, without moving the cursor
+            write!(
+                out,
+                "\x1b_Ga=T,f=24,s={},v={},c={},r={},i={},C=1,q=2,m={};",
+                bitmap.width(),
+                bitmap.height(),
+                area.width,
+                area.height,
+                KITTY_IMAGE_ID,
+                more
+            )?;
+        } else {
+            write!(out, "\x1b_Gm={};", more)?;
+        }
+        out.write_all(chunk)?;
+        write!(out, "\x1b\\")?;
+    }
+    Ok(())
+}
+
+/// Two pixels per cell, the upper one in the foreground color of a half block and the lower one
+/// in the background color
+struct HalfBlocks<'a>(&'a RgbImage);
+
+impl<'a> Widget for HalfBlocks<'a> {
+    fn render(self, area: Rect, buf: &mut Buffer) {
+        if area.area() == 0 {
+            return;
+        }
+        let width = area.width.into();
+        let height = u32::from(area.height) * 2;
+        let scaled = image::imageops::resize(self.0, width, height, FilterType::Triangle);
+        let color = |x: u16, y: u32| {
+            let [r, g, b] = scaled.get_pixel(x.into(), y).0;
+            Color::Rgb(r, g, b)
+        };
+        for y in 0..area.height {
+            for x in 0..area.width {
+                let row = u32::from(y) * 2;
+                buf.get_mut(area.x + x, area.y + y)
+                    .set_symbol("▀")
+                    .set_fg(color(x, row))
+                    .set_bg(color(x, row + 1));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ratatui::{backend::TestBackend, Terminal};
+
+    #[test]
+    fn covers_are_drawn_with_half_blocks() {
+        let mut cover = Cover::new(Protocol::HalfBlocks);
+        assert!(cover.request(Some("https://img/1.jpg".to_string())));
+        assert!(!cover.request(Some("https://img/1.jpg".to_string())));
+        // answers for covers that are not wanted any more are dropped
+        cover.update("https://img/0.jpg", Vec::new());
+        assert!(cover.data.is_none());
+
+        let red = [255, 0, 0];
+        let blue = [0, 0, 255];
+        cover.data = Some(Vec::new());
+        cover.bitmap = RgbImage::from_raw(2, 2, [red, red, blue, blue].concat());
+        assert!(cover.is_shown());
+        let mut terminal = Terminal::new(TestBackend::new(2, 1)).unwrap();
+        terminal.draw(|f| cover.render(f, f.size())).unwrap();
+        let cell = terminal.backend().buffer().get(1, 0);
+        assert_eq!(cell.symbol, "▀");
+        assert_eq!(
+            (cell.fg, cell.bg),
+            (Color::Rgb(255, 0, 0), Color::Rgb(0, 0, 255))
+        );
+
+        let mut out = Vec::new();
+        cover.draw(&mut out).unwrap();
+        assert!(out.is_empty());
+    }
+
+    fn png(width: u32, height: u32) -> Vec<u8> {
+        let mut data = Vec::new();
+        RgbImage::new(width, height)
+            .write_to(&mut Cursor::new(&mut data), image::ImageFormat::Png)
+            .unwrap();
+        data
+    }
+
+    #[test]
+    fn covers_are_decoded_within_limits() {
+        let bitmap = decode(&png(640, 320)).unwrap();
+        assert_eq!(bitmap.dimensions(), (320, 160));
+        assert_eq!(decode(&png(2, 2)).unwrap().dimensions(), (2, 2));
+        // refused before the pixels are allocated
+        assert!(matches!(
+            decode(&png(MAX_SIDE + 1, 1)),
+            Err(image::ImageError::Limits(_))
+        ));
+        assert!(decode(b"not an image").is_err());
+    }
+}
@@ -16,6 +16,9 @@ use super::{
     MessageFromUi, StatefulList, UiItem, UiItemKind, COLOR_GREEN, COLOR_PRIMARY, COLOR_PRIMARY_DARK,
 };
 
+// the next page is requested once the selection gets this close to the last loaded entry
+const LOAD_MORE_DISTANCE: usize = 20;
+
 pub struct Library {
     title: String,
     uuid: String,
@@ -23,6 +26,9 @@ pub struct Library {
     list_state: ListState,
     parent: Option<String>,
     positions: HashMap<String, usize>,
+    // number of entries of the whole node, the list only holds the pages loaded so far
+    total: usize,
+    loading: bool,
     tx: Sender<MessageFromUi>,
 }
 
@@ -35,6 +41,8 @@ impl Library {
             list_state: ListState::default(),
             positions: HashMap::new(),
             parent: None,
+            total: 0,
+            loading: false,
             tx,
         }
     }
@@ -67,6 +75,13 @@ impl Library {
             }
         }
     }
+    pub fn refresh(&mut self) {
+        if self
+            .tx
+            .send(MessageFromUi::RefreshLibraryNode(self.uuid.clone()))
+            .is_err()
+        { /* FIXME: warn */ }
+    }
     pub fn queue_append(&mut self) {
         if let Some(items) = self.get_selected() {
             match self.tx.send(MessageFromUi::AppendTracks(items)) {
@@ -119,46 +134,47 @@ impl Library {
                 .for_each(|i| i.marked = false);
         }
     }
-    pub fn update(&mut self, node: LibraryNode) {
+    pub fn update(&mut self, node: LibraryNode, total: usize) {
         if node.tracks.is_empty() && node.children.is_empty() {
             return;
         }
 
         // if children empty and tracks empty return
-        self.uuid = node.uuid;
-        self.title = node.title;
-        self.parent = node.parent;
+        self.uuid = node.uuid.clone();
+        self.title = node.title.clone();
+        self.parent = node.parent.clone();
+        self.total = total;
+        self.loading = false;
+        self.list = node_items(node);
         self.select(Some(self.prev_selected()));
 
-        if !node.tracks.is_empty() {
-            self.list = node
-                .tracks
-                .iter()
-                .map(|t| UiItem {
-                    uuid: t.uuid.clone(),
-                    title: format!("{} - {}", t.artist, t.title),
-                    kind: UiItemKind::Track,
-                    marked: false,
-                    is_queable: true,
-                })
-                .collect();
-        } else {
-            // if tracks not empty use tracks instead
-            self.list = node
-                .children
-                .iter()
-                .map(|c| UiItem {
-                    uuid: c.uuid.clone(),
-                    title: c.title.clone(),
-                    kind: UiItemKind::Node,
-                    marked: false,
-                    is_queable: c.is_queable,
-                })
-                .collect();
-        }
-
         self.update_selection();
     }
+    /// Adds the next page of the current node
+    pub fn append(&mut self, page: LibraryNode) {
+        if page.uuid != self.uuid {
+            return;
+        }
+        self.loading = false;
+        self.list.extend(node_items(page));
+        self.load_more();
+    }
+    fn load_more(&mut self) {
+        let Some(idx) = self.list_state.selected() else {
+            return;
+        };
+        let len = self.list.len();
+        if self.loading || len >= self.total || idx + LOAD_MORE_DISTANCE < len {
+            return;
+        }
+        if self
+            .tx
+            .send(MessageFromUi::GetLibraryNodePage(self.uuid.clone(), len))
+            .is_ok()
+        {
+            self.loading = true;
+        }
+    }
 
     pub fn render<B: Backend>(&mut self, f: &mut Frame<B>, area: Rect, focused: bool) {
         let library_items: Vec<ListItem> = self
@@ -191,7 +207,11 @@ impl Library {
                     } else {
                         COLOR_PRIMARY_DARK
                     }))
-                    .title(self.title.clone()),
+                    .title(if self.list.len() < self.total {
+                        format!("{} ({}/{})", self.title, self.list.len(), self.total)
+                    } else {
+                        self.title.clone()
+                    }),
             )
             .highlight_style(
                 Style::default()
@@ -220,9 +240,75 @@ impl StatefulList for Library {
                 .or_insert(pos);
         }
         self.list_state.select(idx);
+        self.load_more();
     }
 
     fn selected(&self) -> Option<usize> {
         self.list_state.selected()
     }
 }
+
+/// The children of the node followed by its tracks, the order the server paginates them in
+fn node_items(node: LibraryNode) -> Vec<UiItem> {
+    let children = node.children.into_iter().map(|c| UiItem {
+        uuid: c.uuid,
+        title: c.title,
+        kind: UiItemKind::Node,
+        marked: false,
+        is_queable: c.is_queable,
+    });
+    let tracks = node.tracks.into_iter().map(|t| UiItem {
+        uuid: t.uuid,
+        title: format!("{} - {}", t.artist, t.title),
+        kind: UiItemKind::Track,
+        marked: false,
+        is_queable: true,
+    });
+    children.chain(tracks).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crabidy_core::proto::crabidy::Track;
+
+    fn page(offset: usize, len: usize) -> LibraryNode {
+        LibraryNode {
+            uuid: "node:tidal:playlist:1".to_string(),
+            title: "playlist".to_string(),
+            tracks: (offset..offset + len)
+                .map(|i| Track {
+                    uuid: format!("track:tidal:{}", i),
+                    ..Default::default()
+                })
+                .collect(),
+            ..LibraryNode::new()
+        }
+    }
+
+    #[test]
+    fn loads_next_page_near_the_end() {
+        let (tx, rx) = flume::unbounded();
+        let mut library = Library::new(tx);
+        library.update(page(0, 100), 250);
+        assert!(rx.try_recv().is_err());
+
+        library.select(Some(85));
+        assert!(matches!(
+            rx.try_recv(),
+            Ok(MessageFromUi::GetLibraryNodePage(uuid, 100)) if uuid == "node:tidal:playlist:1"
+        ));
+        // only one page is requested at a time
+        library.next();
+        assert!(rx.try_recv().is_err());
+
+        library.append(page(100, 100));
+        assert_eq!(library.get_size(), 200);
+        assert!(rx.try_recv().is_err());
+        library.last();
+        assert!(matches!(
+            rx.try_recv(),
+            Ok(MessageFromUi::GetLibraryNodePage(_, 200))
+        ));
+    }
+}
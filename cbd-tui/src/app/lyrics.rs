@@ -0,0 +1,201 @@
+use std::{ops::Div, time::Duration};
+
+use flume::Sender;
+use ratatui::{
+    backend::Backend,
+    layout::{Alignment, Rect},
+    style::{Modifier, Style},
+    text::{Span, Spans},
+    widgets::{Block, BorderType, Borders, Paragraph},
+    Frame,
+};
+
+use crabidy_core::proto::crabidy::Lyrics;
+
+use super::{MessageFromUi, COLOR_PRIMARY, COLOR_PRIMARY_DARK, COLOR_RED};
+
+/// The lyrics of the current track, only fetched while they are shown
+pub struct LyricsPane {
+    shown: bool,
+    // the track that is playing, `None` if its provider has no lyrics
+    uuid: Option<String>,
+    requested: Option<String>,
+    // the track the lyrics are of, `None` if there are none for it
+    loaded: Option<(String, Option<Lyrics>)>,
+    tx: Sender<MessageFromUi>,
+}
+
+impl LyricsPane {
+    pub fn new(tx: Sender<MessageFromUi>) -> Self {
+        Self {
+            shown: false,
+            uuid: None,
+            requested: None,
+            loaded: None,
+            tx,
+        }
+    }
+
+    pub fn is_shown(&self) -> bool {
+        self.shown
+    }
+
+    pub fn toggle(&mut self) {
+        self.shown = !self.shown;
+        self.request();
+    }
+
+    pub fn update_track(&mut self, uuid: Option<String>) {
+        self.uuid = uuid;
+        self.request();
+    }
+
+    pub fn update(&mut self, uuid: String, lyrics: Option<Lyrics>) {
+        self.loaded = Some((uuid, lyrics));
+    }
+
+    fn request(&mut self) {
+        if !self.shown || self.uuid == self.requested {
+            return;
+        }
+        if let Some(uuid) = &self.uuid {
+            self.tx.send(MessageFromUi::GetLyrics(uuid.clone()));
+        }
+        self.requested = self.uuid.clone();
+    }
+
+    fn lyrics(&self) -> Result<&Lyrics, &'static str> {
+        let Some(uuid) = &self.uuid else {
+            return Err("No lyrics");
+        };
+        match &self.loaded {
+            Some((loaded, Some(lyrics))) if loaded == uuid => Ok(lyrics),
+            Some((loaded, None)) if loaded == uuid => Err("No lyrics"),
+            _ => Err("Loading lyrics"),
+        }
+    }
+
+    /// The first line to show so the current one stays in the middle. Lyrics that are not synced
+    /// scroll along with the position in the track.
+    fn scroll(
+        lyrics: &Lyrics,
+        height: usize,
+        position: Duration,
+        duration: Option<Duration>,
+    ) -> usize {
+        let last = lyrics.lines.len().saturating_sub(height);
+        if lyrics.synced {
+            let current = lyrics.line_at(position).unwrap_or(0);
+            return current.saturating_sub(height / 2).min(last);
+        }
+        match duration.filter(|d| !d.is_zero()) {
+            Some(duration) => {
+                let ratio = position.as_secs_f64().div(duration.as_secs_f64()).min(1.0);
+                (ratio * last as f64) as usize
+            }
+            None => 0,
+        }
+    }
+
+    pub fn render<B: Backend>(
+        &self,
+        f: &mut Frame<B>,
+        area: Rect,
+        position: Option<Duration>,
+        duration: Option<Duration>,
+    ) {
+        let block = Block::default()
+            .borders(Borders::ALL)
+            .border_type(BorderType::Rounded)
+            .border_style(Style::default().fg(COLOR_PRIMARY_DARK))
+            .title("Lyrics");
+        let height = block.inner(area).height as usize;
+        let position = position.unwrap_or_default();
+
+        let (text, scroll) = match self.lyrics() {
+            Ok(lyrics) => {
+                let current = lyrics.line_at(position);
+                let text: Vec<Spans> = lyrics
+                    .lines
+                    .iter()
+                    .enumerate()
+                    .map(|(idx, line)| {
+                        let style = if Some(idx) == current {
+                            Style::default().fg(COLOR_RED).add_modifier(Modifier::BOLD)
+                        } else if lyrics.synced {
+                            Style::default().fg(COLOR_PRIMARY)
+                        } else {
+                            Style::default()
+                        };
+                        Spans::from(Span::styled(line.text.clone(), style))
+                    })
+                    .collect();
+                (text, Self::scroll(lyrics, height, position, duration))
+            }
+            Err(message) => (vec![Spans::from(Span::raw(message))], 0),
+        };
+
+        let lyrics_p = Paragraph::new(text)
+            .block(block)
+            .alignment(Alignment::Center)
+            .scroll((scroll as u16, 0));
+        f.render_widget(lyrics_p, area);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crabidy_core::proto::crabidy::LyricsLine;
+
+    fn lyrics(synced: bool) -> Lyrics {
+        Lyrics {
+            synced,
+            lines: (0..20)
+                .map(|i| LyricsLine {
+                    start: if synced { i * 1000 } else { 0 },
+                    text: format!("line {}", i),
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn lyrics_are_fetched_when_shown_and_follow_the_track() {
+        let (tx, rx) = flume::unbounded();
+        let mut pane = LyricsPane::new(tx);
+        pane.update_track(Some("track:tidal:1".to_string()));
+        assert!(rx.try_recv().is_err());
+        pane.toggle();
+        assert!(
+            matches!(rx.try_recv(), Ok(MessageFromUi::GetLyrics(uuid)) if uuid == "track:tidal:1")
+        );
+        assert_eq!(pane.lyrics(), Err("Loading lyrics"));
+        pane.update("track:tidal:1".to_string(), Some(lyrics(true)));
+        assert!(pane.lyrics().is_ok());
+
+        // hiding and showing them again does not fetch them again
+        pane.toggle();
+        pane.toggle();
+        assert!(rx.try_recv().is_err());
+
+        pane.update_track(Some("track:tidal:2".to_string()));
+        assert!(rx.try_recv().is_ok());
+        assert_eq!(pane.lyrics(), Err("Loading lyrics"));
+        pane.update("track:tidal:2".to_string(), None);
+        assert_eq!(pane.lyrics(), Err("No lyrics"));
+    }
+
+    #[test]
+    fn the_current_line_stays_in_the_middle() {
+        let synced = lyrics(true);
+        let at = |secs| Duration::from_secs(secs);
+        assert_eq!(LyricsPane::scroll(&synced, 10, at(2), None), 0);
+        assert_eq!(LyricsPane::scroll(&synced, 10, at(12), None), 7);
+        assert_eq!(LyricsPane::scroll(&synced, 10, at(19), None), 10);
+
+        let plain = lyrics(false);
+        assert_eq!(LyricsPane::scroll(&plain, 10, at(30), Some(at(60))), 5);
+        assert_eq!(LyricsPane::scroll(&plain, 10, at(30), None), 0);
+    }
+}
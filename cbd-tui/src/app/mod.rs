@@ -1,36 +1,51 @@
+mod cover;
 mod library;
 mod list;
+mod lyrics;
 mod now_playing;
 mod queue;
+mod search;
+
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
 
 use flume::Sender;
 use ratatui::{
     backend::Backend,
     layout::{Constraint, Direction, Layout},
-    style::Color,
+    style::{Color, Style},
+    text::Span,
+    widgets::Paragraph,
     Frame,
 };
 
 use crabidy_core::proto::crabidy::{
     get_update_stream_response::Update as StreamUpdate, InitResponse as InitialData, LibraryNode,
+    Lyrics, Provider, ProviderCapabilities, ReplayGainMode, SearchResult, Track,
 };
 
 pub use list::StatefulList;
 
 use library::Library;
+use lyrics::LyricsPane;
 use now_playing::NowPlaying;
 use queue::Queue;
+use search::Search;
 
 #[derive(Clone, Copy)]
 pub enum UiFocus {
     Library,
     Queue,
+    Search,
 }
 
 #[derive(Clone, Copy)]
 enum UiItemKind {
     Node,
     Track,
+    Header,
 }
 
 struct UiItem {
@@ -50,16 +65,32 @@ pub const COLOR_GREEN: Color = Color::Rgb(163, 190, 140);
 // const COLOR_ORANGE: Color = Color::Rgb(208, 135, 112);
 // const COLOR_BRIGHT: Color = Color::Rgb(216, 222, 233);
 
+// how long an error is shown at the bottom of the screen
+const ERROR_TIMEOUT: Duration = Duration::from_secs(5);
+
 // FIXME: Rename this
 pub enum MessageToUi {
     Init(InitialData),
-    ReplaceLibraryNode(LibraryNode),
+    Error(String),
+    // the first pages of a node and the total number of its entries
+    ReplaceLibraryNode(LibraryNode, usize),
+    AppendLibraryNode(LibraryNode),
+    SearchResult(SearchResult),
+    Providers(Vec<Provider>),
+    // the url of a cover and its image
+    Cover(String, Vec<u8>),
+    // the uuid of a track and its lyrics
+    Lyrics(String, Option<Lyrics>),
     Update(StreamUpdate),
 }
 
 // FIXME: Rename this
 pub enum MessageFromUi {
     GetLibraryNode(String),
+    // the uuid of the node and the offset of the page
+    GetLibraryNodePage(String, usize),
+    RefreshLibraryNode(String),
+    Search(String),
     AppendTracks(Vec<String>),
     QueueTracks(Vec<String>),
     InsertTracks(Vec<String>, usize),
@@ -69,12 +100,20 @@ pub enum MessageFromUi {
     NextTrack,
     PrevTrack,
     RestartTrack,
+    Seek(Duration),
+    // in milliseconds, negative to seek back
+    SeekBy(i32),
     SetCurrentTrack(usize),
     TogglePlay,
     ChangeVolume(f32),
     ToggleMute,
     ToggleShuffle,
     ToggleRepeat,
+    ToggleCrossfade,
+    SetReplayGainMode(ReplayGainMode),
+    NextDspPreset,
+    GetCover(String),
+    GetLyrics(String),
 }
 
 pub struct App {
@@ -82,48 +121,176 @@ pub struct App {
     pub library: Library,
     pub now_playing: NowPlaying,
     pub queue: Queue,
+    pub search: Search,
+    pub lyrics: LyricsPane,
+    error: Option<(String, Instant)>,
+    // keyed by the provider name in the uuids, e.g. `tidal` in `track:tidal:1234`
+    providers: HashMap<String, ProviderCapabilities>,
 }
 
 impl App {
     pub fn new(tx: Sender<MessageFromUi>) -> App {
         let library = Library::new(tx.clone());
-        let queue = Queue::new(tx);
-        let now_playing = NowPlaying::default();
+        let queue = Queue::new(tx.clone());
+        let search = Search::new(tx.clone());
+        let lyrics = LyricsPane::new(tx.clone());
+        let now_playing = NowPlaying::new(tx);
         App {
             focus: UiFocus::Library,
             library,
             now_playing,
             queue,
+            search,
+            lyrics,
+            error: None,
+            providers: HashMap::new(),
         }
     }
 
+    pub fn update_track(&mut self, track: Option<Track>) {
+        let lyrics = track
+            .as_ref()
+            .map(|track| track.uuid.clone())
+            .filter(|uuid| self.can(uuid, |c| c.lyrics));
+        self.lyrics.update_track(lyrics);
+        self.now_playing.update_track(track);
+    }
+
+    pub fn show_error(&mut self, message: String) {
+        self.error = Some((message, Instant::now()));
+    }
+
+    pub fn update_providers(&mut self, providers: Vec<Provider>) {
+        self.providers = providers
+            .into_iter()
+            .map(|p| (p.name, p.capabilities.unwrap_or_default()))
+            .collect();
+    }
+
+    /// Whether the provider of the uuid has the capability, providers the server did not tell
+    /// about are given the benefit of the doubt
+    pub fn can(&self, uuid: &str, capability: impl Fn(&ProviderCapabilities) -> bool) -> bool {
+        let name = uuid.split(':').nth(1).unwrap_or_default();
+        self.providers.get(name).is_none_or(capability)
+    }
+
+    /// Live streams can not be restarted or seeked in
+    pub fn can_seek(&self) -> bool {
+        self.now_playing
+            .track()
+            .is_none_or(|track| self.can(&track.uuid, |c| c.seek))
+    }
+
     pub fn cycle_active(&mut self) {
         self.focus = match (self.focus, self.queue.is_empty()) {
             (UiFocus::Library, false) => UiFocus::Queue,
             (UiFocus::Library, true) => UiFocus::Library,
             (UiFocus::Queue, _) => UiFocus::Library,
+            (UiFocus::Search, false) => UiFocus::Queue,
+            (UiFocus::Search, true) => UiFocus::Library,
         };
     }
 
+    pub fn open_search(&mut self) {
+        if !self.providers.is_empty() && !self.providers.values().any(|c| c.search) {
+            self.show_error("None of the providers can search".to_string());
+            return;
+        }
+        self.focus = UiFocus::Search;
+        self.search.start_editing();
+    }
+
+    pub fn open_search_result(&mut self) {
+        if self.search.dive() {
+            self.focus = UiFocus::Library;
+        }
+    }
+
     pub fn render<B: Backend>(&mut self, f: &mut Frame<B>) {
-        let full_screen = f.size();
+        if let Some((_, shown)) = &self.error {
+            if shown.elapsed() > ERROR_TIMEOUT {
+                self.error = None;
+            }
+        }
+        let full_screen = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints([
+                Constraint::Min(0),
+                Constraint::Length(if self.error.is_some() { 1 } else { 0 }),
+            ])
+            .split(f.size());
 
         let library_focused = matches!(self.focus, UiFocus::Library);
         let queue_focused = matches!(self.focus, UiFocus::Queue);
+        let search_focused = matches!(self.focus, UiFocus::Search);
 
         let main = Layout::default()
             .direction(Direction::Horizontal)
             .constraints([Constraint::Percentage(50), Constraint::Percentage(50)].as_ref())
-            .split(f.size());
+            .split(full_screen[0]);
 
-        self.library.render(f, main[0], library_focused);
+        if search_focused {
+            self.search.render(f, main[0], search_focused);
+        } else {
+            self.library.render(f, main[0], library_focused);
+        }
 
         let right_side = Layout::default()
             .direction(Direction::Vertical)
             .constraints([Constraint::Percentage(70), Constraint::Max(10)].as_ref())
             .split(main[1]);
 
-        self.queue.render(f, right_side[0], queue_focused);
+        if self.lyrics.is_shown() {
+            let queue_and_lyrics = Layout::default()
+                .direction(Direction::Vertical)
+                .constraints([Constraint::Percentage(40), Constraint::Percentage(60)].as_ref())
+                .split(right_side[0]);
+            self.queue.render(f, queue_and_lyrics[0], queue_focused);
+            self.lyrics.render(
+                f,
+                queue_and_lyrics[1],
+                self.now_playing.position(),
+                self.now_playing.duration(),
+            );
+        } else {
+            self.queue.render(f, right_side[0], queue_focused);
+        }
         self.now_playing.render(f, right_side[1]);
+
+        if let Some((message, _)) = &self.error {
+            let error = Paragraph::new(Span::styled(
+                message.clone(),
+                Style::default().fg(COLOR_RED),
+            ));
+            f.render_widget(error, full_screen[1]);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn actions_follow_the_provider_capabilities() {
+        let (tx, _rx) = flume::unbounded();
+        let mut app = App::new(tx);
+        app.update_providers(vec![Provider {
+            name: "radio".to_string(),
+            title: "radio".to_string(),
+            capabilities: Some(ProviderCapabilities {
+                search: true,
+                ..Default::default()
+            }),
+        }]);
+        assert!(app.can("track:local:/music/a.flac", |c| c.seek));
+        assert!(!app.can("track:radio:aHR0cA", |c| c.seek));
+        assert!(app.can_seek());
+
+        app.now_playing.track = Some(Track {
+            uuid: "track:radio:aHR0cA".to_string(),
+            ..Default::default()
+        });
+        assert!(!app.can_seek());
     }
 }
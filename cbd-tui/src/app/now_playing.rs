@@ -1,8 +1,11 @@
-use std::{ops::Div, time::Duration};
+use std::{io, ops::Div, time::Duration};
 
+use flume::Sender;
 use notify_rust::Notification;
 
-use crabidy_core::proto::crabidy::{PlayState, QueueModifiers, Track, TrackPosition};
+use crabidy_core::proto::crabidy::{
+    Image, PlayState, QueueModifiers, ReplayGainMode, Track, TrackPosition,
+};
 
 use ratatui::{
     backend::Backend,
@@ -13,29 +16,43 @@ use ratatui::{
     Frame,
 };
 
-use super::COLOR_SECONDARY;
+use super::{
+    cover::{Cover, Protocol},
+    MessageFromUi, COLOR_RED, COLOR_SECONDARY,
+};
+
+// the width of the cover that is fetched, enough for terminals that show the image itself
+const COVER_WIDTH: u32 = 320;
 
 pub struct NowPlaying {
     play_state: PlayState,
     duration: Option<Duration>,
     modifiers: QueueModifiers,
+    // the preset of filters the audio passes through
+    dsp_preset: Option<String>,
     position: Option<Duration>,
-    track: Option<Track>,
+    pub(super) track: Option<Track>,
+    // where the progress of the track was drawn, to seek by clicking on it
+    progress_area: Option<Rect>,
+    cover: Cover,
+    tx: Sender<MessageFromUi>,
 }
 
-impl Default for NowPlaying {
-    fn default() -> Self {
+impl NowPlaying {
+    pub fn new(tx: Sender<MessageFromUi>) -> Self {
         NowPlaying {
             play_state: PlayState::Unspecified,
             duration: None,
             modifiers: QueueModifiers::default(),
+            dsp_preset: None,
             position: None,
             track: None,
+            progress_area: None,
+            cover: Cover::new(Protocol::detect()),
+            tx,
         }
     }
-}
 
-impl NowPlaying {
     pub fn update_play_state(&mut self, play_state: PlayState) {
         self.play_state = play_state;
     }
@@ -63,13 +80,61 @@ impl NowPlaying {
                 .show()
                 .unwrap();
         }
+        let cover = active
+            .as_ref()
+            .and_then(|track| Image::pick(track.artwork(), COVER_WIDTH))
+            .map(|image| image.url.clone());
+        if self.cover.request(cover.clone()) {
+            if let Some(url) = cover {
+                self.tx.send(MessageFromUi::GetCover(url));
+            }
+        }
         self.track = active;
     }
+    pub fn update_cover(&mut self, url: &str, data: Vec<u8>) {
+        self.cover.update(url, data);
+    }
+    /// Shows the cover in terminals that draw images themselves
+    pub fn draw_cover(&mut self, out: &mut impl io::Write) -> io::Result<()> {
+        self.cover.draw(out)
+    }
+    pub fn track(&self) -> Option<&Track> {
+        self.track.as_ref()
+    }
+    pub fn position(&self) -> Option<Duration> {
+        self.position
+    }
+    pub fn duration(&self) -> Option<Duration> {
+        self.duration
+    }
     pub fn update_modifiers(&mut self, mods: &QueueModifiers) {
         self.modifiers = mods.clone();
     }
+    pub fn update_dsp_preset(&mut self, dsp_preset: Option<String>) {
+        self.dsp_preset = dsp_preset.filter(|name| !name.is_empty());
+    }
+    /// The position in the track of a click on its progress, if it was on it
+    pub fn position_at(&self, column: u16, row: u16) -> Option<Duration> {
+        let area = self.progress_area?;
+        if row != area.y || column < area.left() || column >= area.right() {
+            return None;
+        }
+        // the line is drawn after the empty label and the space following it
+        let start = area.left() + 1;
+        let width = area.right().saturating_sub(start).max(1);
+        let ratio = f64::from(column.saturating_sub(start)) / f64::from(width);
+        Some(self.duration?.mul_f64(ratio.min(1.0)))
+    }
+    /// The replay gain mode that follows the current one, to cycle through them
+    pub fn next_replay_gain_mode(&self) -> ReplayGainMode {
+        match self.modifiers.replay_gain() {
+            ReplayGainMode::Off => ReplayGainMode::Track,
+            ReplayGainMode::Track => ReplayGainMode::Album,
+            ReplayGainMode::Album => ReplayGainMode::Off,
+        }
+    }
 
-    pub fn render<B: Backend>(&self, f: &mut Frame<B>, area: Rect) {
+    pub fn render<B: Backend>(&mut self, f: &mut Frame<B>, area: Rect) {
         let now_playing_layout = Layout::default()
             .direction(Direction::Vertical)
             .constraints([Constraint::Max(8), Constraint::Max(1)])
@@ -82,29 +147,57 @@ impl NowPlaying {
                 PlayState::Playing => "♫",
                 _ => "",
             };
-            let album_text = match &track.album {
+            let mut album_text = match &track.album {
                 Some(album) => album.title.to_string(),
                 None => "No album".to_string(),
             };
+            // the disc is only worth mentioning for albums with more than one
+            match (track.volume_number, track.track_number) {
+                (Some(disc), Some(number)) if disc > 1 => {
+                    album_text.push_str(&format!(" · Disc {}, Track {}", disc, number))
+                }
+                (_, Some(number)) => album_text.push_str(&format!(" · Track {}", number)),
+                _ => {}
+            }
+            let mut title_text = vec![Span::styled(
+                track.title.to_string(),
+                Style::default().add_modifier(Modifier::BOLD),
+            )];
+            if track.explicit {
+                title_text.push(Span::raw(" "));
+                title_text.push(Span::styled(
+                    "E",
+                    Style::default()
+                        .fg(COLOR_RED)
+                        .add_modifier(Modifier::REVERSED),
+                ));
+            }
+            title_text.push(Span::raw(" by "));
+            title_text.push(Span::styled(
+                track.all_artists(),
+                Style::default().add_modifier(Modifier::BOLD),
+            ));
+            let mut album_line = vec![Span::raw(album_text)];
+            if let Some(quality) = &track.audio_quality {
+                album_line.push(Span::raw(" "));
+                album_line.push(Span::styled(
+                    quality.replace('_', " "),
+                    Style::default().fg(COLOR_SECONDARY),
+                ));
+            }
             let mods = format!(
-                "Shuffle: {}, Repeat {}",
-                self.modifiers.shuffle, self.modifiers.repeat
+                "Shuffle: {}, Repeat {}, Crossfade {}, ReplayGain {:?}, DSP {}",
+                self.modifiers.shuffle,
+                self.modifiers.repeat,
+                self.modifiers.crossfade,
+                self.modifiers.replay_gain(),
+                self.dsp_preset.as_deref().unwrap_or("off")
             );
             vec![
                 Spans::from(Span::raw(mods)),
                 Spans::from(Span::raw(play_text)),
-                Spans::from(vec![
-                    Span::styled(
-                        track.title.to_string(),
-                        Style::default().add_modifier(Modifier::BOLD),
-                    ),
-                    Span::raw(" by "),
-                    Span::styled(
-                        track.artist.to_string(),
-                        Style::default().add_modifier(Modifier::BOLD),
-                    ),
-                ]),
-                Spans::from(Span::raw(album_text)),
+                Spans::from(title_text),
+                Spans::from(album_line),
             ]
         } else {
             vec![
@@ -114,19 +207,33 @@ impl NowPlaying {
             ]
         };
 
+        let block = Block::default()
+            .title("Now playing")
+            .borders(Borders::ALL)
+            .border_type(BorderType::Rounded)
+            .border_style(Style::default().fg(COLOR_SECONDARY));
+        let inner = block.inner(now_playing_layout[0]);
+        f.render_widget(block, now_playing_layout[0]);
+
+        // terminal cells are about twice as high as wide, so the cover is twice as wide as high
+        let cover_width = if self.cover.is_shown() {
+            (inner.height * 2).min(inner.width / 3)
+        } else {
+            0
+        };
+        let media_info_layout = Layout::default()
+            .direction(Direction::Horizontal)
+            .constraints([Constraint::Length(cover_width), Constraint::Min(0)])
+            .split(inner);
+        self.cover.render(f, media_info_layout[0]);
+
         let media_info_p = Paragraph::new(media_info_text)
-            .block(
-                Block::default()
-                    .title("Now playing")
-                    .borders(Borders::ALL)
-                    .border_type(BorderType::Rounded)
-                    .border_style(Style::default().fg(COLOR_SECONDARY)),
-            )
             .alignment(Alignment::Center)
             .wrap(Wrap { trim: true });
 
-        f.render_widget(media_info_p, now_playing_layout[0]);
+        f.render_widget(media_info_p, media_info_layout[1]);
 
+        self.progress_area = None;
         if let (Some(position), Some(duration), Some(track)) =
             (self.position, self.duration, &self.track)
         {
@@ -152,6 +259,7 @@ impl NowPlaying {
                 .gauge_style(Style::default().fg(COLOR_SECONDARY).bg(Color::Black))
                 .ratio(ratio);
             f.render_widget(progress, elapsed_layout[0]);
+            self.progress_area = Some(elapsed_layout[0]);
 
             let pos_min = (pos / 60) % 60;
             let pos_secs = pos % 60;
@@ -178,3 +286,25 @@ impl NowPlaying {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn clicks_on_the_progress_are_positions_in_the_track() {
+        let (tx, _rx) = flume::unbounded();
+        let mut now_playing = NowPlaying::new(tx);
+        now_playing.duration = Some(Duration::from_secs(100));
+        assert_eq!(now_playing.position_at(6, 20), None);
+
+        now_playing.progress_area = Some(Rect::new(4, 20, 11, 1));
+        assert_eq!(now_playing.position_at(5, 20), Some(Duration::ZERO));
+        assert_eq!(
+            now_playing.position_at(10, 20),
+            Some(Duration::from_secs(50))
+        );
+        assert_eq!(now_playing.position_at(10, 19), None);
+        assert_eq!(now_playing.position_at(15, 20), None);
+    }
+}
@@ -0,0 +1,338 @@
+use crossterm::event::KeyCode;
+use flume::Sender;
+use ratatui::{
+    backend::Backend,
+    layout::{Constraint, Direction, Layout, Rect},
+    style::{Modifier, Style},
+    text::Span,
+    widgets::{Block, BorderType, Borders, List, ListItem, ListState, Paragraph},
+    Frame,
+};
+
+use crabidy_core::proto::crabidy::{LibraryNodeChild, SearchResult};
+
+use super::{
+    MessageFromUi, StatefulList, UiItem, UiItemKind, COLOR_GREEN, COLOR_PRIMARY,
+    COLOR_PRIMARY_DARK, COLOR_SECONDARY,
+};
+
+pub struct Search {
+    query: String,
+    editing: bool,
+    searching: bool,
+    list: Vec<UiItem>,
+    list_state: ListState,
+    tx: Sender<MessageFromUi>,
+}
+
+impl Search {
+    pub fn new(tx: Sender<MessageFromUi>) -> Self {
+        Self {
+            query: String::new(),
+            editing: false,
+            searching: false,
+            list: Vec::new(),
+            list_state: ListState::default(),
+            tx,
+        }
+    }
+    pub fn is_editing(&self) -> bool {
+        self.editing
+    }
+    pub fn start_editing(&mut self) {
+        self.editing = true;
+    }
+    /// Handles a key press while the query is edited, returns false if the search was cancelled
+    pub fn handle_input(&mut self, code: KeyCode) -> bool {
+        match code {
+            KeyCode::Char(c) => self.query.push(c),
+            KeyCode::Backspace => {
+                self.query.pop();
+            }
+            KeyCode::Enter => self.submit(),
+            KeyCode::Esc => {
+                self.editing = false;
+                return !self.list.is_empty();
+            }
+            _ => {}
+        }
+        true
+    }
+    fn submit(&mut self) {
+        if self.query.trim().is_empty() {
+            return;
+        }
+        if self
+            .tx
+            .send(MessageFromUi::Search(self.query.clone()))
+            .is_ok()
+        {
+            self.editing = false;
+            self.searching = true;
+        }
+    }
+    pub fn get_selected(&self) -> Option<Vec<String>> {
+        if self.list.iter().any(|i| i.marked) {
+            return Some(
+                self.list
+                    .iter()
+                    .filter(|i| i.marked)
+                    .map(|i| i.uuid.to_string())
+                    .collect(),
+            );
+        }
+        self.list_state
+            .selected()
+            .map(|idx| &self.list[idx])
+            .filter(|i| i.is_queable)
+            .map(|i| vec![i.uuid.to_string()])
+    }
+    /// Opens the selected artist, album or playlist in the library, returns true on success
+    pub fn dive(&mut self) -> bool {
+        if let Some(idx) = self.list_state.selected() {
+            let item = &self.list[idx];
+            if let UiItemKind::Node = item.kind {
+                return self
+                    .tx
+                    .send(MessageFromUi::GetLibraryNode(item.uuid.clone()))
+                    .is_ok();
+            }
+        }
+        false
+    }
+    pub fn queue_append(&mut self) {
+        if let Some(items) = self.get_selected() {
+            match self.tx.send(MessageFromUi::AppendTracks(items)) {
+                Ok(_) => self.remove_marks(),
+                Err(_) => { /* FIXME: warn */ }
+            }
+        }
+    }
+    pub fn queue_queue(&mut self) {
+        if let Some(items) = self.get_selected() {
+            match self.tx.send(MessageFromUi::QueueTracks(items)) {
+                Ok(_) => self.remove_marks(),
+                Err(_) => { /* FIXME: warn */ }
+            }
+        }
+    }
+    pub fn queue_replace(&mut self) {
+        if let Some(items) = self.get_selected() {
+            match self.tx.send(MessageFromUi::ReplaceQueue(items)) {
+                Ok(_) => self.remove_marks(),
+                Err(_) => { /* FIXME: warn */ }
+            }
+        }
+    }
+    pub fn toggle_mark(&mut self) {
+        if let Some(idx) = self.list_state.selected() {
+            let item = &mut self.list[idx];
+            if !item.is_queable {
+                return;
+            }
+            item.marked = !item.marked;
+        }
+    }
+    pub fn remove_marks(&mut self) {
+        self.list
+            .iter_mut()
+            .filter(|i| i.marked)
+            .for_each(|i| i.marked = false);
+    }
+    pub fn update(&mut self, result: SearchResult) {
+        self.searching = false;
+        let tracks: Vec<UiItem> = result
+            .tracks
+            .iter()
+            .map(|t| UiItem {
+                uuid: t.uuid.clone(),
+                title: format!("{} - {}", t.artist, t.title),
+                kind: UiItemKind::Track,
+                marked: false,
+                is_queable: true,
+            })
+            .collect();
+        let groups = [
+            ("Artists", result.artists.iter().map(node_item).collect()),
+            ("Albums", result.albums.iter().map(node_item).collect()),
+            ("Tracks", tracks),
+            (
+                "Playlists",
+                result.playlists.iter().map(node_item).collect(),
+            ),
+        ];
+        self.list = Vec::new();
+        for (header, items) in groups {
+            if items.is_empty() {
+                continue;
+            }
+            self.list.push(header_item(header));
+            self.list.extend(items);
+        }
+        // skip the header of the first group
+        self.select(if self.list.len() > 1 { Some(1) } else { None });
+    }
+
+    pub fn render<B: Backend>(&mut self, f: &mut Frame<B>, area: Rect, focused: bool) {
+        let layout = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints([Constraint::Length(3), Constraint::Min(0)])
+            .split(area);
+
+        let border_color = if focused {
+            COLOR_PRIMARY
+        } else {
+            COLOR_PRIMARY_DARK
+        };
+
+        let input = Paragraph::new(Span::raw(self.query.clone())).block(
+            Block::default()
+                .borders(Borders::ALL)
+                .border_type(BorderType::Rounded)
+                .border_style(Style::default().fg(if self.editing {
+                    COLOR_SECONDARY
+                } else {
+                    border_color
+                }))
+                .title("Search"),
+        );
+        f.render_widget(input, layout[0]);
+        if self.editing {
+            f.set_cursor(
+                layout[0].x + self.query.chars().count() as u16 + 1,
+                layout[0].y + 1,
+            );
+        }
+
+        let result_items: Vec<ListItem> = self
+            .list
+            .iter()
+            .map(|i| {
+                let (text, style) = match (i.kind, i.marked) {
+                    (UiItemKind::Header, _) => (
+                        i.title.to_string(),
+                        Style::default()
+                            .fg(COLOR_SECONDARY)
+                            .add_modifier(Modifier::BOLD),
+                    ),
+                    (_, true) => (
+                        format!("* {}", i.title),
+                        Style::default()
+                            .fg(COLOR_GREEN)
+                            .add_modifier(Modifier::BOLD),
+                    ),
+                    (_, false) => (format!("  {}", i.title), Style::default()),
+                };
+                ListItem::new(Span::from(text)).style(style)
+            })
+            .collect();
+
+        let title = if self.searching {
+            "Searching..."
+        } else if self.list.is_empty() {
+            "No results"
+        } else {
+            "Results"
+        };
+
+        let result_list = List::new(result_items)
+            .block(
+                Block::default()
+                    .borders(Borders::ALL)
+                    .border_type(BorderType::Rounded)
+                    .border_style(Style::default().fg(border_color))
+                    .title(title),
+            )
+            .highlight_style(
+                Style::default()
+                    .bg(border_color)
+                    .add_modifier(Modifier::BOLD),
+            );
+
+        f.render_stateful_widget(result_list, layout[1], &mut self.list_state);
+    }
+}
+
+fn header_item(title: &str) -> UiItem {
+    UiItem {
+        uuid: String::new(),
+        title: title.to_string(),
+        kind: UiItemKind::Header,
+        marked: false,
+        is_queable: false,
+    }
+}
+
+fn node_item(child: &LibraryNodeChild) -> UiItem {
+    UiItem {
+        uuid: child.uuid.clone(),
+        title: child.title.clone(),
+        kind: UiItemKind::Node,
+        marked: false,
+        is_queable: child.is_queable,
+    }
+}
+
+impl StatefulList for Search {
+    fn get_size(&self) -> usize {
+        self.list.len()
+    }
+
+    fn select(&mut self, idx: Option<usize>) {
+        self.list_state.select(idx);
+    }
+
+    fn selected(&self) -> Option<usize> {
+        self.list_state.selected()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crabidy_core::proto::crabidy::Track;
+
+    #[test]
+    fn results_are_grouped_in_order() {
+        let (tx, _rx) = flume::unbounded();
+        let mut search = Search::new(tx);
+        search.update(SearchResult {
+            tracks: vec![Track {
+                uuid: "track:tidal:1".to_string(),
+                title: "Song".to_string(),
+                artist: "Band".to_string(),
+                ..Default::default()
+            }],
+            albums: vec![LibraryNodeChild::new(
+                "node:tidal:album:2".to_string(),
+                "Album".to_string(),
+                true,
+            )],
+            artists: vec![LibraryNodeChild::new(
+                "node:tidal:artist:3".to_string(),
+                "Band".to_string(),
+                true,
+            )],
+            playlists: Vec::new(),
+        });
+        let titles: Vec<&str> = search.list.iter().map(|i| i.title.as_str()).collect();
+        assert_eq!(
+            titles,
+            vec![
+                "Artists",
+                "Band",
+                "Albums",
+                "Album",
+                "Tracks",
+                "Band - Song"
+            ]
+        );
+        assert_eq!(search.selected(), Some(1));
+        assert_eq!(
+            search.get_selected(),
+            Some(vec!["node:tidal:artist:3".to_string()])
+        );
+        search.select(Some(0));
+        assert_eq!(search.get_selected(), None);
+    }
+}
@@ -14,6 +14,7 @@ use crabidy_core::proto::crabidy::{get_update_stream_response::Update as StreamU
 use crossterm::{
     event::{
         self, DisableMouseCapture, EnableMouseCapture, Event, KeyCode, KeyEventKind, KeyModifiers,
+        MouseButton, MouseEventKind,
     },
     execute,
     terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
@@ -30,6 +31,9 @@ use rpc::RpcClient;
 
 static CONFIG: OnceLock<Config> = OnceLock::new();
 
+// covers are a few hundred kilobytes, anything larger is not worth the memory
+const MAX_COVER_BYTES: usize = 16 * 1024 * 1024;
+
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let config = CONFIG.get_or_init(|| crabidy_core::init_config("cbd-tui.toml"));
@@ -54,20 +58,30 @@ async fn orchestrate<'a>(
 ) -> Result<(), Box<dyn Error>> {
     let mut rpc_client = rpc::RpcClient::connect(&config.server.address).await?;
 
-    if let Some(root_node) = rpc_client.get_library_node("node:/").await? {
-        tx.send(MessageToUi::ReplaceLibraryNode(root_node.clone()))?;
-    }
+    let (root_node, total) = rpc_client.get_library_node("node:/").await?;
+    tx.send(MessageToUi::ReplaceLibraryNode(root_node, total))?;
 
     let init_data = rpc_client.init().await?;
     tx.send_async(MessageToUi::Init(init_data)).await?;
 
+    let providers = rpc_client.get_providers().await?;
+    tx.send_async(MessageToUi::Providers(providers)).await?;
+
     loop {
-        if let Err(er) = poll(&mut rpc_client, &rx, &tx).await {
-            println!("ERROR");
+        if let Err(err) = poll(&mut rpc_client, &rx, &tx).await {
+            tx.send(MessageToUi::Error(error_message(err.as_ref())))?;
         }
     }
 }
 
+/// Describes a failed request to the user, the statuses of the server carry the details
+fn error_message(err: &(dyn Error + 'static)) -> String {
+    match err.downcast_ref::<tonic::Status>() {
+        Some(status) => format!("{:?}: {}", status.code(), status.message()),
+        None => err.to_string(),
+    }
+}
+
 async fn poll(
     rpc_client: &mut RpcClient,
     rx: &Receiver<MessageFromUi>,
@@ -77,10 +91,22 @@ async fn poll(
         Ok(msg) = &mut rx.recv_async() => {
             match msg {
                 MessageFromUi::GetLibraryNode(uuid) => {
-                    if let Some(node) = rpc_client.get_library_node(&uuid).await? {
-                        tx.send(MessageToUi::ReplaceLibraryNode(node.clone()));
-                    }
+                    let (node, total) = rpc_client.get_library_node(&uuid).await?;
+                    tx.send_async(MessageToUi::ReplaceLibraryNode(node, total)).await?;
                 },
+                MessageFromUi::GetLibraryNodePage(uuid, offset) => {
+                    let page = rpc_client.get_library_node_page(&uuid, offset).await?;
+                    tx.send_async(MessageToUi::AppendLibraryNode(page)).await?;
+                }
+                MessageFromUi::RefreshLibraryNode(uuid) => {
+                    rpc_client.invalidate_cache(vec![uuid.clone()]).await?;
+                    let (node, total) = rpc_client.get_library_node(&uuid).await?;
+                    tx.send_async(MessageToUi::ReplaceLibraryNode(node, total)).await?;
+                }
+                MessageFromUi::Search(query) => {
+                    let result = rpc_client.search(query).await?;
+                    tx.send_async(MessageToUi::SearchResult(result)).await?;
+                }
                 MessageFromUi::AppendTracks(uuids) => {
                     rpc_client.append_tracks(uuids).await?
                 }
@@ -105,6 +131,12 @@ async fn poll(
                 MessageFromUi::RestartTrack => {
                     rpc_client.restart_track().await?
                 }
+                MessageFromUi::Seek(position) => {
+                    rpc_client.seek(position).await?
+                }
+                MessageFromUi::SeekBy(offset) => {
+                    rpc_client.seek_by(offset).await?
+                }
                 MessageFromUi::SetCurrentTrack(pos) => {
                     rpc_client.set_current_track(pos).await?
                 }
@@ -123,9 +155,35 @@ async fn poll(
                 MessageFromUi::ToggleRepeat => {
                     rpc_client.toggle_repeat().await?
                 }
+                MessageFromUi::ToggleCrossfade => {
+                    rpc_client.toggle_crossfade().await?
+                }
+                MessageFromUi::SetReplayGainMode(mode) => {
+                    rpc_client.set_replay_gain_mode(mode).await?
+                }
+                MessageFromUi::NextDspPreset => {
+                    rpc_client.next_dsp_preset().await?
+                }
                 MessageFromUi::ClearQueue(exclude_current) => {
                     rpc_client.clear_queue(exclude_current).await?
                 }
+                MessageFromUi::GetLyrics(uuid) => {
+                    let lyrics = rpc_client.get_lyrics(&uuid).await?;
+                    tx.send_async(MessageToUi::Lyrics(uuid, lyrics)).await?;
+                }
+                MessageFromUi::GetCover(url) => {
+                    // fetched aside, a slow image server must not hold up the other requests
+                    let tx = tx.clone();
+                    tokio::spawn(async move {
+                        let message = match fetch_cover(&url).await {
+                            Ok(data) => MessageToUi::Cover(url, data),
+                            Err(err) => {
+                                MessageToUi::Error(format!("Could not fetch the cover: {}", err))
+                            }
+                        };
+                        tx.send_async(message).await.ok();
+                    });
+                }
             }
         }
         Some(resp) = rpc_client.update_stream.next() => {
@@ -146,6 +204,19 @@ async fn poll(
     Ok(())
 }
 
+async fn fetch_cover(url: &str) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
+    let mut response = reqwest::get(url).await?.error_for_status()?;
+    // read chunk by chunk, a server sending more than any cover needs is cut off
+    let mut data = Vec::new();
+    while let Some(chunk) = response.chunk().await? {
+        if data.len() + chunk.len() > MAX_COVER_BYTES {
+            return Err(format!("larger than {} bytes", MAX_COVER_BYTES).into());
+        }
+        data.extend_from_slice(&chunk);
+    }
+    Ok(data)
+}
+
 fn run_ui(tx: Sender<MessageFromUi>, rx: Receiver<MessageToUi>) {
     // setup terminal
     enable_raw_mode().unwrap();
@@ -162,15 +233,33 @@ fn run_ui(tx: Sender<MessageFromUi>, rx: Receiver<MessageToUi>) {
     loop {
         for message in rx.try_iter() {
             match message {
-                MessageToUi::ReplaceLibraryNode(node) => {
-                    app.library.update(node);
+                MessageToUi::ReplaceLibraryNode(node, total) => {
+                    app.library.update(node, total);
+                }
+                MessageToUi::AppendLibraryNode(page) => {
+                    app.library.append(page);
+                }
+                MessageToUi::SearchResult(result) => {
+                    app.search.update(result);
+                }
+                MessageToUi::Providers(providers) => {
+                    app.update_providers(providers);
+                }
+                MessageToUi::Cover(url, jpeg) => {
+                    app.now_playing.update_cover(&url, jpeg);
+                }
+                MessageToUi::Lyrics(uuid, lyrics) => {
+                    app.lyrics.update(uuid, lyrics);
+                }
+                MessageToUi::Error(message) => {
+                    app.show_error(message);
                 }
                 MessageToUi::Init(init_data) => {
                     if let Some(queue) = init_data.queue {
                         app.queue.update_queue(queue);
                     }
                     if let Some(track) = init_data.queue_track {
-                        app.now_playing.update_track(track.track);
+                        app.update_track(track.track);
                         app.queue.update_position(track.queue_position as usize);
                     }
                     if let Some(ps) = PlayState::from_i32(init_data.play_state) {
@@ -179,13 +268,14 @@ fn run_ui(tx: Sender<MessageFromUi>, rx: Receiver<MessageToUi>) {
                     if let Some(mods) = init_data.mods {
                         app.now_playing.update_modifiers(&mods);
                     }
+                    app.now_playing.update_dsp_preset(init_data.dsp_preset);
                 }
                 MessageToUi::Update(update) => match update {
                     StreamUpdate::Queue(queue) => {
                         app.queue.update_queue(queue);
                     }
                     StreamUpdate::QueueTrack(track) => {
-                        app.now_playing.update_track(track.track);
+                        app.update_track(track.track);
                         app.queue.update_position(track.queue_position as usize);
                     }
                     StreamUpdate::Position(pos) => app.now_playing.update_position(pos),
@@ -199,29 +289,67 @@ fn run_ui(tx: Sender<MessageFromUi>, rx: Receiver<MessageToUi>) {
                     }
                     StreamUpdate::Mute(_) => { /* FIXME: implement */ }
                     StreamUpdate::Volume(_) => { /* FIXME: implement */ }
+                    StreamUpdate::Error(message) => app.show_error(message),
+                    StreamUpdate::DspPreset(name) => app.now_playing.update_dsp_preset(Some(name)),
                 },
             }
         }
 
         terminal.draw(|f| app.render(f));
+        app.now_playing.draw_cover(terminal.backend_mut());
 
         let timeout = tick_rate
             .checked_sub(last_tick.elapsed())
             .unwrap_or_else(|| Duration::from_secs(0));
 
         if event::poll(timeout).unwrap() {
-            if let Event::Key(key) = event::read().unwrap() {
-                if key.kind == KeyEventKind::Press {
+            let event = event::read().unwrap();
+            if let Event::Mouse(mouse) = event {
+                if mouse.kind == MouseEventKind::Down(MouseButton::Left) && app.can_seek() {
+                    if let Some(position) = app.now_playing.position_at(mouse.column, mouse.row) {
+                        tx.send(MessageFromUi::Seek(position));
+                    }
+                }
+            }
+            if let Event::Key(key) = event {
+                if key.kind == KeyEventKind::Press && app.search.is_editing() {
+                    if !app.search.handle_input(key.code) {
+                        app.focus = UiFocus::Library;
+                    }
+                } else if key.kind == KeyEventKind::Press {
                     match (app.focus, key.modifiers, key.code) {
                         (_, KeyModifiers::NONE, KeyCode::Char('q')) => {
                             break;
                         }
                         (_, KeyModifiers::NONE, KeyCode::Tab) => app.cycle_active(),
+                        (_, KeyModifiers::NONE, KeyCode::Char('/')) => app.open_search(),
+                        (_, KeyModifiers::NONE, KeyCode::Char('y')) => app.lyrics.toggle(),
                         (_, KeyModifiers::NONE, KeyCode::Char(' ')) => {
                             tx.send(MessageFromUi::TogglePlay);
                         }
                         (_, KeyModifiers::NONE, KeyCode::Char('r')) => {
-                            tx.send(MessageFromUi::RestartTrack);
+                            if app.can_seek() {
+                                tx.send(MessageFromUi::RestartTrack);
+                            } else {
+                                app.show_error("Live streams can not be restarted".to_string());
+                            }
+                        }
+                        (_, modifiers, KeyCode::Left | KeyCode::Right)
+                            if modifiers == KeyModifiers::NONE
+                                || modifiers == KeyModifiers::SHIFT =>
+                        {
+                            let offset = if modifiers == KeyModifiers::SHIFT {
+                                30_000
+                            } else {
+                                5_000
+                            };
+                            if !app.can_seek() {
+                                app.show_error("Live streams can not be seeked in".to_string());
+                            } else if key.code == KeyCode::Left {
+                                tx.send(MessageFromUi::SeekBy(-offset));
+                            } else {
+                                tx.send(MessageFromUi::SeekBy(offset));
+                            }
                         }
                         (_, KeyModifiers::SHIFT, KeyCode::Char('J')) => {
                             tx.send(MessageFromUi::ChangeVolume(-0.1));
@@ -238,6 +366,16 @@ fn run_ui(tx: Sender<MessageFromUi>, rx: Receiver<MessageToUi>) {
                         (_, KeyModifiers::NONE, KeyCode::Char('x')) => {
                             tx.send(MessageFromUi::ToggleRepeat);
                         }
+                        (_, KeyModifiers::NONE, KeyCode::Char('f')) => {
+                            tx.send(MessageFromUi::ToggleCrossfade);
+                        }
+                        (_, KeyModifiers::NONE, KeyCode::Char('v')) => {
+                            let mode = app.now_playing.next_replay_gain_mode();
+                            tx.send(MessageFromUi::SetReplayGainMode(mode));
+                        }
+                        (_, KeyModifiers::NONE, KeyCode::Char('e')) => {
+                            tx.send(MessageFromUi::NextDspPreset);
+                        }
                         (_, KeyModifiers::CONTROL, KeyCode::Char('n')) => {
                             app.queue.play_next();
                         }
@@ -268,6 +406,9 @@ fn run_ui(tx: Sender<MessageFromUi>, rx: Receiver<MessageToUi>) {
                         (UiFocus::Library, KeyModifiers::NONE, KeyCode::Char('l')) => {
                             app.library.dive();
                         }
+                        (UiFocus::Library, KeyModifiers::SHIFT, KeyCode::Char('R')) => {
+                            app.library.refresh();
+                        }
                         (UiFocus::Library, KeyModifiers::SHIFT, KeyCode::Char('L')) => {
                             app.library.queue_queue();
                         }
@@ -280,6 +421,42 @@ fn run_ui(tx: Sender<MessageFromUi>, rx: Receiver<MessageToUi>) {
                         (UiFocus::Library, KeyModifiers::NONE, KeyCode::Char('s')) => {
                             app.library.toggle_mark();
                         }
+                        (UiFocus::Search, KeyModifiers::NONE, KeyCode::Esc) => {
+                            app.focus = UiFocus::Library;
+                        }
+                        (UiFocus::Search, KeyModifiers::NONE, KeyCode::Char('g')) => {
+                            app.search.first();
+                        }
+                        (UiFocus::Search, KeyModifiers::SHIFT, KeyCode::Char('G')) => {
+                            app.search.last();
+                        }
+                        (UiFocus::Search, KeyModifiers::NONE, KeyCode::Char('j')) => {
+                            app.search.next();
+                        }
+                        (UiFocus::Search, KeyModifiers::NONE, KeyCode::Char('k')) => {
+                            app.search.prev();
+                        }
+                        (UiFocus::Search, KeyModifiers::CONTROL, KeyCode::Char('d')) => {
+                            app.search.down();
+                        }
+                        (UiFocus::Search, KeyModifiers::CONTROL, KeyCode::Char('u')) => {
+                            app.search.up();
+                        }
+                        (UiFocus::Search, KeyModifiers::NONE, KeyCode::Char('l')) => {
+                            app.open_search_result();
+                        }
+                        (UiFocus::Search, KeyModifiers::SHIFT, KeyCode::Char('L')) => {
+                            app.search.queue_queue();
+                        }
+                        (UiFocus::Search, KeyModifiers::NONE, KeyCode::Char('a')) => {
+                            app.search.queue_append();
+                        }
+                        (UiFocus::Search, KeyModifiers::NONE, KeyCode::Enter) => {
+                            app.search.queue_replace();
+                        }
+                        (UiFocus::Search, KeyModifiers::NONE, KeyCode::Char('s')) => {
+                            app.search.toggle_mark();
+                        }
                         (UiFocus::Queue, KeyModifiers::NONE, KeyCode::Char('p')) => {
                             if let Some(selected) = app.queue.selected() {
                                 app.library.queue_insert(selected);
@@ -1,9 +1,12 @@
 use crabidy_core::proto::crabidy::{
     crabidy_service_client::CrabidyServiceClient, AppendRequest, ChangeVolumeRequest,
-    ClearQueueRequest, GetLibraryNodeRequest, GetUpdateStreamRequest, GetUpdateStreamResponse,
-    InitRequest, InitResponse, InsertRequest, LibraryNode, NextRequest, PrevRequest, QueueRequest,
-    RemoveRequest, ReplaceRequest, RestartTrackRequest, SetCurrentRequest, ToggleMuteRequest,
-    TogglePlayRequest, ToggleRepeatRequest, ToggleShuffleRequest,
+    ClearQueueRequest, GetDspPresetsRequest, GetLibraryNodeRequest, GetLyricsRequest,
+    GetProvidersRequest, GetUpdateStreamRequest, GetUpdateStreamResponse, InitRequest,
+    InitResponse, InsertRequest, InvalidateCacheRequest, LibraryNode, Lyrics, NextRequest,
+    PrevRequest, Provider, QueueRequest, RemoveRequest, ReplaceRequest, ReplayGainMode,
+    RestartTrackRequest, SearchRequest, SearchResult, SeekByRequest, SeekRequest,
+    SetCurrentRequest, SetDspPresetRequest, SetReplayGainModeRequest, ToggleCrossfadeRequest,
+    ToggleMuteRequest, TogglePlayRequest, ToggleRepeatRequest, ToggleShuffleRequest,
 };
 
 use std::{collections::HashMap, error::Error, fmt, time::Duration};
@@ -29,8 +32,12 @@ impl fmt::Display for RpcClientError {
 
 impl Error for RpcClientError {}
 
+// number of entries of a library node fetched at once
+const LIBRARY_PAGE_SIZE: u32 = 100;
+
 pub struct RpcClient {
-    library_node_cache: HashMap<String, LibraryNode>,
+    // the pages of the nodes loaded so far and the total number of their entries
+    library_node_cache: HashMap<String, (LibraryNode, usize)>,
     client: CrabidyServiceClient<Channel>,
     pub update_stream: Streaming<GetUpdateStreamResponse>,
 }
@@ -41,7 +48,7 @@ impl RpcClient {
         let mut client = CrabidyServiceClient::new(endpoint);
 
         let update_stream = Self::get_update_stream(&mut client).await;
-        let library_node_cache: HashMap<String, LibraryNode> = HashMap::new();
+        let library_node_cache: HashMap<String, (LibraryNode, usize)> = HashMap::new();
 
         Ok(RpcClient {
             client,
@@ -73,26 +80,93 @@ impl RpcClient {
         Ok(response.into_inner())
     }
 
+    pub async fn get_providers(&mut self) -> Result<Vec<Provider>, Box<dyn Error>> {
+        let get_providers_request = Request::new(GetProvidersRequest {});
+        let response = self.client.get_providers(get_providers_request).await?;
+        Ok(response.into_inner().providers)
+    }
+
+    pub async fn get_lyrics(&mut self, uuid: &str) -> Result<Option<Lyrics>, Box<dyn Error>> {
+        let get_lyrics_request = Request::new(GetLyricsRequest {
+            uuid: uuid.to_string(),
+        });
+        let response = self.client.get_lyrics(get_lyrics_request).await?;
+        Ok(response.into_inner().lyrics)
+    }
+
+    /// Returns all cached entries of the node or its first page, and the total number of entries
     pub async fn get_library_node(
         &mut self,
         uuid: &str,
-    ) -> Result<Option<&LibraryNode>, Box<dyn Error>> {
-        if self.library_node_cache.contains_key(uuid) {
-            return Ok(self.library_node_cache.get(uuid));
+    ) -> Result<(LibraryNode, usize), Box<dyn Error>> {
+        // saved playlists can change while the client is running
+        if !uuid.starts_with("node:saved") {
+            if let Some(cached) = self.library_node_cache.get(uuid) {
+                return Ok(cached.clone());
+            }
+        }
+        let (node, total) = self.fetch_library_node_page(uuid, 0).await?;
+        self.library_node_cache
+            .insert(uuid.to_string(), (node.clone(), total));
+        Ok((node, total))
+    }
+
+    /// Returns the page of the node starting at `offset`
+    pub async fn get_library_node_page(
+        &mut self,
+        uuid: &str,
+        offset: usize,
+    ) -> Result<LibraryNode, Box<dyn Error>> {
+        let (page, total) = self.fetch_library_node_page(uuid, offset).await?;
+        if let Some((node, cached_total)) = self.library_node_cache.get_mut(uuid) {
+            if node.entries_len() == offset {
+                node.extend(page.clone());
+                *cached_total = total;
+            }
         }
+        Ok(page)
+    }
+
+    async fn fetch_library_node_page(
+        &mut self,
+        uuid: &str,
+        offset: usize,
+    ) -> Result<(LibraryNode, usize), Box<dyn Error>> {
         let get_library_node_request = Request::new(GetLibraryNodeRequest {
             uuid: uuid.to_string(),
+            offset: offset as u32,
+            limit: LIBRARY_PAGE_SIZE,
         });
         let response = self
             .client
             .get_library_node(get_library_node_request)
-            .await?;
-        if let Some(library_node) = response.into_inner().node {
-            self.library_node_cache
-                .insert(uuid.to_string(), library_node);
-            return Ok(self.library_node_cache.get(uuid));
+            .await?
+            .into_inner();
+        match response.node {
+            Some(node) => Ok((node, response.total as usize)),
+            None => Err(Box::new(RpcClientError::NotFound)),
+        }
+    }
+
+    /// Makes the server and the client fetch the nodes again from the providers
+    pub async fn invalidate_cache(&mut self, uuids: Vec<String>) -> Result<(), Box<dyn Error>> {
+        for uuid in &uuids {
+            self.library_node_cache.remove(uuid);
         }
-        Err(Box::new(RpcClientError::NotFound))
+        let invalidate_cache_request = Request::new(InvalidateCacheRequest { uuids });
+        self.client
+            .invalidate_cache(invalidate_cache_request)
+            .await?;
+        Ok(())
+    }
+
+    pub async fn search(&mut self, query: String) -> Result<SearchResult, Box<dyn Error>> {
+        let search_request = Request::new(SearchRequest { query });
+        let response = self.client.search(search_request).await?;
+        response
+            .into_inner()
+            .result
+            .ok_or_else(|| Box::new(RpcClientError::NotFound) as Box<dyn Error>)
     }
 
     pub async fn append_tracks(&mut self, uuids: Vec<String>) -> Result<(), Box<dyn Error>> {
@@ -158,6 +232,20 @@ impl RpcClient {
         Ok(())
     }
 
+    pub async fn seek(&mut self, position: Duration) -> Result<(), Box<dyn Error>> {
+        let seek_request = Request::new(SeekRequest {
+            position: position.as_millis() as u32,
+        });
+        self.client.seek(seek_request).await?;
+        Ok(())
+    }
+
+    pub async fn seek_by(&mut self, offset: i32) -> Result<(), Box<dyn Error>> {
+        let seek_by_request = Request::new(SeekByRequest { offset });
+        self.client.seek_by(seek_by_request).await?;
+        Ok(())
+    }
+
     pub async fn set_current_track(&mut self, pos: usize) -> Result<(), Box<dyn Error>> {
         let set_current_request = Request::new(SetCurrentRequest {
             position: pos as u32,
@@ -184,6 +272,46 @@ impl RpcClient {
         Ok(())
     }
 
+    pub async fn toggle_crossfade(&mut self) -> Result<(), Box<dyn Error>> {
+        let toggle_crossfade_request = Request::new(ToggleCrossfadeRequest {});
+        self.client
+            .toggle_crossfade(toggle_crossfade_request)
+            .await?;
+        Ok(())
+    }
+
+    pub async fn set_replay_gain_mode(
+        &mut self,
+        mode: ReplayGainMode,
+    ) -> Result<(), Box<dyn Error>> {
+        let set_replay_gain_mode_request =
+            Request::new(SetReplayGainModeRequest { mode: mode as i32 });
+        self.client
+            .set_replay_gain_mode(set_replay_gain_mode_request)
+            .await?;
+        Ok(())
+    }
+
+    /// Switches to the preset after the active one, the filters are turned off after the last one
+    pub async fn next_dsp_preset(&mut self) -> Result<(), Box<dyn Error>> {
+        let get_dsp_presets_request = Request::new(GetDspPresetsRequest {});
+        let response = self
+            .client
+            .get_dsp_presets(get_dsp_presets_request)
+            .await?
+            .into_inner();
+        let active = response
+            .active
+            .and_then(|active| response.presets.iter().position(|name| *name == active));
+        let name = match active {
+            Some(idx) => response.presets.get(idx + 1).cloned(),
+            None => response.presets.first().cloned(),
+        };
+        let set_dsp_preset_request = Request::new(SetDspPresetRequest { name });
+        self.client.set_dsp_preset(set_dsp_preset_request).await?;
+        Ok(())
+    }
+
     pub async fn change_volume(&mut self, delta: f32) -> Result<(), Box<dyn Error>> {
         let change_volume_request = Request::new(ChangeVolumeRequest { delta });
         self.client.change_volume(change_volume_request).await?;
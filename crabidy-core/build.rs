@@ -1,4 +1,8 @@
 fn main() -> Result<(), Box<dyn std::error::Error>> {
-    tonic_build::compile_protos("crabidy/v1/crabidy.proto")?;
+    tonic_build::configure()
+        // The server persists some of the data types, e.g. the tracks of saved playlists
+        .type_attribute(".", "#[derive(serde::Serialize, serde::Deserialize)]")
+        .message_attribute(".", "#[serde(default)]")
+        .compile(&["crabidy/v1/crabidy.proto"], &["crabidy/v1"])?;
     Ok(())
 }
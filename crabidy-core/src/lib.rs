@@ -2,12 +2,18 @@ use std::{
     fs::{create_dir_all, read_to_string, File},
     io::Write,
     path::Path,
+    time::Duration,
 };
 
 use async_trait::async_trait;
 pub use clap_serde_derive::{self, clap, serde, ClapSerde};
-use proto::crabidy::{LibraryNode, LibraryNodeChild, Track};
+use proto::crabidy::{
+    Image, LibraryNode, LibraryNodeChild, Lyrics, ProviderCapabilities, ReplayGainMode,
+    SearchResult, Track,
+};
 
+pub mod lyrics;
+pub mod plugin;
 pub mod proto;
 
 #[async_trait]
@@ -16,26 +22,211 @@ pub trait ProviderClient: std::fmt::Debug + Send + Sync {
     where
         Self: Sized;
     fn settings(&self) -> String;
+    /// The urls the track can be streamed from, the player uses the first one. Besides `http(s)`
+    /// urls and local paths, a track split into segments, e.g. by MPEG-DASH, is returned as a
+    /// single `data:text/uri-list;base64,<list>` url. The list holds the url of the
+    /// initialization segment followed by the ones of the media segments, one per CRLF
+    /// terminated line, lines starting with `#` are comments. The player downloads the segments
+    /// one after the other and decodes them as one file, see
+    /// `stream_download::segmented::uri_list`.
     async fn get_urls_for_track(&self, track_uuid: &str) -> Result<Vec<String>, ProviderError>;
     async fn get_metadata_for_track(&self, track_uuid: &str) -> Result<Track, ProviderError>;
     fn get_lib_root(&self) -> LibraryNode;
+    /// What clients can do with the provider, see [`ProviderCapabilities::basic`]
+    fn capabilities(&self) -> ProviderCapabilities {
+        ProviderCapabilities::basic()
+    }
     async fn get_lib_node(&self, list_uuid: &str) -> Result<LibraryNode, ProviderError>;
+    /// Returns at most `limit` entries of the node starting at `offset` and the total number of
+    /// entries, see [`LibraryNode::paginate`]. Providers backed by paginated APIs should only
+    /// fetch the requested page.
+    async fn get_lib_node_page(
+        &self,
+        list_uuid: &str,
+        offset: usize,
+        limit: usize,
+    ) -> Result<(LibraryNode, usize), ProviderError> {
+        let mut node = self.get_lib_node(list_uuid).await?;
+        let total = node.paginate(offset, limit);
+        Ok((node, total))
+    }
+    async fn search(&self, query: &str) -> Result<SearchResult, ProviderError>;
+    /// The track a url of an imported playlist refers to, if the provider can play it
+    async fn track_for_url(&self, url: &str) -> Result<Option<Track>, ProviderError> {
+        let _ = url;
+        Ok(None)
+    }
+    /// Where playback of the track continues when it is played again, e.g. for podcast episodes
+    /// that were not listened to the end
+    async fn get_resume_position(
+        &self,
+        track_uuid: &str,
+    ) -> Result<Option<Duration>, ProviderError> {
+        let _ = track_uuid;
+        Ok(None)
+    }
+    /// Called regularly while the track is playing, providers that resume tracks remember the
+    /// position. The duration is `None` if it is not known.
+    async fn save_position(
+        &self,
+        track_uuid: &str,
+        position: Duration,
+        duration: Option<Duration>,
+    ) -> Result<(), ProviderError> {
+        let _ = (track_uuid, position, duration);
+        Ok(())
+    }
+    /// Writes the positions passed to `save_position` that were held back, called when playback
+    /// stops, when it moves on to another track and on shutdown
+    async fn flush_positions(&self) -> Result<(), ProviderError> {
+        Ok(())
+    }
+    /// The lyrics of the track, `None` if the provider has none for it
+    async fn get_lyrics(&self, track_uuid: &str) -> Result<Option<Lyrics>, ProviderError> {
+        let _ = track_uuid;
+        Ok(None)
+    }
 }
 
-#[derive(Clone, Debug, Hash)]
+/// What went wrong in a provider, with enough context to tell the user and to pick a fitting
+/// gRPC status code
+#[derive(Clone, Debug, Hash, PartialEq, Eq)]
 pub enum ProviderError {
+    /// The configuration can not be used
     Config(String),
-    UnknownUser,
-    CouldNotLogin,
-    FetchError,
-    MalformedUuid,
-    InternalError,
-    Other,
+    /// The provider has no user to act for, e.g. before logging in for the first time
+    UnknownUser {
+        provider: String,
+    },
+    CouldNotLogin {
+        provider: String,
+        cause: String,
+    },
+    /// A request of the provider failed, `status` is the HTTP status if there was a response
+    FetchError {
+        provider: String,
+        uuid: Option<String>,
+        status: Option<u16>,
+        cause: String,
+    },
+    /// The uuid is well formed, but the provider has nothing for it
+    NotFound {
+        uuid: String,
+    },
+    MalformedUuid {
+        uuid: String,
+    },
+    /// The provider can not be used right now, e.g. because it is not enabled
+    Unavailable {
+        provider: String,
+        cause: String,
+    },
+    InternalError(String),
+    Other(String),
+}
+
+impl ProviderError {
+    pub fn fetch(provider: &str, status: Option<u16>, cause: impl ToString) -> Self {
+        Self::FetchError {
+            provider: provider.to_string(),
+            uuid: None,
+            status,
+            cause: cause.to_string(),
+        }
+    }
+
+    pub fn malformed_uuid(uuid: &str) -> Self {
+        Self::MalformedUuid {
+            uuid: uuid.to_string(),
+        }
+    }
+
+    pub fn not_found(uuid: &str) -> Self {
+        Self::NotFound {
+            uuid: uuid.to_string(),
+        }
+    }
+
+    /// Adds the uuid that was requested to fetch errors that don't know it yet
+    pub fn with_uuid(self, requested: &str) -> Self {
+        match self {
+            Self::FetchError {
+                provider,
+                uuid: None,
+                status,
+                cause,
+            } => Self::FetchError {
+                provider,
+                uuid: Some(requested.to_string()),
+                status,
+                cause,
+            },
+            err => err,
+        }
+    }
 }
 
 impl std::fmt::Display for ProviderError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{:?}", self)
+        match self {
+            Self::Config(cause) => write!(f, "invalid configuration: {}", cause),
+            Self::UnknownUser { provider } => write!(f, "{}: no user logged in", provider),
+            Self::CouldNotLogin { provider, cause } => {
+                write!(f, "{}: login failed: {}", provider, cause)
+            }
+            Self::FetchError {
+                provider,
+                uuid,
+                status,
+                cause,
+            } => {
+                write!(f, "{}: ", provider)?;
+                if let Some(uuid) = uuid {
+                    write!(f, "fetching {} failed", uuid)?;
+                } else {
+                    write!(f, "request failed")?;
+                }
+                if let Some(status) = status {
+                    write!(f, " with HTTP {}", status)?;
+                }
+                write!(f, ": {}", cause)
+            }
+            Self::NotFound { uuid } => write!(f, "{} not found", uuid),
+            Self::MalformedUuid { uuid } => write!(f, "malformed uuid {:?}", uuid),
+            Self::Unavailable { provider, cause } => {
+                write!(f, "{} is unavailable: {}", provider, cause)
+            }
+            Self::InternalError(cause) => write!(f, "internal error: {}", cause),
+            Self::Other(cause) => write!(f, "{}", cause),
+        }
+    }
+}
+
+impl std::error::Error for ProviderError {}
+
+impl From<ProviderError> for tonic::Status {
+    fn from(err: ProviderError) -> Self {
+        let code = match &err {
+            ProviderError::Config(_) => tonic::Code::FailedPrecondition,
+            ProviderError::UnknownUser { .. } | ProviderError::CouldNotLogin { .. } => {
+                tonic::Code::Unauthenticated
+            }
+            ProviderError::FetchError { status, .. } => match status {
+                Some(401) => tonic::Code::Unauthenticated,
+                Some(403) => tonic::Code::PermissionDenied,
+                Some(404) => tonic::Code::NotFound,
+                Some(429) => tonic::Code::ResourceExhausted,
+                Some(400..=499) => tonic::Code::InvalidArgument,
+                // no response at all or a server error, trying again later might work
+                _ => tonic::Code::Unavailable,
+            },
+            ProviderError::NotFound { .. } => tonic::Code::NotFound,
+            ProviderError::MalformedUuid { .. } => tonic::Code::InvalidArgument,
+            ProviderError::Unavailable { .. } => tonic::Code::Unavailable,
+            ProviderError::InternalError(_) => tonic::Code::Internal,
+            ProviderError::Other(_) => tonic::Code::Unknown,
+        };
+        tonic::Status::new(code, err.to_string())
     }
 }
 
@@ -48,8 +239,39 @@ impl LibraryNode {
             parent: None,
             tracks: Vec::new(),
             is_queable: false,
+            capabilities: None,
+            artwork: Vec::new(),
         }
     }
+
+    /// Number of children and tracks, the entries a node is paginated by
+    pub fn entries_len(&self) -> usize {
+        self.children.len() + self.tracks.len()
+    }
+
+    /// Keeps at most `limit` entries starting at `offset`, children come before tracks. A limit
+    /// of 0 keeps all entries from the offset on. Returns the number of entries before.
+    pub fn paginate(&mut self, offset: usize, limit: usize) -> usize {
+        let total = self.entries_len();
+        let end = if limit == 0 {
+            total
+        } else {
+            total.min(offset.saturating_add(limit))
+        };
+        let children_len = self.children.len();
+        self.tracks.truncate(end.saturating_sub(children_len));
+        self.tracks
+            .drain(..offset.saturating_sub(children_len).min(self.tracks.len()));
+        self.children.truncate(end);
+        self.children.drain(..offset.min(self.children.len()));
+        total
+    }
+
+    /// Appends the entries of the following page of the same node
+    pub fn extend(&mut self, page: LibraryNode) {
+        self.children.extend(page.children);
+        self.tracks.extend(page.tracks);
+    }
 }
 
 impl LibraryNodeChild {
@@ -62,6 +284,90 @@ impl LibraryNodeChild {
     }
 }
 
+impl Track {
+    /// The artwork of the track itself or else the cover of its album
+    pub fn artwork(&self) -> &[Image] {
+        match &self.album {
+            Some(album) if self.artwork.is_empty() => &album.artwork,
+            _ => &self.artwork,
+        }
+    }
+
+    /// The names of all artists, or the main artist if the provider does not list them
+    pub fn all_artists(&self) -> String {
+        if self.artists.is_empty() {
+            return self.artist.clone();
+        }
+        self.artists
+            .iter()
+            .map(|artist| artist.name.as_str())
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+
+    /// The factor its samples are scaled by to play the track about as loud as others, lowered
+    /// so its peak does not clip. Tracks without a gain are played as they are.
+    pub fn normalization_gain(&self, mode: ReplayGainMode) -> f32 {
+        let (gain, peak) = match mode {
+            ReplayGainMode::Off => return 1.0,
+            ReplayGainMode::Album if self.album_replay_gain.is_some() => {
+                (self.album_replay_gain, self.album_peak)
+            }
+            ReplayGainMode::Track | ReplayGainMode::Album => (self.replay_gain, self.peak),
+        };
+        let Some(gain) = gain else {
+            return 1.0;
+        };
+        let factor = 10f32.powf(gain / 20.0);
+        match peak.filter(|peak| *peak > 0.0) {
+            Some(peak) => factor.min(1.0 / peak),
+            None => factor,
+        }
+    }
+}
+
+impl Image {
+    pub fn new(url: String, width: u32, height: u32) -> Self {
+        Self { url, width, height }
+    }
+
+    /// The smallest of the images at least `width` pixels wide, or the largest one if none is
+    pub fn pick(images: &[Image], width: u32) -> Option<&Image> {
+        images
+            .iter()
+            .filter(|image| width <= image.width)
+            .min_by_key(|image| image.width)
+            .or_else(|| images.iter().max_by_key(|image| image.width))
+    }
+}
+
+impl ProviderCapabilities {
+    /// Searching and seeking, what every provider supports unless it says otherwise
+    pub fn basic() -> Self {
+        Self {
+            search: true,
+            seek: true,
+            ..Default::default()
+        }
+    }
+}
+
+impl SearchResult {
+    pub fn is_empty(&self) -> bool {
+        self.tracks.is_empty()
+            && self.albums.is_empty()
+            && self.artists.is_empty()
+            && self.playlists.is_empty()
+    }
+
+    pub fn extend(&mut self, other: SearchResult) {
+        self.tracks.extend(other.tracks);
+        self.albums.extend(other.albums);
+        self.artists.extend(other.artists);
+        self.playlists.extend(other.playlists);
+    }
+}
+
 pub enum QueueError {
     NotQueable,
 }
@@ -94,3 +400,109 @@ where
     }
     T::default().merge_clap()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn provider_errors_map_to_status_codes() {
+        let err = ProviderError::fetch("tidal", Some(404), "no such album")
+            .with_uuid("node:tidal:album:1");
+        assert_eq!(
+            err.to_string(),
+            "tidal: fetching node:tidal:album:1 failed with HTTP 404: no such album"
+        );
+        let status = tonic::Status::from(err);
+        assert_eq!(status.code(), tonic::Code::NotFound);
+        assert_eq!(
+            tonic::Status::from(ProviderError::fetch("tidal", None, "timeout")).code(),
+            tonic::Code::Unavailable
+        );
+        assert_eq!(
+            tonic::Status::from(ProviderError::malformed_uuid("node")).code(),
+            tonic::Code::InvalidArgument
+        );
+    }
+
+    #[test]
+    fn normalization_gain_keeps_the_peak_below_full_scale() {
+        let track = Track {
+            replay_gain: Some(-6.0),
+            peak: Some(0.9),
+            album_replay_gain: Some(6.0),
+            album_peak: Some(0.9),
+            ..Default::default()
+        };
+        assert_eq!(track.normalization_gain(ReplayGainMode::Off), 1.0);
+        let gain = track.normalization_gain(ReplayGainMode::Track);
+        assert!((gain - 0.501).abs() < 0.001);
+        // +6 dB would clip
+        let gain = track.normalization_gain(ReplayGainMode::Album);
+        assert!((gain - 1.0 / 0.9).abs() < 0.001);
+
+        let single = Track {
+            album_replay_gain: None,
+            ..track
+        };
+        let gain = single.normalization_gain(ReplayGainMode::Album);
+        assert!((gain - 0.501).abs() < 0.001);
+        assert_eq!(
+            Track::default().normalization_gain(ReplayGainMode::Track),
+            1.0
+        );
+    }
+
+    #[test]
+    fn artwork_falls_back_to_the_album_cover() {
+        let cover: Vec<Image> = [80, 320, 1280]
+            .into_iter()
+            .map(|size| Image::new(format!("https://img/{}.jpg", size), size, size))
+            .collect();
+        let track = Track {
+            album: Some(proto::crabidy::Album {
+                artwork: cover.clone(),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        assert_eq!(track.artwork(), &cover[..]);
+        assert_eq!(Image::pick(track.artwork(), 200).unwrap().width, 320);
+        assert_eq!(Image::pick(track.artwork(), 2000).unwrap().width, 1280);
+        assert_eq!(Image::pick(&[], 200), None);
+    }
+
+    #[test]
+    fn paginate_spans_children_and_tracks() {
+        let node = LibraryNode {
+            children: (0..3)
+                .map(|i| LibraryNodeChild::new(format!("node:test:{}", i), i.to_string(), true))
+                .collect(),
+            tracks: (3..6)
+                .map(|i| Track {
+                    uuid: format!("track:test:{}", i),
+                    ..Default::default()
+                })
+                .collect(),
+            ..LibraryNode::new()
+        };
+
+        let mut page = node.clone();
+        assert_eq!(page.paginate(2, 2), 6);
+        assert_eq!(page.children[0].uuid, "node:test:2");
+        assert_eq!(page.tracks[0].uuid, "track:test:3");
+        assert_eq!(page.entries_len(), 2);
+
+        let mut rest = node.clone();
+        rest.paginate(4, 0);
+        assert!(rest.children.is_empty());
+        assert_eq!(rest.tracks.len(), 2);
+
+        let mut beyond = node;
+        beyond.paginate(10, 5);
+        assert_eq!(beyond.entries_len(), 0);
+
+        page.extend(rest);
+        assert_eq!(page.entries_len(), 4);
+    }
+}
@@ -0,0 +1,181 @@
+//! Lyrics as providers get them, either plain text or in the LRC format, where every line starts
+//! with the time it is sung at:
+//!
+//! ```text
+//! [ar:Artist]
+//! [offset:+250]
+//! [00:12.30]First line
+//! [00:17.05][01:02.10]Chorus, sung twice
+//! ```
+use std::time::Duration;
+
+use crate::proto::crabidy::{Lyrics, LyricsLine};
+
+impl Lyrics {
+    /// Parses LRC lyrics, text without any timestamps becomes plain lyrics
+    pub fn parse(text: &str) -> Self {
+        let mut offset = 0i64;
+        let mut lines = Vec::new();
+        for line in text.lines() {
+            let mut rest = line.trim();
+            let mut starts = Vec::new();
+            while let Some((tag, after)) = rest.strip_prefix('[').and_then(|r| r.split_once(']')) {
+                if let Some(start) = parse_timestamp(tag) {
+                    starts.push(start);
+                } else if let Some(value) = tag.strip_prefix("offset:") {
+                    offset = value.trim().parse().unwrap_or(0);
+                } else if !tag.contains(':') {
+                    // brackets that are part of the text
+                    break;
+                }
+                rest = after;
+            }
+            let text = strip_word_timestamps(rest);
+            lines.extend(starts.into_iter().map(|start| (start, text.clone())));
+        }
+        if lines.is_empty() {
+            return Self::plain(text);
+        }
+        // a positive offset shows the lines earlier
+        lines.sort_by_key(|(start, _)| *start);
+        let lines = lines
+            .into_iter()
+            .map(|(start, text)| LyricsLine {
+                start: start.saturating_sub(offset).clamp(0, u32::MAX as i64) as u32,
+                text,
+            })
+            .collect();
+        Self {
+            synced: true,
+            lines,
+        }
+    }
+
+    pub fn plain(text: &str) -> Self {
+        let lines: Vec<&str> = text.lines().map(str::trim).collect();
+        let first = lines.iter().position(|l| !l.is_empty()).unwrap_or(0);
+        let last = lines
+            .iter()
+            .rposition(|l| !l.is_empty())
+            .map_or(0, |l| l + 1);
+        Self {
+            synced: false,
+            lines: lines[first..last.max(first)]
+                .iter()
+                .map(|text| LyricsLine {
+                    start: 0,
+                    text: text.to_string(),
+                })
+                .collect(),
+        }
+    }
+
+    /// The index of the line sung at the position, `None` before the first line and for lyrics
+    /// that are not synced
+    pub fn line_at(&self, position: Duration) -> Option<usize> {
+        if !self.synced {
+            return None;
+        }
+        let position = position.as_millis();
+        self.lines
+            .partition_point(|line| line.start as u128 <= position)
+            .checked_sub(1)
+    }
+}
+
+/// Milliseconds of timestamps like `01:02.10`, `01:02:10` or `01:02`, `None` for ones too large
+/// to be a time in a track
+fn parse_timestamp(tag: &str) -> Option<i64> {
+    let (minutes, seconds) = tag.split_once(':')?;
+    let (seconds, fraction) = match seconds.split_once(['.', ':']) {
+        Some((seconds, fraction)) => (seconds, fraction),
+        None => (seconds, ""),
+    };
+    let minutes: i64 = minutes.trim().parse().ok()?;
+    let seconds: i64 = seconds.parse().ok()?;
+    if !fraction.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    // hundredths in most files, but milliseconds are used as well
+    let millis: i64 = format!("{:0<3}", fraction)[..3].parse().ok()?;
+    minutes
+        .checked_mul(60)?
+        .checked_add(seconds)?
+        .checked_mul(1000)?
+        .checked_add(millis)
+}
+
+/// Removes the times of single words of the enhanced format, `<00:12.50>First <00:13.10>line`
+fn strip_word_timestamps(text: &str) -> String {
+    let mut stripped = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find('<') {
+        let Some(end) = rest[start..].find('>') else {
+            break;
+        };
+        stripped.push_str(&rest[..start]);
+        let tag = &rest[start + 1..start + end];
+        if parse_timestamp(tag).is_none() {
+            stripped.push_str(&rest[start..start + end + 1]);
+        }
+        rest = &rest[start + end + 1..];
+    }
+    stripped.push_str(rest);
+    stripped.trim().to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lrc_lines_are_sorted_and_shifted() {
+        let lyrics = Lyrics::parse(
+            "[ar:Crabs]\r\n[offset:+250]\r\n[00:12.30]First line\r\n\
+             [00:17.05][01:02.1]Chorus [x2]\r\n[00:20.500]<00:20.50>Word <00:21.00>by word\r\n",
+        );
+        assert!(lyrics.synced);
+        let lines: Vec<(u32, &str)> = lyrics
+            .lines
+            .iter()
+            .map(|line| (line.start, line.text.as_str()))
+            .collect();
+        assert_eq!(
+            lines,
+            [
+                (12_050, "First line"),
+                (16_800, "Chorus [x2]"),
+                (20_250, "Word by word"),
+                (61_850, "Chorus [x2]"),
+            ]
+        );
+        assert_eq!(lyrics.line_at(Duration::from_secs(5)), None);
+        assert_eq!(lyrics.line_at(Duration::from_millis(16_800)), Some(1));
+        assert_eq!(lyrics.line_at(Duration::from_secs(600)), Some(3));
+    }
+
+    #[test]
+    fn text_without_timestamps_is_plain() {
+        let lyrics = Lyrics::parse("\n[Verse 1]\nFirst line\n\nSecond line\n\n");
+        assert!(!lyrics.synced);
+        let lines: Vec<&str> = lyrics.lines.iter().map(|l| l.text.as_str()).collect();
+        assert_eq!(lines, ["[Verse 1]", "First line", "", "Second line"]);
+        assert_eq!(lyrics.line_at(Duration::from_secs(5)), None);
+        assert!(Lyrics::parse("").lines.is_empty());
+    }
+
+    #[test]
+    fn out_of_range_times_do_not_overflow() {
+        assert_eq!(parse_timestamp("99999999999999999:00"), None);
+        assert_eq!(parse_timestamp("00:9223372036854775807"), None);
+        let lyrics = Lyrics::parse(
+            "[offset:-9223372036854775808]\n[99999999999999999:00]Never\n[00:01.00]Line\n",
+        );
+        let lines: Vec<(u32, &str)> = lyrics
+            .lines
+            .iter()
+            .map(|line| (line.start, line.text.as_str()))
+            .collect();
+        assert_eq!(lines, [(u32::MAX, "Line")]);
+    }
+}
@@ -0,0 +1,203 @@
+//! The protocol of providers that run as separate executables. The server spawns the plugin and
+//! writes one json request per line to its stdin, the plugin answers every request with one json
+//! response per line on its stdout. Anything the plugin writes to stderr ends up in the log of the
+//! server.
+//!
+//! A request names the method of [`ProviderClient`](crate::ProviderClient) it mirrors:
+//!
+//! ```json
+//! {"id":2,"method":"get_lib_node","params":{"uuid":"node:example:album:1"}}
+//! ```
+//!
+//! and the response carries the same id with either a result or an error:
+//!
+//! ```json
+//! {"id":2,"result":{"uuid":"node:example:album:1","title":"Album","tracks":[],...}}
+//! {"id":2,"error":{"kind":"not_found","uuid":"node:example:album:1"}}
+//! ```
+//!
+//! The first request is always `init`, the plugin uses the name it is given as provider prefix
+//! of its uuids, e.g. `track:<name>:1234`.
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    proto::crabidy::{LibraryNode, ProviderCapabilities},
+    ProviderError,
+};
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct PluginRequest {
+    pub id: u64,
+    #[serde(flatten)]
+    pub call: PluginCall,
+}
+
+/// The methods a plugin answers, the comments name the type of the result
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "method", content = "params", rename_all = "snake_case")]
+pub enum PluginCall {
+    /// [`InitResult`], `settings` is the content of the toml settings file of the plugin
+    Init { name: String, settings: String },
+    /// `LibraryNode`
+    GetLibNode { uuid: String },
+    /// `Track`
+    GetMetadataForTrack { uuid: String },
+    /// A list of urls
+    GetUrlsForTrack { uuid: String },
+    /// `SearchResult`
+    Search { query: String },
+    /// `Lyrics`, without lines if there are none. Only sent to plugins with the `lyrics`
+    /// capability
+    GetLyrics { uuid: String },
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct PluginResponse<T> {
+    pub id: u64,
+    pub result: Option<T>,
+    pub error: Option<PluginError>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct InitResult {
+    /// Written back to the settings file, e.g. with a token obtained while logging in
+    pub settings: String,
+    /// The library root is asked for often, so it is sent once and refreshed whenever the
+    /// server fetches the node of the root again
+    pub root: LibraryNode,
+    /// What the plugin supports, searching and seeking if it is not given
+    pub capabilities: Option<ProviderCapabilities>,
+}
+
+/// A [`ProviderError`] as plugins report it
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PluginError {
+    pub kind: PluginErrorKind,
+    /// The cause, the provider and uuid are added by the server
+    pub message: String,
+    pub uuid: Option<String>,
+    /// The HTTP status of a failed request
+    pub status: Option<u16>,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PluginErrorKind {
+    Config,
+    UnknownUser,
+    CouldNotLogin,
+    Fetch,
+    NotFound,
+    MalformedUuid,
+    Unavailable,
+    Internal,
+    #[default]
+    Other,
+}
+
+impl PluginError {
+    /// The error with the name of the plugin as provider
+    pub fn into_provider_error(self, provider: &str) -> ProviderError {
+        let provider = provider.to_string();
+        let uuid = self.uuid.unwrap_or_default();
+        match self.kind {
+            PluginErrorKind::Config => ProviderError::Config(self.message),
+            PluginErrorKind::UnknownUser => ProviderError::UnknownUser { provider },
+            PluginErrorKind::CouldNotLogin => ProviderError::CouldNotLogin {
+                provider,
+                cause: self.message,
+            },
+            PluginErrorKind::Fetch => ProviderError::FetchError {
+                provider,
+                uuid: Some(uuid).filter(|u| !u.is_empty()),
+                status: self.status,
+                cause: self.message,
+            },
+            PluginErrorKind::NotFound => ProviderError::NotFound { uuid },
+            PluginErrorKind::MalformedUuid => ProviderError::MalformedUuid { uuid },
+            PluginErrorKind::Unavailable => ProviderError::Unavailable {
+                provider,
+                cause: self.message,
+            },
+            PluginErrorKind::Internal => ProviderError::InternalError(self.message),
+            PluginErrorKind::Other => ProviderError::Other(self.message),
+        }
+    }
+}
+
+impl From<ProviderError> for PluginError {
+    fn from(err: ProviderError) -> Self {
+        let (kind, message, uuid, status) = match err {
+            ProviderError::Config(cause) => (PluginErrorKind::Config, cause, None, None),
+            ProviderError::UnknownUser { .. } => {
+                (PluginErrorKind::UnknownUser, String::new(), None, None)
+            }
+            ProviderError::CouldNotLogin { cause, .. } => {
+                (PluginErrorKind::CouldNotLogin, cause, None, None)
+            }
+            ProviderError::FetchError {
+                uuid,
+                status,
+                cause,
+                ..
+            } => (PluginErrorKind::Fetch, cause, uuid, status),
+            ProviderError::NotFound { uuid } => {
+                (PluginErrorKind::NotFound, String::new(), Some(uuid), None)
+            }
+            ProviderError::MalformedUuid { uuid } => (
+                PluginErrorKind::MalformedUuid,
+                String::new(),
+                Some(uuid),
+                None,
+            ),
+            ProviderError::Unavailable { cause, .. } => {
+                (PluginErrorKind::Unavailable, cause, None, None)
+            }
+            ProviderError::InternalError(cause) => (PluginErrorKind::Internal, cause, None, None),
+            ProviderError::Other(cause) => (PluginErrorKind::Other, cause, None, None),
+        };
+        Self {
+            kind,
+            message,
+            uuid,
+            status,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn errors_survive_the_wire_format() {
+        let request = PluginRequest {
+            id: 2,
+            call: PluginCall::GetLibNode {
+                uuid: "node:example:album:1".to_string(),
+            },
+        };
+        let json = serde_json::to_string(&request).unwrap();
+        assert_eq!(
+            json,
+            r#"{"id":2,"method":"get_lib_node","params":{"uuid":"node:example:album:1"}}"#
+        );
+        assert_eq!(
+            serde_json::from_str::<PluginRequest>(&json).unwrap(),
+            request
+        );
+
+        let err = ProviderError::fetch("example", Some(410), "gone").with_uuid("track:example:7");
+        let response = PluginResponse::<LibraryNode> {
+            id: 2,
+            result: None,
+            error: Some(err.clone().into()),
+        };
+        let json = serde_json::to_string(&response).unwrap();
+        let response = serde_json::from_str::<PluginResponse<LibraryNode>>(&json).unwrap();
+        let decoded = response.error.unwrap().into_provider_error("example");
+        assert_eq!(decoded.to_string(), err.to_string());
+    }
+}
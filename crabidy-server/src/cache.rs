@@ -0,0 +1,311 @@
+use crate::config::CacheConfig;
+use crabidy_core::proto::crabidy::{LibraryNode, Track};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+use tracing::{debug, error, instrument};
+
+/// Library nodes and track metadata fetched from the providers
+#[derive(Debug)]
+pub struct Cache {
+    ttls: Ttls,
+    max_nodes: usize,
+    max_tracks: usize,
+    // only set if the cache should be kept on disk
+    path: Option<PathBuf>,
+    entries: Entries,
+    dirty: bool,
+}
+
+#[derive(Debug)]
+struct Ttls {
+    album: Duration,
+    artist: Duration,
+    playlist: Duration,
+    node: Duration,
+    track: Duration,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Entries {
+    nodes: HashMap<String, Entry<LibraryNode>>,
+    tracks: HashMap<String, Entry<Track>>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Entry<T> {
+    value: T,
+    inserted: SystemTime,
+}
+
+impl<T> Entry<T> {
+    fn new(value: T) -> Self {
+        Self {
+            value,
+            inserted: SystemTime::now(),
+        }
+    }
+
+    fn is_fresh(&self, ttl: Duration) -> bool {
+        self.inserted
+            .elapsed()
+            .map(|age| age < ttl)
+            .unwrap_or(false)
+    }
+}
+
+impl Cache {
+    pub fn new(config: &CacheConfig, path: PathBuf) -> Self {
+        let mut cache = Self {
+            ttls: Ttls {
+                album: Duration::from_secs(config.album_ttl),
+                artist: Duration::from_secs(config.artist_ttl),
+                playlist: Duration::from_secs(config.playlist_ttl),
+                node: Duration::from_secs(config.node_ttl),
+                track: Duration::from_secs(config.track_ttl),
+            },
+            max_nodes: config.max_nodes,
+            max_tracks: config.max_tracks,
+            path: config.persist.then_some(path),
+            entries: Entries::default(),
+            dirty: false,
+        };
+        if let Some(path) = &cache.path {
+            cache.entries = load(path);
+            cache.remove_expired();
+        }
+        cache
+    }
+
+    pub fn get_node(&self, uuid: &str) -> Option<LibraryNode> {
+        let entry = self.entries.nodes.get(uuid)?;
+        entry
+            .is_fresh(self.node_ttl(uuid))
+            .then(|| entry.value.clone())
+    }
+
+    pub fn get_track(&self, uuid: &str) -> Option<Track> {
+        let entry = self.entries.tracks.get(uuid)?;
+        entry.is_fresh(self.ttls.track).then(|| entry.value.clone())
+    }
+
+    /// Caches the node and the metadata of its tracks
+    pub fn insert_node(&mut self, node: &LibraryNode) {
+        for track in &node.tracks {
+            self.insert_track(track);
+        }
+        if self.node_ttl(&node.uuid).is_zero() || self.max_nodes == 0 {
+            return;
+        }
+        self.entries
+            .nodes
+            .insert(node.uuid.clone(), Entry::new(node.clone()));
+        self.dirty = true;
+        evict(&mut self.entries.nodes, self.max_nodes);
+    }
+
+    pub fn insert_track(&mut self, track: &Track) {
+        if self.ttls.track.is_zero() || self.max_tracks == 0 {
+            return;
+        }
+        self.entries
+            .tracks
+            .insert(track.uuid.clone(), Entry::new(track.clone()));
+        self.dirty = true;
+        evict(&mut self.entries.tracks, self.max_tracks);
+    }
+
+    /// Drops the entry with the uuid, the root node of a provider like `node:tidal` drops
+    /// everything of the provider
+    pub fn invalidate(&mut self, uuid: &str) {
+        let mut segments = uuid.split(':');
+        match (segments.next(), segments.next(), segments.next()) {
+            (Some("node"), Some(provider), None) => {
+                let node_prefix = format!("node:{}:", provider);
+                let track_prefix = format!("track:{}:", provider);
+                self.entries
+                    .nodes
+                    .retain(|uuid, _| !uuid.starts_with(&node_prefix));
+                self.entries
+                    .tracks
+                    .retain(|uuid, _| !uuid.starts_with(&track_prefix));
+                self.entries.nodes.remove(uuid);
+            }
+            _ => {
+                self.entries.nodes.remove(uuid);
+                self.entries.tracks.remove(uuid);
+            }
+        }
+        self.dirty = true;
+    }
+
+    pub fn clear(&mut self) {
+        self.entries = Entries::default();
+        self.dirty = true;
+    }
+
+    /// Writes the cache to disk if it should be persisted and changed since the last save
+    #[instrument(skip(self))]
+    pub fn persist(&mut self) {
+        let Some(path) = &self.path else {
+            return;
+        };
+        if !self.dirty {
+            return;
+        }
+        let json = match serde_json::to_vec(&self.entries) {
+            Ok(json) => json,
+            Err(err) => {
+                error!("failed to serialize cache: {}", err);
+                return;
+            }
+        };
+        if let Some(dir) = path.parent() {
+            if let Err(err) = fs::create_dir_all(dir) {
+                error!("failed to create {:?}: {}", dir, err);
+                return;
+            }
+        }
+        match fs::write(path, json) {
+            Ok(_) => self.dirty = false,
+            Err(err) => error!("failed to write cache to {:?}: {}", path, err),
+        }
+    }
+
+    fn node_ttl(&self, uuid: &str) -> Duration {
+        match uuid.split(':').nth(2) {
+            Some("album") => self.ttls.album,
+            Some("artist") => self.ttls.artist,
+            Some("playlist") => self.ttls.playlist,
+            _ => self.ttls.node,
+        }
+    }
+
+    fn remove_expired(&mut self) {
+        let node_ttls: HashMap<String, Duration> = self
+            .entries
+            .nodes
+            .keys()
+            .map(|uuid| (uuid.clone(), self.node_ttl(uuid)))
+            .collect();
+        self.entries
+            .nodes
+            .retain(|uuid, entry| entry.is_fresh(node_ttls[uuid]));
+        let track_ttl = self.ttls.track;
+        self.entries
+            .tracks
+            .retain(|_, entry| entry.is_fresh(track_ttl));
+    }
+}
+
+fn load(path: &Path) -> Entries {
+    let Ok(json) = fs::read(path) else {
+        debug!("no cache to load from {:?}", path);
+        return Entries::default();
+    };
+    serde_json::from_slice(&json).unwrap_or_else(|err| {
+        error!("failed to parse cache: {}", err);
+        Entries::default()
+    })
+}
+
+/// Drops the oldest tenth of the entries once there are more than `max`
+fn evict<T>(entries: &mut HashMap<String, Entry<T>>, max: usize) {
+    if entries.len() <= max {
+        return;
+    }
+    if max == 0 {
+        entries.clear();
+        return;
+    }
+    let mut inserted: Vec<SystemTime> = entries.values().map(|e| e.inserted).collect();
+    inserted.sort_unstable();
+    let keep = max - max / 10;
+    let cutoff = inserted[inserted.len() - keep];
+    entries.retain(|_, entry| entry.inserted >= cutoff);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> CacheConfig {
+        CacheConfig {
+            album_ttl: 60,
+            artist_ttl: 60,
+            playlist_ttl: 0,
+            node_ttl: 60,
+            track_ttl: 60,
+            max_nodes: 10,
+            max_tracks: 10,
+            persist: false,
+        }
+    }
+
+    fn node(uuid: &str, tracks: &[&str]) -> LibraryNode {
+        LibraryNode {
+            uuid: uuid.to_string(),
+            tracks: tracks
+                .iter()
+                .map(|uuid| Track {
+                    uuid: uuid.to_string(),
+                    ..Default::default()
+                })
+                .collect(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn caches_nodes_and_their_tracks() {
+        let mut cache = Cache::new(&config(), PathBuf::new());
+        cache.insert_node(&node("node:tidal:album:1", &["track:tidal:1"]));
+        cache.insert_node(&node("node:tidal:playlist:2", &["track:tidal:2"]));
+        assert!(cache.get_node("node:tidal:album:1").is_some());
+        // playlists are not cached at all with a ttl of 0, but their tracks are
+        assert!(cache.get_node("node:tidal:playlist:2").is_none());
+        assert!(cache.get_track("track:tidal:2").is_some());
+    }
+
+    #[test]
+    fn invalidate_provider_root_drops_everything_of_the_provider() {
+        let mut cache = Cache::new(&config(), PathBuf::new());
+        cache.insert_node(&node("node:tidal:album:1", &["track:tidal:1"]));
+        cache.insert_node(&node("node:local:album:1", &["track:local:/a.flac"]));
+        cache.invalidate("node:tidal");
+        assert!(cache.get_node("node:tidal:album:1").is_none());
+        assert!(cache.get_track("track:tidal:1").is_none());
+        assert!(cache.get_node("node:local:album:1").is_some());
+        cache.invalidate("track:local:/a.flac");
+        assert!(cache.get_track("track:local:/a.flac").is_none());
+    }
+
+    #[test]
+    fn evicts_oldest_entries() {
+        let mut cache = Cache::new(&config(), PathBuf::new());
+        for i in 0..11 {
+            cache.insert_node(&node(&format!("node:tidal:album:{}", i), &[]));
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        assert_eq!(cache.entries.nodes.len(), 9);
+        assert!(cache.get_node("node:tidal:album:0").is_none());
+        assert!(cache.get_node("node:tidal:album:10").is_some());
+    }
+
+    #[test]
+    fn a_maximum_of_zero_disables_the_cache() {
+        let config = CacheConfig {
+            max_nodes: 0,
+            max_tracks: 0,
+            ..config()
+        };
+        let mut cache = Cache::new(&config, PathBuf::new());
+        cache.insert_node(&node("node:tidal:album:1", &["track:tidal:1"]));
+        assert!(cache.get_node("node:tidal:album:1").is_none());
+        assert!(cache.get_track("track:tidal:1").is_none());
+    }
+}
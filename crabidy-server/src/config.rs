@@ -0,0 +1,154 @@
+use audio_player::Filter;
+use crabidy_core::{
+    clap, clap_serde_derive,
+    serde::{Deserialize, Serialize},
+    ClapSerde,
+};
+use std::collections::BTreeMap;
+
+#[derive(ClapSerde, Serialize, Debug)]
+#[clap(author, version, about)]
+pub struct Config {
+    #[clap_serde]
+    #[clap(flatten)]
+    pub server: ServerConfig,
+
+    #[clap_serde]
+    #[clap(flatten)]
+    pub playback: PlaybackConfig,
+
+    #[clap_serde]
+    #[clap(flatten)]
+    pub providers: ProvidersConfig,
+}
+
+#[derive(ClapSerde, Serialize, Debug)]
+pub struct ServerConfig {
+    /// Address the gRPC server listens on
+    #[default("0.0.0.0:50051".to_string())]
+    #[clap(short, long)]
+    pub address: String,
+}
+
+#[derive(ClapSerde, Serialize, Debug)]
+pub struct PlaybackConfig {
+    /// Seconds tracks are crossfaded while crossfading is turned on, tracks of the same album
+    /// still follow each other without a gap
+    #[default(6)]
+    #[clap(long = "crossfade")]
+    pub crossfade: u64,
+
+    /// Chains of filters the audio passes through, e.g. to correct the response of headphones,
+    /// keyed by the name they are switched to with
+    #[clap(skip)]
+    pub presets: BTreeMap<String, Vec<Filter>>,
+}
+
+#[derive(ClapSerde, Serialize, Debug)]
+pub struct ProvidersConfig {
+    /// Providers to enable, each one is shown as a root node in the library
+    #[default(vec!["tidal".to_string(), "local".to_string()])]
+    #[clap(short = 'p', long = "provider")]
+    pub enabled: Vec<String>,
+
+    #[clap_serde]
+    #[clap(flatten)]
+    pub cache: CacheConfig,
+
+    /// Providers running as separate executables, keyed by the name they are enabled with
+    #[clap(skip)]
+    pub plugins: BTreeMap<String, PluginConfig>,
+}
+
+/// A provider running as a separate executable, see `crabidy_core::plugin` for its protocol
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct PluginConfig {
+    /// The executable, searched in `PATH` unless it is a path
+    pub command: String,
+    pub args: Vec<String>,
+    /// Seconds to wait for a response before the plugin is restarted
+    pub timeout: u64,
+}
+
+impl Default for PluginConfig {
+    fn default() -> Self {
+        Self {
+            command: String::new(),
+            args: Vec::new(),
+            timeout: 30,
+        }
+    }
+}
+
+/// How long library nodes and track metadata fetched from the providers are kept, all durations
+/// are in seconds and 0 disables caching for the kind of entry
+#[derive(ClapSerde, Serialize, Debug)]
+pub struct CacheConfig {
+    /// Seconds album nodes are cached
+    #[default(7 * 24 * 60 * 60)]
+    #[clap(long = "cache-album-ttl")]
+    pub album_ttl: u64,
+
+    /// Seconds artist nodes are cached
+    #[default(24 * 60 * 60)]
+    #[clap(long = "cache-artist-ttl")]
+    pub artist_ttl: u64,
+
+    /// Seconds playlist nodes are cached
+    #[default(60 * 60)]
+    #[clap(long = "cache-playlist-ttl")]
+    pub playlist_ttl: u64,
+
+    /// Seconds all other nodes, e.g. the favorites of a user, are cached
+    #[default(10 * 60)]
+    #[clap(long = "cache-node-ttl")]
+    pub node_ttl: u64,
+
+    /// Seconds track metadata is cached
+    #[default(7 * 24 * 60 * 60)]
+    #[clap(long = "cache-track-ttl")]
+    pub track_ttl: u64,
+
+    /// Maximum number of cached library nodes, 0 disables caching them
+    #[default(5000)]
+    #[clap(long = "cache-max-nodes")]
+    pub max_nodes: usize,
+
+    /// Maximum number of cached tracks, 0 disables caching them
+    #[default(50000)]
+    #[clap(long = "cache-max-tracks")]
+    pub max_tracks: usize,
+
+    /// Keep the cache on disk, so it survives restarts of the server
+    #[default(false)]
+    #[clap(long = "cache-persist")]
+    pub persist: bool,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn presets_are_read_from_the_playback_section() {
+        let opt = toml::from_str::<<PlaybackConfig as ClapSerde>::Opt>(
+            r#"
+            [presets]
+            headphones = [
+              { type = "preamp", gain = -4.0 },
+              { type = "peaking", frequency = 120.0, gain = 3.5, q = 0.8 },
+              { type = "high_shelf", frequency = 8000.0, gain = -2.0 },
+            ]
+            bedroom = [{ type = "graphic", gains = [2.0, 1.0, 0.0] }, { type = "mono" }]
+            "#,
+        )
+        .unwrap();
+        let config = PlaybackConfig::from(opt);
+        assert_eq!(config.crossfade, 6);
+        let headphones = &config.presets["headphones"];
+        assert_eq!(headphones[0], Filter::Preamp { gain: -4.0 });
+        assert!(matches!(headphones[2], Filter::HighShelf { q, .. } if q > 0.7 && q < 0.71));
+        assert_eq!(config.presets["bedroom"][1], Filter::Mono);
+    }
+}
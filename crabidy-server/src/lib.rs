@@ -1,9 +1,10 @@
-use crabidy_core::proto::crabidy::{Queue, Track};
+use crabidy_core::proto::crabidy::{Album, Artist, Queue, ReplayGainMode, Track};
 use rand::{seq::SliceRandom, thread_rng};
+use serde::{Deserialize, Serialize};
 use std::time::SystemTime;
 use tracing::{debug, error};
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct QueueManager {
     created_at: SystemTime,
     current_offset: usize,
@@ -11,6 +12,14 @@ pub struct QueueManager {
     tracks: Vec<Track>,
     pub repeat: bool,
     pub shuffle: bool,
+    #[serde(default)]
+    pub crossfade: bool,
+    #[serde(default = "replay_gain_off")]
+    pub replay_gain: ReplayGainMode,
+}
+
+fn replay_gain_off() -> ReplayGainMode {
+    ReplayGainMode::Off
 }
 
 impl From<QueueManager> for Queue {
@@ -36,6 +45,8 @@ impl QueueManager {
             tracks: Vec::new(),
             repeat: false,
             shuffle: false,
+            crossfade: false,
+            replay_gain: ReplayGainMode::Off,
         }
     }
     pub fn current_position(&self) -> usize {
@@ -46,6 +57,10 @@ impl QueueManager {
         }
     }
 
+    pub fn tracks(&self) -> &[Track] {
+        &self.tracks
+    }
+
     pub fn is_last_track(&self) -> bool {
         self.current_position() == self.tracks.len() - 1
     }
@@ -110,6 +125,20 @@ impl QueueManager {
         }
     }
 
+    /// The track `next_track` moves on to, unless it is only picked when getting there, which is
+    /// the case when a shuffled queue starts over
+    pub fn upcoming_track(&self) -> Option<Track> {
+        let offset = if self.current_offset + 1 < self.tracks.len() {
+            self.current_offset + 1
+        } else if self.repeat && !self.shuffle {
+            0
+        } else {
+            return None;
+        };
+        let position = *self.play_order.get(offset)?;
+        self.tracks.get(position).cloned()
+    }
+
     pub fn prev_track(&mut self) -> Option<Track> {
         if 0 < self.current_offset {
             self.current_offset -= 1;
@@ -276,6 +305,49 @@ impl QueueManager {
     }
 }
 
+/// Whether the tracks are of the same album, which are not crossfaded
+pub fn is_same_album(track: &Track, other: &Track) -> bool {
+    let (Some(album), Some(other)) = (&track.album, &other.album) else {
+        return false;
+    };
+    match (&album.uuid, &other.uuid) {
+        (Some(uuid), Some(other)) => uuid == other,
+        _ => !album.title.is_empty() && album.title == other.title,
+    }
+}
+
+/// Shows the title a live stream announced, usually `Artist - Title`, in place of the queued
+/// track, e.g. a radio station, which becomes the album
+pub fn with_stream_title(track: Track, stream_title: &str) -> Track {
+    let (artist, artists, title) = match stream_title.split_once(" - ") {
+        Some((artist, title)) => (
+            artist.to_string(),
+            vec![Artist {
+                name: artist.to_string(),
+                uuid: None,
+            }],
+            title.to_string(),
+        ),
+        None => (
+            track.artist.clone(),
+            track.artists.clone(),
+            stream_title.to_string(),
+        ),
+    };
+    Track {
+        artist,
+        artists,
+        title,
+        album: Some(Album {
+            title: track.title.clone(),
+            release_date: None,
+            artwork: Vec::new(),
+            uuid: None,
+        }),
+        ..track
+    }
+}
+
 #[cfg(test)]
 
 mod tests {
@@ -289,4 +361,92 @@ mod tests {
     fn random_delete_after() {}
     #[test]
     fn random_select_track() {}
+
+    #[test]
+    fn shuffled_queue_survives_serialization() {
+        let tracks: Vec<Track> = (0..20)
+            .map(|i| Track {
+                uuid: format!("track:tidal:{}", i),
+                ..Default::default()
+            })
+            .collect();
+        let mut queue = QueueManager::new();
+        queue.replace_with_tracks(&tracks);
+        queue.shuffle_on();
+        queue.next_track();
+        queue.next_track();
+        let json = serde_json::to_string(&queue).unwrap();
+        let restored: QueueManager = serde_json::from_str(&json).unwrap();
+        assert!(restored.shuffle);
+        assert_eq!(restored.play_order, queue.play_order);
+        assert_eq!(restored.current_offset, queue.current_offset);
+        assert_eq!(restored.current_track(), queue.current_track());
+    }
+
+    #[test]
+    fn upcoming_track_is_the_next_one() {
+        let tracks: Vec<Track> = (0..5)
+            .map(|i| Track {
+                uuid: format!("track:tidal:{}", i),
+                ..Default::default()
+            })
+            .collect();
+        let mut queue = QueueManager::new();
+        queue.replace_with_tracks(&tracks);
+        queue.shuffle_on();
+        for _ in 0..4 {
+            let upcoming = queue.upcoming_track();
+            assert!(upcoming.is_some());
+            assert_eq!(upcoming, queue.next_track());
+        }
+        assert_eq!(queue.upcoming_track(), None);
+        // a shuffled queue is shuffled again when starting over
+        queue.repeat = true;
+        assert_eq!(queue.upcoming_track(), None);
+        queue.shuffle_off();
+        queue.set_current_position(4);
+        assert_eq!(queue.upcoming_track(), Some(tracks[0].clone()));
+        assert_eq!(queue.upcoming_track(), queue.next_track());
+    }
+
+    #[test]
+    fn albums_are_compared_by_uuid_or_title() {
+        let track = |album: Option<(&str, Option<&str>)>| Track {
+            album: album.map(|(title, uuid)| Album {
+                title: title.to_string(),
+                uuid: uuid.map(String::from),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let live = track(Some(("Live", Some("node:tidal:album:1"))));
+        assert!(is_same_album(&live, &live));
+        assert!(!is_same_album(
+            &live,
+            &track(Some(("Live", Some("node:tidal:album:2"))))
+        ));
+        assert!(is_same_album(&live, &track(Some(("Live", None)))));
+        assert!(!is_same_album(
+            &track(Some(("", None))),
+            &track(Some(("", None)))
+        ));
+        assert!(!is_same_album(&track(None), &track(None)));
+    }
+
+    #[test]
+    fn stream_titles_replace_the_station() {
+        let station = Track {
+            uuid: "track:radio:1".to_string(),
+            title: "Groove Salad".to_string(),
+            ..Default::default()
+        };
+        let track = with_stream_title(station.clone(), "Boards of Canada - Roygbiv");
+        assert_eq!(track.uuid, station.uuid);
+        assert_eq!(track.artist, "Boards of Canada");
+        assert_eq!(track.title, "Roygbiv");
+        assert_eq!(track.album.unwrap().title, "Groove Salad");
+        let track = with_stream_title(station, "Station jingle");
+        assert_eq!(track.artist, "");
+        assert_eq!(track.title, "Station jingle");
+    }
 }
@@ -1,17 +1,28 @@
 use audio_player::PlayerMessage;
 use crabidy_core::proto::crabidy::{
-    crabidy_service_server::CrabidyServiceServer, InitResponse, LibraryNode, PlayState, Track,
+    crabidy_service_server::CrabidyServiceServer, GetDspPresetsResponse, InitResponse, LibraryNode,
+    Lyrics, PlayState, Provider, ReplayGainMode, SearchResult, Track,
 };
 use crabidy_core::{ProviderClient, ProviderError};
+use std::time::Duration;
 use tracing::{debug_span, error, info, instrument, level_filters, warn, Span};
 use tracing_subscriber::{filter::Targets, prelude::*};
 
+mod cache;
+mod config;
+use config::Config;
 mod playback;
 use playback::Playback;
+mod playlist_file;
+use playlist_file::PlaylistEntry;
+mod plugin;
 mod provider;
 use provider::ProviderOrchestrator;
 mod rpc;
 use rpc::RpcService;
+mod saved;
+mod state;
+use state::PlaybackState;
 
 use tonic::{transport::Server, Result};
 
@@ -39,12 +50,22 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     info!("audio player started initialized");
 
+    let config: Config = crabidy_core::init_config("crabidy-server.toml");
+
     let (update_tx, _) = tokio::sync::broadcast::channel(2048);
-    let orchestrator = ProviderOrchestrator::init("")
+    let providers_config = toml::to_string(&config.providers)?;
+    let orchestrator = ProviderOrchestrator::init(&providers_config)
         .await
         .expect("failed to init orchestrator");
 
-    let playback = Playback::new(update_tx.clone(), orchestrator.provider_tx.clone());
+    let state_path = PlaybackState::path();
+    let playback = Playback::new(
+        update_tx.clone(),
+        orchestrator.provider_tx.clone(),
+        state_path,
+        Duration::from_secs(config.playback.crossfade),
+        config.playback.presets.clone(),
+    );
 
     let playback_tx = playback.playback_tx.clone();
     let player_msg = playback.player.messages.clone();
@@ -54,6 +75,8 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     });
     info!("gstreamer bus handler started");
 
+    let playback_tx = playback.playback_tx.clone();
+    let provider_tx = orchestrator.provider_tx.clone();
     let crabidy_service = RpcService::new(
         update_tx,
         playback.playback_tx.clone(),
@@ -64,15 +87,61 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     playback.run();
     info!("playback started");
 
-    let addr = "0.0.0.0:50051".parse()?;
+    let addr = config.server.address.parse()?;
     Server::builder()
         .add_service(CrabidyServiceServer::new(crabidy_service))
-        .serve(addr)
+        .serve_with_shutdown(addr, shutdown_signal())
         .await?;
 
+    info!("shutting down");
+    let (result_tx, result_rx) = flume::bounded(1);
+    let span = debug_span!("play-chan");
+    if playback_tx
+        .send_async(PlaybackMessage::SaveState { result_tx, span })
+        .await
+        .is_ok()
+    {
+        result_rx.recv_async().await.ok();
+    }
+    let span = debug_span!("prov-chan");
+    if let Err(err) = provider_tx
+        .send_async(ProviderMessage::FlushPositions { span })
+        .await
+    {
+        error!("failed to send message: {}", err);
+    }
+    let (result_tx, result_rx) = flume::bounded(1);
+    let span = debug_span!("prov-chan");
+    if provider_tx
+        .send_async(ProviderMessage::PersistCache { result_tx, span })
+        .await
+        .is_ok()
+    {
+        result_rx.recv_async().await.ok();
+    }
+
     Ok(())
 }
 
+async fn shutdown_signal() {
+    #[cfg(unix)]
+    {
+        let Ok(mut terminate) =
+            tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+        else {
+            error!("failed to listen for SIGTERM");
+            tokio::signal::ctrl_c().await.ok();
+            return;
+        };
+        tokio::select! {
+            _ = tokio::signal::ctrl_c() => {},
+            _ = terminate.recv() => {},
+        }
+    }
+    #[cfg(not(unix))]
+    tokio::signal::ctrl_c().await.ok();
+}
+
 #[instrument(skip(rx, tx))]
 fn poll_play_bus(rx: flume::Receiver<PlayerMessage>, tx: flume::Sender<PlaybackMessage>) {
     for msg in rx.iter() {
@@ -83,6 +152,11 @@ fn poll_play_bus(rx: flume::Receiver<PlayerMessage>, tx: flume::Sender<PlaybackM
                     error!("failed to send next message: {}", err);
                 }
             }
+            PlayerMessage::NextStarted => {
+                if let Err(err) = tx.send(PlaybackMessage::NextStarted { span }) {
+                    error!("failed to send next started message: {}", err);
+                }
+            }
             PlayerMessage::Stopped => {
                 if let Err(err) = tx.send(PlaybackMessage::StateChanged {
                     state: PlayState::Stopped,
@@ -116,6 +190,11 @@ fn poll_play_bus(rx: flume::Receiver<PlayerMessage>, tx: flume::Sender<PlaybackM
                     error!("failed to send elapsed message: {}", err);
                 }
             }
+            PlayerMessage::StreamTitle { title } => {
+                if let Err(err) = tx.send(PlaybackMessage::StreamTitleChanged { title, span }) {
+                    error!("failed to send stream title message: {}", err);
+                }
+            }
             PlayerMessage::Duration { duration } => {
                 if let Err(err) = tx.send(PlaybackMessage::PostitionChanged {
                     duration: duration.as_millis() as u32,
@@ -133,7 +212,10 @@ fn poll_play_bus(rx: flume::Receiver<PlayerMessage>, tx: flume::Sender<PlaybackM
 pub enum ProviderMessage {
     GetLibraryNode {
         uuid: String,
-        result_tx: flume::Sender<Result<LibraryNode, ProviderError>>,
+        offset: usize,
+        limit: usize,
+        // the page of the node and the total number of its entries
+        result_tx: flume::Sender<Result<(LibraryNode, usize), ProviderError>>,
         span: Span,
     },
     GetTrack {
@@ -151,6 +233,67 @@ pub enum ProviderMessage {
         result_tx: flume::Sender<Vec<Track>>,
         span: Span,
     },
+    Search {
+        query: String,
+        result_tx: flume::Sender<Result<SearchResult, ProviderError>>,
+        span: Span,
+    },
+    GetProviders {
+        result_tx: flume::Sender<Vec<Provider>>,
+        span: Span,
+    },
+    GetLyrics {
+        uuid: String,
+        result_tx: flume::Sender<Result<Option<Lyrics>, ProviderError>>,
+        span: Span,
+    },
+    SavePlaylist {
+        name: String,
+        tracks: Vec<Track>,
+        result_tx: flume::Sender<Result<String, ProviderError>>,
+        span: Span,
+    },
+    RenamePlaylist {
+        uuid: String,
+        name: String,
+        result_tx: flume::Sender<Result<(), ProviderError>>,
+        span: Span,
+    },
+    DeletePlaylist {
+        uuid: String,
+        result_tx: flume::Sender<Result<(), ProviderError>>,
+        span: Span,
+    },
+    ResolvePlaylist {
+        entries: Vec<PlaylistEntry>,
+        // the tracks that were found and the entries that could not be resolved
+        result_tx: flume::Sender<(Vec<Track>, Vec<PlaylistEntry>)>,
+        span: Span,
+    },
+    GetResumePosition {
+        uuid: String,
+        result_tx: flume::Sender<Option<Duration>>,
+        span: Span,
+    },
+    SavePosition {
+        uuid: String,
+        position: Duration,
+        duration: Option<Duration>,
+        span: Span,
+    },
+    // writes the positions providers held back, they are handled in order so the positions saved
+    // before are included
+    FlushPositions {
+        span: Span,
+    },
+    InvalidateCache {
+        uuids: Vec<String>,
+        span: Span,
+    },
+    PersistCache {
+        result_tx: flume::Sender<()>,
+        span: Span,
+    },
 }
 
 #[derive(Debug)]
@@ -184,6 +327,15 @@ pub enum PlaybackMessage {
         exclude_current: bool,
         span: Span,
     },
+    SaveQueue {
+        name: String,
+        result_tx: flume::Sender<Result<String, ProviderError>>,
+        span: Span,
+    },
+    GetQueueTracks {
+        result_tx: flume::Sender<Vec<Track>>,
+        span: Span,
+    },
     SetCurrent {
         position: u32,
         span: Span,
@@ -194,6 +346,23 @@ pub enum PlaybackMessage {
     ToggleRepeat {
         span: Span,
     },
+    ToggleCrossfade {
+        span: Span,
+    },
+    SetReplayGainMode {
+        mode: ReplayGainMode,
+        span: Span,
+    },
+    GetDspPresets {
+        result_tx: flume::Sender<GetDspPresetsResponse>,
+        span: Span,
+    },
+    // false if there is no preset of the name
+    SetDspPreset {
+        name: Option<String>,
+        result_tx: flume::Sender<bool>,
+        span: Span,
+    },
     TogglePlay {
         span: Span,
     },
@@ -210,12 +379,24 @@ pub enum PlaybackMessage {
     Next {
         span: Span,
     },
+    // the player went on with the preloaded track by itself
+    NextStarted {
+        span: Span,
+    },
     Prev {
         span: Span,
     },
     RestartTrack {
         span: Span,
     },
+    Seek {
+        position: u32,
+        span: Span,
+    },
+    SeekBy {
+        offset: i32,
+        span: Span,
+    },
     StateChanged {
         state: PlayState,
         span: Span,
@@ -229,9 +410,42 @@ pub enum PlaybackMessage {
         muted: bool,
         span: Span,
     },
+    SaveState {
+        result_tx: flume::Sender<()>,
+        span: Span,
+    },
     PostitionChanged {
         duration: u32,
         position: u32,
         span: Span,
     },
+    StreamTitleChanged {
+        title: String,
+        span: Span,
+    },
+}
+
+impl PlaybackMessage {
+    /// Whether handling the message changes the state that is restored after a restart
+    fn changes_state(&self) -> bool {
+        matches!(
+            self,
+            PlaybackMessage::Replace { .. }
+                | PlaybackMessage::Queue { .. }
+                | PlaybackMessage::Append { .. }
+                | PlaybackMessage::Remove { .. }
+                | PlaybackMessage::Insert { .. }
+                | PlaybackMessage::Clear { .. }
+                | PlaybackMessage::SetCurrent { .. }
+                | PlaybackMessage::ToggleShuffle { .. }
+                | PlaybackMessage::ToggleRepeat { .. }
+                | PlaybackMessage::ToggleCrossfade { .. }
+                | PlaybackMessage::SetReplayGainMode { .. }
+                | PlaybackMessage::SetDspPreset { .. }
+                | PlaybackMessage::ChangeVolume { .. }
+                | PlaybackMessage::Next { .. }
+                | PlaybackMessage::NextStarted { .. }
+                | PlaybackMessage::Prev { .. }
+        )
+    }
 }
@@ -1,17 +1,32 @@
+use crate::state::PlaybackState;
 use crate::PlaybackMessage;
 use crate::ProviderMessage;
-use audio_player::Player;
+use audio_player::{Filter, Player};
 use crabidy_core::proto::crabidy::QueueModifiers;
 use crabidy_core::proto::crabidy::{
-    get_update_stream_response::Update as StreamUpdate, InitResponse, PlayState, QueueTrack, Track,
-    TrackPosition,
+    get_update_stream_response::Update as StreamUpdate, GetDspPresetsResponse, InitResponse,
+    PlayState, QueueTrack, Track, TrackPosition,
 };
 use crabidy_core::ProviderError;
-use crabidy_server::QueueManager;
+use crabidy_server::{is_same_album, with_stream_title, QueueManager};
+use std::collections::BTreeMap;
+use std::path::PathBuf;
 use std::sync::Mutex;
+use std::time::Duration;
 use tracing::debug_span;
 use tracing::{debug, error, instrument, trace, warn, Instrument};
 
+// Positions are passed on to the provider when they moved this many milliseconds, providers that
+// resume tracks would be flooded with every update of the player otherwise
+const POSITION_SAVE_INTERVAL: u32 = 5000;
+// Tracks that were barely started are not resumed
+const MIN_SAVED_POSITION: u32 = 5000;
+// The next track is preloaded this many milliseconds before the playing one ends, early enough
+// for slow connections while the urls some providers hand out expire after a while
+const PRELOAD_AHEAD: u32 = 30000;
+// Longer crossfades would start before the next track is preloaded
+const MAX_CROSSFADE: Duration = Duration::from_secs(20);
+
 pub struct Playback {
     update_tx: tokio::sync::broadcast::Sender<StreamUpdate>,
     provider_tx: flume::Sender<ProviderMessage>,
@@ -19,6 +34,18 @@ pub struct Playback {
     playback_rx: flume::Receiver<PlaybackMessage>,
     queue: Mutex<QueueManager>,
     state: Mutex<PlayState>,
+    // the title announced by the live stream that is playing
+    stream_title: Mutex<Option<String>>,
+    // the position of the playing track last passed on to its provider, in milliseconds
+    saved_position: Mutex<u32>,
+    // the track the player was told to preload
+    preloaded: Mutex<Option<String>>,
+    crossfade: Duration,
+    presets: BTreeMap<String, Vec<Filter>>,
+    // the preset the audio is filtered with
+    dsp_preset: Mutex<Option<String>>,
+    state_path: PathBuf,
+    restored_volume: Option<f32>,
     pub player: Player,
 }
 
@@ -26,10 +53,22 @@ impl Playback {
     pub fn new(
         update_tx: tokio::sync::broadcast::Sender<StreamUpdate>,
         provider_tx: flume::Sender<ProviderMessage>,
+        state_path: PathBuf,
+        crossfade: Duration,
+        presets: BTreeMap<String, Vec<Filter>>,
     ) -> Self {
         let (playback_tx, playback_rx) = flume::bounded(10);
-        let queue = Mutex::new(QueueManager::new());
+        let (queue, restored_volume, dsp_preset) = match PlaybackState::load(&state_path) {
+            Some(restored) => (restored.queue, Some(restored.volume), restored.dsp_preset),
+            None => (QueueManager::new(), None, None),
+        };
+        // presets may have been removed from the config since
+        let dsp_preset = Mutex::new(dsp_preset.filter(|name| presets.contains_key(name)));
+        let queue = Mutex::new(queue);
         let state = Mutex::new(PlayState::Stopped);
+        let stream_title = Mutex::new(None);
+        let saved_position = Mutex::new(0);
+        let preloaded = Mutex::new(None);
         let player = Player::default();
         Self {
             update_tx,
@@ -38,18 +77,40 @@ impl Playback {
             playback_rx,
             queue,
             state,
+            stream_title,
+            saved_position,
+            preloaded,
+            crossfade: crossfade.min(MAX_CROSSFADE),
+            presets,
+            dsp_preset,
+            state_path,
+            restored_volume,
             player,
         }
     }
 
     pub fn run(self) {
         tokio::spawn(async move {
+            if let Some(volume) = self.restored_volume {
+                if let Err(err) = self.player.set_volume(volume).await {
+                    error!("failed to restore volume: {:?}", err)
+                }
+            }
+            if let Some(filters) = self.dsp_filters() {
+                if let Err(err) = self.player.set_dsp(filters).await {
+                    error!("failed to restore dsp preset: {:?}", err)
+                }
+            }
             while let Ok(message) = self.playback_rx.recv_async().await {
+                let changes_state = message.changes_state();
                 match message {
                     PlaybackMessage::Init { result_tx, span } => {
                         let _e = span.enter();
+                        let volume = self.player.volume().await.unwrap_or_default();
                         let repeat;
                         let shuffle;
+                        let crossfade;
+                        let replay_gain;
                         let response = {
                             let Ok(queue) = self.queue.lock() else {
                                 error!("failed to get queue lock");
@@ -58,9 +119,11 @@ impl Playback {
                             debug!("got queue lock");
                             repeat = queue.repeat;
                             shuffle = queue.shuffle;
+                            crossfade = queue.crossfade;
+                            replay_gain = queue.replay_gain as i32;
                             let queue_track = QueueTrack {
                                 queue_position: queue.current_position() as u32,
-                                track: queue.current_track(),
+                                track: self.with_current_stream_title(queue.current_track()),
                             };
                             trace!("queue_track {:?}", queue_track);
                             debug!("released queue_track lock");
@@ -84,10 +147,16 @@ impl Playback {
                                 queue: Some(queue.clone().into()),
                                 queue_track: Some(queue_track),
                                 play_state: play_state as i32,
-                                volume: 0.0,
+                                volume,
                                 mute: false,
                                 position: Some(position),
-                                mods: Some(QueueModifiers { repeat, shuffle }),
+                                dsp_preset: self.dsp_preset.lock().ok().and_then(|p| p.clone()),
+                                mods: Some(QueueModifiers {
+                                    repeat,
+                                    shuffle,
+                                    crossfade,
+                                    replay_gain,
+                                }),
                             }
                         };
                         trace!("response {:?}", response);
@@ -100,8 +169,9 @@ impl Playback {
                         let mut all_tracks = Vec::new();
                         for uuid in uuids {
                             if is_track(&uuid) {
-                                if let Ok(track) = self.get_track(&uuid).in_current_span().await {
-                                    all_tracks.push(track);
+                                match self.get_track(&uuid).in_current_span().await {
+                                    Ok(track) => all_tracks.push(track),
+                                    Err(err) => self.report_error(&err),
                                 }
                             } else {
                                 let tracks = self.flatten_node(&uuid).in_current_span().await;
@@ -134,8 +204,9 @@ impl Playback {
                         let mut all_tracks = Vec::new();
                         for uuid in uuids {
                             if is_track(&uuid) {
-                                if let Ok(track) = self.get_track(&uuid).in_current_span().await {
-                                    all_tracks.push(track);
+                                match self.get_track(&uuid).in_current_span().await {
+                                    Ok(track) => all_tracks.push(track),
+                                    Err(err) => self.report_error(&err),
                                 }
                             } else {
                                 let tracks = self.flatten_node(&uuid).in_current_span().await;
@@ -167,8 +238,9 @@ impl Playback {
                         let mut all_tracks = Vec::new();
                         for uuid in uuids {
                             if is_track(&uuid) {
-                                if let Ok(track) = self.get_track(&uuid).in_current_span().await {
-                                    all_tracks.push(track);
+                                match self.get_track(&uuid).in_current_span().await {
+                                    Ok(track) => all_tracks.push(track),
+                                    Err(err) => self.report_error(&err),
                                 }
                             } else {
                                 let tracks = self.flatten_node(&uuid).in_current_span().await;
@@ -242,8 +314,9 @@ impl Playback {
                         let mut all_tracks = Vec::new();
                         for uuid in uuids {
                             if is_track(&uuid) {
-                                if let Ok(track) = self.get_track(&uuid).in_current_span().await {
-                                    all_tracks.push(track);
+                                match self.get_track(&uuid).in_current_span().await {
+                                    Ok(track) => all_tracks.push(track),
+                                    Err(err) => self.report_error(&err),
                                 }
                             } else {
                                 let tracks = self.flatten_node(&uuid).in_current_span().await;
@@ -297,6 +370,42 @@ impl Playback {
                         }
                     }
 
+                    PlaybackMessage::SaveQueue {
+                        name,
+                        result_tx,
+                        span,
+                    } => {
+                        let _e = span.enter();
+                        debug!("saving queue");
+                        let tracks = {
+                            let Ok(queue) = self.queue.lock() else {
+                                debug!("got queue lock");
+                                continue;
+                            };
+                            debug!("got queue lock");
+                            queue.tracks().to_vec()
+                        };
+                        debug!("queue lock released");
+                        let result = self.save_playlist(name, tracks).in_current_span().await;
+                        if let Err(err) = result_tx.send(result) {
+                            error!("failed to send response: {:#?}", err);
+                        }
+                    }
+
+                    PlaybackMessage::GetQueueTracks { result_tx, span } => {
+                        let _e = span.enter();
+                        let tracks = {
+                            let Ok(queue) = self.queue.lock() else {
+                                error!("poisend queue lock");
+                                continue;
+                            };
+                            queue.tracks().to_vec()
+                        };
+                        if let Err(err) = result_tx.send(tracks) {
+                            error!("failed to send response: {:#?}", err);
+                        }
+                    }
+
                     PlaybackMessage::SetCurrent {
                         position: queue_position,
                         span,
@@ -321,6 +430,8 @@ impl Playback {
                         debug!("toggling shuffle");
                         let shuffle;
                         let repeat;
+                        let crossfade;
+                        let replay_gain;
                         {
                             let Ok(mut queue) = self.queue.lock() else {
                                 debug!("got queue lock");
@@ -328,6 +439,8 @@ impl Playback {
                             };
                             debug!("got queue lock");
                             repeat = queue.repeat;
+                            crossfade = queue.crossfade;
+                            replay_gain = queue.replay_gain as i32;
                             if queue.shuffle {
                                 queue.shuffle_off()
                             } else {
@@ -337,7 +450,12 @@ impl Playback {
                         }
                         debug!("queue lock released");
                         let queue_update_tx = self.update_tx.clone();
-                        let update = StreamUpdate::Mods(QueueModifiers { shuffle, repeat });
+                        let update = StreamUpdate::Mods(QueueModifiers {
+                            shuffle,
+                            repeat,
+                            crossfade,
+                            replay_gain,
+                        });
                         if let Err(err) = queue_update_tx.send(update) {
                             trace!("{:?}", err)
                         }
@@ -348,6 +466,8 @@ impl Playback {
                         debug!("toggling repeat");
                         let shuffle;
                         let repeat;
+                        let crossfade;
+                        let replay_gain;
                         {
                             let Ok(mut queue) = self.queue.lock() else {
                                 debug!("got queue lock");
@@ -355,6 +475,8 @@ impl Playback {
                             };
                             debug!("got queue lock");
                             shuffle = queue.shuffle;
+                            crossfade = queue.crossfade;
+                            replay_gain = queue.replay_gain as i32;
                             if queue.repeat {
                                 queue.repeat = false
                             } else {
@@ -364,12 +486,113 @@ impl Playback {
                         }
                         debug!("queue lock released");
                         let queue_update_tx = self.update_tx.clone();
-                        let update = StreamUpdate::Mods(QueueModifiers { shuffle, repeat });
+                        let update = StreamUpdate::Mods(QueueModifiers {
+                            shuffle,
+                            repeat,
+                            crossfade,
+                            replay_gain,
+                        });
                         if let Err(err) = queue_update_tx.send(update) {
                             trace!("{:?}", err)
                         }
                     }
 
+                    PlaybackMessage::ToggleCrossfade { span } => {
+                        let _e = span.enter();
+                        debug!("toggling crossfade");
+                        let mods = {
+                            let Ok(mut queue) = self.queue.lock() else {
+                                debug!("got queue lock");
+                                continue;
+                            };
+                            debug!("got queue lock");
+                            queue.crossfade = !queue.crossfade;
+                            QueueModifiers {
+                                shuffle: queue.shuffle,
+                                repeat: queue.repeat,
+                                crossfade: queue.crossfade,
+                                replay_gain: queue.replay_gain as i32,
+                            }
+                        };
+                        debug!("queue lock released");
+                        // the next track is preloaded again to be crossfaded or not
+                        self.forget_preloaded();
+                        let update = StreamUpdate::Mods(mods);
+                        if let Err(err) = self.update_tx.send(update) {
+                            trace!("{:?}", err)
+                        }
+                    }
+
+                    PlaybackMessage::GetDspPresets { result_tx, span } => {
+                        let _e = span.enter();
+                        let response = GetDspPresetsResponse {
+                            presets: self.presets.keys().cloned().collect(),
+                            active: self.dsp_preset.lock().ok().and_then(|p| p.clone()),
+                        };
+                        if let Err(err) = result_tx.send(response) {
+                            error!("failed to send response: {:#?}", err);
+                        }
+                    }
+
+                    PlaybackMessage::SetDspPreset {
+                        name,
+                        result_tx,
+                        span,
+                    } => {
+                        let _e = span.enter();
+                        debug!("setting dsp preset {:?}", name);
+                        let filters = match &name {
+                            Some(name) => self.presets.get(name).cloned(),
+                            None => Some(Vec::new()),
+                        };
+                        let Some(filters) = filters else {
+                            if let Err(err) = result_tx.send(false) {
+                                error!("failed to send response: {:#?}", err);
+                            }
+                            continue;
+                        };
+                        if let Err(err) = self.player.set_dsp(filters).in_current_span().await {
+                            error!("{:?}", err)
+                        }
+                        if let Ok(mut dsp_preset) = self.dsp_preset.lock() {
+                            dsp_preset.clone_from(&name);
+                        }
+                        let update = StreamUpdate::DspPreset(name.unwrap_or_default());
+                        if let Err(err) = self.update_tx.send(update) {
+                            trace!("{:?}", err)
+                        }
+                        if let Err(err) = result_tx.send(true) {
+                            error!("failed to send response: {:#?}", err);
+                        }
+                    }
+
+                    PlaybackMessage::SetReplayGainMode { mode, span } => {
+                        let _e = span.enter();
+                        debug!("setting replay gain mode to {:?}", mode);
+                        let mods = {
+                            let Ok(mut queue) = self.queue.lock() else {
+                                debug!("got queue lock");
+                                continue;
+                            };
+                            debug!("got queue lock");
+                            queue.replay_gain = mode;
+                            QueueModifiers {
+                                shuffle: queue.shuffle,
+                                repeat: queue.repeat,
+                                crossfade: queue.crossfade,
+                                replay_gain: queue.replay_gain as i32,
+                            }
+                        };
+                        debug!("queue lock released");
+                        // the playing track keeps its gain, the next one is preloaded with the
+                        // new one
+                        self.forget_preloaded();
+                        let update = StreamUpdate::Mods(mods);
+                        if let Err(err) = self.update_tx.send(update) {
+                            trace!("{:?}", err)
+                        }
+                    }
+
                     PlaybackMessage::TogglePlay { span } => {
                         let _e = span.enter();
                         debug!("toggling play");
@@ -386,6 +609,7 @@ impl Playback {
                                 if let Err(err) = self.player.pause().await {
                                     error!("{:?}", err)
                                 }
+                                self.flush_positions().in_current_span().await;
                             } else if let Err(err) = self.player.unpause().await {
                                 error!("{:?}", err)
                             }
@@ -399,6 +623,7 @@ impl Playback {
                         if let Err(err) = self.player.stop().await {
                             error!("{:?}", err)
                         }
+                        self.flush_positions().in_current_span().await;
                     }
 
                     PlaybackMessage::ChangeVolume { delta, span } => {
@@ -436,6 +661,46 @@ impl Playback {
                         self.play_or_stop(track).in_current_span().await;
                     }
 
+                    PlaybackMessage::NextStarted { span } => {
+                        let _e = span.enter();
+                        debug!("next track started");
+                        let preloaded = self.preloaded.lock().ok().and_then(|mut p| p.take());
+                        let (track, is_preloaded) = {
+                            let Ok(mut queue) = self.queue.lock() else {
+                                error!("poisend queue lock");
+                                continue;
+                            };
+                            let upcoming = queue.upcoming_track().map(|track| track.uuid);
+                            let is_preloaded = preloaded.is_some() && upcoming == preloaded;
+                            (queue.next_track(), is_preloaded)
+                        };
+                        debug!("released queue lock and got track {:?}", track);
+                        match track {
+                            Some(track) if is_preloaded => {
+                                if let Ok(mut stream_title) = self.stream_title.lock() {
+                                    *stream_title = None;
+                                }
+                                let queue_track = {
+                                    let Ok(queue) = self.queue.lock() else {
+                                        error!("poisend queue lock");
+                                        continue;
+                                    };
+                                    QueueTrack {
+                                        queue_position: queue.current_position() as u32,
+                                        track: queue.current_track(),
+                                    }
+                                };
+                                let update = StreamUpdate::QueueTrack(queue_track);
+                                if let Err(err) = self.update_tx.send(update) {
+                                    trace!("{:?}", err)
+                                }
+                                self.resume(&track.uuid).in_current_span().await;
+                            }
+                            // the queue changed after the track was preloaded
+                            track => self.play_or_stop(track).in_current_span().await,
+                        }
+                    }
+
                     PlaybackMessage::Prev { span } => {
                         let _e = span.enter();
                         debug!("preving");
@@ -474,11 +739,40 @@ impl Playback {
                     PlaybackMessage::RestartTrack { span } => {
                         let _e = span.enter();
                         debug!("restarting track");
+                        self.forget_preloaded();
                         if let Err(err) = self.player.restart().await {
                             error!("{:?}", err)
                         }
                     }
 
+                    PlaybackMessage::Seek { position, span } => {
+                        let _e = span.enter();
+                        debug!("seeking to {}ms", position);
+                        self.seek(Duration::from_millis(position.into()))
+                            .in_current_span()
+                            .await;
+                    }
+
+                    PlaybackMessage::SeekBy { offset, span } => {
+                        let _e = span.enter();
+                        debug!("seeking by {}ms", offset);
+                        let elapsed = match self.player.elapsed().in_current_span().await {
+                            Ok(elapsed) => elapsed,
+                            Err(err) => {
+                                self.report_seek_error(&err);
+                                continue;
+                            }
+                        };
+                        let back = offset < 0;
+                        let offset = Duration::from_millis(offset.unsigned_abs().into());
+                        let position = if back {
+                            elapsed.saturating_sub(offset)
+                        } else {
+                            elapsed + offset
+                        };
+                        self.seek(position).in_current_span().await;
+                    }
+
                     PlaybackMessage::VolumeChanged { volume, span } => {
                         let _e = span.enter();
                         trace!("volume changed");
@@ -511,12 +805,94 @@ impl Playback {
                         if let Err(err) = update_tx.send(update) {
                             trace!("{:?}", err)
                         }
+                        self.save_position(duration, position)
+                            .in_current_span()
+                            .await;
+                        self.preload_next(duration, position)
+                            .in_current_span()
+                            .await;
+                    }
+
+                    PlaybackMessage::StreamTitleChanged { title, span } => {
+                        let _e = span.enter();
+                        debug!("stream title changed to {}", title);
+                        if let Ok(mut stream_title) = self.stream_title.lock() {
+                            *stream_title = Some(title);
+                        }
+                        let queue_track = {
+                            let Ok(queue) = self.queue.lock() else {
+                                error!("poisend queue lock");
+                                continue;
+                            };
+                            QueueTrack {
+                                queue_position: queue.current_position() as u32,
+                                track: self.with_current_stream_title(queue.current_track()),
+                            }
+                        };
+                        let update_tx = self.update_tx.clone();
+                        let update = StreamUpdate::QueueTrack(queue_track);
+                        if let Err(err) = update_tx.send(update) {
+                            trace!("{:?}", err)
+                        }
+                    }
+
+                    PlaybackMessage::SaveState { result_tx, span } => {
+                        let _e = span.enter();
+                        debug!("saving state");
+                        self.save_state().in_current_span().await;
+                        if let Err(err) = result_tx.send(()) {
+                            error!("failed to send response: {:#?}", err);
+                        }
                     }
                 }
+                if changes_state {
+                    self.save_state().await;
+                }
             }
         });
     }
 
+    #[instrument(skip(self))]
+    async fn save_state(&self) {
+        let volume = match self.player.volume().in_current_span().await {
+            Ok(volume) => volume,
+            Err(err) => {
+                warn!("failed to get volume: {:?}", err);
+                1.0
+            }
+        };
+        let queue = {
+            let Ok(queue) = self.queue.lock() else {
+                error!("poisend queue lock");
+                return;
+            };
+            queue.clone()
+        };
+        let dsp_preset = self.dsp_preset.lock().ok().and_then(|p| p.clone());
+        let state = PlaybackState {
+            queue,
+            volume,
+            dsp_preset,
+        };
+        if let Err(err) = state.save(&self.state_path).in_current_span().await {
+            error!("failed to save playback state: {}", err);
+        }
+    }
+
+    /// The filters of the preset in use
+    fn dsp_filters(&self) -> Option<Vec<Filter>> {
+        let dsp_preset = self.dsp_preset.lock().ok()?;
+        self.presets.get(dsp_preset.as_ref()?).cloned()
+    }
+
+    fn with_current_stream_title(&self, track: Option<Track>) -> Option<Track> {
+        let stream_title = self.stream_title.lock().ok()?.clone();
+        match (track, stream_title) {
+            (Some(track), Some(title)) => Some(with_stream_title(track, &title)),
+            (track, _) => track,
+        }
+    }
+
     #[instrument(skip(self))]
     async fn flatten_node(&self, uuid: &str) -> Vec<Track> {
         debug!("flattening node");
@@ -539,6 +915,14 @@ impl Playback {
         tracks
     }
 
+    /// Tells the clients about errors they would not learn about otherwise
+    fn report_error(&self, err: &ProviderError) {
+        warn!("{}", err);
+        if let Err(err) = self.update_tx.send(StreamUpdate::Error(err.to_string())) {
+            trace!("{:?}", err)
+        }
+    }
+
     #[instrument(skip(self))]
     async fn get_track(&self, uuid: &str) -> Result<Track, ProviderError> {
         debug!("getting track");
@@ -552,12 +936,12 @@ impl Playback {
         })
         .in_current_span()
         .await
-        .map_err(|_| ProviderError::InternalError)?;
+        .map_err(|_| ProviderError::InternalError("provider channel closed".to_string()))?;
         result_rx
             .recv_async()
             .in_current_span()
             .await
-            .map_err(|_| ProviderError::InternalError)?
+            .map_err(|_| ProviderError::InternalError("provider channel closed".to_string()))?
     }
 
     #[instrument(skip(self))]
@@ -573,12 +957,38 @@ impl Playback {
         })
         .in_current_span()
         .await
-        .map_err(|_| ProviderError::InternalError)?;
+        .map_err(|_| ProviderError::InternalError("provider channel closed".to_string()))?;
         result_rx
             .recv_async()
             .in_current_span()
             .await
-            .map_err(|_| ProviderError::InternalError)?
+            .map_err(|_| ProviderError::InternalError("provider channel closed".to_string()))?
+    }
+
+    #[instrument(skip(self, tracks))]
+    async fn save_playlist(
+        &self,
+        name: String,
+        tracks: Vec<Track>,
+    ) -> Result<String, ProviderError> {
+        debug!("saving playlist");
+        let tx = self.provider_tx.clone();
+        let (result_tx, result_rx) = flume::bounded(1);
+        let span = tracing::trace_span!("prov-chan");
+        tx.send_async(ProviderMessage::SavePlaylist {
+            name,
+            tracks,
+            result_tx,
+            span,
+        })
+        .in_current_span()
+        .await
+        .map_err(|_| ProviderError::InternalError("provider channel closed".to_string()))?;
+        result_rx
+            .recv_async()
+            .in_current_span()
+            .await
+            .map_err(|_| ProviderError::InternalError("provider channel closed".to_string()))?
     }
 
     #[instrument(skip(self))]
@@ -590,7 +1000,7 @@ impl Playback {
                 match self.get_urls_for_track(&uuid).in_current_span().await {
                     Ok(urls) => break urls,
                     Err(err) => {
-                        warn!("no urls found for track {:?}: {}", track.uuid, err);
+                        self.report_error(&err);
                         uuid = {
                             let Ok(mut queue) = self.queue.lock() else {
                                 debug!("got queue lock");
@@ -605,13 +1015,19 @@ impl Playback {
                     }
                 }
             };
-            {
+            if let Ok(mut stream_title) = self.stream_title.lock() {
+                *stream_title = None;
+            }
+            let gain = {
                 let Ok(queue) = self.queue.lock() else {
                     error!("poisend queue lock");
                     return
                 };
                 let queue_update_tx = self.update_tx.clone();
                 let track = queue.current_track();
+                let gain = track
+                    .as_ref()
+                    .map_or(1.0, |track| track.normalization_gain(queue.replay_gain));
                 let update = StreamUpdate::QueueTrack(QueueTrack {
                     queue_position: queue.current_position() as u32,
                     track,
@@ -619,12 +1035,18 @@ impl Playback {
                 if let Err(err) = queue_update_tx.send(update) {
                     trace!("{:?}", err)
                 }
+                gain
+            };
+            self.forget_preloaded();
+            match self.player.play(&urls[0], gain).await {
+                Ok(_) => self.resume(&uuid).in_current_span().await,
+                Err(err) => error!("{:?}", err),
             }
-            if let Err(err) = self.player.play(&urls[0]).await {
+        } else {
+            if let Err(err) = self.player.stop().await {
                 error!("{:?}", err)
-            };
-        } else if let Err(err) = self.player.stop().await {
-            error!("{:?}", err)
+            }
+            self.flush_positions().in_current_span().await;
         }
     }
 
@@ -637,7 +1059,7 @@ impl Playback {
                 match self.get_urls_for_track(&uuid).in_current_span().await {
                     Ok(urls) => break urls,
                     Err(err) => {
-                        warn!("no urls found for track {:?}: {}", track.uuid, err);
+                        self.report_error(&err);
                         uuid = {
                             let Ok(mut queue) = self.queue.lock() else {
                                 debug!("poisend queue lock");
@@ -652,13 +1074,19 @@ impl Playback {
                     }
                 }
             };
-            {
+            if let Ok(mut stream_title) = self.stream_title.lock() {
+                *stream_title = None;
+            }
+            let gain = {
                 let Ok(queue) = self.queue.lock() else {
                     error!("poisend queue lock");
                     return
                 };
                 let queue_update_tx = self.update_tx.clone();
                 let track = queue.current_track();
+                let gain = track
+                    .as_ref()
+                    .map_or(1.0, |track| track.normalization_gain(queue.replay_gain));
                 let update = StreamUpdate::QueueTrack(QueueTrack {
                     queue_position: queue.current_position() as u32,
                     track,
@@ -666,12 +1094,194 @@ impl Playback {
                 if let Err(err) = queue_update_tx.send(update) {
                     trace!("{:?}", err)
                 }
+                gain
+            };
+            self.forget_preloaded();
+            match self.player.play(&urls[0], gain).await {
+                Ok(_) => self.resume(&uuid).in_current_span().await,
+                Err(err) => error!("{:?}", err),
             }
-            if let Err(err) = self.player.play(&urls[0]).await {
+        }
+    }
+
+    /// Lets the player open the track that follows the playing one shortly before it ends, so it
+    /// follows without a gap. Checked with every position update as the queue may change.
+    #[instrument(skip(self))]
+    async fn preload_next(&self, duration: u32, position: u32) {
+        // live streams do not end
+        if duration == 0 || PRELOAD_AHEAD < duration.saturating_sub(position) {
+            return;
+        }
+        let (upcoming, crossfade, gain) = {
+            let Ok(queue) = self.queue.lock() else {
+                error!("poisend queue lock");
+                return;
+            };
+            let upcoming = queue.upcoming_track();
+            // tracks of the same album, e.g. of a live album, are meant to follow each other
+            let crossfade = match (queue.current_track(), &upcoming) {
+                (Some(current), Some(upcoming))
+                    if queue.crossfade && !is_same_album(&current, upcoming) =>
+                {
+                    self.crossfade
+                }
+                _ => Duration::ZERO,
+            };
+            let gain = upcoming
+                .as_ref()
+                .map_or(1.0, |track| track.normalization_gain(queue.replay_gain));
+            (upcoming.map(|track| track.uuid), crossfade, gain)
+        };
+        {
+            let Ok(mut preloaded) = self.preloaded.lock() else {
+                error!("poisend preloaded lock");
+                return;
+            };
+            if *preloaded == upcoming {
+                return;
+            }
+            preloaded.clone_from(&upcoming);
+        }
+        let url = match upcoming {
+            Some(uuid) => match self.get_urls_for_track(&uuid).in_current_span().await {
+                Ok(urls) => urls.into_iter().next(),
+                Err(err) => {
+                    // the track is skipped once it is due
+                    warn!("failed to preload {}: {}", uuid, err);
+                    None
+                }
+            },
+            None => None,
+        };
+        debug!("preloading {:?} with a crossfade of {:?}", url, crossfade);
+        if let Err(err) = self.player.preload(url.as_deref(), crossfade, gain).await {
+            error!("{:?}", err)
+        }
+    }
+
+    /// The player drops the preloaded track when it plays another one
+    fn forget_preloaded(&self) {
+        if let Ok(mut preloaded) = self.preloaded.lock() {
+            *preloaded = None;
+        }
+    }
+
+    /// Continues the track that just started where it was stopped, if its provider remembers it
+    #[instrument(skip(self))]
+    async fn resume(&self, uuid: &str) {
+        // where the track that played before was left
+        self.flush_positions().in_current_span().await;
+        let tx = self.provider_tx.clone();
+        let (result_tx, result_rx) = flume::bounded(1);
+        let span = tracing::trace_span!("prov-chan");
+        let position = match tx
+            .send_async(ProviderMessage::GetResumePosition {
+                uuid: uuid.to_string(),
+                result_tx,
+                span,
+            })
+            .in_current_span()
+            .await
+        {
+            Ok(_) => result_rx
+                .recv_async()
+                .in_current_span()
+                .await
+                .ok()
+                .flatten(),
+            Err(err) => {
+                error!("failed to send message: {}", err);
+                None
+            }
+        };
+        if let Ok(mut saved_position) = self.saved_position.lock() {
+            *saved_position = position.map_or(0, |p| p.as_millis() as u32);
+        }
+        if let Some(position) = position {
+            debug!("resuming at {:?}", position);
+            if let Err(err) = self.player.seek_to(position).await {
                 error!("{:?}", err)
             }
         }
     }
+
+    /// Seeks in the playing track and reports the position it goes on at, the player does not
+    /// seek past the end
+    #[instrument(skip(self))]
+    async fn seek(&self, position: Duration) {
+        let position = match self.player.seek_to(position).in_current_span().await {
+            Ok(position) => position,
+            Err(err) => {
+                self.report_seek_error(&err);
+                return;
+            }
+        };
+        let duration = self.player.duration().in_current_span().await;
+        let update = StreamUpdate::Position(TrackPosition {
+            duration: duration.map_or(0, |d| d.as_millis() as u32),
+            position: position.as_millis() as u32,
+        });
+        if let Err(err) = self.update_tx.send(update) {
+            trace!("{:?}", err)
+        }
+    }
+
+    fn report_seek_error(&self, err: &anyhow::Error) {
+        warn!("failed to seek: {}", err);
+        let update = StreamUpdate::Error(format!("Could not seek: {}", err));
+        if let Err(err) = self.update_tx.send(update) {
+            trace!("{:?}", err)
+        }
+    }
+
+    #[instrument(skip(self))]
+    async fn save_position(&self, duration: u32, position: u32) {
+        if position < MIN_SAVED_POSITION {
+            return;
+        }
+        {
+            let Ok(mut saved_position) = self.saved_position.lock() else {
+                error!("poisend saved position lock");
+                return;
+            };
+            if position.abs_diff(*saved_position) < POSITION_SAVE_INTERVAL {
+                return;
+            }
+            *saved_position = position;
+        }
+        let Some(track) = self.queue.lock().ok().and_then(|q| q.current_track()) else {
+            return;
+        };
+        let span = tracing::trace_span!("prov-chan");
+        if let Err(err) = self
+            .provider_tx
+            .send_async(ProviderMessage::SavePosition {
+                uuid: track.uuid,
+                position: Duration::from_millis(position.into()),
+                // unknown for live streams
+                duration: (duration != 0).then(|| Duration::from_millis(duration.into())),
+                span,
+            })
+            .in_current_span()
+            .await
+        {
+            error!("failed to send message: {}", err);
+        }
+    }
+
+    /// Lets the providers write the positions they held back, when playback stops or moves on to
+    /// another track
+    async fn flush_positions(&self) {
+        let span = tracing::trace_span!("prov-chan");
+        if let Err(err) = self
+            .provider_tx
+            .send_async(ProviderMessage::FlushPositions { span })
+            .in_current_span()
+            .await
+        {
+            error!("failed to send message: {}", err);
+        }
+    }
 }
 
 fn is_track(uuid: &str) -> bool {
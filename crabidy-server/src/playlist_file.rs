@@ -0,0 +1,276 @@
+//! Playlist files to move playlists between crabidy and other players. Local tracks are written
+//! with their path, the tracks of all other providers with their uuid, since their stream urls
+//! expire. Artist and title are always written, so entries can also be found by searching for
+//! them.
+use crabidy_core::proto::crabidy::{PlaylistFormat, Track};
+use std::path::Path;
+use url::Url;
+
+/// An entry of an imported playlist, with everything that can help to find its track
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct PlaylistEntry {
+    /// A path, url or track uuid
+    pub location: Option<String>,
+    /// The track uuid, XSPF keeps it next to the location
+    pub identifier: Option<String>,
+    pub artist: Option<String>,
+    pub title: Option<String>,
+}
+
+impl PlaylistEntry {
+    /// How the entry is reported when no track is found for it
+    pub fn describe(&self) -> String {
+        match (&self.artist, &self.title, &self.location) {
+            (Some(artist), Some(title), _) => format!("{} - {}", artist, title),
+            (None, Some(title), _) => title.clone(),
+            (_, None, Some(location)) => location.clone(),
+            _ => self.identifier.clone().unwrap_or_default(),
+        }
+    }
+}
+
+pub fn export(format: PlaylistFormat, title: &str, tracks: &[Track]) -> String {
+    match format {
+        PlaylistFormat::Xspf => write_xspf(title, tracks),
+        PlaylistFormat::M3u | PlaylistFormat::Unspecified => write_m3u(title, tracks),
+    }
+}
+
+/// Parses the playlist, an unspecified format is recognized from the content
+pub fn import(format: PlaylistFormat, content: &str) -> Result<Vec<PlaylistEntry>, String> {
+    let format = match format {
+        PlaylistFormat::Unspecified if content.trim_start().starts_with('<') => {
+            PlaylistFormat::Xspf
+        }
+        PlaylistFormat::Unspecified => PlaylistFormat::M3u,
+        format => format,
+    };
+    match format {
+        PlaylistFormat::Xspf => parse_xspf(content),
+        _ => Ok(parse_m3u(content)),
+    }
+}
+
+fn write_m3u(title: &str, tracks: &[Track]) -> String {
+    let mut m3u = format!("#EXTM3U\n#PLAYLIST:{}\n", title);
+    for track in tracks {
+        // -1 is the duration of tracks whose duration is not known
+        let secs = track.duration.map_or(-1, |ms| (ms / 1000) as i64);
+        m3u.push_str(&format!(
+            "#EXTINF:{},{} - {}\n{}\n",
+            secs,
+            track.artist,
+            track.title,
+            location(track)
+        ));
+    }
+    m3u
+}
+
+fn parse_m3u(content: &str) -> Vec<PlaylistEntry> {
+    let mut entries = Vec::new();
+    let mut info = None;
+    for line in content.lines().map(str::trim) {
+        if let Some(extinf) = line.strip_prefix("#EXTINF:") {
+            // the display name follows the first comma, `Artist - Title` by convention
+            let name = extinf.split_once(',').map_or("", |(_, name)| name.trim());
+            info = Some(match name.split_once(" - ") {
+                Some((artist, title)) => (Some(artist.trim()), Some(title.trim())),
+                None if name.is_empty() => (None, None),
+                None => (None, Some(name)),
+            });
+        } else if !line.is_empty() && !line.starts_with('#') {
+            let (artist, title) = info.take().unwrap_or((None, None));
+            entries.push(PlaylistEntry {
+                location: Some(local_path(line).unwrap_or_else(|| line.to_string())),
+                identifier: None,
+                artist: artist.map(str::to_string),
+                title: title.map(str::to_string),
+            });
+        }
+    }
+    entries
+}
+
+fn write_xspf(title: &str, tracks: &[Track]) -> String {
+    let mut xspf = format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+         <playlist version=\"1\" xmlns=\"http://xspf.org/ns/0/\">\n  \
+         <title>{}</title>\n  <trackList>\n",
+        escape(title)
+    );
+    for track in tracks {
+        xspf.push_str("    <track>\n");
+        // locations are URIs in XSPF
+        let location = local_path_of(track)
+            .and_then(|path| Url::from_file_path(path).ok())
+            .map_or(track.uuid.clone(), |url| url.to_string());
+        xspf.push_str(&format!(
+            "      <location>{}</location>\n",
+            escape(&location)
+        ));
+        xspf.push_str(&format!(
+            "      <identifier>{}</identifier>\n",
+            escape(&track.uuid)
+        ));
+        xspf.push_str(&format!("      <title>{}</title>\n", escape(&track.title)));
+        xspf.push_str(&format!(
+            "      <creator>{}</creator>\n",
+            escape(&track.artist)
+        ));
+        if let Some(album) = &track.album {
+            xspf.push_str(&format!("      <album>{}</album>\n", escape(&album.title)));
+        }
+        if let Some(duration) = track.duration {
+            xspf.push_str(&format!("      <duration>{}</duration>\n", duration));
+        }
+        xspf.push_str("    </track>\n");
+    }
+    xspf.push_str("  </trackList>\n</playlist>\n");
+    xspf
+}
+
+fn parse_xspf(content: &str) -> Result<Vec<PlaylistEntry>, String> {
+    let doc = roxmltree::Document::parse(content).map_err(|err| err.to_string())?;
+    let root = doc.root_element();
+    if root.tag_name().name() != "playlist" {
+        return Err(format!("not a XSPF playlist: <{}>", root.tag_name().name()));
+    }
+    let text = |node: roxmltree::Node, name: &str| {
+        node.children()
+            .find(|n| n.is_element() && n.tag_name().name() == name)
+            .and_then(|n| n.text())
+            .map(str::trim)
+            .filter(|text| !text.is_empty())
+            .map(str::to_string)
+    };
+    let entries = root
+        .descendants()
+        .filter(|n| n.is_element() && n.tag_name().name() == "track")
+        .map(|track| PlaylistEntry {
+            location: text(track, "location")
+                .map(|location| local_path(&location).unwrap_or(location)),
+            identifier: text(track, "identifier"),
+            artist: text(track, "creator"),
+            title: text(track, "title"),
+        })
+        .collect();
+    Ok(entries)
+}
+
+fn location(track: &Track) -> String {
+    local_path_of(track).map_or(track.uuid.clone(), str::to_string)
+}
+
+fn local_path_of(track: &Track) -> Option<&str> {
+    track.uuid.strip_prefix("track:local:")
+}
+
+/// The path of entries that are local files, either as a path or as a `file://` url
+fn local_path(location: &str) -> Option<String> {
+    if location.starts_with("file://") {
+        let url = Url::parse(location).ok()?;
+        return Some(url.to_file_path().ok()?.to_string_lossy().to_string());
+    }
+    Path::new(location)
+        .is_absolute()
+        .then(|| location.to_string())
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crabidy_core::proto::crabidy::Album;
+
+    fn tracks() -> Vec<Track> {
+        vec![
+            Track {
+                uuid: "track:local:/music/Crabs & Co/shell song.flac".to_string(),
+                artist: "Crabs & Co".to_string(),
+                title: "Shell <Song>".to_string(),
+                duration: Some(185_000),
+                album: Some(Album {
+                    title: "Beach".to_string(),
+                    release_date: None,
+                    artwork: Vec::new(),
+                    uuid: None,
+                }),
+                artwork: Vec::new(),
+                ..Default::default()
+            },
+            Track {
+                uuid: "track:tidal:1234".to_string(),
+                artist: "Lobster".to_string(),
+                title: "Claws".to_string(),
+                duration: None,
+                album: None,
+                artwork: Vec::new(),
+                ..Default::default()
+            },
+        ]
+    }
+
+    fn expected() -> Vec<PlaylistEntry> {
+        vec![
+            PlaylistEntry {
+                location: Some("/music/Crabs & Co/shell song.flac".to_string()),
+                identifier: None,
+                artist: Some("Crabs & Co".to_string()),
+                title: Some("Shell <Song>".to_string()),
+            },
+            PlaylistEntry {
+                location: Some("track:tidal:1234".to_string()),
+                identifier: None,
+                artist: Some("Lobster".to_string()),
+                title: Some("Claws".to_string()),
+            },
+        ]
+    }
+
+    #[test]
+    fn m3u_round_trips() {
+        let m3u = export(PlaylistFormat::M3u, "Beach", &tracks());
+        assert_eq!(
+            m3u,
+            "#EXTM3U\n#PLAYLIST:Beach\n\
+             #EXTINF:185,Crabs & Co - Shell <Song>\n/music/Crabs & Co/shell song.flac\n\
+             #EXTINF:-1,Lobster - Claws\ntrack:tidal:1234\n"
+        );
+        assert_eq!(
+            import(PlaylistFormat::Unspecified, &m3u).unwrap(),
+            expected()
+        );
+    }
+
+    #[test]
+    fn xspf_round_trips() {
+        let xspf = export(PlaylistFormat::Xspf, "Beach", &tracks());
+        assert!(xspf
+            .contains("<location>file:///music/Crabs%20&amp;%20Co/shell%20song.flac</location>"));
+        let mut expected = expected();
+        expected[0].identifier = Some("track:local:/music/Crabs & Co/shell song.flac".to_string());
+        expected[1].identifier = Some("track:tidal:1234".to_string());
+        assert_eq!(
+            import(PlaylistFormat::Unspecified, &xspf).unwrap(),
+            expected
+        );
+    }
+
+    #[test]
+    fn foreign_playlists_are_imported() {
+        let m3u = "#EXTM3U\r\n#EXTINF:200,Only A Title\r\nrelative/song.mp3\r\n\r\nhttp://radio.example/stream\r\n";
+        let entries = import(PlaylistFormat::M3u, m3u).unwrap();
+        assert_eq!(entries[0].title.as_deref(), Some("Only A Title"));
+        assert_eq!(entries[0].location.as_deref(), Some("relative/song.mp3"));
+        assert_eq!(entries[1].describe(), "http://radio.example/stream");
+        assert!(import(PlaylistFormat::Xspf, "<html/>").is_err());
+    }
+}
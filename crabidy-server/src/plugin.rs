@@ -0,0 +1,362 @@
+//! Providers running as separate executables, talking the protocol of `crabidy_core::plugin`
+//! over their stdin and stdout. A plugin that exits, sends garbage or does not answer in time is
+//! killed and started again with the next request.
+use crate::config::PluginConfig;
+use async_trait::async_trait;
+use crabidy_core::{
+    plugin::{InitResult, PluginCall, PluginError, PluginRequest, PluginResponse},
+    proto::crabidy::{LibraryNode, Lyrics, ProviderCapabilities, SearchResult, Track},
+    ProviderClient, ProviderError,
+};
+use serde::de::DeserializeOwned;
+use std::{
+    process::Stdio,
+    sync::RwLock,
+    time::{Duration, Instant},
+};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader, Lines},
+    process::{Child, ChildStdin, ChildStdout, Command},
+    sync::Mutex,
+};
+use tracing::{debug, info, warn, Instrument};
+
+// A plugin that keeps crashing is not started more often than this
+const RESTART_DELAY: Duration = Duration::from_secs(5);
+
+#[derive(Debug)]
+pub struct PluginClient {
+    name: String,
+    config: PluginConfig,
+    settings: RwLock<String>,
+    root: RwLock<LibraryNode>,
+    capabilities: RwLock<ProviderCapabilities>,
+    // requests are sent one at a time, the process is only replaced while holding the lock
+    process: Mutex<Process>,
+}
+
+#[derive(Debug, Default)]
+struct Process {
+    running: Option<Running>,
+    started_at: Option<Instant>,
+}
+
+#[derive(Debug)]
+struct Running {
+    // killed when dropped
+    _child: Child,
+    stdin: ChildStdin,
+    stdout: Lines<BufReader<ChildStdout>>,
+    next_id: u64,
+}
+
+impl PluginClient {
+    /// Starts the plugin, `raw_toml_settings` are handed to it with the `init` request
+    pub async fn spawn(
+        name: &str,
+        config: PluginConfig,
+        raw_toml_settings: &str,
+    ) -> Result<Self, ProviderError> {
+        let client = Self {
+            name: name.to_string(),
+            config,
+            settings: RwLock::new(raw_toml_settings.to_string()),
+            root: RwLock::new(LibraryNode::new()),
+            capabilities: RwLock::new(ProviderCapabilities::basic()),
+            process: Mutex::new(Process::default()),
+        };
+        {
+            let mut process = client.process.lock().await;
+            client.start(&mut process).in_current_span().await?;
+        }
+        Ok(client)
+    }
+
+    async fn start(&self, process: &mut Process) -> Result<(), ProviderError> {
+        if let Some(started_at) = process.started_at {
+            if started_at.elapsed() < RESTART_DELAY {
+                return Err(self.unavailable("restarted too often, trying again shortly"));
+            }
+        }
+        process.started_at = Some(Instant::now());
+        debug!("starting plugin {}: {:?}", self.name, self.config.command);
+        let mut child = Command::new(&self.config.command)
+            .args(&self.config.args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()
+            .map_err(|err| self.unavailable(err))?;
+        let (Some(stdin), Some(stdout), Some(stderr)) =
+            (child.stdin.take(), child.stdout.take(), child.stderr.take())
+        else {
+            return Err(ProviderError::InternalError(
+                "plugin without piped stdio".to_string(),
+            ));
+        };
+        let name = self.name.clone();
+        tokio::spawn(
+            async move {
+                let mut lines = BufReader::new(stderr).lines();
+                while let Ok(Some(line)) = lines.next_line().await {
+                    info!("plugin {}: {}", name, line);
+                }
+            }
+            .in_current_span(),
+        );
+        let mut running = Running {
+            _child: child,
+            stdin,
+            stdout: BufReader::new(stdout).lines(),
+            next_id: 0,
+        };
+        let settings = self.settings();
+        let init = PluginCall::Init {
+            name: self.name.clone(),
+            settings,
+        };
+        let result: InitResult = match self.exchange(&mut running, init).await {
+            Ok(result) => result.map_err(|err| err.into_provider_error(&self.name))?,
+            Err(cause) => return Err(self.unavailable(cause)),
+        };
+        if let Ok(mut settings) = self.settings.write() {
+            *settings = result.settings;
+        }
+        if let Ok(mut root) = self.root.write() {
+            *root = result.root;
+        }
+        if let Ok(mut capabilities) = self.capabilities.write() {
+            *capabilities = result
+                .capabilities
+                .unwrap_or_else(ProviderCapabilities::basic);
+        }
+        process.running = Some(running);
+        Ok(())
+    }
+
+    async fn call<T: DeserializeOwned>(&self, call: PluginCall) -> Result<T, ProviderError> {
+        let mut process = self.process.lock().await;
+        if process.running.is_none() {
+            self.start(&mut process).in_current_span().await?;
+        }
+        let Some(running) = process.running.as_mut() else {
+            return Err(self.unavailable("not running"));
+        };
+        match self.exchange(running, call).await {
+            Ok(result) => result.map_err(|err| err.into_provider_error(&self.name)),
+            Err(cause) => {
+                warn!("stopping plugin {}: {}", self.name, cause);
+                process.running = None;
+                Err(self.unavailable(cause))
+            }
+        }
+    }
+
+    /// Sends the request and reads its response. Fails if the plugin can not be talked to any
+    /// more, errors reported by the plugin are the inner result.
+    async fn exchange<T: DeserializeOwned>(
+        &self,
+        running: &mut Running,
+        call: PluginCall,
+    ) -> Result<Result<T, PluginError>, String> {
+        running.next_id += 1;
+        let id = running.next_id;
+        let mut request =
+            serde_json::to_string(&PluginRequest { id, call }).map_err(|err| err.to_string())?;
+        request.push('\n');
+        let exchange = async {
+            running
+                .stdin
+                .write_all(request.as_bytes())
+                .await
+                .map_err(|err| err.to_string())?;
+            running.stdin.flush().await.map_err(|err| err.to_string())?;
+            let line = running
+                .stdout
+                .next_line()
+                .await
+                .map_err(|err| err.to_string())?
+                .ok_or_else(|| "the plugin exited".to_string())?;
+            let response = serde_json::from_str::<PluginResponse<T>>(&line)
+                .map_err(|err| format!("invalid response: {}", err))?;
+            if response.id != id {
+                return Err(format!("expected response {}, got {}", id, response.id));
+            }
+            match (response.result, response.error) {
+                (_, Some(err)) => Ok(Err(err)),
+                (Some(result), None) => Ok(Ok(result)),
+                (None, None) => Err("response without result or error".to_string()),
+            }
+        };
+        let timeout = Duration::from_secs(self.config.timeout);
+        tokio::time::timeout(timeout, exchange)
+            .await
+            .map_err(|_| format!("no response within {} seconds", self.config.timeout))?
+    }
+
+    fn unavailable(&self, cause: impl ToString) -> ProviderError {
+        ProviderError::Unavailable {
+            provider: self.name.clone(),
+            cause: cause.to_string(),
+        }
+    }
+}
+
+#[async_trait]
+impl ProviderClient for PluginClient {
+    async fn init(_raw_toml_settings: &str) -> Result<Self, ProviderError> {
+        Err(ProviderError::Config(
+            "plugins are started with PluginClient::spawn".to_string(),
+        ))
+    }
+
+    fn settings(&self) -> String {
+        self.settings
+            .read()
+            .map(|settings| settings.clone())
+            .unwrap_or_default()
+    }
+
+    async fn get_urls_for_track(&self, track_uuid: &str) -> Result<Vec<String>, ProviderError> {
+        self.call(PluginCall::GetUrlsForTrack {
+            uuid: track_uuid.to_string(),
+        })
+        .in_current_span()
+        .await
+    }
+
+    async fn get_metadata_for_track(&self, track_uuid: &str) -> Result<Track, ProviderError> {
+        self.call(PluginCall::GetMetadataForTrack {
+            uuid: track_uuid.to_string(),
+        })
+        .in_current_span()
+        .await
+    }
+
+    fn get_lib_root(&self) -> LibraryNode {
+        self.root
+            .read()
+            .map(|root| root.clone())
+            .unwrap_or_else(|_| LibraryNode::new())
+    }
+
+    fn capabilities(&self) -> ProviderCapabilities {
+        self.capabilities
+            .read()
+            .map(|capabilities| capabilities.clone())
+            .unwrap_or_else(|_| ProviderCapabilities::basic())
+    }
+
+    async fn get_lib_node(&self, list_uuid: &str) -> Result<LibraryNode, ProviderError> {
+        let node: LibraryNode = self
+            .call(PluginCall::GetLibNode {
+                uuid: list_uuid.to_string(),
+            })
+            .in_current_span()
+            .await?;
+        if let Ok(mut root) = self.root.write() {
+            if root.uuid == node.uuid {
+                *root = node.clone();
+            }
+        }
+        Ok(node)
+    }
+
+    async fn search(&self, query: &str) -> Result<SearchResult, ProviderError> {
+        self.call(PluginCall::Search {
+            query: query.to_string(),
+        })
+        .in_current_span()
+        .await
+    }
+
+    async fn get_lyrics(&self, track_uuid: &str) -> Result<Option<Lyrics>, ProviderError> {
+        let lyrics: Lyrics = self
+            .call(PluginCall::GetLyrics {
+                uuid: track_uuid.to_string(),
+            })
+            .in_current_span()
+            .await?;
+        Ok(Some(lyrics).filter(|lyrics| !lyrics.lines.is_empty()))
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    // Answers init, get_lib_node and get_lyrics, exits when asked for track urls and gives up on
+    // searches
+    const PLUGIN: &str = r#"
+n=0
+while read -r line; do
+  n=$((n+1))
+  case "$line" in
+    *'"method":"init"'*)
+      printf '%s\n' '{"id":'$n',"result":{"settings":"token = \"abc\"\n","root":{"uuid":"node:echo","title":"Echo"},"capabilities":{"search":true,"lyrics":true}}}' ;;
+    *'"method":"get_lib_node"'*)
+      echo "started" >&2
+      printf '%s\n' '{"id":'$n',"result":{"uuid":"node:echo","title":"Echo","tracks":[{"uuid":"track:echo:1","title":"One"}]}}' ;;
+    *'"method":"get_metadata_for_track"'*)
+      printf '%s\n' '{"id":'$n',"error":{"kind":"not_found","uuid":"track:echo:2"}}' ;;
+    *'"method":"get_lyrics"'*)
+      printf '%s\n' '{"id":'$n',"result":{"synced":true,"lines":[{"start":1200,"text":"Echo"}]}}' ;;
+    *'"method":"get_urls_for_track"'*)
+      exit 1 ;;
+    *)
+      sleep 5 ;;
+  esac
+done
+"#;
+
+    fn config() -> PluginConfig {
+        PluginConfig {
+            command: "sh".to_string(),
+            args: vec!["-c".to_string(), PLUGIN.to_string()],
+            timeout: 1,
+        }
+    }
+
+    #[tokio::test]
+    async fn plugins_are_called_and_supervised() {
+        let client = PluginClient::spawn("echo", config(), "").await.unwrap();
+        assert_eq!(client.settings(), "token = \"abc\"\n");
+        assert_eq!(client.get_lib_root().title, "Echo");
+        assert!(client.capabilities().lyrics);
+        assert!(!client.capabilities().seek);
+        assert!(client.get_lib_root().tracks.is_empty());
+
+        let node = client.get_lib_node("node:echo").await.unwrap();
+        assert_eq!(node.tracks[0].title, "One");
+        assert_eq!(client.get_lib_root().tracks.len(), 1);
+
+        let lyrics = client.get_lyrics("track:echo:1").await.unwrap().unwrap();
+        assert_eq!(lyrics.line_at(Duration::from_secs(2)), Some(0));
+
+        let err = client.get_metadata_for_track("track:echo:2").await;
+        assert!(matches!(err, Err(ProviderError::NotFound { uuid }) if uuid == "track:echo:2"));
+
+        // the plugin exits, and is not started again right away
+        let err = client.get_urls_for_track("track:echo:1").await;
+        assert!(matches!(err, Err(ProviderError::Unavailable { .. })));
+        assert!(client.get_lib_node("node:echo").await.is_err());
+
+        client.process.lock().await.started_at = Some(Instant::now() - RESTART_DELAY);
+        assert!(client.get_lib_node("node:echo").await.is_ok());
+
+        // requests without an answer time out
+        let err = client.search("crab").await;
+        assert!(matches!(err, Err(ProviderError::Unavailable { .. })));
+    }
+
+    #[tokio::test]
+    async fn missing_executables_are_unavailable() {
+        let config = PluginConfig {
+            command: "/nonexistent/crabidy-plugin".to_string(),
+            ..config()
+        };
+        let err = PluginClient::spawn("missing", config, "").await;
+        assert!(matches!(err, Err(ProviderError::Unavailable { .. })));
+    }
+}
@@ -1,74 +1,319 @@
-use crate::ProviderMessage;
+use crate::{
+    cache::Cache,
+    config::{PluginConfig, ProvidersConfig},
+    playlist_file::PlaylistEntry,
+    plugin::PluginClient,
+    saved::SavedPlaylists,
+    state::data_dir,
+    ProviderMessage,
+};
 use async_trait::async_trait;
 use crabidy_core::{
-    proto::crabidy::{LibraryNode, LibraryNodeChild, Track},
-    ProviderClient, ProviderError,
+    proto::crabidy::{LibraryNode, LibraryNodeChild, Lyrics, Provider, SearchResult, Track},
+    ClapSerde, ProviderClient, ProviderError,
+};
+use futures::{future::join_all, stream, StreamExt};
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+    sync::{Arc, RwLock},
+    time::{Duration, Instant},
 };
-use std::{fs, path::PathBuf, sync::Arc};
 use tracing::{debug, error, instrument, warn, Instrument};
 
+const CACHE_PERSIST_INTERVAL: Duration = Duration::from_secs(5 * 60);
+// Playlist entries resolved at the same time when importing a playlist
+const RESOLVE_CONCURRENCY: usize = 8;
+
 #[derive(Debug)]
 pub struct ProviderOrchestrator {
     pub provider_tx: flume::Sender<ProviderMessage>,
     provider_rx: flume::Receiver<ProviderMessage>,
-    // known_tracks: RwLock<HashMap<String, Track>>,
-    // known_nodes: RwLock<HashMap<String, LibraryNode>>,
-    tidal_client: Arc<tidaldy::Client>,
+    cache: RwLock<Cache>,
+    config: ProvidersConfig,
+    // keyed by the provider prefix of the uuids, e.g. `tidal` in `node:tidal:album:1234`
+    providers: BTreeMap<String, Box<dyn ProviderClient>>,
+    // the titles of their root nodes, keyed like the providers, building the roots can be slow
+    titles: BTreeMap<String, String>,
+    saved: RwLock<SavedPlaylists>,
 }
 
 impl ProviderOrchestrator {
     pub fn run(self) {
-        tokio::spawn(async move {
-            while let Ok(msg) = self.provider_rx.recv_async().await {
-                match msg {
-                    ProviderMessage::GetLibraryNode {
-                        uuid,
-                        result_tx,
-                        span,
-                    } => {
-                        let _e = span.enter();
-                        let result = self.get_lib_node(&uuid).in_current_span().await;
-                        if let Err(err) = result_tx.send_async(result).in_current_span().await {
-                            error!("failed to send result: {}", err);
-                        }
+        tokio::spawn(Arc::new(self).receive());
+    }
+    async fn receive(self: Arc<Self>) {
+        let mut last_persisted = Instant::now();
+        while let Ok(msg) = self.provider_rx.recv_async().await {
+            match msg {
+                ProviderMessage::GetLibraryNode {
+                    uuid,
+                    offset,
+                    limit,
+                    result_tx,
+                    span,
+                } => {
+                    let _e = span.enter();
+                    let result = self
+                        .get_lib_node_page(&uuid, offset, limit)
+                        .in_current_span()
+                        .await;
+                    if let Err(err) = result_tx.send_async(result).in_current_span().await {
+                        error!("failed to send result: {}", err);
                     }
-                    ProviderMessage::GetTrack {
-                        uuid,
-                        result_tx,
-                        span,
-                    } => {
-                        let _e = span.enter();
-                        let result = self.get_metadata_for_track(&uuid).in_current_span().await;
-                        if let Err(err) = result_tx.send_async(result).in_current_span().await {
-                            error!("failed to send result: {}", err);
-                        }
+                }
+                ProviderMessage::GetTrack {
+                    uuid,
+                    result_tx,
+                    span,
+                } => {
+                    let _e = span.enter();
+                    let result = self.get_metadata_for_track(&uuid).in_current_span().await;
+                    if let Err(err) = result_tx.send_async(result).in_current_span().await {
+                        error!("failed to send result: {}", err);
                     }
-                    ProviderMessage::GetTrackUrls {
-                        uuid,
-                        result_tx,
-                        span,
-                    } => {
-                        let _e = span.enter();
-                        let result = self.get_urls_for_track(&uuid).in_current_span().await;
-                        if let Err(err) = result_tx.send_async(result).in_current_span().await {
-                            error!("failed to send result: {}", err);
-                        }
+                }
+                ProviderMessage::GetTrackUrls {
+                    uuid,
+                    result_tx,
+                    span,
+                } => {
+                    let _e = span.enter();
+                    let result = self.get_urls_for_track(&uuid).in_current_span().await;
+                    if let Err(err) = result_tx.send_async(result).in_current_span().await {
+                        error!("failed to send result: {}", err);
+                    }
+                }
+                ProviderMessage::FlattenNode {
+                    uuid,
+                    result_tx,
+                    span,
+                } => {
+                    let _e = span.enter();
+                    let result = self.flatten_node(&uuid).in_current_span().await;
+                    if let Err(err) = result_tx.send_async(result).in_current_span().await {
+                        error!("failed to send result: {}", err);
+                    }
+                }
+                ProviderMessage::Search {
+                    query,
+                    result_tx,
+                    span,
+                } => {
+                    let _e = span.enter();
+                    let result = self.search(&query).in_current_span().await;
+                    if let Err(err) = result_tx.send_async(result).in_current_span().await {
+                        error!("failed to send result: {}", err);
+                    }
+                }
+                ProviderMessage::GetProviders { result_tx, span } => {
+                    let _e = span.enter();
+                    let providers = self.providers();
+                    if let Err(err) = result_tx.send_async(providers).in_current_span().await {
+                        error!("failed to send result: {}", err);
+                    }
+                }
+                ProviderMessage::GetLyrics {
+                    uuid,
+                    result_tx,
+                    span,
+                } => {
+                    let _e = span.enter();
+                    let result = self.get_lyrics(&uuid).in_current_span().await;
+                    if let Err(err) = result_tx.send_async(result).in_current_span().await {
+                        error!("failed to send result: {}", err);
                     }
-                    ProviderMessage::FlattenNode {
-                        uuid,
-                        result_tx,
-                        span,
-                    } => {
-                        let _e = span.enter();
-                        let result = self.flatten_node(&uuid).in_current_span().await;
-                        if let Err(err) = result_tx.send_async(result).in_current_span().await {
-                            error!("failed to send result: {}", err);
+                }
+                ProviderMessage::SavePlaylist {
+                    name,
+                    tracks,
+                    result_tx,
+                    span,
+                } => {
+                    let _e = span.enter();
+                    let result = match self.saved.write() {
+                        Ok(mut saved) => saved.save(&name, tracks),
+                        Err(_) => Err(ProviderError::InternalError(
+                            "poisoned saved playlists lock".to_string(),
+                        )),
+                    };
+                    if let Err(err) = result_tx.send_async(result).in_current_span().await {
+                        error!("failed to send result: {}", err);
+                    }
+                }
+                ProviderMessage::RenamePlaylist {
+                    uuid,
+                    name,
+                    result_tx,
+                    span,
+                } => {
+                    let _e = span.enter();
+                    let result = match self.saved.write() {
+                        Ok(mut saved) => saved.rename(&uuid, &name),
+                        Err(_) => Err(ProviderError::InternalError(
+                            "poisoned saved playlists lock".to_string(),
+                        )),
+                    };
+                    if let Err(err) = result_tx.send_async(result).in_current_span().await {
+                        error!("failed to send result: {}", err);
+                    }
+                }
+                ProviderMessage::DeletePlaylist {
+                    uuid,
+                    result_tx,
+                    span,
+                } => {
+                    let _e = span.enter();
+                    let result = match self.saved.write() {
+                        Ok(mut saved) => saved.delete(&uuid),
+                        Err(_) => Err(ProviderError::InternalError(
+                            "poisoned saved playlists lock".to_string(),
+                        )),
+                    };
+                    if let Err(err) = result_tx.send_async(result).in_current_span().await {
+                        error!("failed to send result: {}", err);
+                    }
+                }
+                ProviderMessage::ResolvePlaylist {
+                    entries,
+                    result_tx,
+                    span,
+                } => {
+                    // this takes a while, the other messages are handled in the meantime
+                    let orchestrator = self.clone();
+                    tokio::spawn(
+                        async move {
+                            let result = orchestrator
+                                .resolve_entries(entries)
+                                .in_current_span()
+                                .await;
+                            if let Err(err) = result_tx.send_async(result).in_current_span().await {
+                                error!("failed to send result: {}", err);
+                            }
+                        }
+                        .instrument(span),
+                    );
+                }
+                ProviderMessage::GetResumePosition {
+                    uuid,
+                    result_tx,
+                    span,
+                } => {
+                    let _e = span.enter();
+                    let position = match self.get_resume_position(&uuid).in_current_span().await {
+                        Ok(position) => position,
+                        Err(err) => {
+                            warn!("failed to get resume position: {}", err);
+                            None
                         }
+                    };
+                    if let Err(err) = result_tx.send_async(position).in_current_span().await {
+                        error!("failed to send result: {}", err);
+                    }
+                }
+                ProviderMessage::SavePosition {
+                    uuid,
+                    position,
+                    duration,
+                    span,
+                } => {
+                    let _e = span.enter();
+                    if let Err(err) = self
+                        .save_position(&uuid, position, duration)
+                        .in_current_span()
+                        .await
+                    {
+                        warn!("failed to save position: {}", err);
+                    }
+                }
+                ProviderMessage::FlushPositions { span } => {
+                    let _e = span.enter();
+                    if let Err(err) = self.flush_positions().in_current_span().await {
+                        warn!("failed to flush positions: {}", err);
+                    }
+                }
+                ProviderMessage::InvalidateCache { uuids, span } => {
+                    let _e = span.enter();
+                    debug!("invalidating cache for {:?}", uuids);
+                    let Ok(mut cache) = self.cache.write() else {
+                        error!("poisoned cache lock");
+                        continue;
+                    };
+                    if uuids.is_empty() {
+                        cache.clear();
+                    }
+                    for uuid in uuids {
+                        cache.invalidate(&uuid);
+                    }
+                }
+                ProviderMessage::PersistCache { result_tx, span } => {
+                    let _e = span.enter();
+                    self.persist_cache();
+                    last_persisted = Instant::now();
+                    if let Err(err) = result_tx.send_async(()).in_current_span().await {
+                        error!("failed to send result: {}", err);
                     }
                 }
             }
-        });
+            if CACHE_PERSIST_INTERVAL < last_persisted.elapsed() {
+                self.persist_cache();
+                last_persisted = Instant::now();
+            }
+        }
+    }
+    fn persist_cache(&self) {
+        match self.cache.write() {
+            Ok(mut cache) => cache.persist(),
+            Err(_) => error!("poisoned cache lock"),
+        }
     }
+    /// The enabled providers and the saved playlists, in the order of the library root
+    fn providers(&self) -> Vec<Provider> {
+        let saved = Provider {
+            name: "saved".to_string(),
+            title: "saved".to_string(),
+            capabilities: Some(SavedPlaylists::capabilities()),
+        };
+        self.providers
+            .iter()
+            .map(|(name, provider)| Provider {
+                name: name.clone(),
+                title: self.title(name),
+                capabilities: Some(provider.capabilities()),
+            })
+            .chain(std::iter::once(saved))
+            .collect()
+    }
+    /// Root nodes of providers carry their capabilities
+    fn add_capabilities(&self, node: &mut LibraryNode) {
+        // `node:<name>`, deeper nodes have more parts
+        let Some(name) = node.uuid.strip_prefix("node:") else {
+            return;
+        };
+        if let Some(provider) = self.providers.get(name) {
+            node.capabilities = Some(provider.capabilities());
+        }
+    }
+    fn title(&self, name: &str) -> String {
+        self.titles
+            .get(name)
+            .cloned()
+            .unwrap_or_else(|| name.to_string())
+    }
+    fn provider_for(&self, uuid: &str) -> Result<&dyn ProviderClient, ProviderError> {
+        let Some(name) = uuid.split(':').nth(1) else {
+            return Err(ProviderError::malformed_uuid(uuid));
+        };
+        self.providers.get(name).map(|p| p.as_ref()).ok_or_else(|| {
+            warn!("no provider {} for uuid {}", name, uuid);
+            ProviderError::Unavailable {
+                provider: name.to_string(),
+                cause: "not enabled or failed to initialize".to_string(),
+            }
+        })
+    }
+
     #[instrument(skip(self))]
     async fn flatten_node(&self, node_uuid: &str) -> Vec<Track> {
         let mut tracks = Vec::with_capacity(1000);
@@ -85,12 +330,81 @@ impl ProviderOrchestrator {
         }
         tracks
     }
+
+    /// Resolves the entries of an imported playlist a few at a time, the tracks are in the order
+    /// of the entries
+    async fn resolve_entries(
+        &self,
+        entries: Vec<PlaylistEntry>,
+    ) -> (Vec<Track>, Vec<PlaylistEntry>) {
+        let resolved: Vec<_> = stream::iter(entries)
+            .map(|entry| async move { (self.resolve_entry(&entry).in_current_span().await, entry) })
+            .buffered(RESOLVE_CONCURRENCY)
+            .collect()
+            .await;
+        let mut tracks = Vec::with_capacity(resolved.len());
+        let mut unresolved = Vec::new();
+        for (track, entry) in resolved {
+            match track {
+                Some(track) => tracks.push(track),
+                None => unresolved.push(entry),
+            }
+        }
+        (tracks, unresolved)
+    }
+
+    /// Finds the track of an imported playlist entry by its uuid, as local file, by its url and
+    /// at last by searching for its artist and title. Local files are looked up by the local
+    /// provider like any other track, which refuses the ones outside of its music dirs
+    #[instrument(skip(self))]
+    async fn resolve_entry(&self, entry: &PlaylistEntry) -> Option<Track> {
+        let mut uuids: Vec<String> = entry.identifier.iter().cloned().collect();
+        let mut url = None;
+        if let Some(location) = &entry.location {
+            if location.starts_with("track:") {
+                uuids.push(location.clone());
+            } else if Path::new(location).is_absolute() {
+                uuids.push(format!("track:local:{}", location));
+            } else if location.contains("://") {
+                url = Some(location);
+            }
+        }
+        for uuid in uuids {
+            match self.get_metadata_for_track(&uuid).in_current_span().await {
+                Ok(track) => return Some(track),
+                Err(err) => debug!("{} can not be imported: {}", uuid, err),
+            }
+        }
+        if let Some(url) = url {
+            match self.track_for_url(url).in_current_span().await {
+                Ok(Some(track)) => return Some(track),
+                Ok(None) => {}
+                Err(err) => warn!("failed to resolve {}: {}", url, err),
+            }
+        }
+        let title = entry.title.as_ref()?.to_lowercase();
+        let artist = entry.artist.as_ref().map(|a| a.to_lowercase());
+        let query = match &artist {
+            Some(artist) => format!("{} {}", artist, title),
+            None => title.clone(),
+        };
+        let result = self.search(&query).in_current_span().await.ok()?;
+        result.tracks.into_iter().find(|track| {
+            track.title.to_lowercase() == title
+                && artist
+                    .as_ref()
+                    .is_none_or(|artist| track.artist.to_lowercase().contains(artist))
+        })
+    }
 }
 
 #[async_trait]
 impl ProviderClient for ProviderOrchestrator {
-    #[instrument(skip(_s))]
-    async fn init(_s: &str) -> Result<Self, ProviderError> {
+    #[instrument(skip(raw_toml_settings))]
+    async fn init(raw_toml_settings: &str) -> Result<Self, ProviderError> {
+        let config = toml::from_str::<<ProvidersConfig as ClapSerde>::Opt>(raw_toml_settings)
+            .map(ProvidersConfig::from)
+            .map_err(|e| ProviderError::Config(e.to_string()))?;
         let config_dir = dirs::config_dir()
             .map(|d| d.join("crabidy"))
             .unwrap_or(PathBuf::from("/tmp"));
@@ -104,54 +418,78 @@ impl ProviderClient for ProviderOrchestrator {
                 .await
                 .map_err(|e| ProviderError::Config(e.to_string()))?;
         }
-        let config_file = config_dir.join("tidaly.toml");
-        let raw_toml_settings = fs::read_to_string(&config_file).unwrap_or("".to_owned());
-        let tidal_client = Arc::new(
-            tidaldy::Client::init(&raw_toml_settings)
+        let mut providers = BTreeMap::new();
+        let mut titles = BTreeMap::new();
+        for name in &config.enabled {
+            match init_provider(name, &config_dir, &config.plugins)
                 .in_current_span()
                 .await
-                .expect("Failed to init Tidal clienta"),
-        );
-        let new_toml_config = tidal_client.settings();
-        if let Err(err) = tokio::fs::write(&config_file, new_toml_config)
-            .in_current_span()
-            .await
-        {
-            error!("Failed to write config file: {}", err);
-        };
+            {
+                Ok(provider) => {
+                    debug!("initialized provider {}", name);
+                    titles.insert(name.clone(), provider.get_lib_root().title);
+                    providers.insert(name.clone(), provider);
+                }
+                Err(err) => error!("Failed to init provider {}: {}", name, err),
+            }
+        }
+        let saved = RwLock::new(SavedPlaylists::load(data_dir().join("playlists.json")));
+        let cache = RwLock::new(Cache::new(&config.cache, data_dir().join("cache.json")));
         let (provider_tx, provider_rx) = flume::bounded(100);
         Ok(Self {
             provider_rx,
             provider_tx,
-            tidal_client,
+            cache,
+            config,
+            providers,
+            titles,
+            saved,
         })
     }
     #[instrument(skip(self))]
     fn settings(&self) -> String {
-        "".to_owned()
+        toml::to_string_pretty(&self.config).unwrap_or_default()
     }
     #[instrument(skip(self))]
     async fn get_urls_for_track(&self, track_uuid: &str) -> Result<Vec<String>, ProviderError> {
         debug!("get_urls_for_track");
-        self.tidal_client
+        self.provider_for(track_uuid)?
             .get_urls_for_track(track_uuid)
             .in_current_span()
             .await
+            .map_err(|err| err.with_uuid(track_uuid))
     }
     #[instrument(skip(self))]
     async fn get_metadata_for_track(&self, track_uuid: &str) -> Result<Track, ProviderError> {
         debug!("get_metadata_for_track");
-        self.tidal_client
+        if let Some(track) = self.cache.read().ok().and_then(|c| c.get_track(track_uuid)) {
+            debug!("cache hit");
+            return Ok(track);
+        }
+        let track = self
+            .provider_for(track_uuid)?
             .get_metadata_for_track(track_uuid)
             .in_current_span()
             .await
+            .map_err(|err| err.with_uuid(track_uuid))?;
+        if let Ok(mut cache) = self.cache.write() {
+            cache.insert_track(&track);
+        }
+        Ok(track)
     }
     #[instrument(skip(self))]
     fn get_lib_root(&self) -> LibraryNode {
         debug!("get_lib_root in provider manager");
         let mut root_node = LibraryNode::new();
-        let child = LibraryNodeChild::new("node:tidal".to_owned(), "tidal".to_owned(), false);
-        root_node.children.push(child);
+        for name in self.providers.keys() {
+            let child = LibraryNodeChild::new(format!("node:{}", name), self.title(name), false);
+            root_node.children.push(child);
+        }
+        root_node.children.push(LibraryNodeChild::new(
+            "node:saved".to_string(),
+            "saved".to_string(),
+            false,
+        ));
         root_node
     }
     #[instrument(skip(self))]
@@ -161,11 +499,270 @@ impl ProviderClient for ProviderOrchestrator {
             debug!("get global root");
             return Ok(self.get_lib_root());
         }
-        if uuid == "node:tidal" {
-            debug!("get tidal root");
-            return Ok(self.tidal_client.get_lib_root());
+        if SavedPlaylists::is_saved(uuid) {
+            let Ok(saved) = self.saved.read() else {
+                return Err(ProviderError::InternalError(
+                    "poisoned saved playlists lock".to_string(),
+                ));
+            };
+            return saved.get_node(uuid);
+        }
+        if let Some(node) = self.cache.read().ok().and_then(|c| c.get_node(uuid)) {
+            debug!("cache hit");
+            return Ok(node);
+        }
+        let mut node = self
+            .provider_for(uuid)?
+            .get_lib_node(uuid)
+            .in_current_span()
+            .await
+            .map_err(|err| err.with_uuid(uuid))?;
+        self.add_capabilities(&mut node);
+        if let Ok(mut cache) = self.cache.write() {
+            cache.insert_node(&node);
+        }
+        Ok(node)
+    }
+    #[instrument(skip(self))]
+    async fn get_lib_node_page(
+        &self,
+        uuid: &str,
+        offset: usize,
+        limit: usize,
+    ) -> Result<(LibraryNode, usize), ProviderError> {
+        debug!("get_lib_node_page in provider manager");
+        let is_cached = self
+            .cache
+            .read()
+            .map_or(false, |c| c.get_node(uuid).is_some());
+        // whole nodes are cheap to paginate here, only pages of uncached provider nodes are
+        // fetched from the provider itself
+        if limit == 0 || is_cached || uuid == "node:/" || SavedPlaylists::is_saved(uuid) {
+            let mut node = self.get_lib_node(uuid).in_current_span().await?;
+            let total = node.paginate(offset, limit);
+            return Ok((node, total));
+        }
+        let (mut node, total) = self
+            .provider_for(uuid)?
+            .get_lib_node_page(uuid, offset, limit)
+            .in_current_span()
+            .await
+            .map_err(|err| err.with_uuid(uuid))?;
+        self.add_capabilities(&mut node);
+        if let Ok(mut cache) = self.cache.write() {
+            if offset == 0 && node.entries_len() == total {
+                cache.insert_node(&node);
+            } else {
+                node.tracks.iter().for_each(|t| cache.insert_track(t));
+            }
+        }
+        Ok((node, total))
+    }
+    #[instrument(skip(self))]
+    async fn search(&self, query: &str) -> Result<SearchResult, ProviderError> {
+        debug!("search in provider manager");
+        let searches = self
+            .providers
+            .iter()
+            .filter(|(_, provider)| provider.capabilities().search)
+            .map(|(name, provider)| async move { (name, provider.search(query).await) });
+        let mut result = SearchResult::default();
+        for (name, provider_result) in join_all(searches).in_current_span().await {
+            match provider_result {
+                Ok(provider_result) => result.extend(provider_result),
+                Err(err) => warn!("search in provider {} failed: {}", name, err),
+            }
+        }
+        Ok(result)
+    }
+    #[instrument(skip(self))]
+    async fn track_for_url(&self, url: &str) -> Result<Option<Track>, ProviderError> {
+        for (name, provider) in &self.providers {
+            match provider.track_for_url(url).in_current_span().await {
+                Ok(Some(track)) => return Ok(Some(track)),
+                Ok(None) => {}
+                Err(err) => warn!("provider {} failed to resolve {}: {}", name, url, err),
+            }
+        }
+        Ok(None)
+    }
+    #[instrument(skip(self))]
+    async fn get_resume_position(
+        &self,
+        track_uuid: &str,
+    ) -> Result<Option<Duration>, ProviderError> {
+        self.provider_for(track_uuid)?
+            .get_resume_position(track_uuid)
+            .in_current_span()
+            .await
+    }
+    #[instrument(skip(self))]
+    async fn save_position(
+        &self,
+        track_uuid: &str,
+        position: Duration,
+        duration: Option<Duration>,
+    ) -> Result<(), ProviderError> {
+        self.provider_for(track_uuid)?
+            .save_position(track_uuid, position, duration)
+            .in_current_span()
+            .await
+    }
+    #[instrument(skip(self))]
+    async fn flush_positions(&self) -> Result<(), ProviderError> {
+        let flushed = join_all(self.providers.iter().map(|(name, provider)| async move {
+            (name, provider.flush_positions().in_current_span().await)
+        }))
+        .await;
+        for (name, result) in flushed {
+            if let Err(err) = result {
+                warn!("failed to flush positions of {}: {}", name, err);
+            }
+        }
+        Ok(())
+    }
+    #[instrument(skip(self))]
+    async fn get_lyrics(&self, track_uuid: &str) -> Result<Option<Lyrics>, ProviderError> {
+        let provider = self.provider_for(track_uuid)?;
+        if !provider.capabilities().lyrics {
+            return Ok(None);
+        }
+        provider
+            .get_lyrics(track_uuid)
+            .in_current_span()
+            .await
+            .map_err(|err| err.with_uuid(track_uuid))
+    }
+}
+
+/// Creates the provider registered under `name`, its settings are read from and written back to
+/// a toml file in the config directory. New providers only need to be added here.
+#[instrument(skip(config_dir))]
+async fn init_provider(
+    name: &str,
+    config_dir: &Path,
+    plugins: &BTreeMap<String, PluginConfig>,
+) -> Result<Box<dyn ProviderClient>, ProviderError> {
+    match name {
+        "tidal" => init_from_config_file::<tidaldy::Client>(&config_dir.join("tidaly.toml")).await,
+        "local" => init_from_config_file::<localdy::Client>(&config_dir.join("localdy.toml")).await,
+        "subsonic" => {
+            init_from_config_file::<subsonicdy::Client>(&config_dir.join("subsonicdy.toml")).await
+        }
+        "jellyfin" => {
+            init_from_config_file::<jellyfindy::Client>(&config_dir.join("jellyfindy.toml")).await
+        }
+        "radio" => init_from_config_file::<radiody::Client>(&config_dir.join("radiody.toml")).await,
+        "podcast" => {
+            init_from_config_file::<podcastdy::Client>(&config_dir.join("podcastdy.toml")).await
+        }
+        _ => match plugins.get(name) {
+            Some(plugin) => {
+                let config_file = config_dir.join(format!("{}.toml", name));
+                let raw_toml_settings = fs::read_to_string(&config_file).unwrap_or("".to_owned());
+                let client = PluginClient::spawn(name, plugin.clone(), &raw_toml_settings)
+                    .in_current_span()
+                    .await?;
+                write_settings(&config_file, client.settings()).await;
+                Ok(Box::new(client))
+            }
+            None => Err(ProviderError::Config(format!("unknown provider {}", name))),
+        },
+    }
+}
+
+async fn init_from_config_file<P: ProviderClient + 'static>(
+    config_file: &Path,
+) -> Result<Box<dyn ProviderClient>, ProviderError> {
+    let raw_toml_settings = fs::read_to_string(config_file).unwrap_or("".to_owned());
+    let client = P::init(&raw_toml_settings).in_current_span().await?;
+    write_settings(config_file, client.settings()).await;
+    Ok(Box::new(client))
+}
+
+async fn write_settings(config_file: &Path, new_toml_config: String) {
+    if let Err(err) = tokio::fs::write(config_file, new_toml_config)
+        .in_current_span()
+        .await
+    {
+        error!("Failed to write config file: {}", err);
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A mono wav file with a few silent frames
+    fn wav() -> Vec<u8> {
+        let data = [0u8; 20];
+        let mut wav = Vec::new();
+        wav.extend_from_slice(b"RIFF");
+        wav.extend_from_slice(&(36 + data.len() as u32).to_le_bytes());
+        wav.extend_from_slice(b"WAVEfmt ");
+        wav.extend_from_slice(&16u32.to_le_bytes());
+        wav.extend_from_slice(&1u16.to_le_bytes());
+        wav.extend_from_slice(&1u16.to_le_bytes());
+        wav.extend_from_slice(&1000u32.to_le_bytes());
+        wav.extend_from_slice(&2000u32.to_le_bytes());
+        wav.extend_from_slice(&2u16.to_le_bytes());
+        wav.extend_from_slice(&16u16.to_le_bytes());
+        wav.extend_from_slice(b"data");
+        wav.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        wav.extend_from_slice(&data);
+        wav
+    }
+
+    #[tokio::test]
+    async fn imported_paths_are_only_resolved_in_the_music_dirs() {
+        let dir = std::env::temp_dir().join(format!("crabidy-import-{}", std::process::id()));
+        let music = dir.join("music");
+        fs::create_dir_all(&music).unwrap();
+        fs::write(music.join("song.wav"), wav()).unwrap();
+        fs::write(dir.join("secret.wav"), wav()).unwrap();
+        let local = localdy::Client::new(localdy::config::Settings {
+            music_dirs: vec![music.clone()],
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+        let config = ProvidersConfig::default();
+        let (provider_tx, provider_rx) = flume::unbounded();
+        let orchestrator = ProviderOrchestrator {
+            provider_tx,
+            provider_rx,
+            cache: RwLock::new(Cache::new(&config.cache, PathBuf::new())),
+            config,
+            providers: BTreeMap::from([(
+                "local".to_string(),
+                Box::new(local) as Box<dyn ProviderClient>,
+            )]),
+            titles: BTreeMap::new(),
+            saved: RwLock::new(SavedPlaylists::load(dir.join("playlists.json"))),
+        };
+        let entry = |path: PathBuf| PlaylistEntry {
+            location: Some(path.display().to_string()),
+            ..Default::default()
+        };
+
+        // added after the scan, so it is read from the file
+        fs::write(music.join("new.wav"), wav()).unwrap();
+        for path in [music.join("song.wav"), music.join("new.wav")] {
+            assert!(orchestrator.resolve_entry(&entry(path)).await.is_some());
+        }
+        for path in [dir.join("secret.wav"), music.join("../secret.wav")] {
+            assert_eq!(orchestrator.resolve_entry(&entry(path)).await, None);
         }
-        debug!("tidal node");
-        self.tidal_client.get_lib_node(uuid).in_current_span().await
+        let paths = [
+            music.join("new.wav"),
+            dir.join("secret.wav"),
+            music.join("song.wav"),
+        ];
+        let (tracks, unresolved) = orchestrator
+            .resolve_entries(paths.into_iter().map(entry).collect())
+            .await;
+        assert!(tracks[0].uuid.ends_with("new.wav") && tracks[1].uuid.ends_with("song.wav"));
+        assert_eq!(unresolved, vec![entry(dir.join("secret.wav"))]);
+        fs::remove_dir_all(dir).unwrap();
     }
 }
@@ -1,14 +1,22 @@
-use crate::{PlaybackMessage, ProviderMessage};
+use crate::{playlist_file, PlaybackMessage, ProviderMessage};
 use crabidy_core::proto::crabidy::{
     crabidy_service_server::CrabidyService, get_update_stream_response::Update as StreamUpdate,
     AppendRequest, AppendResponse, ChangeVolumeRequest, ChangeVolumeResponse, ClearQueueRequest,
-    ClearQueueResponse, GetLibraryNodeRequest, GetLibraryNodeResponse, GetUpdateStreamRequest,
-    GetUpdateStreamResponse, InitRequest, InitResponse, InsertRequest, InsertResponse, NextRequest,
-    NextResponse, PrevRequest, PrevResponse, QueueRequest, QueueResponse, RemoveRequest,
-    RemoveResponse, ReplaceRequest, ReplaceResponse, RestartTrackRequest, RestartTrackResponse,
-    SaveQueueRequest, SaveQueueResponse, SetCurrentRequest, SetCurrentResponse, StopRequest,
-    StopResponse, ToggleMuteRequest, ToggleMuteResponse, TogglePlayRequest, TogglePlayResponse,
-    ToggleRepeatRequest, ToggleRepeatResponse, ToggleShuffleRequest, ToggleShuffleResponse,
+    ClearQueueResponse, DeletePlaylistRequest, DeletePlaylistResponse, ExportPlaylistRequest,
+    ExportPlaylistResponse, GetDspPresetsRequest, GetDspPresetsResponse, GetLibraryNodeRequest,
+    GetLibraryNodeResponse, GetLyricsRequest, GetLyricsResponse, GetProvidersRequest,
+    GetProvidersResponse, GetUpdateStreamRequest, GetUpdateStreamResponse, ImportPlaylistRequest,
+    ImportPlaylistResponse, InitRequest, InitResponse, InsertRequest, InsertResponse,
+    InvalidateCacheRequest, InvalidateCacheResponse, LoadPlaylistRequest, LoadPlaylistResponse,
+    NextRequest, NextResponse, PrevRequest, PrevResponse, QueueRequest, QueueResponse,
+    RemoveRequest, RemoveResponse, RenamePlaylistRequest, RenamePlaylistResponse, ReplaceRequest,
+    ReplaceResponse, ReplayGainMode, RestartTrackRequest, RestartTrackResponse, SaveQueueRequest,
+    SaveQueueResponse, SearchRequest, SearchResponse, SeekByRequest, SeekByResponse, SeekRequest,
+    SeekResponse, SetCurrentRequest, SetCurrentResponse, SetDspPresetRequest, SetDspPresetResponse,
+    SetReplayGainModeRequest, SetReplayGainModeResponse, StopRequest, StopResponse,
+    ToggleCrossfadeRequest, ToggleCrossfadeResponse, ToggleMuteRequest, ToggleMuteResponse,
+    TogglePlayRequest, TogglePlayResponse, ToggleRepeatRequest, ToggleRepeatResponse,
+    ToggleShuffleRequest, ToggleShuffleResponse,
 };
 use futures::TryStreamExt;
 use std::pin::Pin;
@@ -72,7 +80,8 @@ impl CrabidyService for RpcService {
         &self,
         request: Request<GetLibraryNodeRequest>,
     ) -> Result<Response<GetLibraryNodeResponse>, Status> {
-        let uuid = request.into_inner().uuid;
+        let req = request.into_inner();
+        let uuid = req.uuid;
         Span::current().record("uuid", &uuid);
         debug!("Received get_library_node request");
         let provider_tx = self.provider_tx.clone();
@@ -80,6 +89,130 @@ impl CrabidyService for RpcService {
         let span = debug_span!("prov-chan");
         provider_tx
             .send_async(ProviderMessage::GetLibraryNode {
+                uuid,
+                offset: req.offset as usize,
+                limit: req.limit as usize,
+                result_tx,
+                span,
+            })
+            .in_current_span()
+            .await
+            .map_err(|_| Status::internal("Failed to send request via channel"))?;
+        let result = result_rx
+            .recv_async()
+            .in_current_span()
+            .await
+            .map_err(|e| {
+                error!("{:?}", e);
+                Status::internal("Failed to receive response from provider channel")
+            })?;
+        match result {
+            Ok((node, total)) => Ok(Response::new(GetLibraryNodeResponse {
+                node: Some(node),
+                total: total as u32,
+            })),
+            Err(err) => {
+                error!("{:?}", err);
+                Err(err.into())
+            }
+        }
+    }
+
+    #[instrument(skip(self, request), fields(query))]
+    async fn search(
+        &self,
+        request: Request<SearchRequest>,
+    ) -> Result<Response<SearchResponse>, Status> {
+        let query = request.into_inner().query;
+        Span::current().record("query", &query);
+        debug!("Received search request");
+        let provider_tx = self.provider_tx.clone();
+        let (result_tx, result_rx) = flume::bounded(1);
+        let span = debug_span!("prov-chan");
+        provider_tx
+            .send_async(ProviderMessage::Search {
+                query,
+                result_tx,
+                span,
+            })
+            .in_current_span()
+            .await
+            .map_err(|_| Status::internal("Failed to send request via channel"))?;
+        let result = result_rx
+            .recv_async()
+            .in_current_span()
+            .await
+            .map_err(|e| {
+                error!("{:?}", e);
+                Status::internal("Failed to receive response from provider channel")
+            })?;
+        match result {
+            Ok(result) => Ok(Response::new(SearchResponse {
+                result: Some(result),
+            })),
+            Err(err) => {
+                error!("{:?}", err);
+                Err(err.into())
+            }
+        }
+    }
+
+    #[instrument(skip(self, request), fields(uuids))]
+    async fn invalidate_cache(
+        &self,
+        request: Request<InvalidateCacheRequest>,
+    ) -> Result<Response<InvalidateCacheResponse>, Status> {
+        let uuids = request.into_inner().uuids;
+        Span::current().record("uuids", format!("{:?}", uuids));
+        debug!("Received invalidate_cache request");
+        let provider_tx = self.provider_tx.clone();
+        let span = debug_span!("prov-chan");
+        provider_tx
+            .send_async(ProviderMessage::InvalidateCache { uuids, span })
+            .in_current_span()
+            .await
+            .map_err(|_| Status::internal("Failed to send request via channel"))?;
+        Ok(Response::new(InvalidateCacheResponse {}))
+    }
+
+    #[instrument(skip(self, _request))]
+    async fn get_providers(
+        &self,
+        _request: Request<GetProvidersRequest>,
+    ) -> Result<Response<GetProvidersResponse>, Status> {
+        debug!("Received get_providers request");
+        let provider_tx = self.provider_tx.clone();
+        let (result_tx, result_rx) = flume::bounded(1);
+        let span = debug_span!("prov-chan");
+        provider_tx
+            .send_async(ProviderMessage::GetProviders { result_tx, span })
+            .in_current_span()
+            .await
+            .map_err(|_| Status::internal("Failed to send request via channel"))?;
+        let providers = result_rx
+            .recv_async()
+            .in_current_span()
+            .await
+            .map_err(|e| {
+                error!("{:?}", e);
+                Status::internal("Failed to receive response from provider channel")
+            })?;
+        Ok(Response::new(GetProvidersResponse { providers }))
+    }
+
+    #[instrument(skip(self, request), fields(uuid))]
+    async fn get_lyrics(
+        &self,
+        request: Request<GetLyricsRequest>,
+    ) -> Result<Response<GetLyricsResponse>, Status> {
+        let uuid = request.into_inner().uuid;
+        Span::current().record("uuid", &uuid);
+        debug!("Received get_lyrics request");
+        let provider_tx = self.provider_tx.clone();
+        let (result_tx, result_rx) = flume::bounded(1);
+        let span = debug_span!("prov-chan");
+        provider_tx
+            .send_async(ProviderMessage::GetLyrics {
                 uuid,
                 result_tx,
                 span,
@@ -96,10 +229,10 @@ impl CrabidyService for RpcService {
                 Status::internal("Failed to receive response from provider channel")
             })?;
         match result {
-            Ok(node) => Ok(Response::new(GetLibraryNodeResponse { node: Some(node) })),
+            Ok(lyrics) => Ok(Response::new(GetLyricsResponse { lyrics })),
             Err(err) => {
                 error!("{:?}", err);
-                Err(Status::internal(err.to_string()))
+                Err(err.into())
             }
         }
     }
@@ -286,6 +419,46 @@ impl CrabidyService for RpcService {
         Ok(Response::new(reply))
     }
 
+    #[instrument(skip(self, _request))]
+    async fn toggle_crossfade(
+        &self,
+        _request: tonic::Request<ToggleCrossfadeRequest>,
+    ) -> std::result::Result<tonic::Response<ToggleCrossfadeResponse>, tonic::Status> {
+        debug!("Received toggle_crossfade request");
+        let playback_tx = self.playback_tx.clone();
+        let span = debug_span!("play-chan");
+        if let Err(err) = playback_tx
+            .send_async(PlaybackMessage::ToggleCrossfade { span })
+            .in_current_span()
+            .await
+        {
+            error!("Failed to send request via channel: {}", err);
+        }
+        let reply = ToggleCrossfadeResponse {};
+        Ok(Response::new(reply))
+    }
+
+    #[instrument(skip(self, request))]
+    async fn set_replay_gain_mode(
+        &self,
+        request: tonic::Request<SetReplayGainModeRequest>,
+    ) -> std::result::Result<tonic::Response<SetReplayGainModeResponse>, tonic::Status> {
+        debug!("Received set_replay_gain_mode request");
+        let mode = ReplayGainMode::from_i32(request.into_inner().mode)
+            .ok_or_else(|| Status::invalid_argument("Unknown replay gain mode"))?;
+        let playback_tx = self.playback_tx.clone();
+        let span = debug_span!("play-chan");
+        if let Err(err) = playback_tx
+            .send_async(PlaybackMessage::SetReplayGainMode { mode, span })
+            .in_current_span()
+            .await
+        {
+            error!("Failed to send request via channel: {}", err);
+        }
+        let reply = SetReplayGainModeResponse {};
+        Ok(Response::new(reply))
+    }
+
     #[instrument(skip(self, _request))]
     async fn get_update_stream(
         &self,
@@ -311,16 +484,284 @@ impl CrabidyService for RpcService {
         Ok(Response::new(Box::pin(output_stream)))
     }
 
-    #[instrument(skip(self, _request))]
+    #[instrument(skip(self, request), fields(name))]
     async fn save_queue(
         &self,
-        _request: tonic::Request<SaveQueueRequest>,
+        request: tonic::Request<SaveQueueRequest>,
     ) -> std::result::Result<tonic::Response<SaveQueueResponse>, tonic::Status> {
+        let name = request.into_inner().name;
+        Span::current().record("name", &name);
         debug!("Received save_queue request");
-        let reply = SaveQueueResponse {};
+        if name.trim().is_empty() {
+            return Err(Status::invalid_argument("Playlist name must not be empty"));
+        }
+        let playback_tx = self.playback_tx.clone();
+        let (result_tx, result_rx) = flume::bounded(1);
+        let span = debug_span!("play-chan");
+        playback_tx
+            .send_async(PlaybackMessage::SaveQueue {
+                name,
+                result_tx,
+                span,
+            })
+            .in_current_span()
+            .await
+            .map_err(|_| Status::internal("Failed to send request via channel"))?;
+        let result = result_rx
+            .recv_async()
+            .in_current_span()
+            .await
+            .map_err(|e| {
+                error!("{:?}", e);
+                Status::internal("Failed to receive response from playback channel")
+            })?;
+        match result {
+            Ok(uuid) => Ok(Response::new(SaveQueueResponse { uuid })),
+            Err(err) => {
+                error!("{:?}", err);
+                Err(err.into())
+            }
+        }
+    }
+
+    #[instrument(skip(self, request), fields(uuid))]
+    async fn load_playlist(
+        &self,
+        request: tonic::Request<LoadPlaylistRequest>,
+    ) -> std::result::Result<tonic::Response<LoadPlaylistResponse>, tonic::Status> {
+        let uuid = request.into_inner().uuid;
+        Span::current().record("uuid", &uuid);
+        debug!("Received load_playlist request");
+        if !uuid.starts_with("node:saved:") {
+            return Err(Status::invalid_argument("Not a saved playlist"));
+        }
+        let playback_tx = self.playback_tx.clone();
+        let span = debug_span!("play-chan");
+        playback_tx
+            .send_async(PlaybackMessage::Replace {
+                uuids: vec![uuid],
+                span,
+            })
+            .in_current_span()
+            .await
+            .map_err(|_| Status::internal("Failed to send request via channel"))?;
+        let reply = LoadPlaylistResponse {};
         Ok(Response::new(reply))
     }
 
+    #[instrument(skip(self, request), fields(uuid, name))]
+    async fn rename_playlist(
+        &self,
+        request: tonic::Request<RenamePlaylistRequest>,
+    ) -> std::result::Result<tonic::Response<RenamePlaylistResponse>, tonic::Status> {
+        let RenamePlaylistRequest { uuid, name } = request.into_inner();
+        Span::current().record("uuid", &uuid);
+        Span::current().record("name", &name);
+        debug!("Received rename_playlist request");
+        if name.trim().is_empty() {
+            return Err(Status::invalid_argument("Playlist name must not be empty"));
+        }
+        let provider_tx = self.provider_tx.clone();
+        let (result_tx, result_rx) = flume::bounded(1);
+        let span = debug_span!("prov-chan");
+        provider_tx
+            .send_async(ProviderMessage::RenamePlaylist {
+                uuid,
+                name,
+                result_tx,
+                span,
+            })
+            .in_current_span()
+            .await
+            .map_err(|_| Status::internal("Failed to send request via channel"))?;
+        let result = result_rx
+            .recv_async()
+            .in_current_span()
+            .await
+            .map_err(|e| {
+                error!("{:?}", e);
+                Status::internal("Failed to receive response from provider channel")
+            })?;
+        match result {
+            Ok(()) => Ok(Response::new(RenamePlaylistResponse {})),
+            Err(err) => {
+                error!("{:?}", err);
+                Err(err.into())
+            }
+        }
+    }
+
+    #[instrument(skip(self, request), fields(uuid))]
+    async fn delete_playlist(
+        &self,
+        request: tonic::Request<DeletePlaylistRequest>,
+    ) -> std::result::Result<tonic::Response<DeletePlaylistResponse>, tonic::Status> {
+        let uuid = request.into_inner().uuid;
+        Span::current().record("uuid", &uuid);
+        debug!("Received delete_playlist request");
+        let provider_tx = self.provider_tx.clone();
+        let (result_tx, result_rx) = flume::bounded(1);
+        let span = debug_span!("prov-chan");
+        provider_tx
+            .send_async(ProviderMessage::DeletePlaylist {
+                uuid,
+                result_tx,
+                span,
+            })
+            .in_current_span()
+            .await
+            .map_err(|_| Status::internal("Failed to send request via channel"))?;
+        let result = result_rx
+            .recv_async()
+            .in_current_span()
+            .await
+            .map_err(|e| {
+                error!("{:?}", e);
+                Status::internal("Failed to receive response from provider channel")
+            })?;
+        match result {
+            Ok(()) => Ok(Response::new(DeletePlaylistResponse {})),
+            Err(err) => {
+                error!("{:?}", err);
+                Err(err.into())
+            }
+        }
+    }
+
+    #[instrument(skip(self, request), fields(uuid))]
+    async fn export_playlist(
+        &self,
+        request: tonic::Request<ExportPlaylistRequest>,
+    ) -> std::result::Result<tonic::Response<ExportPlaylistResponse>, tonic::Status> {
+        let req = request.into_inner();
+        let format = req.format();
+        let uuid = req.uuid;
+        Span::current().record("uuid", &uuid);
+        debug!("Received export_playlist request");
+        let (title, tracks) = if uuid.is_empty() {
+            let (result_tx, result_rx) = flume::bounded(1);
+            let span = debug_span!("play-chan");
+            self.playback_tx
+                .send_async(PlaybackMessage::GetQueueTracks { result_tx, span })
+                .in_current_span()
+                .await
+                .map_err(|_| Status::internal("Failed to send request via channel"))?;
+            let tracks = result_rx
+                .recv_async()
+                .in_current_span()
+                .await
+                .map_err(|e| {
+                    error!("{:?}", e);
+                    Status::internal("Failed to receive response from playback channel")
+                })?;
+            ("queue".to_string(), tracks)
+        } else {
+            // only the title of the node is needed, its tracks come from flattening it
+            let (result_tx, result_rx) = flume::bounded(1);
+            let span = debug_span!("prov-chan");
+            self.provider_tx
+                .send_async(ProviderMessage::GetLibraryNode {
+                    uuid: uuid.clone(),
+                    offset: 0,
+                    limit: 1,
+                    result_tx,
+                    span,
+                })
+                .in_current_span()
+                .await
+                .map_err(|_| Status::internal("Failed to send request via channel"))?;
+            let (node, _) = result_rx
+                .recv_async()
+                .in_current_span()
+                .await
+                .map_err(|e| {
+                    error!("{:?}", e);
+                    Status::internal("Failed to receive response from provider channel")
+                })??;
+            if !node.is_queable {
+                return Err(Status::invalid_argument(
+                    "Only queueable nodes can be exported",
+                ));
+            }
+            let (result_tx, result_rx) = flume::bounded(1);
+            let span = debug_span!("prov-chan");
+            self.provider_tx
+                .send_async(ProviderMessage::FlattenNode {
+                    uuid,
+                    result_tx,
+                    span,
+                })
+                .in_current_span()
+                .await
+                .map_err(|_| Status::internal("Failed to send request via channel"))?;
+            let tracks = result_rx
+                .recv_async()
+                .in_current_span()
+                .await
+                .map_err(|e| {
+                    error!("{:?}", e);
+                    Status::internal("Failed to receive response from provider channel")
+                })?;
+            (node.title, tracks)
+        };
+        let content = playlist_file::export(format, &title, &tracks);
+        Ok(Response::new(ExportPlaylistResponse { content }))
+    }
+
+    #[instrument(skip(self, request))]
+    async fn import_playlist(
+        &self,
+        request: tonic::Request<ImportPlaylistRequest>,
+    ) -> std::result::Result<tonic::Response<ImportPlaylistResponse>, tonic::Status> {
+        let req = request.into_inner();
+        debug!("Received import_playlist request");
+        let entries = playlist_file::import(req.format(), &req.content)
+            .map_err(|err| Status::invalid_argument(format!("Invalid playlist: {}", err)))?;
+        let (result_tx, result_rx) = flume::bounded(1);
+        let span = debug_span!("prov-chan");
+        self.provider_tx
+            .send_async(ProviderMessage::ResolvePlaylist {
+                entries,
+                result_tx,
+                span,
+            })
+            .in_current_span()
+            .await
+            .map_err(|_| Status::internal("Failed to send request via channel"))?;
+        let (tracks, unresolved) = result_rx
+            .recv_async()
+            .in_current_span()
+            .await
+            .map_err(|e| {
+                error!("{:?}", e);
+                Status::internal("Failed to receive response from provider channel")
+            })?;
+        let uuids: Vec<String> = tracks.into_iter().map(|track| track.uuid).collect();
+        if !uuids.is_empty() {
+            let span = debug_span!("play-chan");
+            let message = if req.replace {
+                PlaybackMessage::Replace {
+                    uuids: uuids.clone(),
+                    span,
+                }
+            } else {
+                PlaybackMessage::Append {
+                    uuids: uuids.clone(),
+                    span,
+                }
+            };
+            self.playback_tx
+                .send_async(message)
+                .in_current_span()
+                .await
+                .map_err(|_| Status::internal("Failed to send request via channel"))?;
+        }
+        Ok(Response::new(ImportPlaylistResponse {
+            uuids,
+            unresolved: unresolved.iter().map(|entry| entry.describe()).collect(),
+        }))
+    }
+
     /// Playback
     #[instrument(skip(self, _request))]
     async fn toggle_play(
@@ -456,4 +897,97 @@ impl CrabidyService for RpcService {
         let reply = RestartTrackResponse {};
         Ok(Response::new(reply))
     }
+
+    #[instrument(skip(self, request))]
+    async fn seek(
+        &self,
+        request: tonic::Request<SeekRequest>,
+    ) -> std::result::Result<tonic::Response<SeekResponse>, tonic::Status> {
+        debug!("Received seek request");
+        let position = request.into_inner().position;
+        let playback_tx = self.playback_tx.clone();
+        let span = debug_span!("play-chan");
+        if let Err(err) = playback_tx
+            .send_async(PlaybackMessage::Seek { position, span })
+            .in_current_span()
+            .await
+        {
+            error!("Failed to send request via channel: {}", err);
+        }
+        let reply = SeekResponse {};
+        Ok(Response::new(reply))
+    }
+
+    #[instrument(skip(self, request))]
+    async fn seek_by(
+        &self,
+        request: tonic::Request<SeekByRequest>,
+    ) -> std::result::Result<tonic::Response<SeekByResponse>, tonic::Status> {
+        debug!("Received seek_by request");
+        let offset = request.into_inner().offset;
+        let playback_tx = self.playback_tx.clone();
+        let span = debug_span!("play-chan");
+        if let Err(err) = playback_tx
+            .send_async(PlaybackMessage::SeekBy { offset, span })
+            .in_current_span()
+            .await
+        {
+            error!("Failed to send request via channel: {}", err);
+        }
+        let reply = SeekByResponse {};
+        Ok(Response::new(reply))
+    }
+
+    #[instrument(skip(self, _request))]
+    async fn get_dsp_presets(
+        &self,
+        _request: tonic::Request<GetDspPresetsRequest>,
+    ) -> std::result::Result<tonic::Response<GetDspPresetsResponse>, tonic::Status> {
+        debug!("Received get_dsp_presets request");
+        let playback_tx = self.playback_tx.clone();
+        let (result_tx, result_rx) = flume::bounded(1);
+        let span = debug_span!("play-chan");
+        playback_tx
+            .send_async(PlaybackMessage::GetDspPresets { result_tx, span })
+            .in_current_span()
+            .await
+            .map_err(|_| Status::internal("Failed to send request via channel"))?;
+        let reply = result_rx
+            .recv_async()
+            .in_current_span()
+            .await
+            .map_err(|_| Status::internal("Failed to receive response from playback channel"))?;
+        Ok(Response::new(reply))
+    }
+
+    #[instrument(skip(self, request))]
+    async fn set_dsp_preset(
+        &self,
+        request: tonic::Request<SetDspPresetRequest>,
+    ) -> std::result::Result<tonic::Response<SetDspPresetResponse>, tonic::Status> {
+        debug!("Received set_dsp_preset request");
+        let name = request.into_inner().name;
+        let playback_tx = self.playback_tx.clone();
+        let (result_tx, result_rx) = flume::bounded(1);
+        let span = debug_span!("play-chan");
+        playback_tx
+            .send_async(PlaybackMessage::SetDspPreset {
+                name,
+                result_tx,
+                span,
+            })
+            .in_current_span()
+            .await
+            .map_err(|_| Status::internal("Failed to send request via channel"))?;
+        let found = result_rx
+            .recv_async()
+            .in_current_span()
+            .await
+            .map_err(|_| Status::internal("Failed to receive response from playback channel"))?;
+        if !found {
+            return Err(Status::not_found("No DSP preset of that name"));
+        }
+        let reply = SetDspPresetResponse {};
+        Ok(Response::new(reply))
+    }
 }
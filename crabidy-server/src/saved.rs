@@ -0,0 +1,219 @@
+use crabidy_core::{
+    proto::crabidy::{LibraryNode, LibraryNodeChild, ProviderCapabilities, Track},
+    ProviderError,
+};
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, fs, path::PathBuf};
+use tracing::{debug, error, instrument, warn};
+
+const ROOT_UUID: &str = "node:saved";
+
+/// Queues saved under a name, they are stored as json in the data directory of the server and
+/// show up in the library as the `node:saved` subtree
+#[derive(Debug, Default)]
+pub struct SavedPlaylists {
+    path: PathBuf,
+    playlists: BTreeMap<u64, SavedPlaylist>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct SavedPlaylist {
+    name: String,
+    tracks: Vec<Track>,
+}
+
+impl SavedPlaylists {
+    #[instrument]
+    pub fn load(path: PathBuf) -> Self {
+        let playlists = match fs::read_to_string(&path) {
+            Ok(json) => serde_json::from_str(&json).unwrap_or_else(|err| {
+                error!("failed to parse saved playlists: {}", err);
+                BTreeMap::new()
+            }),
+            Err(err) => {
+                debug!("no saved playlists: {}", err);
+                BTreeMap::new()
+            }
+        };
+        Self { path, playlists }
+    }
+
+    pub fn is_saved(uuid: &str) -> bool {
+        uuid == ROOT_UUID || uuid.starts_with("node:saved:")
+    }
+
+    /// The saved playlists are only edited, they are not searched and their tracks belong to
+    /// other providers
+    pub fn capabilities() -> ProviderCapabilities {
+        ProviderCapabilities {
+            edit_playlists: true,
+            ..Default::default()
+        }
+    }
+
+    pub fn root_node(&self) -> LibraryNode {
+        let children = self
+            .playlists
+            .iter()
+            .map(|(id, playlist)| {
+                LibraryNodeChild::new(playlist_uuid(*id), playlist.name.clone(), true)
+            })
+            .collect();
+        LibraryNode {
+            uuid: ROOT_UUID.to_string(),
+            title: "saved".to_string(),
+            children,
+            parent: Some(LibraryNode::new().uuid),
+            tracks: Vec::new(),
+            is_queable: false,
+            capabilities: Some(Self::capabilities()),
+            artwork: Vec::new(),
+        }
+    }
+
+    pub fn get_node(&self, uuid: &str) -> Result<LibraryNode, ProviderError> {
+        if uuid == ROOT_UUID {
+            return Ok(self.root_node());
+        }
+        let id = playlist_id(uuid)?;
+        let playlist = self
+            .playlists
+            .get(&id)
+            .ok_or_else(|| ProviderError::not_found(uuid))?;
+        Ok(LibraryNode {
+            uuid: uuid.to_string(),
+            title: playlist.name.clone(),
+            children: Vec::new(),
+            parent: Some(ROOT_UUID.to_string()),
+            tracks: playlist.tracks.clone(),
+            is_queable: true,
+            capabilities: None,
+            artwork: Vec::new(),
+        })
+    }
+
+    /// Saves the tracks under the given name and returns the uuid of the playlist, a playlist with
+    /// the same name is overwritten
+    #[instrument(skip(self, tracks))]
+    pub fn save(&mut self, name: &str, tracks: Vec<Track>) -> Result<String, ProviderError> {
+        let name = name.trim();
+        let id = self
+            .playlists
+            .iter()
+            .find(|(_, p)| p.name == name)
+            .map(|(id, _)| *id)
+            .unwrap_or_else(|| self.playlists.keys().last().map_or(1, |id| id + 1));
+        self.playlists.insert(
+            id,
+            SavedPlaylist {
+                name: name.to_string(),
+                tracks,
+            },
+        );
+        self.persist()?;
+        Ok(playlist_uuid(id))
+    }
+
+    #[instrument(skip(self))]
+    pub fn rename(&mut self, uuid: &str, name: &str) -> Result<(), ProviderError> {
+        let name = name.trim();
+        let id = playlist_id(uuid)?;
+        let playlist = self
+            .playlists
+            .get_mut(&id)
+            .ok_or_else(|| ProviderError::not_found(uuid))?;
+        playlist.name = name.to_string();
+        self.persist()
+    }
+
+    #[instrument(skip(self))]
+    pub fn delete(&mut self, uuid: &str) -> Result<(), ProviderError> {
+        let id = playlist_id(uuid)?;
+        if self.playlists.remove(&id).is_none() {
+            return Err(ProviderError::not_found(uuid));
+        }
+        self.persist()
+    }
+
+    /// Writes the playlists to a temporary file first, so a crash while writing never leaves
+    /// them truncated
+    fn persist(&self) -> Result<(), ProviderError> {
+        let json = serde_json::to_string_pretty(&self.playlists)
+            .map_err(|err| ProviderError::InternalError(err.to_string()))?;
+        let write = || {
+            if let Some(dir) = self.path.parent() {
+                fs::create_dir_all(dir)?;
+            }
+            let tmp_path = self.path.with_extension("json.tmp");
+            fs::write(&tmp_path, json)?;
+            fs::rename(&tmp_path, &self.path)
+        };
+        write().map_err(|err| {
+            warn!(
+                "failed to write saved playlists to {:?}: {}",
+                self.path, err
+            );
+            ProviderError::InternalError(format!("failed to write saved playlists: {}", err))
+        })
+    }
+}
+
+fn playlist_uuid(id: u64) -> String {
+    format!("{}:{}", ROOT_UUID, id)
+}
+
+fn playlist_id(uuid: &str) -> Result<u64, ProviderError> {
+    uuid.strip_prefix("node:saved:")
+        .and_then(|id| id.parse().ok())
+        .ok_or_else(|| ProviderError::malformed_uuid(uuid))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn track(uuid: &str) -> Track {
+        Track {
+            uuid: uuid.to_string(),
+            title: uuid.to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn save_rename_delete_survive_reload() {
+        let path = std::env::temp_dir()
+            .join(format!("crabidy-saved-{}", std::process::id()))
+            .join("playlists.json");
+        let mut saved = SavedPlaylists::load(path.clone());
+        let uuid = saved
+            .save(
+                "Monday",
+                vec![track("track:tidal:1"), track("track:local:/a.flac")],
+            )
+            .unwrap();
+        assert_eq!(uuid, "node:saved:1");
+        assert!(!path.with_extension("json.tmp").exists());
+        let other = saved.save("Tuesday", vec![track("track:tidal:2")]).unwrap();
+        assert_eq!(other, "node:saved:2");
+        // saving under an existing name overwrites the playlist
+        assert_eq!(
+            saved.save("Monday", vec![track("track:tidal:3")]).unwrap(),
+            uuid
+        );
+        saved.rename(&other, "Wednesday").unwrap();
+
+        let saved = SavedPlaylists::load(path.clone());
+        let root = saved.root_node();
+        let names: Vec<&str> = root.children.iter().map(|c| c.title.as_str()).collect();
+        assert_eq!(names, vec!["Monday", "Wednesday"]);
+        let node = saved.get_node(&uuid).unwrap();
+        assert_eq!(node.tracks, vec![track("track:tidal:3")]);
+
+        let mut saved = saved;
+        saved.delete(&uuid).unwrap();
+        assert!(saved.get_node(&uuid).is_err());
+        assert!(saved.delete("node:saved:x").is_err());
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+}
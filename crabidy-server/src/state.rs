@@ -0,0 +1,58 @@
+use crabidy_server::QueueManager;
+use serde::{Deserialize, Serialize};
+use std::{fs, io, path::PathBuf};
+use tracing::{debug, error, instrument};
+
+/// Returns the directory the server keeps its data in, e.g. saved playlists and the playback
+/// state
+pub fn data_dir() -> PathBuf {
+    dirs::data_dir()
+        .or_else(dirs::config_dir)
+        .map(|d| d.join("crabidy"))
+        .unwrap_or(PathBuf::from("/tmp/crabidy"))
+}
+
+/// Everything needed to continue where the user left off after a restart of the server
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PlaybackState {
+    pub queue: QueueManager,
+    pub volume: f32,
+    #[serde(default)]
+    pub dsp_preset: Option<String>,
+}
+
+impl PlaybackState {
+    pub fn path() -> PathBuf {
+        data_dir().join("state.json")
+    }
+
+    #[instrument]
+    pub fn load(path: &PathBuf) -> Option<Self> {
+        let json = match fs::read_to_string(path) {
+            Ok(json) => json,
+            Err(err) => {
+                debug!("no playback state to restore: {}", err);
+                return None;
+            }
+        };
+        match serde_json::from_str(&json) {
+            Ok(state) => Some(state),
+            Err(err) => {
+                error!("failed to parse playback state: {}", err);
+                None
+            }
+        }
+    }
+
+    /// Writes the state to a temporary file first, so a crash while writing never leaves a
+    /// corrupted state behind
+    pub async fn save(&self, path: &PathBuf) -> io::Result<()> {
+        let json = serde_json::to_vec(self)?;
+        if let Some(dir) = path.parent() {
+            tokio::fs::create_dir_all(dir).await?;
+        }
+        let tmp_path = path.with_extension("json.tmp");
+        tokio::fs::write(&tmp_path, json).await?;
+        tokio::fs::rename(&tmp_path, path).await
+    }
+}
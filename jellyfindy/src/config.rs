@@ -0,0 +1,44 @@
+use rand::{distributions::Alphanumeric, Rng};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Settings {
+    /// Base url of the server, e.g. `http://localhost:8096`
+    pub url: String,
+    /// An api key created in the dashboard, used instead of logging in with the password
+    pub api_key: Option<String>,
+    /// The user to act for, with an api key the first user of the server if empty
+    pub username: String,
+    pub password: String,
+    /// Name the server sees requests coming from
+    pub client_name: String,
+    /// Identifies this server among the devices of the user
+    pub device_id: String,
+    pub login: LoginConfig,
+}
+
+/// Filled in after logging in, so the password is only needed when the token expires
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct LoginConfig {
+    pub access_token: Option<String>,
+    pub user_id: Option<String>,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        let device_id: String = rand::thread_rng()
+            .sample_iter(&Alphanumeric)
+            .take(16)
+            .map(char::from)
+            .collect();
+        Self {
+            url: "http://localhost:8096".to_string(),
+            api_key: None,
+            username: String::new(),
+            password: String::new(),
+            client_name: "crabidy".to_string(),
+            device_id: format!("crabidy-{}", device_id),
+            login: LoginConfig::default(),
+        }
+    }
+}
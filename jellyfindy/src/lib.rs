@@ -0,0 +1,622 @@
+//! A provider for the music libraries of a Jellyfin server
+use async_trait::async_trait;
+use crabidy_core::{
+    proto::crabidy::{LibraryNode, LibraryNodeChild, SearchResult, Track},
+    ProviderError,
+};
+use reqwest::{header::AUTHORIZATION, Client as HttpClient, Url};
+use serde::de::DeserializeOwned;
+use tracing::{debug, instrument, warn};
+pub mod config;
+pub mod models;
+pub use models::*;
+
+const SEARCH_COUNT: usize = 50;
+// formats the decoder can play as they are, everything else is transcoded to mp3
+const CONTAINERS: &str = "flac,mp3,ogg,opus,wav,m4a";
+
+#[derive(Debug)]
+pub struct Client {
+    http_client: HttpClient,
+    settings: config::Settings,
+}
+
+#[async_trait]
+impl crabidy_core::ProviderClient for Client {
+    #[instrument(skip(raw_toml_settings))]
+    async fn init(raw_toml_settings: &str) -> Result<Self, ProviderError> {
+        let settings: config::Settings = if let Ok(settings) = toml::from_str(raw_toml_settings) {
+            settings
+        } else {
+            let settings = config::Settings::default();
+            warn!(
+                "could not parse toml settings: {:#?} using default settings instead: {:#?}",
+                raw_toml_settings, settings
+            );
+            settings
+        };
+        let mut client = Self::new(settings)?;
+        client.login().await?;
+        Ok(client)
+    }
+
+    #[instrument(skip(self))]
+    fn settings(&self) -> String {
+        toml::to_string_pretty(&self.settings).unwrap_or_default()
+    }
+
+    #[instrument(skip(self))]
+    async fn get_urls_for_track(&self, track_uuid: &str) -> Result<Vec<String>, ProviderError> {
+        debug!("get_urls_for_track {}", track_uuid);
+        let id = track_id(track_uuid)?;
+        Ok(vec![self.stream_url(id)?])
+    }
+
+    #[instrument(skip(self))]
+    async fn get_metadata_for_track(&self, track_uuid: &str) -> Result<Track, ProviderError> {
+        debug!("get_metadata_for_track {}", track_uuid);
+        let id = track_id(track_uuid)?;
+        Ok((&self.item(id).await?).into())
+    }
+
+    #[instrument(skip(self))]
+    fn get_lib_root(&self) -> LibraryNode {
+        debug!("get_lib_root in jellyfindy");
+        LibraryNode {
+            uuid: "node:jellyfin".to_string(),
+            title: "jellyfin".to_string(),
+            parent: Some(LibraryNode::new().uuid),
+            tracks: Vec::new(),
+            children: vec![
+                LibraryNodeChild::new(
+                    "node:jellyfin:artists".to_string(),
+                    "artists".to_string(),
+                    false,
+                ),
+                LibraryNodeChild::new(
+                    "node:jellyfin:albums".to_string(),
+                    "albums".to_string(),
+                    false,
+                ),
+                LibraryNodeChild::new(
+                    "node:jellyfin:playlists".to_string(),
+                    "playlists".to_string(),
+                    false,
+                ),
+            ],
+            is_queable: false,
+            capabilities: None,
+            artwork: Vec::new(),
+        }
+    }
+
+    #[instrument(skip(self))]
+    async fn get_lib_node(&self, uuid: &str) -> Result<LibraryNode, ProviderError> {
+        Ok(self.get_lib_node_page(uuid, 0, 0).await?.0)
+    }
+
+    #[instrument(skip(self))]
+    async fn get_lib_node_page(
+        &self,
+        uuid: &str,
+        offset: usize,
+        limit: usize,
+    ) -> Result<(LibraryNode, usize), ProviderError> {
+        debug!("get_lib_node_page in jellyfindy {}", uuid);
+        let mut split = uuid.splitn(4, ':');
+        let (Some("node"), Some("jellyfin"), module, id) =
+            (split.next(), split.next(), split.next(), split.next())
+        else {
+            return Err(ProviderError::malformed_uuid(uuid));
+        };
+        let user_id = self.user_id()?;
+        let user_items = format!("Users/{}/Items", user_id);
+        let list_node = |title: &str| LibraryNode {
+            uuid: uuid.to_string(),
+            title: title.to_string(),
+            parent: Some("node:jellyfin".to_string()),
+            tracks: Vec::new(),
+            children: Vec::new(),
+            is_queable: false,
+            capabilities: None,
+            artwork: Vec::new(),
+        };
+        let page = match (module, id) {
+            (None, _) => {
+                let mut node = self.get_lib_root();
+                let total = node.paginate(offset, limit);
+                (node, total)
+            }
+            (Some("artists"), None) => {
+                let (artists, total) = self
+                    .items(
+                        "Artists/AlbumArtists",
+                        &[("UserId", user_id.to_string())],
+                        offset,
+                        limit,
+                    )
+                    .await?;
+                let mut node = list_node("artists");
+                node.children = artists.iter().map(LibraryNodeChild::from).collect();
+                (node, total)
+            }
+            (Some("albums"), None) => {
+                let (albums, total) = self
+                    .items(
+                        &user_items,
+                        &[
+                            ("IncludeItemTypes", "MusicAlbum".to_string()),
+                            ("Recursive", "true".to_string()),
+                            ("SortBy", "SortName".to_string()),
+                        ],
+                        offset,
+                        limit,
+                    )
+                    .await?;
+                let mut node = list_node("albums");
+                node.children = albums.iter().map(LibraryNodeChild::from).collect();
+                (node, total)
+            }
+            (Some("playlists"), None) => {
+                let (playlists, total) = self
+                    .items(
+                        &user_items,
+                        &[
+                            ("IncludeItemTypes", "Playlist".to_string()),
+                            ("Recursive", "true".to_string()),
+                            ("SortBy", "SortName".to_string()),
+                        ],
+                        offset,
+                        limit,
+                    )
+                    .await?;
+                let mut node = list_node("playlists");
+                node.children = playlists.iter().map(LibraryNodeChild::from).collect();
+                (node, total)
+            }
+            (Some("artist"), Some(id)) => {
+                let artist = self.item(id).await?;
+                let (albums, total) = self
+                    .items(
+                        &user_items,
+                        &[
+                            ("IncludeItemTypes", "MusicAlbum".to_string()),
+                            ("Recursive", "true".to_string()),
+                            ("AlbumArtistIds", id.to_string()),
+                            ("SortBy", "ProductionYear,SortName".to_string()),
+                        ],
+                        offset,
+                        limit,
+                    )
+                    .await?;
+                let node = LibraryNode {
+                    uuid: uuid.to_string(),
+                    title: artist.name,
+                    parent: Some("node:jellyfin:artists".to_string()),
+                    tracks: Vec::new(),
+                    children: albums.iter().map(LibraryNodeChild::from).collect(),
+                    is_queable: true,
+                    capabilities: None,
+                    artwork: Vec::new(),
+                };
+                (node, total)
+            }
+            (Some("album"), Some(id)) => {
+                let album = self.item(id).await?;
+                let (tracks, total) = self
+                    .items(
+                        &user_items,
+                        &[
+                            ("ParentId", id.to_string()),
+                            ("IncludeItemTypes", "Audio".to_string()),
+                            (
+                                "SortBy",
+                                "ParentIndexNumber,IndexNumber,SortName".to_string(),
+                            ),
+                        ],
+                        offset,
+                        limit,
+                    )
+                    .await?;
+                let parent = match album.album_artists.first() {
+                    Some(artist) => format!("node:jellyfin:artist:{}", artist.id),
+                    None => "node:jellyfin:albums".to_string(),
+                };
+                let node = LibraryNode {
+                    uuid: uuid.to_string(),
+                    title: album.name,
+                    parent: Some(parent),
+                    tracks: tracks.iter().map(Track::from).collect(),
+                    children: Vec::new(),
+                    is_queable: true,
+                    capabilities: None,
+                    artwork: Vec::new(),
+                };
+                (node, total)
+            }
+            (Some("playlist"), Some(id)) => {
+                let playlist = self.item(id).await?;
+                let (tracks, total) = self
+                    .items(
+                        &format!("Playlists/{}/Items", id),
+                        &[("UserId", user_id.to_string())],
+                        offset,
+                        limit,
+                    )
+                    .await?;
+                let node = LibraryNode {
+                    uuid: uuid.to_string(),
+                    title: playlist.name,
+                    parent: Some("node:jellyfin:playlists".to_string()),
+                    tracks: tracks.iter().map(Track::from).collect(),
+                    children: Vec::new(),
+                    is_queable: true,
+                    capabilities: None,
+                    artwork: Vec::new(),
+                };
+                (node, total)
+            }
+            _ => return Err(ProviderError::malformed_uuid(uuid)),
+        };
+        Ok(page)
+    }
+
+    #[instrument(skip(self))]
+    async fn search(&self, query: &str) -> Result<SearchResult, ProviderError> {
+        debug!("search in jellyfindy {}", query);
+        let (found, _) = self
+            .items(
+                &format!("Users/{}/Items", self.user_id()?),
+                &[
+                    ("SearchTerm", query.to_string()),
+                    (
+                        "IncludeItemTypes",
+                        "MusicArtist,MusicAlbum,Audio,Playlist".to_string(),
+                    ),
+                    ("Recursive", "true".to_string()),
+                ],
+                0,
+                SEARCH_COUNT,
+            )
+            .await?;
+        let mut result = SearchResult::default();
+        for item in &found {
+            match item.kind.as_str() {
+                "MusicArtist" => result.artists.push(item.into()),
+                "MusicAlbum" => result.albums.push(item.into()),
+                "Playlist" => result.playlists.push(item.into()),
+                "Audio" => result.tracks.push(item.into()),
+                _ => {}
+            }
+        }
+        Ok(result)
+    }
+}
+
+impl Client {
+    pub fn new(settings: config::Settings) -> Result<Self, ProviderError> {
+        let http_client = HttpClient::builder()
+            .build()
+            .map_err(|err| ProviderError::InternalError(err.to_string()))?;
+        Ok(Self {
+            http_client,
+            settings,
+        })
+    }
+
+    /// Uses the api key if there is one, otherwise the token of the last login if it is still
+    /// valid or logs in again with username and password
+    #[instrument(skip(self))]
+    pub async fn login(&mut self) -> Result<(), ProviderError> {
+        if let Some(api_key) = self.settings.api_key.clone().filter(|key| !key.is_empty()) {
+            self.settings.login.access_token = Some(api_key);
+            let users: Vec<User> = self.get("Users", &[]).await?;
+            let username = &self.settings.username;
+            let user = users
+                .into_iter()
+                .find(|user| username.is_empty() || user.name == *username)
+                .ok_or_else(|| ProviderError::UnknownUser {
+                    provider: "jellyfin".to_string(),
+                })?;
+            self.settings.login.user_id = Some(user.id);
+            return Ok(());
+        }
+        if self.settings.login.access_token.is_some() {
+            match self.get::<User>("Users/Me", &[]).await {
+                Ok(user) => {
+                    self.settings.login.user_id = Some(user.id);
+                    return Ok(());
+                }
+                Err(err) => debug!("stored token is not valid anymore: {}", err),
+            }
+        }
+        self.settings.login.access_token = None;
+        let auth: AuthenticationResult = self
+            .http_client
+            .post(self.endpoint_url("Users/AuthenticateByName"))
+            .header(AUTHORIZATION, self.authorization())
+            .json(&AuthenticateByName {
+                username: self.settings.username.clone(),
+                pw: self.settings.password.clone(),
+            })
+            .send()
+            .await
+            .and_then(|r| r.error_for_status())
+            .map_err(login_error)?
+            .json()
+            .await
+            .map_err(login_error)?;
+        self.settings.login.access_token = Some(auth.access_token);
+        self.settings.login.user_id = Some(auth.user.id);
+        Ok(())
+    }
+
+    /// An url to stream the track from, the server sends the file as it is if the decoder can
+    /// play it and transcodes it otherwise. It carries the token so it can be played without
+    /// any further requests.
+    pub fn stream_url(&self, id: &str) -> Result<String, ProviderError> {
+        let params = [
+            ("UserId", self.user_id()?.to_string()),
+            ("DeviceId", self.settings.device_id.clone()),
+            (
+                "api_key",
+                self.settings.login.access_token.clone().unwrap_or_default(),
+            ),
+            ("Container", CONTAINERS.to_string()),
+            ("TranscodingContainer", "mp3".to_string()),
+            ("TranscodingProtocol", "http".to_string()),
+            ("AudioCodec", "mp3".to_string()),
+        ];
+        Url::parse_with_params(
+            &self.endpoint_url(&format!("Audio/{}/universal", id)),
+            &params,
+        )
+        .map(String::from)
+        .map_err(|err| ProviderError::Config(format!("invalid jellyfin url: {}", err)))
+    }
+
+    #[instrument(skip(self))]
+    pub async fn item(&self, id: &str) -> Result<Item, ProviderError> {
+        self.get(&format!("Users/{}/Items/{}", self.user_id()?, id), &[])
+            .await
+    }
+
+    /// Fetches `limit` items starting at `offset`, or all of them from the offset on with a limit
+    /// of 0, and returns them with the total number of items
+    #[instrument(skip(self))]
+    pub async fn items(
+        &self,
+        path: &str,
+        query: &[(&str, String)],
+        offset: usize,
+        limit: usize,
+    ) -> Result<(Vec<Item>, usize), ProviderError> {
+        let mut params = query.to_vec();
+        params.push(("StartIndex", offset.to_string()));
+        if limit > 0 {
+            params.push(("Limit", limit.to_string()));
+        }
+        let items: Items = self.get(path, &params).await?;
+        Ok((items.items, items.total_record_count))
+    }
+
+    #[instrument(skip(self))]
+    pub async fn get<T: DeserializeOwned>(
+        &self,
+        path: &str,
+        query: &[(&str, String)],
+    ) -> Result<T, ProviderError> {
+        debug!("get {}", path);
+        self.http_client
+            .get(self.endpoint_url(path))
+            .header(AUTHORIZATION, self.authorization())
+            .query(query)
+            .send()
+            .await
+            .and_then(|r| r.error_for_status())
+            .map_err(fetch_error)?
+            .json()
+            .await
+            .map_err(fetch_error)
+    }
+
+    fn user_id(&self) -> Result<&str, ProviderError> {
+        self.settings
+            .login
+            .user_id
+            .as_deref()
+            .ok_or_else(|| ProviderError::UnknownUser {
+                provider: "jellyfin".to_string(),
+            })
+    }
+
+    fn endpoint_url(&self, path: &str) -> String {
+        format!("{}/{}", self.settings.url.trim_end_matches('/'), path)
+    }
+
+    fn authorization(&self) -> String {
+        let mut authorization = format!(
+            r#"MediaBrowser Client="{}", Device="crabidy", DeviceId="{}", Version="{}""#,
+            self.settings.client_name,
+            self.settings.device_id,
+            env!("CARGO_PKG_VERSION")
+        );
+        if let Some(token) = &self.settings.login.access_token {
+            authorization.push_str(&format!(r#", Token="{}""#, token));
+        }
+        authorization
+    }
+}
+
+fn fetch_error(err: reqwest::Error) -> ProviderError {
+    ProviderError::fetch("jellyfin", err.status().map(|s| s.as_u16()), err)
+}
+
+fn login_error(err: reqwest::Error) -> ProviderError {
+    ProviderError::CouldNotLogin {
+        provider: "jellyfin".to_string(),
+        cause: err.to_string(),
+    }
+}
+
+fn track_id(uuid: &str) -> Result<&str, ProviderError> {
+    uuid.strip_prefix("track:jellyfin:")
+        .ok_or_else(|| ProviderError::malformed_uuid(uuid))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crabidy_core::ProviderClient;
+    use wiremock::{matchers::path, Mock, MockServer, Request, ResponseTemplate};
+
+    /// Answers requests for the paths of the routes with their json and everything else with a
+    /// 404
+    async fn stub_server(routes: Vec<(&'static str, &'static str)>) -> MockServer {
+        let server = MockServer::start().await;
+        for (route, body) in routes {
+            Mock::given(path(route))
+                .respond_with(ResponseTemplate::new(200).set_body_raw(body, "application/json"))
+                .mount(&server)
+                .await;
+        }
+        server
+    }
+
+    async fn requests(server: &MockServer) -> Vec<Request> {
+        server.received_requests().await.unwrap()
+    }
+
+    fn authorization(request: &Request) -> &str {
+        request
+            .headers
+            .get("authorization")
+            .and_then(|value| value.to_str().ok())
+            .unwrap_or_default()
+    }
+
+    fn settings(url: String) -> config::Settings {
+        config::Settings {
+            url,
+            username: "crab".to_string(),
+            password: "secret".to_string(),
+            device_id: "test-device".to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn logs_in_with_the_password_and_maps_albums() {
+        let server = stub_server(vec![
+            (
+                "/Users/AuthenticateByName",
+                r#"{"AccessToken":"tok","User":{"Id":"u-1","Name":"crab"}}"#,
+            ),
+            (
+                "/Users/u-1/Items/al-1",
+                r#"{"Id":"al-1","Name":"Album","Type":"MusicAlbum",
+                    "AlbumArtists":[{"Id":"ar-1","Name":"Band"}]}"#,
+            ),
+            (
+                "/Users/u-1/Items",
+                r#"{"Items":[
+                    {"Id":"1","Name":"First","Type":"Audio","Album":"Album","AlbumId":"al-1",
+                     "Artists":["Band"],"ProductionYear":2001,"RunTimeTicks":610000000,
+                     "IndexNumber":1,"ParentIndexNumber":1,"NormalizationGain":-6.2},
+                    {"Id":"2","Name":"Second","Type":"Audio","Album":"Album","AlbumArtist":"Band"}
+                ],"TotalRecordCount":2}"#,
+            ),
+        ])
+        .await;
+        let mut client = Client::new(settings(server.uri())).unwrap();
+        client.login().await.unwrap();
+        let login = &requests(&server).await[0];
+        assert!(authorization(login).contains(r#"DeviceId="test-device""#));
+        assert!(!authorization(login).contains("Token="));
+
+        let node = client
+            .get_lib_node("node:jellyfin:album:al-1")
+            .await
+            .unwrap();
+        assert_eq!(node.title, "Album");
+        assert_eq!(node.parent.as_deref(), Some("node:jellyfin:artist:ar-1"));
+        let uuids: Vec<&str> = node.tracks.iter().map(|t| t.uuid.as_str()).collect();
+        assert_eq!(uuids, vec!["track:jellyfin:1", "track:jellyfin:2"]);
+        assert_eq!(node.tracks[0].duration, Some(61000));
+        assert_eq!(node.tracks[1].artist, "Band");
+        assert_eq!(node.tracks[0].track_number, Some(1));
+        assert_eq!(node.tracks[0].replay_gain, Some(-6.2));
+        assert_eq!(
+            node.tracks[0].album.as_ref().unwrap().uuid.as_deref(),
+            Some("node:jellyfin:album:al-1")
+        );
+
+        let requests = requests(&server).await;
+        assert!(authorization(&requests[1]).contains(r#"Token="tok""#));
+        let query = requests[2].url.query().unwrap_or_default();
+        assert!(query.contains("ParentId=al-1"));
+        assert!(client.settings().contains("tok"));
+    }
+
+    #[tokio::test]
+    async fn api_keys_pick_the_configured_user() {
+        let server = stub_server(vec![(
+            "/Users",
+            r#"[{"Id":"u-1","Name":"crab"},{"Id":"u-2","Name":"other"}]"#,
+        )])
+        .await;
+        let mut client = Client::new(config::Settings {
+            api_key: Some("key".to_string()),
+            username: "other".to_string(),
+            ..settings(server.uri())
+        })
+        .unwrap();
+        client.login().await.unwrap();
+
+        let urls = client
+            .get_urls_for_track("track:jellyfin:42")
+            .await
+            .unwrap();
+        let url = Url::parse(&urls[0]).unwrap();
+        assert_eq!(url.path(), "/Audio/42/universal");
+        let params: Vec<(String, String)> = url.query_pairs().into_owned().collect();
+        assert!(params.contains(&("api_key".to_string(), "key".to_string())));
+        assert!(params.contains(&("UserId".to_string(), "u-2".to_string())));
+    }
+
+    #[tokio::test]
+    async fn failed_logins_are_reported() {
+        let server = stub_server(Vec::new()).await;
+        let mut client = Client::new(settings(server.uri())).unwrap();
+        assert!(matches!(
+            client.login().await,
+            Err(ProviderError::CouldNotLogin { .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn pages_are_requested_from_the_server() {
+        let server = stub_server(vec![(
+            "/Artists/AlbumArtists",
+            r#"{"Items":[
+                {"Id":"ar-3","Name":"C","Type":"MusicArtist"},
+                {"Id":"ar-4","Name":"D","Type":"MusicArtist"}
+            ],"TotalRecordCount":10}"#,
+        )])
+        .await;
+        let mut settings = settings(server.uri());
+        settings.login.access_token = Some("tok".to_string());
+        settings.login.user_id = Some("u-1".to_string());
+        let client = Client::new(settings).unwrap();
+        let (node, total) = client
+            .get_lib_node_page("node:jellyfin:artists", 2, 2)
+            .await
+            .unwrap();
+        assert_eq!(total, 10);
+        assert_eq!(node.children[0].uuid, "node:jellyfin:artist:ar-3");
+        let requests = requests(&server).await;
+        let query = requests[0].url.query().unwrap_or_default();
+        assert!(query.contains("StartIndex=2"));
+        assert!(query.contains("Limit=2"));
+    }
+}
@@ -0,0 +1,114 @@
+use crabidy_core::proto::crabidy::{Album, Artist, LibraryNodeChild, Track};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct AuthenticateByName {
+    pub username: String,
+    pub pw: String,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, rename_all = "PascalCase")]
+pub struct AuthenticationResult {
+    pub access_token: String,
+    pub user: User,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, rename_all = "PascalCase")]
+pub struct User {
+    pub id: String,
+    pub name: String,
+}
+
+/// A page of items, `total_record_count` is the number of items of all pages
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, rename_all = "PascalCase")]
+pub struct Items {
+    pub items: Vec<Item>,
+    pub total_record_count: usize,
+}
+
+/// Artists, albums, playlists and tracks are all items, told apart by their `Type`
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, rename_all = "PascalCase")]
+pub struct Item {
+    pub id: String,
+    pub name: String,
+    #[serde(rename = "Type")]
+    pub kind: String,
+    pub album: Option<String>,
+    pub album_id: Option<String>,
+    pub album_artist: Option<String>,
+    pub artists: Vec<String>,
+    pub album_artists: Vec<NameIdPair>,
+    pub production_year: Option<u32>,
+    // in ticks of 100 nanoseconds
+    pub run_time_ticks: Option<u64>,
+    // the number of the track and of its disc
+    pub index_number: Option<u32>,
+    pub parent_index_number: Option<u32>,
+    // in dB, of the track on its own
+    pub normalization_gain: Option<f32>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, rename_all = "PascalCase")]
+pub struct NameIdPair {
+    pub id: String,
+    pub name: String,
+}
+
+impl From<&Item> for Track {
+    fn from(item: &Item) -> Self {
+        Self {
+            uuid: format!("track:jellyfin:{}", item.id),
+            title: item.name.clone(),
+            artist: item
+                .artists
+                .first()
+                .or(item.album_artist.as_ref())
+                .cloned()
+                .unwrap_or_default(),
+            album: item.album.as_ref().map(|album| Album {
+                title: album.clone(),
+                release_date: item.production_year.map(|y| y.to_string()),
+                artwork: Vec::new(),
+                uuid: item
+                    .album_id
+                    .as_ref()
+                    .map(|id| format!("node:jellyfin:album:{}", id)),
+            }),
+            duration: item.run_time_ticks.map(|t| (t / 10_000) as u32),
+            artwork: Vec::new(),
+            artists: item
+                .artists
+                .iter()
+                .map(|name| Artist {
+                    name: name.clone(),
+                    uuid: None,
+                })
+                .collect(),
+            track_number: item.index_number,
+            volume_number: item.parent_index_number,
+            replay_gain: item.normalization_gain,
+            ..Default::default()
+        }
+    }
+}
+
+impl From<&Item> for LibraryNodeChild {
+    fn from(item: &Item) -> Self {
+        let module = match item.kind.as_str() {
+            "MusicArtist" => "artist",
+            "Playlist" => "playlist",
+            _ => "album",
+        };
+        Self::new(
+            format!("node:jellyfin:{}:{}", module, item.id),
+            item.name.clone(),
+            true,
+        )
+    }
+}
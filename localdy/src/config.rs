@@ -0,0 +1,23 @@
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Settings {
+    pub music_dirs: Vec<PathBuf>,
+    pub extensions: Vec<String>,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        let music_dir = dirs::audio_dir()
+            .or_else(|| dirs::home_dir().map(|home| home.join("Music")))
+            .unwrap_or(PathBuf::from("/music"));
+        Self {
+            music_dirs: vec![music_dir],
+            extensions: ["flac", "mp3", "ogg", "opus", "m4a", "wav"]
+                .iter()
+                .map(|e| e.to_string())
+                .collect(),
+        }
+    }
+}
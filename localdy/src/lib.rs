@@ -0,0 +1,467 @@
+//! A provider for music files on the machine running the server
+use std::{
+    collections::{BTreeMap, HashMap},
+    path::{Path, PathBuf},
+};
+
+use async_trait::async_trait;
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use crabidy_core::proto::crabidy::{
+    LibraryNode, LibraryNodeChild, Lyrics, ProviderCapabilities, SearchResult, Track,
+};
+use tracing::{debug, instrument, warn};
+pub mod config;
+pub mod models;
+mod scanner;
+pub use models::*;
+
+const MAX_SEARCH_TRACKS: usize = 100;
+
+#[derive(Debug)]
+pub struct Client {
+    settings: config::Settings,
+    library: Library,
+}
+
+#[derive(Debug, Default)]
+struct Library {
+    // keyed by the path of the file
+    tracks: HashMap<String, LocalTrack>,
+    // album artist -> album -> paths of the tracks
+    artists: BTreeMap<String, BTreeMap<String, Vec<String>>>,
+}
+
+impl Library {
+    fn new(tracks: Vec<LocalTrack>) -> Self {
+        let mut library = Self::default();
+        for track in tracks {
+            let path = track.path.to_string_lossy().to_string();
+            library
+                .artists
+                .entry(track.album_artist.clone())
+                .or_default()
+                .entry(track.album.clone())
+                .or_default()
+                .push(path.clone());
+            library.tracks.insert(path, track);
+        }
+        for albums in library.artists.values_mut() {
+            for paths in albums.values_mut() {
+                paths.sort_by_key(|p| {
+                    let t = &library.tracks[p];
+                    (
+                        t.disc_number.unwrap_or(0),
+                        t.track_number.unwrap_or(0),
+                        t.title.clone(),
+                    )
+                });
+            }
+        }
+        library
+    }
+}
+
+#[async_trait]
+impl crabidy_core::ProviderClient for Client {
+    #[instrument(skip(raw_toml_settings))]
+    async fn init(raw_toml_settings: &str) -> Result<Self, crabidy_core::ProviderError> {
+        let settings: config::Settings = if let Ok(settings) = toml::from_str(raw_toml_settings) {
+            settings
+        } else {
+            let settings = config::Settings::default();
+            warn!(
+                "could not parse toml settings: {:#?} using default settings instead: {:#?}",
+                raw_toml_settings, settings
+            );
+            settings
+        };
+        Self::new(settings).await
+    }
+
+    #[instrument(skip(self))]
+    fn settings(&self) -> String {
+        toml::to_string_pretty(&self.settings).unwrap_or_default()
+    }
+
+    #[instrument(skip(self))]
+    async fn get_urls_for_track(
+        &self,
+        track_uuid: &str,
+    ) -> Result<Vec<String>, crabidy_core::ProviderError> {
+        debug!("get_urls_for_track {}", track_uuid);
+        let path = self.music_path(track_uuid)?;
+        if !path.is_file() {
+            return Err(crabidy_core::ProviderError::not_found(track_uuid));
+        }
+        Ok(vec![path.to_string_lossy().to_string()])
+    }
+
+    #[instrument(skip(self))]
+    async fn get_metadata_for_track(
+        &self,
+        track_uuid: &str,
+    ) -> Result<Track, crabidy_core::ProviderError> {
+        debug!("get_metadata_for_track {}", track_uuid);
+        let path = path_from_uuid(track_uuid)?;
+        if let Some(track) = self.library.tracks.get(path) {
+            return Ok(track.into());
+        }
+        // The file might have been added after the last scan
+        let path = self.music_path(track_uuid)?;
+        tokio::task::spawn_blocking(move || scanner::read_track(&path))
+            .await
+            .map_err(|err| crabidy_core::ProviderError::InternalError(err.to_string()))?
+            .map(|track| track.into())
+            .ok_or_else(|| crabidy_core::ProviderError::not_found(track_uuid))
+    }
+
+    #[instrument(skip(self))]
+    fn get_lib_root(&self) -> LibraryNode {
+        debug!("get_lib_root in localdy");
+        let global_root = LibraryNode::new();
+        let children = self
+            .library
+            .artists
+            .keys()
+            .map(|artist| {
+                LibraryNodeChild::new(
+                    format!("node:local:artist:{}", encode_name(artist)),
+                    artist.clone(),
+                    true,
+                )
+            })
+            .collect();
+        LibraryNode {
+            uuid: "node:local".to_string(),
+            title: "local".to_string(),
+            parent: Some(global_root.uuid),
+            tracks: Vec::new(),
+            children,
+            is_queable: false,
+            capabilities: None,
+            artwork: Vec::new(),
+        }
+    }
+
+    fn capabilities(&self) -> ProviderCapabilities {
+        ProviderCapabilities {
+            lyrics: true,
+            ..ProviderCapabilities::basic()
+        }
+    }
+
+    /// Lyrics from a `.lrc` file next to the track, e.g. `song.lrc` for `song.flac`, or from the
+    /// tags of the track
+    #[instrument(skip(self))]
+    async fn get_lyrics(
+        &self,
+        track_uuid: &str,
+    ) -> Result<Option<Lyrics>, crabidy_core::ProviderError> {
+        debug!("get_lyrics {}", track_uuid);
+        let path = self.music_path(track_uuid)?;
+        let text = tokio::task::spawn_blocking(move || {
+            std::fs::read_to_string(path.with_extension("lrc"))
+                .ok()
+                .or_else(|| scanner::read_lyrics(&path))
+        })
+        .await
+        .map_err(|err| crabidy_core::ProviderError::InternalError(err.to_string()))?;
+        Ok(text
+            .map(|text| Lyrics::parse(&text))
+            .filter(|lyrics| !lyrics.lines.is_empty()))
+    }
+
+    #[instrument(skip(self))]
+    async fn get_lib_node(&self, uuid: &str) -> Result<LibraryNode, crabidy_core::ProviderError> {
+        debug!("get_lib_node in localdy {}", uuid);
+        let mut split = uuid.splitn(4, ':');
+        let (Some("node"), Some("local"), module, id) =
+            (split.next(), split.next(), split.next(), split.next())
+        else {
+            return Err(crabidy_core::ProviderError::malformed_uuid(uuid));
+        };
+        match (module, id) {
+            (None, _) => Ok(self.get_lib_root()),
+            (Some("artist"), Some(artist_id)) => {
+                let artist = decode_name(artist_id)?;
+                let albums = self
+                    .library
+                    .artists
+                    .get(&artist)
+                    .ok_or_else(|| crabidy_core::ProviderError::not_found(uuid))?;
+                let children = albums
+                    .keys()
+                    .map(|album| {
+                        LibraryNodeChild::new(
+                            format!("node:local:album:{}:{}", artist_id, encode_name(album)),
+                            album.clone(),
+                            true,
+                        )
+                    })
+                    .collect();
+                Ok(LibraryNode {
+                    uuid: uuid.to_string(),
+                    title: artist,
+                    parent: Some("node:local".to_string()),
+                    tracks: Vec::new(),
+                    children,
+                    is_queable: true,
+                    capabilities: None,
+                    artwork: Vec::new(),
+                })
+            }
+            (Some("album"), Some(ids)) => {
+                let Some((artist_id, album_id)) = ids.split_once(':') else {
+                    return Err(crabidy_core::ProviderError::malformed_uuid(uuid));
+                };
+                let artist = decode_name(artist_id)?;
+                let album = decode_name(album_id)?;
+                let paths = self
+                    .library
+                    .artists
+                    .get(&artist)
+                    .and_then(|albums| albums.get(&album))
+                    .ok_or_else(|| crabidy_core::ProviderError::not_found(uuid))?;
+                let tracks = paths
+                    .iter()
+                    .filter_map(|p| self.library.tracks.get(p))
+                    .map(|t| t.into())
+                    .collect();
+                Ok(LibraryNode {
+                    uuid: uuid.to_string(),
+                    title: album,
+                    parent: Some(format!("node:local:artist:{}", artist_id)),
+                    tracks,
+                    children: Vec::new(),
+                    is_queable: true,
+                    capabilities: None,
+                    artwork: Vec::new(),
+                })
+            }
+            _ => Err(crabidy_core::ProviderError::malformed_uuid(uuid)),
+        }
+    }
+
+    #[instrument(skip(self))]
+    async fn search(&self, query: &str) -> Result<SearchResult, crabidy_core::ProviderError> {
+        debug!("search in localdy {}", query);
+        let query = query.to_lowercase();
+        let matches = |s: &str| s.to_lowercase().contains(&query);
+        let mut result = SearchResult::default();
+        for (artist, albums) in &self.library.artists {
+            let artist_id = encode_name(artist);
+            if matches(artist) {
+                result.artists.push(LibraryNodeChild::new(
+                    format!("node:local:artist:{}", artist_id),
+                    artist.clone(),
+                    true,
+                ));
+            }
+            for (album, paths) in albums {
+                if matches(album) {
+                    result.albums.push(LibraryNodeChild::new(
+                        format!("node:local:album:{}:{}", artist_id, encode_name(album)),
+                        album.clone(),
+                        true,
+                    ));
+                }
+                result.tracks.extend(
+                    paths
+                        .iter()
+                        .filter_map(|p| self.library.tracks.get(p))
+                        .filter(|t| matches(&t.title))
+                        .map(Track::from),
+                );
+            }
+        }
+        result.tracks.truncate(MAX_SEARCH_TRACKS);
+        Ok(result)
+    }
+}
+
+impl Client {
+    pub async fn new(settings: config::Settings) -> Result<Self, crabidy_core::ProviderError> {
+        let scan_settings = settings.clone();
+        let tracks = tokio::task::spawn_blocking(move || scanner::scan(&scan_settings))
+            .await
+            .map_err(|err| crabidy_core::ProviderError::InternalError(err.to_string()))?;
+        Ok(Self {
+            settings,
+            library: Library::new(tracks),
+        })
+    }
+
+    /// The path of the track, as long as it is in one of the music dirs. The uuids come from
+    /// clients, which must not get to open any other file of the machine
+    fn music_path(&self, track_uuid: &str) -> Result<PathBuf, crabidy_core::ProviderError> {
+        let path = path_from_uuid(track_uuid)?;
+        if self.library.tracks.contains_key(path) {
+            return Ok(PathBuf::from(path));
+        }
+        // resolves `..` and symlinks, so neither leads out of the music dirs
+        let path = Path::new(path)
+            .canonicalize()
+            .map_err(|_| crabidy_core::ProviderError::not_found(track_uuid))?;
+        let in_music_dir = self
+            .settings
+            .music_dirs
+            .iter()
+            .filter_map(|dir| dir.canonicalize().ok())
+            .any(|dir| path.starts_with(dir));
+        if !in_music_dir {
+            warn!("refusing {:?} outside of the music dirs", path);
+            return Err(crabidy_core::ProviderError::not_found(track_uuid));
+        }
+        Ok(path)
+    }
+}
+
+fn path_from_uuid(uuid: &str) -> Result<&str, crabidy_core::ProviderError> {
+    uuid.strip_prefix("track:local:")
+        .ok_or_else(|| crabidy_core::ProviderError::malformed_uuid(uuid))
+}
+
+// Artist and album names can contain any character, including the `:` used as separator in the
+// uuids, so they are encoded
+fn encode_name(name: &str) -> String {
+    URL_SAFE_NO_PAD.encode(name)
+}
+
+fn decode_name(encoded: &str) -> Result<String, crabidy_core::ProviderError> {
+    let bytes = URL_SAFE_NO_PAD
+        .decode(encoded)
+        .map_err(|_| crabidy_core::ProviderError::malformed_uuid(encoded))?;
+    String::from_utf8(bytes).map_err(|_| crabidy_core::ProviderError::malformed_uuid(encoded))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn track(path: &str, album: &str, number: u32) -> LocalTrack {
+        LocalTrack {
+            path: PathBuf::from(path),
+            title: path.to_string(),
+            artist: "Artist: The Band".to_string(),
+            album_artist: "Artist: The Band".to_string(),
+            album: album.to_string(),
+            release_date: None,
+            track_number: Some(number),
+            disc_number: None,
+            duration: None,
+            track_gain: None,
+            track_peak: None,
+            album_gain: None,
+            album_peak: None,
+        }
+    }
+
+    #[test]
+    fn names_survive_encoding() {
+        let name = "AC/DC: Live at River Plate";
+        assert_eq!(decode_name(&encode_name(name)).unwrap(), name);
+        assert!(!encode_name(name).contains(':'));
+    }
+
+    #[test]
+    fn library_sorts_albums_by_track_number() {
+        let library = Library::new(vec![
+            track("/music/b.flac", "Album", 2),
+            track("/music/a.flac", "Album", 1),
+            track("/music/c.flac", "Other", 1),
+        ]);
+        let albums = &library.artists["Artist: The Band"];
+        assert_eq!(albums.len(), 2);
+        assert_eq!(albums["Album"], vec!["/music/a.flac", "/music/b.flac"]);
+    }
+
+    #[tokio::test]
+    async fn browse_artist_album_track() {
+        use crabidy_core::ProviderClient;
+        let client = Client {
+            settings: config::Settings::default(),
+            library: Library::new(vec![track("/music/a.flac", "Album", 1)]),
+        };
+        let root = client.get_lib_root();
+        assert_eq!(root.children.len(), 1);
+        let artist = client.get_lib_node(&root.children[0].uuid).await.unwrap();
+        assert_eq!(artist.title, "Artist: The Band");
+        let album = client.get_lib_node(&artist.children[0].uuid).await.unwrap();
+        assert_eq!(album.title, "Album");
+        assert_eq!(album.parent, Some(artist.uuid));
+        assert_eq!(album.tracks[0].uuid, "track:local:/music/a.flac");
+    }
+
+    #[tokio::test]
+    async fn search_is_case_insensitive() {
+        use crabidy_core::ProviderClient;
+        let client = Client {
+            settings: config::Settings::default(),
+            library: Library::new(vec![
+                track("/music/a.flac", "Greatest Hits", 1),
+                track("/music/hits.flac", "Other", 1),
+            ]),
+        };
+        let result = client.search("HITS").await.unwrap();
+        assert_eq!(result.albums.len(), 1);
+        assert_eq!(result.tracks.len(), 1);
+        assert!(result.artists.is_empty());
+        let result = client.search("the band").await.unwrap();
+        assert_eq!(result.artists.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn lyrics_are_read_from_lrc_files() {
+        use crabidy_core::ProviderClient;
+        let dir = std::env::temp_dir().join(format!("localdy-lyrics-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("song.lrc"), "[00:01.50]First\n[00:03.00]Second\n").unwrap();
+        std::fs::write(dir.join("song.flac"), "").unwrap();
+        std::fs::write(dir.join("other.flac"), "").unwrap();
+        let client = Client {
+            settings: config::Settings {
+                music_dirs: vec![dir.clone()],
+                ..Default::default()
+            },
+            library: Library::default(),
+        };
+        let uuid = format!("track:local:{}", dir.join("song.flac").display());
+        let lyrics = client.get_lyrics(&uuid).await.unwrap().unwrap();
+        assert!(lyrics.synced);
+        assert_eq!(lyrics.lines[1].text, "Second");
+        let uuid = format!("track:local:{}", dir.join("other.flac").display());
+        assert_eq!(client.get_lyrics(&uuid).await.unwrap(), None);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn files_outside_the_music_dirs_are_not_found() {
+        use crabidy_core::ProviderClient;
+        let dir = std::env::temp_dir().join(format!("localdy-outside-{}", std::process::id()));
+        let music = dir.join("music");
+        std::fs::create_dir_all(&music).unwrap();
+        std::fs::write(music.join("song.flac"), "").unwrap();
+        std::fs::write(dir.join("secret.flac"), "").unwrap();
+        let client = Client {
+            settings: config::Settings {
+                music_dirs: vec![music.clone()],
+                ..Default::default()
+            },
+            library: Library::default(),
+        };
+        let uuid = |path: PathBuf| format!("track:local:{}", path.display());
+        assert!(client
+            .get_urls_for_track(&uuid(music.join("song.flac")))
+            .await
+            .is_ok());
+        for path in [dir.join("secret.flac"), music.join("../secret.flac")] {
+            assert!(matches!(
+                client.get_urls_for_track(&uuid(path.clone())).await,
+                Err(crabidy_core::ProviderError::NotFound { .. })
+            ));
+            assert!(client.get_metadata_for_track(&uuid(path)).await.is_err());
+        }
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
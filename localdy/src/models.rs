@@ -0,0 +1,69 @@
+use std::{path::PathBuf, time::Duration};
+
+use crate::encode_name;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct LocalTrack {
+    pub path: PathBuf,
+    pub title: String,
+    pub artist: String,
+    pub album_artist: String,
+    pub album: String,
+    pub release_date: Option<String>,
+    pub track_number: Option<u32>,
+    pub disc_number: Option<u32>,
+    pub duration: Option<Duration>,
+    // ReplayGain in dB and peaks in full scale
+    pub track_gain: Option<f32>,
+    pub track_peak: Option<f32>,
+    pub album_gain: Option<f32>,
+    pub album_peak: Option<f32>,
+}
+
+impl LocalTrack {
+    pub fn uuid(&self) -> String {
+        format!("track:local:{}", self.path.to_string_lossy())
+    }
+}
+
+impl From<&LocalTrack> for crabidy_core::proto::crabidy::Track {
+    fn from(track: &LocalTrack) -> Self {
+        // the library is browsed by album artist
+        let artist_id = encode_name(&track.album_artist);
+        Self {
+            uuid: track.uuid(),
+            title: track.title.clone(),
+            artist: track.artist.clone(),
+            album: Some(crabidy_core::proto::crabidy::Album {
+                title: track.album.clone(),
+                release_date: track.release_date.clone(),
+                artwork: Vec::new(),
+                uuid: Some(format!(
+                    "node:local:album:{}:{}",
+                    artist_id,
+                    encode_name(&track.album)
+                )),
+            }),
+            duration: track.duration.map(|d| d.as_millis() as u32),
+            artwork: Vec::new(),
+            artists: vec![crabidy_core::proto::crabidy::Artist {
+                name: track.artist.clone(),
+                uuid: (track.artist == track.album_artist)
+                    .then(|| format!("node:local:artist:{}", artist_id)),
+            }],
+            track_number: track.track_number,
+            volume_number: track.disc_number,
+            replay_gain: track.track_gain,
+            peak: track.track_peak,
+            album_replay_gain: track.album_gain,
+            album_peak: track.album_peak,
+            ..Default::default()
+        }
+    }
+}
+
+impl From<LocalTrack> for crabidy_core::proto::crabidy::Track {
+    fn from(track: LocalTrack) -> Self {
+        (&track).into()
+    }
+}
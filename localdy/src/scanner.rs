@@ -0,0 +1,219 @@
+use std::{
+    fs::{self, File},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use symphonia::{
+    core::{
+        formats::FormatOptions,
+        io::MediaSourceStream,
+        meta::{MetadataOptions, StandardTagKey, Tag},
+        probe::Hint,
+    },
+    default::get_probe,
+};
+use tracing::{debug, instrument, warn};
+
+use crate::{config::Settings, models::LocalTrack};
+
+#[instrument(skip(settings))]
+pub fn scan(settings: &Settings) -> Vec<LocalTrack> {
+    let mut tracks = Vec::new();
+    let mut dirs_to_go: Vec<PathBuf> = settings.music_dirs.clone();
+    while let Some(dir) = dirs_to_go.pop() {
+        let entries = match fs::read_dir(&dir) {
+            Ok(entries) => entries,
+            Err(err) => {
+                warn!("could not read directory {:?}: {}", dir, err);
+                continue;
+            }
+        };
+        for entry in entries.flatten() {
+            let Ok(file_type) = entry.file_type() else {
+                continue;
+            };
+            let path = entry.path();
+            if file_type.is_dir() {
+                dirs_to_go.push(path);
+            } else if file_type.is_file() && has_audio_extension(&path, &settings.extensions) {
+                if let Some(track) = read_track(&path) {
+                    tracks.push(track);
+                }
+            }
+        }
+    }
+    debug!("found {} tracks", tracks.len());
+    tracks
+}
+
+fn has_audio_extension(path: &Path, extensions: &[String]) -> bool {
+    path.extension()
+        .and_then(|e| e.to_str())
+        .map(|e| extensions.iter().any(|ext| ext.eq_ignore_ascii_case(e)))
+        .unwrap_or(false)
+}
+
+#[instrument]
+pub fn read_track(path: &Path) -> Option<LocalTrack> {
+    // uuids are strings, so we can't address files with non utf8 paths
+    path.to_str()?;
+    let (tags, duration) = read_tags(path)?;
+
+    let tag_value = |key: StandardTagKey| -> Option<String> {
+        tags.iter()
+            .rev()
+            .find(|t| t.std_key == Some(key))
+            .map(|t| t.value.to_string())
+            .filter(|v| !v.trim().is_empty())
+    };
+    // Opus files only have R128 gains, which are not mapped to a standard key
+    let raw_value = |key: &str| -> Option<String> {
+        tags.iter()
+            .rev()
+            .find(|t| t.key.eq_ignore_ascii_case(key))
+            .map(|t| t.value.to_string())
+    };
+    let gain = |key: StandardTagKey, r128_key: &str| {
+        tag_value(key)
+            .and_then(|g| parse_gain(&g))
+            .or_else(|| raw_value(r128_key).and_then(|g| parse_r128_gain(&g)))
+    };
+
+    let title = tag_value(StandardTagKey::TrackTitle).unwrap_or_else(|| {
+        path.file_stem()
+            .map(|s| s.to_string_lossy().to_string())
+            .unwrap_or_default()
+    });
+    let artist = tag_value(StandardTagKey::Artist).unwrap_or_else(|| "Unknown Artist".to_string());
+    let album_artist = tag_value(StandardTagKey::AlbumArtist).unwrap_or_else(|| artist.clone());
+    let album = tag_value(StandardTagKey::Album).unwrap_or_else(|| {
+        path.parent()
+            .and_then(|p| p.file_name())
+            .map(|s| s.to_string_lossy().to_string())
+            .unwrap_or_else(|| "Unknown Album".to_string())
+    });
+
+    Some(LocalTrack {
+        path: path.to_path_buf(),
+        title,
+        artist,
+        album_artist,
+        album,
+        release_date: tag_value(StandardTagKey::Date)
+            .or_else(|| tag_value(StandardTagKey::ReleaseDate)),
+        track_number: tag_value(StandardTagKey::TrackNumber).and_then(|n| parse_number(&n)),
+        disc_number: tag_value(StandardTagKey::DiscNumber).and_then(|n| parse_number(&n)),
+        duration,
+        track_gain: gain(StandardTagKey::ReplayGainTrackGain, "R128_TRACK_GAIN"),
+        track_peak: tag_value(StandardTagKey::ReplayGainTrackPeak).and_then(|p| parse_gain(&p)),
+        album_gain: gain(StandardTagKey::ReplayGainAlbumGain, "R128_ALBUM_GAIN"),
+        album_peak: tag_value(StandardTagKey::ReplayGainAlbumPeak).and_then(|p| parse_gain(&p)),
+    })
+}
+
+/// The lyrics embedded in the tags of the file, e.g. an ID3 `USLT` frame or a `LYRICS` comment
+#[instrument]
+pub fn read_lyrics(path: &Path) -> Option<String> {
+    let (tags, _) = read_tags(path)?;
+    tags.into_iter()
+        .rev()
+        .find(|t| t.std_key == Some(StandardTagKey::Lyrics))
+        .map(|t| t.value.to_string())
+        .filter(|v| !v.trim().is_empty())
+}
+
+/// The tags and the duration of the file
+fn read_tags(path: &Path) -> Option<(Vec<Tag>, Option<Duration>)> {
+    let file = File::open(path).ok()?;
+    let mss = MediaSourceStream::new(Box::new(file), Default::default());
+    let mut hint = Hint::new();
+    if let Some(extension) = path.extension().and_then(|e| e.to_str()) {
+        hint.with_extension(extension);
+    }
+    let mut probed = match get_probe().format(
+        &hint,
+        mss,
+        &FormatOptions::default(),
+        &MetadataOptions::default(),
+    ) {
+        Ok(probed) => probed,
+        Err(err) => {
+            warn!("could not probe {:?}: {}", path, err);
+            return None;
+        }
+    };
+
+    // Tags found while probing (e.g. ID3v2) come first, the ones of the container format win
+    let mut tags: Vec<Tag> = probed
+        .metadata
+        .get()
+        .as_ref()
+        .and_then(|m| m.current().map(|r| r.tags().to_vec()))
+        .unwrap_or_default();
+    if let Some(revision) = probed.format.metadata().current() {
+        tags.extend(revision.tags().iter().cloned());
+    }
+
+    let duration = probed.format.default_track().and_then(|track| {
+        let time_base = track.codec_params.time_base?;
+        let frames = track.codec_params.n_frames?;
+        let time = time_base.calc_time(frames);
+        Some(Duration::from_secs_f64(time.seconds as f64 + time.frac))
+    });
+    Some((tags, duration))
+}
+
+/// Parses numbers like "3" or "3/12" as found in track and disc number tags
+fn parse_number(value: &str) -> Option<u32> {
+    value.split('/').next()?.trim().parse().ok()
+}
+
+/// Parses ReplayGain values like "-7.89 dB" or peaks like "0.988547"
+fn parse_gain(value: &str) -> Option<f32> {
+    let value = value.trim();
+    let number = value
+        .strip_suffix("dB")
+        .or_else(|| value.strip_suffix("db"))
+        .unwrap_or(value);
+    number.trim().parse().ok().filter(|g: &f32| g.is_finite())
+}
+
+/// R128 gains are in 1/256 dB relative to -23 LUFS, 5 dB quieter than the ReplayGain reference
+fn parse_r128_gain(value: &str) -> Option<f32> {
+    let gain: i16 = value.trim().parse().ok()?;
+    Some(gain as f32 / 256.0 + 5.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_track_numbers() {
+        assert_eq!(parse_number("3"), Some(3));
+        assert_eq!(parse_number("03/12"), Some(3));
+        assert_eq!(parse_number(" 7 "), Some(7));
+        assert_eq!(parse_number("A1"), None);
+    }
+
+    #[test]
+    fn parse_gains() {
+        assert_eq!(parse_gain("-7.89 dB"), Some(-7.89));
+        assert_eq!(parse_gain("+1.5 dB"), Some(1.5));
+        assert_eq!(parse_gain("0.988547"), Some(0.988547));
+        assert_eq!(parse_gain("loud"), None);
+        assert_eq!(parse_r128_gain("-2560"), Some(-5.0));
+        assert_eq!(parse_r128_gain("0"), Some(5.0));
+    }
+
+    #[test]
+    fn audio_extensions() {
+        let extensions = Settings::default().extensions;
+        let is_audio = |path| has_audio_extension(Path::new(path), &extensions);
+        assert!(is_audio("/music/a.FLAC"));
+        assert!(is_audio("/music/b.mp3"));
+        assert!(!is_audio("/music/cover.jpg"));
+        assert!(!is_audio("/music/README"));
+    }
+}
@@ -0,0 +1,23 @@
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Settings {
+    /// The RSS or Atom feeds of the subscribed shows, urls or paths of local files
+    pub feeds: Vec<String>,
+    /// Where the playback positions of the episodes are kept
+    pub positions_file: PathBuf,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        let positions_file = dirs::data_dir()
+            .map(|d| d.join("crabidy"))
+            .unwrap_or(PathBuf::from("/tmp/crabidy"))
+            .join("podcast-positions.json");
+        Self {
+            feeds: Vec::new(),
+            positions_file,
+        }
+    }
+}
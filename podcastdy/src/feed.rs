@@ -0,0 +1,197 @@
+//! Parses the RSS and Atom feeds podcasts are published with, only the parts needed to list and
+//! play the episodes are kept
+
+use roxmltree::Node;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Feed {
+    pub title: String,
+    pub author: Option<String>,
+    /// In the order of the feed, which is newest first for most podcasts
+    pub episodes: Vec<Episode>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Episode {
+    /// Identifies the episode within its feed, the enclosure url if the feed has no guid
+    pub guid: String,
+    pub title: String,
+    /// The enclosed audio file
+    pub url: String,
+    /// In seconds
+    pub duration: Option<u32>,
+    pub published: Option<String>,
+}
+
+/// Parses an RSS 2.0 or Atom feed, entries without audio are left out
+pub fn parse(xml: &str) -> Result<Feed, String> {
+    let doc = roxmltree::Document::parse(xml).map_err(|err| err.to_string())?;
+    let root = doc.root_element();
+    match root.tag_name().name() {
+        "rss" => {
+            let channel = child(root, "channel").ok_or("rss feed without channel")?;
+            Ok(parse_rss(channel))
+        }
+        "feed" => Ok(parse_atom(root)),
+        other => Err(format!("not a feed: <{}>", other)),
+    }
+}
+
+fn parse_rss(channel: Node) -> Feed {
+    let episodes = children(channel, "item")
+        .filter_map(|item| {
+            let url = child(item, "enclosure")?
+                .attribute("url")?
+                .trim()
+                .to_string();
+            Some(Episode {
+                guid: child_text(item, "guid").unwrap_or_else(|| url.clone()),
+                title: child_text(item, "title").unwrap_or_else(|| url.clone()),
+                duration: child_text(item, "duration").and_then(|d| parse_duration(&d)),
+                published: child_text(item, "pubDate"),
+                url,
+            })
+        })
+        .collect();
+    Feed {
+        title: child_text(channel, "title").unwrap_or_default(),
+        // `itunes:author` is used far more than the `author` email of plain RSS
+        author: child_text(channel, "author"),
+        episodes,
+    }
+}
+
+fn parse_atom(feed: Node) -> Feed {
+    let episodes = children(feed, "entry")
+        .filter_map(|entry| {
+            let url = children(entry, "link")
+                .find(|link| link.attribute("rel") == Some("enclosure"))?
+                .attribute("href")?
+                .trim()
+                .to_string();
+            Some(Episode {
+                guid: child_text(entry, "id").unwrap_or_else(|| url.clone()),
+                title: child_text(entry, "title").unwrap_or_else(|| url.clone()),
+                duration: child_text(entry, "duration").and_then(|d| parse_duration(&d)),
+                published: child_text(entry, "published").or_else(|| child_text(entry, "updated")),
+                url,
+            })
+        })
+        .collect();
+    Feed {
+        title: child_text(feed, "title").unwrap_or_default(),
+        author: child(feed, "author").and_then(|author| child_text(author, "name")),
+        episodes,
+    }
+}
+
+/// Durations are given as seconds, `MM:SS` or `HH:MM:SS`
+fn parse_duration(duration: &str) -> Option<u32> {
+    duration.trim().split(':').try_fold(0, |secs: u32, part| {
+        Some(secs * 60 + part.parse::<u32>().ok()?)
+    })
+}
+
+// Elements are matched by their local name, so namespaced ones like `itunes:duration` are found
+// no matter which prefix the feed uses
+fn children<'a, 'input: 'a>(
+    node: Node<'a, 'input>,
+    name: &'a str,
+) -> impl Iterator<Item = Node<'a, 'input>> + 'a {
+    node.children()
+        .filter(move |n| n.is_element() && n.tag_name().name() == name)
+}
+
+fn child<'a, 'input>(node: Node<'a, 'input>, name: &str) -> Option<Node<'a, 'input>> {
+    node.children()
+        .find(|n| n.is_element() && n.tag_name().name() == name)
+}
+
+fn child_text(node: Node, name: &str) -> Option<String> {
+    let text = child(node, name)?.text()?.trim();
+    if text.is_empty() {
+        None
+    } else {
+        Some(text.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rss_episodes_are_parsed() {
+        let feed = parse(
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+            <rss version="2.0" xmlns:itunes="http://www.itunes.com/dtds/podcast-1.0.dtd">
+              <channel>
+                <title>Crab Talk</title>
+                <itunes:author>The Crabs</itunes:author>
+                <item>
+                  <title><![CDATA[Episode 2: Shells & more]]></title>
+                  <guid isPermaLink="false">ep-2</guid>
+                  <pubDate>Tue, 02 May 2023 10:00:00 GMT</pubDate>
+                  <enclosure url="https://example.com/2.mp3" type="audio/mpeg" length="1"/>
+                  <itunes:duration>01:02:03</itunes:duration>
+                </item>
+                <item>
+                  <title>Trailer without audio</title>
+                </item>
+                <item>
+                  <title>Episode 1</title>
+                  <enclosure url="https://example.com/1.mp3" type="audio/mpeg"/>
+                  <itunes:duration>95</itunes:duration>
+                </item>
+              </channel>
+            </rss>"#,
+        )
+        .unwrap();
+        assert_eq!(feed.title, "Crab Talk");
+        assert_eq!(feed.author.as_deref(), Some("The Crabs"));
+        assert_eq!(feed.episodes.len(), 2);
+        assert_eq!(feed.episodes[0].title, "Episode 2: Shells & more");
+        assert_eq!(feed.episodes[0].guid, "ep-2");
+        assert_eq!(feed.episodes[0].duration, Some(3723));
+        assert_eq!(feed.episodes[1].guid, "https://example.com/1.mp3");
+        assert_eq!(feed.episodes[1].duration, Some(95));
+    }
+
+    #[test]
+    fn atom_entries_are_parsed() {
+        let feed = parse(
+            r#"<feed xmlns="http://www.w3.org/2005/Atom">
+              <title>Atomic</title>
+              <author><name>Someone</name></author>
+              <entry>
+                <id>urn:uuid:1</id>
+                <title>First</title>
+                <published>2023-05-01T00:00:00Z</published>
+                <link rel="alternate" href="https://example.com/first"/>
+                <link rel="enclosure" href="https://example.com/first.ogg"/>
+              </entry>
+            </feed>"#,
+        )
+        .unwrap();
+        assert_eq!(feed.title, "Atomic");
+        assert_eq!(feed.author.as_deref(), Some("Someone"));
+        assert_eq!(
+            feed.episodes,
+            vec![Episode {
+                guid: "urn:uuid:1".to_string(),
+                title: "First".to_string(),
+                url: "https://example.com/first.ogg".to_string(),
+                duration: None,
+                published: Some("2023-05-01T00:00:00Z".to_string()),
+            }]
+        );
+    }
+
+    #[test]
+    fn other_documents_are_rejected() {
+        assert!(parse("<html></html>").is_err());
+        assert!(parse("not xml").is_err());
+        assert_eq!(parse_duration("12:30"), Some(750));
+        assert_eq!(parse_duration("soon"), None);
+    }
+}
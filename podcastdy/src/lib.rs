@@ -0,0 +1,465 @@
+//! A provider for podcasts, the shows are subscribed to with their RSS or Atom feeds and episodes
+//! resume where they were stopped
+use std::{collections::HashMap, path::Path, sync::Mutex, sync::RwLock, time::Duration};
+
+use async_trait::async_trait;
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use crabidy_core::{
+    proto::crabidy::{Album, LibraryNode, LibraryNodeChild, SearchResult, Track},
+    ProviderError,
+};
+use futures::future::join_all;
+use tracing::{debug, error, instrument, warn};
+pub mod config;
+pub mod feed;
+pub mod positions;
+
+use feed::{Episode, Feed};
+use positions::Positions;
+
+#[derive(Debug)]
+pub struct Client {
+    http_client: reqwest::Client,
+    settings: config::Settings,
+    // the last fetched version of every feed, keyed by its url or path
+    feeds: RwLock<HashMap<String, Feed>>,
+    positions: Mutex<Positions>,
+}
+
+#[async_trait]
+impl crabidy_core::ProviderClient for Client {
+    #[instrument(skip(raw_toml_settings))]
+    async fn init(raw_toml_settings: &str) -> Result<Self, ProviderError> {
+        let settings: config::Settings = if let Ok(settings) = toml::from_str(raw_toml_settings) {
+            settings
+        } else {
+            let settings = config::Settings::default();
+            warn!(
+                "could not parse toml settings: {:#?} using default settings instead: {:#?}",
+                raw_toml_settings, settings
+            );
+            settings
+        };
+        Self::new(settings).await
+    }
+
+    #[instrument(skip(self))]
+    fn settings(&self) -> String {
+        toml::to_string_pretty(&self.settings).unwrap_or_default()
+    }
+
+    #[instrument(skip(self))]
+    async fn get_urls_for_track(&self, track_uuid: &str) -> Result<Vec<String>, ProviderError> {
+        debug!("get_urls_for_track {}", track_uuid);
+        let (_, episode) = self.find_episode(track_uuid).await?;
+        Ok(vec![episode.url])
+    }
+
+    #[instrument(skip(self))]
+    async fn get_metadata_for_track(&self, track_uuid: &str) -> Result<Track, ProviderError> {
+        debug!("get_metadata_for_track {}", track_uuid);
+        let (feed, episode) = self.find_episode(track_uuid).await?;
+        let source = source_from_uuid(track_uuid)?;
+        Ok(episode_track(&source, &feed, &episode))
+    }
+
+    #[instrument(skip(self))]
+    fn get_lib_root(&self) -> LibraryNode {
+        debug!("get_lib_root in podcastdy");
+        let feeds = self.feeds.read().ok();
+        let children = self
+            .settings
+            .feeds
+            .iter()
+            .map(|source| {
+                // feeds that could not be fetched yet are shown by their url
+                let title = feeds
+                    .as_ref()
+                    .and_then(|feeds| feeds.get(source))
+                    .map_or(source.clone(), |feed| feed.title.clone());
+                LibraryNodeChild::new(show_uuid(source), title, true)
+            })
+            .collect();
+        LibraryNode {
+            uuid: "node:podcast".to_string(),
+            title: "podcasts".to_string(),
+            parent: Some(LibraryNode::new().uuid),
+            tracks: Vec::new(),
+            children,
+            is_queable: false,
+            capabilities: None,
+            artwork: Vec::new(),
+        }
+    }
+
+    #[instrument(skip(self))]
+    async fn get_lib_node(&self, uuid: &str) -> Result<LibraryNode, ProviderError> {
+        debug!("get_lib_node in podcastdy {}", uuid);
+        if uuid == "node:podcast" {
+            return Ok(self.get_lib_root());
+        }
+        let source = uuid
+            .strip_prefix("node:podcast:show:")
+            .ok_or_else(|| ProviderError::malformed_uuid(uuid))
+            .and_then(|encoded| decode(uuid, encoded))?;
+        // the node is asked for again when the cache of the server expires, so new episodes
+        // show up
+        let feed = match self.fetch_feed(&source).await {
+            Ok(feed) => feed,
+            Err(err) => {
+                let known = self.known_feed(&source).ok_or(err.clone())?;
+                warn!("using the last fetched version of {}: {}", source, err);
+                known
+            }
+        };
+        Ok(LibraryNode {
+            uuid: uuid.to_string(),
+            title: feed.title.clone(),
+            parent: Some("node:podcast".to_string()),
+            tracks: feed
+                .episodes
+                .iter()
+                .map(|episode| episode_track(&source, &feed, episode))
+                .collect(),
+            children: Vec::new(),
+            is_queable: true,
+            capabilities: None,
+            artwork: Vec::new(),
+        })
+    }
+
+    #[instrument(skip(self))]
+    async fn search(&self, query: &str) -> Result<SearchResult, ProviderError> {
+        debug!("search in podcastdy {}", query);
+        let query = query.to_lowercase();
+        let Ok(feeds) = self.feeds.read() else {
+            return Err(ProviderError::InternalError(
+                "poisoned feeds lock".to_string(),
+            ));
+        };
+        let mut result = SearchResult::default();
+        for (source, feed) in feeds.iter() {
+            if feed.title.to_lowercase().contains(&query) {
+                result.albums.push(LibraryNodeChild::new(
+                    show_uuid(source),
+                    feed.title.clone(),
+                    true,
+                ));
+            }
+            result.tracks.extend(
+                feed.episodes
+                    .iter()
+                    .filter(|episode| episode.title.to_lowercase().contains(&query))
+                    .map(|episode| episode_track(source, feed, episode)),
+            );
+        }
+        Ok(result)
+    }
+
+    #[instrument(skip(self))]
+    async fn get_resume_position(
+        &self,
+        track_uuid: &str,
+    ) -> Result<Option<Duration>, ProviderError> {
+        let Ok(positions) = self.positions.lock() else {
+            return Err(ProviderError::InternalError(
+                "poisoned positions lock".to_string(),
+            ));
+        };
+        Ok(positions.get(track_uuid))
+    }
+
+    #[instrument(skip(self))]
+    async fn save_position(
+        &self,
+        track_uuid: &str,
+        position: Duration,
+        duration: Option<Duration>,
+    ) -> Result<(), ProviderError> {
+        let (json, path) = {
+            let Ok(mut positions) = self.positions.lock() else {
+                return Err(ProviderError::InternalError(
+                    "poisoned positions lock".to_string(),
+                ));
+            };
+            let Some(json) = positions.update(track_uuid, position, duration) else {
+                return Ok(());
+            };
+            (json, positions.path().to_path_buf())
+        };
+        write_positions(&path, json).await
+    }
+
+    #[instrument(skip(self))]
+    async fn flush_positions(&self) -> Result<(), ProviderError> {
+        let (json, path) = {
+            let Ok(mut positions) = self.positions.lock() else {
+                return Err(ProviderError::InternalError(
+                    "poisoned positions lock".to_string(),
+                ));
+            };
+            let Some(json) = positions.flush() else {
+                return Ok(());
+            };
+            (json, positions.path().to_path_buf())
+        };
+        write_positions(&path, json).await
+    }
+}
+
+impl Client {
+    pub async fn new(settings: config::Settings) -> Result<Self, ProviderError> {
+        let http_client = reqwest::Client::builder()
+            .build()
+            .map_err(|err| ProviderError::InternalError(err.to_string()))?;
+        let positions = Mutex::new(Positions::load(settings.positions_file.clone()));
+        let client = Self {
+            http_client,
+            settings,
+            feeds: RwLock::new(HashMap::new()),
+            positions,
+        };
+        // the titles of the shows are needed for the library root
+        let fetches = client
+            .settings
+            .feeds
+            .iter()
+            .map(|source| client.fetch_feed(source));
+        for result in join_all(fetches).await {
+            if let Err(err) = result {
+                warn!("could not fetch podcast feed: {}", err);
+            }
+        }
+        Ok(client)
+    }
+
+    /// Fetches and parses the feed, remote feeds are urls and everything else is read as a file
+    async fn fetch_feed(&self, source: &str) -> Result<Feed, ProviderError> {
+        let xml = if source.starts_with("http://") || source.starts_with("https://") {
+            self.http_client
+                .get(source)
+                .send()
+                .await
+                .and_then(|r| r.error_for_status())
+                .map_err(fetch_error)?
+                .text()
+                .await
+                .map_err(fetch_error)?
+        } else {
+            let path = source.strip_prefix("file://").unwrap_or(source);
+            tokio::fs::read_to_string(path)
+                .await
+                .map_err(|err| ProviderError::fetch("podcast", None, err))?
+        };
+        let feed = feed::parse(&xml).map_err(|err| {
+            ProviderError::fetch("podcast", None, format!("invalid feed {}: {}", source, err))
+        })?;
+        match self.feeds.write() {
+            Ok(mut feeds) => {
+                feeds.insert(source.to_string(), feed.clone());
+            }
+            Err(_) => error!("poisoned feeds lock"),
+        }
+        Ok(feed)
+    }
+
+    fn known_feed(&self, source: &str) -> Option<Feed> {
+        self.feeds.read().ok()?.get(source).cloned()
+    }
+
+    async fn find_episode(&self, track_uuid: &str) -> Result<(Feed, Episode), ProviderError> {
+        let source = source_from_uuid(track_uuid)?;
+        let guid = track_uuid
+            .rsplit(':')
+            .next()
+            .ok_or_else(|| ProviderError::malformed_uuid(track_uuid))
+            .and_then(|encoded| decode(track_uuid, encoded))?;
+        let feed = match self.known_feed(&source) {
+            Some(feed) => feed,
+            None => self.fetch_feed(&source).await?,
+        };
+        let episode = feed
+            .episodes
+            .iter()
+            .find(|episode| episode.guid == guid)
+            .cloned()
+            .ok_or_else(|| ProviderError::not_found(track_uuid))?;
+        Ok((feed, episode))
+    }
+}
+
+fn show_uuid(source: &str) -> String {
+    format!("node:podcast:show:{}", URL_SAFE_NO_PAD.encode(source))
+}
+
+// The feed and the guid of the episode can both contain the `:` used as separator in the uuids,
+// so they are encoded
+fn episode_track(source: &str, feed: &Feed, episode: &Episode) -> Track {
+    Track {
+        uuid: format!(
+            "track:podcast:{}:{}",
+            URL_SAFE_NO_PAD.encode(source),
+            URL_SAFE_NO_PAD.encode(&episode.guid)
+        ),
+        title: episode.title.clone(),
+        artist: feed.author.clone().unwrap_or_else(|| feed.title.clone()),
+        album: Some(Album {
+            title: feed.title.clone(),
+            release_date: episode.published.clone(),
+            artwork: Vec::new(),
+            uuid: None,
+        }),
+        duration: episode.duration.map(|secs| secs * 1000),
+        artwork: Vec::new(),
+        ..Default::default()
+    }
+}
+
+fn source_from_uuid(uuid: &str) -> Result<String, ProviderError> {
+    let encoded = uuid
+        .strip_prefix("track:podcast:")
+        .and_then(|rest| rest.split(':').next())
+        .ok_or_else(|| ProviderError::malformed_uuid(uuid))?;
+    decode(uuid, encoded)
+}
+
+fn decode(uuid: &str, encoded: &str) -> Result<String, ProviderError> {
+    let bytes = URL_SAFE_NO_PAD
+        .decode(encoded)
+        .map_err(|_| ProviderError::malformed_uuid(uuid))?;
+    String::from_utf8(bytes).map_err(|_| ProviderError::malformed_uuid(uuid))
+}
+
+fn fetch_error(err: reqwest::Error) -> ProviderError {
+    ProviderError::fetch("podcast", err.status().map(|s| s.as_u16()), err)
+}
+
+async fn write_positions(path: &Path, json: String) -> Result<(), ProviderError> {
+    debug!("persisting podcast positions");
+    if let Some(dir) = path.parent() {
+        tokio::fs::create_dir_all(dir)
+            .await
+            .map_err(|err| ProviderError::InternalError(err.to_string()))?;
+    }
+    tokio::fs::write(path, json)
+        .await
+        .map_err(|err| ProviderError::InternalError(err.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crabidy_core::ProviderClient;
+    use wiremock::{matchers::path, Mock, MockServer, ResponseTemplate};
+
+    const FEED: &str = r#"<rss version="2.0"><channel>
+        <title>Crab Talk</title>
+        <item>
+          <title>Molting</title>
+          <guid>https://example.com/episodes/2</guid>
+          <enclosure url="https://example.com/2.mp3"/>
+          <duration>3600</duration>
+        </item>
+        <item>
+          <title>Pilot</title>
+          <enclosure url="https://example.com/1.mp3"/>
+        </item>
+      </channel></rss>"#;
+
+    /// Serves the feed as `/feed.xml`
+    async fn serve_feed() -> MockServer {
+        let server = MockServer::start().await;
+        Mock::given(path("/feed.xml"))
+            .respond_with(ResponseTemplate::new(200).set_body_raw(FEED, "application/rss+xml"))
+            .mount(&server)
+            .await;
+        server
+    }
+
+    #[tokio::test]
+    async fn shows_list_their_episodes() {
+        let dir = std::env::temp_dir().join(format!("podcastdy-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let local_feed = dir.join("local.xml");
+        std::fs::write(&local_feed, FEED.replace("Crab Talk", "Local Crabs")).unwrap();
+        let server = serve_feed().await;
+        let remote_feed = format!("{}/feed.xml", server.uri());
+        let client = Client::new(config::Settings {
+            feeds: vec![
+                remote_feed.clone(),
+                local_feed.to_string_lossy().to_string(),
+                dir.join("missing.xml").to_string_lossy().to_string(),
+            ],
+            positions_file: dir.join("positions.json"),
+        })
+        .await
+        .unwrap();
+
+        let root = client.get_lib_root();
+        let titles: Vec<&str> = root.children.iter().map(|c| c.title.as_str()).collect();
+        assert_eq!(titles[..2], ["Crab Talk", "Local Crabs"]);
+        assert!(titles[2].ends_with("missing.xml"));
+
+        let show = client.get_lib_node(&root.children[0].uuid).await.unwrap();
+        assert!(show.is_queable);
+        assert_eq!(show.tracks.len(), 2);
+        let molting = &show.tracks[0];
+        assert_eq!(molting.title, "Molting");
+        assert_eq!(molting.artist, "Crab Talk");
+        assert_eq!(molting.duration, Some(3_600_000));
+        assert_eq!(
+            client.get_urls_for_track(&molting.uuid).await.unwrap(),
+            vec!["https://example.com/2.mp3"]
+        );
+        assert_eq!(
+            client
+                .get_metadata_for_track(&show.tracks[1].uuid)
+                .await
+                .unwrap()
+                .title,
+            "Pilot"
+        );
+        assert!(client.get_lib_node(&root.children[2].uuid).await.is_err());
+
+        let found = client.search("pilot").await.unwrap();
+        assert_eq!(found.tracks.len(), 2);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn episodes_resume_where_they_were_stopped() {
+        let dir = std::env::temp_dir().join(format!("podcastdy-resume-{}", std::process::id()));
+        let settings = config::Settings {
+            feeds: Vec::new(),
+            positions_file: dir.join("positions.json"),
+        };
+        let uuid = "track:podcast:feed:episode";
+        let duration = Some(Duration::from_secs(3600));
+        let client = Client::new(settings.clone()).await.unwrap();
+        assert_eq!(client.get_resume_position(uuid).await.unwrap(), None);
+        client
+            .save_position(uuid, Duration::from_secs(754), duration)
+            .await
+            .unwrap();
+        // held back until the positions are written again, e.g. when playback stops
+        client
+            .save_position(uuid, Duration::from_secs(781), duration)
+            .await
+            .unwrap();
+        client.flush_positions().await.unwrap();
+
+        // the position survives a restart
+        let client = Client::new(settings.clone()).await.unwrap();
+        assert_eq!(
+            client.get_resume_position(uuid).await.unwrap(),
+            Some(Duration::from_secs(781))
+        );
+        client
+            .save_position(uuid, Duration::from_secs(3590), duration)
+            .await
+            .unwrap();
+        let client = Client::new(settings).await.unwrap();
+        assert_eq!(client.get_resume_position(uuid).await.unwrap(), None);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,125 @@
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+use tracing::{debug, error};
+
+// Episodes stopped this close to their end count as listened to and start from the beginning
+// the next time
+const FINISHED_MARGIN: Duration = Duration::from_secs(30);
+// Positions change with every update while an episode plays, they are only written this often
+const PERSIST_INTERVAL: Duration = Duration::from_secs(30);
+
+/// How far the episodes that were not listened to the end have been played, keyed by the track
+/// uuid and kept in a json file
+#[derive(Debug)]
+pub struct Positions {
+    path: PathBuf,
+    // in seconds
+    positions: BTreeMap<String, u64>,
+    persisted_at: Option<Instant>,
+    // the positions changed since they were written
+    unsaved: bool,
+}
+
+impl Positions {
+    pub fn load(path: PathBuf) -> Self {
+        let positions = match std::fs::read_to_string(&path) {
+            Ok(json) => serde_json::from_str(&json).unwrap_or_else(|err| {
+                error!("failed to parse podcast positions: {}", err);
+                BTreeMap::new()
+            }),
+            Err(err) => {
+                debug!("no podcast positions to restore: {}", err);
+                BTreeMap::new()
+            }
+        };
+        Self {
+            path,
+            positions,
+            persisted_at: None,
+            unsaved: false,
+        }
+    }
+
+    pub fn get(&self, uuid: &str) -> Option<Duration> {
+        self.positions
+            .get(uuid)
+            .map(|secs| Duration::from_secs(*secs))
+    }
+
+    /// Remembers the position, or forgets it when the episode is finished. Returns the json to
+    /// write to the positions file when it is due.
+    pub fn update(
+        &mut self,
+        uuid: &str,
+        position: Duration,
+        duration: Option<Duration>,
+    ) -> Option<String> {
+        let finished = duration.is_some_and(|d| d <= position + FINISHED_MARGIN);
+        let changed = if finished {
+            self.positions.remove(uuid).is_some()
+        } else {
+            self.positions.insert(uuid.to_string(), position.as_secs()) != Some(position.as_secs())
+        };
+        self.unsaved |= changed;
+        let due = self
+            .persisted_at
+            .is_none_or(|at| PERSIST_INTERVAL <= at.elapsed());
+        // finishing an episode is always written, it would be resumed at its end otherwise
+        if !changed || !(due || finished) {
+            return None;
+        }
+        self.flush()
+    }
+
+    /// The json to write to the positions file if there are changes that were not written yet
+    pub fn flush(&mut self) -> Option<String> {
+        if !self.unsaved {
+            return None;
+        }
+        self.unsaved = false;
+        self.persisted_at = Some(Instant::now());
+        serde_json::to_string(&self.positions).ok()
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn positions_are_persisted_at_intervals_and_forgotten_when_finished() {
+        let mut positions = Positions::load(PathBuf::from("/nonexistent/positions.json"));
+        let duration = Some(Duration::from_secs(600));
+        let json = positions.update("track:podcast:a", Duration::from_secs(100), duration);
+        assert_eq!(json.as_deref(), Some(r#"{"track:podcast:a":100}"#));
+        // written a moment ago
+        assert_eq!(
+            positions.update("track:podcast:a", Duration::from_secs(110), duration),
+            None
+        );
+        assert_eq!(
+            positions.get("track:podcast:a"),
+            Some(Duration::from_secs(110))
+        );
+        // e.g. when playback stops
+        assert_eq!(
+            positions.flush().as_deref(),
+            Some(r#"{"track:podcast:a":110}"#)
+        );
+        assert_eq!(positions.flush(), None);
+        assert_eq!(
+            positions
+                .update("track:podcast:a", Duration::from_secs(590), duration)
+                .as_deref(),
+            Some("{}")
+        );
+        assert_eq!(positions.get("track:podcast:a"), None);
+    }
+}
@@ -0,0 +1,28 @@
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Settings {
+    pub stations: Vec<Station>,
+    /// `.m3u` and `.pls` files whose entries are added to the stations
+    pub playlists: Vec<PathBuf>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Station {
+    pub name: String,
+    /// The stream, or a `.m3u`/`.pls` playlist of streams as many stations publish them
+    pub url: String,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            stations: vec![Station {
+                name: "SomaFM: Groove Salad".to_string(),
+                url: "https://ice1.somafm.com/groovesalad-128-mp3".to_string(),
+            }],
+            playlists: Vec::new(),
+        }
+    }
+}
@@ -0,0 +1,312 @@
+//! A provider for internet radio stations, listed in the settings or imported from playlists
+use std::path::Path;
+
+use async_trait::async_trait;
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use crabidy_core::{
+    proto::crabidy::{LibraryNode, ProviderCapabilities, SearchResult, Track},
+    ProviderError,
+};
+use tracing::{debug, instrument, warn};
+pub mod config;
+pub mod playlist;
+
+use config::Station;
+
+#[derive(Debug)]
+pub struct Client {
+    http_client: reqwest::Client,
+    settings: config::Settings,
+    // the configured stations followed by the ones of the playlists
+    stations: Vec<Station>,
+}
+
+#[async_trait]
+impl crabidy_core::ProviderClient for Client {
+    #[instrument(skip(raw_toml_settings))]
+    async fn init(raw_toml_settings: &str) -> Result<Self, ProviderError> {
+        let settings: config::Settings = if let Ok(settings) = toml::from_str(raw_toml_settings) {
+            settings
+        } else {
+            let settings = config::Settings::default();
+            warn!(
+                "could not parse toml settings: {:#?} using default settings instead: {:#?}",
+                raw_toml_settings, settings
+            );
+            settings
+        };
+        Self::new(settings).await
+    }
+
+    #[instrument(skip(self))]
+    fn settings(&self) -> String {
+        toml::to_string_pretty(&self.settings).unwrap_or_default()
+    }
+
+    #[instrument(skip(self))]
+    async fn get_urls_for_track(&self, track_uuid: &str) -> Result<Vec<String>, ProviderError> {
+        debug!("get_urls_for_track {}", track_uuid);
+        let url = url_from_uuid(track_uuid)?;
+        let Some(extension) = playlist_extension(&url) else {
+            return Ok(vec![url]);
+        };
+        // the station publishes a playlist of its streams, they are tried in order
+        let content = self
+            .http_client
+            .get(&url)
+            .send()
+            .await
+            .and_then(|r| r.error_for_status())
+            .map_err(fetch_error)?
+            .text()
+            .await
+            .map_err(fetch_error)?;
+        let urls: Vec<String> = playlist::parse(extension, &content)
+            .unwrap_or_default()
+            .into_iter()
+            .map(|station| station.url)
+            .collect();
+        if urls.is_empty() {
+            return Err(ProviderError::fetch(
+                "radio",
+                None,
+                format!("no streams in playlist {}", url),
+            ));
+        }
+        Ok(urls)
+    }
+
+    #[instrument(skip(self))]
+    async fn get_metadata_for_track(&self, track_uuid: &str) -> Result<Track, ProviderError> {
+        debug!("get_metadata_for_track {}", track_uuid);
+        let url = url_from_uuid(track_uuid)?;
+        Ok(station_track(&self.station(&url)))
+    }
+
+    #[instrument(skip(self))]
+    fn get_lib_root(&self) -> LibraryNode {
+        debug!("get_lib_root in radiody");
+        LibraryNode {
+            uuid: "node:radio".to_string(),
+            title: "radio".to_string(),
+            parent: Some(LibraryNode::new().uuid),
+            tracks: self.stations.iter().map(station_track).collect(),
+            children: Vec::new(),
+            is_queable: true,
+            capabilities: None,
+            artwork: Vec::new(),
+        }
+    }
+
+    fn capabilities(&self) -> ProviderCapabilities {
+        // stations are live streams
+        ProviderCapabilities {
+            seek: false,
+            ..ProviderCapabilities::basic()
+        }
+    }
+
+    #[instrument(skip(self))]
+    async fn get_lib_node(&self, uuid: &str) -> Result<LibraryNode, ProviderError> {
+        debug!("get_lib_node in radiody {}", uuid);
+        if uuid != "node:radio" {
+            return Err(ProviderError::malformed_uuid(uuid));
+        }
+        Ok(self.get_lib_root())
+    }
+
+    #[instrument(skip(self))]
+    async fn search(&self, query: &str) -> Result<SearchResult, ProviderError> {
+        debug!("search in radiody {}", query);
+        let query = query.to_lowercase();
+        Ok(SearchResult {
+            tracks: self
+                .stations
+                .iter()
+                .filter(|station| station.name.to_lowercase().contains(&query))
+                .map(station_track)
+                .collect(),
+            ..Default::default()
+        })
+    }
+
+    // Urls of other remote files in imported playlists are left to be found by their title
+    #[instrument(skip(self))]
+    async fn track_for_url(&self, url: &str) -> Result<Option<Track>, ProviderError> {
+        debug!("track_for_url in radiody {}", url);
+        if !url.starts_with("http://") && !url.starts_with("https://") {
+            return Ok(None);
+        }
+        let is_station = self.stations.iter().any(|station| station.url == url);
+        if !is_station && playlist_extension(url).is_none() {
+            return Ok(None);
+        }
+        Ok(Some(station_track(&self.station(url))))
+    }
+}
+
+impl Client {
+    pub async fn new(settings: config::Settings) -> Result<Self, ProviderError> {
+        let http_client = reqwest::Client::builder()
+            .build()
+            .map_err(|err| ProviderError::InternalError(err.to_string()))?;
+        let mut stations = settings.stations.clone();
+        for path in &settings.playlists {
+            match read_playlist(path).await {
+                Ok(imported) => stations.extend(imported),
+                Err(err) => warn!("could not import stations from {:?}: {}", path, err),
+            }
+        }
+        Ok(Self {
+            http_client,
+            settings,
+            stations,
+        })
+    }
+
+    // Playlists of stations passed to `track_for_url` are no configured stations, they are named
+    // after their url
+    fn station(&self, url: &str) -> Station {
+        self.stations
+            .iter()
+            .find(|station| station.url == url)
+            .cloned()
+            .unwrap_or(Station {
+                name: url.to_string(),
+                url: url.to_string(),
+            })
+    }
+}
+
+async fn read_playlist(path: &Path) -> Result<Vec<Station>, ProviderError> {
+    let content = tokio::fs::read_to_string(path)
+        .await
+        .map_err(|err| ProviderError::Config(err.to_string()))?;
+    let extension = path
+        .extension()
+        .and_then(|e| e.to_str())
+        .unwrap_or_default();
+    playlist::parse(extension, &content)
+        .ok_or_else(|| ProviderError::Config("playlists need to be .m3u or .pls".to_string()))
+}
+
+/// Stations are endless tracks, so they have no duration
+fn station_track(station: &Station) -> Track {
+    Track {
+        uuid: format!("track:radio:{}", URL_SAFE_NO_PAD.encode(&station.url)),
+        title: station.name.clone(),
+        artist: String::new(),
+        album: None,
+        duration: None,
+        artwork: Vec::new(),
+        ..Default::default()
+    }
+}
+
+// The urls contain the `:` used as separator in the uuids, so they are encoded
+fn url_from_uuid(uuid: &str) -> Result<String, ProviderError> {
+    let encoded = uuid
+        .strip_prefix("track:radio:")
+        .ok_or_else(|| ProviderError::malformed_uuid(uuid))?;
+    let bytes = URL_SAFE_NO_PAD
+        .decode(encoded)
+        .map_err(|_| ProviderError::malformed_uuid(uuid))?;
+    String::from_utf8(bytes).map_err(|_| ProviderError::malformed_uuid(uuid))
+}
+
+fn playlist_extension(url: &str) -> Option<&'static str> {
+    let path = url.split(['?', '#']).next().unwrap_or(url).to_lowercase();
+    if path.ends_with(".m3u") {
+        Some("m3u")
+    } else if path.ends_with(".pls") {
+        Some("pls")
+    } else {
+        None
+    }
+}
+
+fn fetch_error(err: reqwest::Error) -> ProviderError {
+    ProviderError::fetch("radio", err.status().map(|s| s.as_u16()), err)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crabidy_core::ProviderClient;
+
+    #[tokio::test]
+    async fn stations_are_queueable_tracks() {
+        let dir = std::env::temp_dir().join(format!("radiody-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let pls = dir.join("imported.pls");
+        std::fs::write(
+            &pls,
+            "[playlist]\nFile1=http://two.example/stream\nTitle1=Two FM\n",
+        )
+        .unwrap();
+        let client = Client::new(config::Settings {
+            stations: vec![Station {
+                name: "One FM".to_string(),
+                url: "http://one.example:8000/stream?type=.mp3".to_string(),
+            }],
+            playlists: vec![pls, dir.join("missing.m3u")],
+        })
+        .await
+        .unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let root = client.get_lib_node("node:radio").await.unwrap();
+        assert!(root.is_queable);
+        let titles: Vec<&str> = root.tracks.iter().map(|t| t.title.as_str()).collect();
+        assert_eq!(titles, vec!["One FM", "Two FM"]);
+        assert_eq!(root.tracks[0].duration, None);
+        assert!(!client.capabilities().seek);
+
+        let uuid = &root.tracks[0].uuid;
+        assert_eq!(
+            client.get_urls_for_track(uuid).await.unwrap(),
+            vec!["http://one.example:8000/stream?type=.mp3"]
+        );
+        assert_eq!(
+            client.get_metadata_for_track(uuid).await.unwrap().title,
+            "One FM"
+        );
+        assert_eq!(
+            client.search("two").await.unwrap().tracks[0].title,
+            "Two FM"
+        );
+
+        let known = client
+            .track_for_url("http://two.example/stream")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(known.title, "Two FM");
+        let imported = client
+            .track_for_url("http://three.example/listen.pls")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(imported.title, "http://three.example/listen.pls");
+        assert_eq!(
+            client.get_metadata_for_track(&imported.uuid).await.unwrap(),
+            imported
+        );
+        for url in ["http://three.example/song.mp3", "/music/a.flac"] {
+            assert_eq!(client.track_for_url(url).await.unwrap(), None);
+        }
+    }
+
+    #[test]
+    fn playlist_urls_are_recognized() {
+        assert_eq!(
+            playlist_extension("http://radio.example/listen.PLS?sid=1"),
+            Some("pls")
+        );
+        assert_eq!(
+            playlist_extension("http://radio.example/live.m3u"),
+            Some("m3u")
+        );
+        assert_eq!(playlist_extension("http://radio.example/live.mp3"), None);
+    }
+}
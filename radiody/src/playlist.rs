@@ -0,0 +1,104 @@
+//! Reads the stations of `.m3u` and `.pls` playlists
+use crate::config::Station;
+
+/// Parses the playlist by its extension, `None` if it is neither m3u nor pls
+pub fn parse(extension: &str, content: &str) -> Option<Vec<Station>> {
+    match extension.to_lowercase().as_str() {
+        "m3u" => Some(parse_m3u(content)),
+        "pls" => Some(parse_pls(content)),
+        _ => None,
+    }
+}
+
+/// Every line that is not a comment is an url, an `#EXTINF:<length>,<title>` line before it
+/// names it
+pub fn parse_m3u(content: &str) -> Vec<Station> {
+    let mut stations = Vec::new();
+    let mut name = None;
+    for line in content.lines().map(str::trim) {
+        if let Some(info) = line.strip_prefix("#EXTINF:") {
+            name = info
+                .split_once(',')
+                .map(|(_, title)| title.trim().to_string());
+        } else if !line.is_empty() && !line.starts_with('#') {
+            stations.push(Station {
+                name: name.take().unwrap_or_else(|| line.to_string()),
+                url: line.to_string(),
+            });
+        }
+    }
+    stations
+}
+
+/// An ini file with `FileN` urls and optional `TitleN` names
+pub fn parse_pls(content: &str) -> Vec<Station> {
+    let entries: Vec<(String, &str)> = content
+        .lines()
+        .filter_map(|line| line.trim().split_once('='))
+        .map(|(key, value)| (key.trim().to_lowercase(), value.trim()))
+        .collect();
+    let value = |key: &str| entries.iter().find(|(k, _)| k == key).map(|(_, v)| *v);
+    let mut files: Vec<(u32, &str)> = entries
+        .iter()
+        .filter_map(|(key, url)| Some((key.strip_prefix("file")?.parse().ok()?, *url)))
+        .collect();
+    files.sort_by_key(|(n, _)| *n);
+    files
+        .into_iter()
+        .map(|(n, url)| Station {
+            name: value(&format!("title{}", n))
+                .filter(|title| !title.is_empty())
+                .unwrap_or(url)
+                .to_string(),
+            url: url.to_string(),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn m3u_names_come_from_extinf() {
+        let stations = parse_m3u(
+            "#EXTM3U\n\
+             #EXTINF:-1,Radio One\n\
+             http://one.example/stream\n\
+             \n\
+             http://two.example/stream\n",
+        );
+        assert_eq!(
+            stations,
+            vec![
+                Station {
+                    name: "Radio One".to_string(),
+                    url: "http://one.example/stream".to_string(),
+                },
+                Station {
+                    name: "http://two.example/stream".to_string(),
+                    url: "http://two.example/stream".to_string(),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn pls_entries_are_ordered_by_number() {
+        let stations = parse_pls(
+            "[playlist]\n\
+             File2=http://two.example/stream\n\
+             Title2=Radio Two\n\
+             File1=http://one.example/stream\n\
+             NumberOfEntries=2\n\
+             Version=2\n",
+        );
+        let urls: Vec<&str> = stations.iter().map(|s| s.url.as_str()).collect();
+        assert_eq!(
+            urls,
+            vec!["http://one.example/stream", "http://two.example/stream"]
+        );
+        assert_eq!(stations[0].name, "http://one.example/stream");
+        assert_eq!(stations[1].name, "Radio Two");
+    }
+}
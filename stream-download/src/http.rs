@@ -1,7 +1,10 @@
 use async_trait::async_trait;
 use bytes::Bytes;
-use futures::Stream;
-use reqwest::Client;
+use futures::{future, stream, Stream};
+use reqwest::{
+    header::{self, HeaderMap},
+    Client,
+};
 use std::{
     pin::Pin,
     str::FromStr,
@@ -9,20 +12,71 @@ use std::{
 };
 use tracing::{info, warn};
 
-use crate::source::SourceStream;
+use crate::{
+    icy::{stream_title, IcyDemuxer},
+    source::SourceStream,
+};
+
+/// Called with the titles announced in the ICY metadata of a stream
+pub type TitleHandler = Box<dyn Fn(String) + Send + Sync>;
 
 pub struct HttpStream {
     stream: Box<dyn Stream<Item = Result<Bytes, reqwest::Error>> + Unpin + Send + Sync>,
     client: Client,
     content_length: Option<u64>,
     url: reqwest::Url,
+    is_live: bool,
+    icy: Option<IcyDemuxer>,
+    on_title: Option<TitleHandler>,
+}
+
+/// Shoutcast/Icecast servers send `icy-` headers, other servers send streams that never end as
+/// audio without a length
+fn is_live(headers: &HeaderMap) -> bool {
+    let is_audio = headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|content_type| content_type.to_str().ok())
+        .is_some_and(|content_type| content_type.starts_with("audio/"));
+    headers.keys().any(|name| name.as_str().starts_with("icy-"))
+        || is_audio && !headers.contains_key(header::CONTENT_LENGTH)
+}
+
+impl HttpStream {
+    /// Whether the stream never ends, e.g. of a Shoutcast/Icecast server
+    pub fn is_live(&self) -> bool {
+        self.is_live
+    }
+
+    pub fn set_title_handler(&mut self, on_title: TitleHandler) {
+        self.on_title = Some(on_title);
+    }
 }
 
 impl Stream for HttpStream {
     type Item = Result<Bytes, reqwest::Error>;
 
     fn poll_next(mut self: Pin<&mut Self>, cx: &mut task::Context<'_>) -> Poll<Option<Self::Item>> {
-        Pin::new(&mut self.stream).poll_next(cx)
+        let this = &mut *self;
+        let Some(icy) = &mut this.icy else {
+            return Pin::new(&mut this.stream).poll_next(cx);
+        };
+        loop {
+            let bytes = match Pin::new(&mut this.stream).poll_next(cx) {
+                Poll::Ready(Some(Ok(bytes))) => bytes,
+                other => return other,
+            };
+            let (audio, metadata) = icy.push(&bytes);
+            for title in metadata.iter().filter_map(|m| stream_title(m.as_str())) {
+                info!("Stream title {title}");
+                if let Some(on_title) = &this.on_title {
+                    on_title(title);
+                }
+            }
+            // chunks with nothing but metadata are skipped
+            if !audio.is_empty() {
+                return Poll::Ready(Some(Ok(Bytes::from(audio))));
+            }
+        }
     }
 }
 
@@ -31,27 +85,46 @@ impl SourceStream for HttpStream {
     type Url = reqwest::Url;
     type Error = reqwest::Error;
 
-    async fn create(url: Self::Url) -> Self {
+    async fn create(url: Self::Url) -> Result<Self, Self::Error> {
         let client = Client::new();
         info!("Requesting content length");
-        let response = client.get(url.as_str()).send().await.unwrap();
+        let response = client
+            .get(url.as_str())
+            .header("Icy-MetaData", "1")
+            .send()
+            .await?
+            .error_for_status()?;
 
         let mut content_length = None;
-        if let Some(length) = response.headers().get(reqwest::header::CONTENT_LENGTH) {
-            let length = u64::from_str(length.to_str().unwrap()).unwrap();
-            info!("Got content length {length}");
-            content_length = Some(length);
+        if let Some(length) = response.headers().get(header::CONTENT_LENGTH) {
+            content_length = length.to_str().ok().and_then(|l| u64::from_str(l).ok());
+            info!("Got content length {:?}", content_length);
         } else {
             warn!("Content length header missing");
         }
 
+        let is_live = is_live(response.headers());
+        let icy = response
+            .headers()
+            .get("icy-metaint")
+            .and_then(|metaint| metaint.to_str().ok())
+            .and_then(|metaint| metaint.parse::<usize>().ok())
+            .filter(|metaint| *metaint > 0)
+            .map(|metaint| {
+                info!("Got ICY metadata interval {metaint}");
+                IcyDemuxer::new(metaint)
+            });
+
         let stream = response.bytes_stream();
-        Self {
+        Ok(Self {
             stream: Box::new(stream),
             client,
             content_length,
             url,
-        }
+            is_live,
+            icy,
+            on_title: None,
+        })
     }
 
     async fn content_length(&self) -> Option<u64> {
@@ -59,23 +132,63 @@ impl SourceStream for HttpStream {
     }
     async fn seek(&mut self, pos: u64) {
         info!("Seeking");
-        self.stream = Box::new(
-            self.client
-                .get(self.url.as_str())
-                .header(
-                    "Range",
-                    format!(
-                        "bytes={pos}-{}",
-                        self.content_length
-                            .map(|l| l.to_string())
-                            .unwrap_or_default()
-                    ),
-                )
-                .send()
-                .await
-                .unwrap()
-                .bytes_stream(),
-        );
+        let response = self
+            .client
+            .get(self.url.as_str())
+            .header(
+                "Range",
+                format!(
+                    "bytes={pos}-{}",
+                    self.content_length
+                        .map(|l| l.to_string())
+                        .unwrap_or_default()
+                ),
+            )
+            .send()
+            .await
+            .and_then(|response| response.error_for_status());
+        self.stream = match response {
+            Ok(response) => Box::new(response.bytes_stream()),
+            // the download ends with the error
+            Err(err) => Box::new(stream::once(future::ready(Err(err)))),
+        };
         info!("Done seeking");
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::open_http;
+    use reqwest::header::HeaderValue;
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    fn headers(headers: &[(&'static str, &'static str)]) -> HeaderMap {
+        headers
+            .iter()
+            .map(|(name, value)| (name.parse().unwrap(), HeaderValue::from_static(value)))
+            .collect()
+    }
+
+    #[test]
+    fn endless_streams_are_live() {
+        assert!(is_live(&headers(&[("icy-name", "Radio")])));
+        assert!(is_live(&headers(&[("content-type", "audio/mpeg")])));
+        assert!(!is_live(&headers(&[
+            ("content-type", "audio/mpeg"),
+            ("content-length", "1024"),
+        ])));
+        assert!(!is_live(&headers(&[("content-type", "text/html")])));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn failed_requests_are_errors() {
+        let server = MockServer::start().await;
+        Mock::given(wiremock::matchers::any())
+            .respond_with(ResponseTemplate::new(404))
+            .mount(&server)
+            .await;
+        let url = reqwest::Url::parse(&server.uri()).unwrap();
+        assert!(open_http(url, Box::new(|_| {})).is_err());
+    }
+}
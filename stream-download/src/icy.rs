@@ -0,0 +1,129 @@
+//! Shoutcast/Icecast servers interleave the audio with metadata blocks when the request asks for
+//! them with `Icy-MetaData: 1`, the `icy-metaint` response header tells how many bytes of audio
+//! come before each block.
+
+/// Splits the body of a response into audio and metadata
+#[derive(Debug)]
+pub struct IcyDemuxer {
+    metaint: usize,
+    audio_left: usize,
+    // bytes left of the metadata block that is being read
+    metadata_left: Option<usize>,
+    metadata: Vec<u8>,
+}
+
+impl IcyDemuxer {
+    pub fn new(metaint: usize) -> Self {
+        Self {
+            metaint,
+            audio_left: metaint,
+            metadata_left: None,
+            metadata: Vec::new(),
+        }
+    }
+
+    /// Returns the audio of the chunk and the metadata blocks completed by it, chunks don't need
+    /// to line up with the blocks
+    pub fn push(&mut self, mut chunk: &[u8]) -> (Vec<u8>, Vec<String>) {
+        let mut audio = Vec::with_capacity(chunk.len());
+        let mut metadata = Vec::new();
+        while !chunk.is_empty() {
+            match self.metadata_left {
+                None if 0 < self.audio_left => {
+                    let len = self.audio_left.min(chunk.len());
+                    audio.extend_from_slice(&chunk[..len]);
+                    self.audio_left -= len;
+                    chunk = &chunk[len..];
+                }
+                None => {
+                    // the length byte counts in blocks of 16 bytes
+                    let len = chunk[0] as usize * 16;
+                    chunk = &chunk[1..];
+                    if len == 0 {
+                        self.audio_left = self.metaint;
+                    } else {
+                        self.metadata_left = Some(len);
+                    }
+                }
+                Some(left) => {
+                    let len = left.min(chunk.len());
+                    self.metadata.extend_from_slice(&chunk[..len]);
+                    chunk = &chunk[len..];
+                    if len < left {
+                        self.metadata_left = Some(left - len);
+                        continue;
+                    }
+                    // blocks are padded with zeros
+                    let block = String::from_utf8_lossy(&self.metadata);
+                    metadata.push(block.trim_end_matches('\0').to_string());
+                    self.metadata.clear();
+                    self.metadata_left = None;
+                    self.audio_left = self.metaint;
+                }
+            }
+        }
+        (audio, metadata)
+    }
+}
+
+/// The `StreamTitle` of a metadata block like `StreamTitle='Artist - Title';StreamUrl='';`
+pub fn stream_title(metadata: &str) -> Option<String> {
+    let start = metadata.find("StreamTitle='")? + "StreamTitle='".len();
+    let rest = &metadata[start..];
+    // titles can contain quotes themselves, the value only ends with a quote before a semicolon
+    let end = rest.find("';").or_else(|| rest.rfind('\''))?;
+    let title = rest[..end].trim();
+    if title.is_empty() {
+        None
+    } else {
+        Some(title.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn block(metadata: &str) -> Vec<u8> {
+        let len = metadata.len().div_ceil(16);
+        let mut block = vec![len as u8];
+        block.extend_from_slice(metadata.as_bytes());
+        block.resize(1 + len * 16, 0);
+        block
+    }
+
+    #[test]
+    fn demuxer_strips_metadata_across_chunks() {
+        let mut body = b"abcd".to_vec();
+        body.extend(block("StreamTitle='Band - Song';"));
+        body.extend(b"efgh");
+        body.push(0);
+        body.extend(b"ijkl");
+
+        let mut demuxer = IcyDemuxer::new(4);
+        let mut audio = Vec::new();
+        let mut metadata = Vec::new();
+        // odd chunk sizes split the length byte and the blocks
+        for chunk in body.chunks(3) {
+            let (a, m) = demuxer.push(chunk);
+            audio.extend(a);
+            metadata.extend(m);
+        }
+        assert_eq!(audio, b"abcdefghijkl");
+        assert_eq!(metadata, vec!["StreamTitle='Band - Song';"]);
+    }
+
+    #[test]
+    fn stream_titles_are_extracted() {
+        assert_eq!(
+            stream_title("StreamTitle='Guns N' Roses - Don't Cry';StreamUrl='';").as_deref(),
+            Some("Guns N' Roses - Don't Cry")
+        );
+        assert_eq!(
+            stream_title("StreamTitle='Band - Song'").as_deref(),
+            Some("Band - Song")
+        );
+        assert_eq!(stream_title("StreamTitle='';"), None);
+        assert_eq!(stream_title("StreamUrl='http://radio';"), None);
+    }
+}
@@ -1,5 +1,6 @@
 use source::{Source, SourceHandle, SourceStream};
 use std::{
+    future::Future,
     io::{self, BufReader, Read, Seek, SeekFrom},
     thread,
 };
@@ -9,8 +10,14 @@ use tracing::debug;
 
 #[cfg(feature = "http")]
 pub mod http;
+pub mod icy;
+mod live;
+#[cfg(feature = "http")]
+pub mod segmented;
 pub mod source;
 
+pub use live::LiveStream;
+
 #[derive(Debug)]
 pub struct StreamDownload {
     output_reader: BufReader<NamedTempFile>,
@@ -24,29 +31,23 @@ impl StreamDownload {
         Self::new::<http::HttpStream>(url)
     }
 
+    /// Plays the segments of a stream split into segments, e.g. by MPEG-DASH, as one file
+    #[cfg(feature = "http")]
+    pub fn new_segmented(urls: Vec<reqwest::Url>) -> Self {
+        Self::new::<segmented::SegmentedStream>(urls)
+    }
+
     pub fn new<S: SourceStream>(url: S::Url) -> Self {
         let tempfile = tempfile::Builder::new().tempfile().unwrap();
-        let source = Source::new(tempfile.reopen().unwrap());
+        let mut source = Source::new(tempfile.reopen().unwrap());
         let handle = source.source_handle();
 
-        if let Ok(handle) = tokio::runtime::Handle::try_current() {
-            handle.spawn(async move {
-                let stream = S::create(url).await;
-                source.download(stream).await;
-            });
-        } else {
-            thread::spawn(move || {
-                let rt = tokio::runtime::Builder::new_current_thread()
-                    .enable_all()
-                    .build()
-                    .unwrap();
-
-                rt.block_on(async move {
-                    let stream = S::create(url).await;
-                    source.download(stream).await;
-                });
-            });
-        };
+        spawn(async move {
+            match S::create(url).await {
+                Ok(stream) => source.download(stream).await,
+                Err(err) => source.fail(err),
+            }
+        });
 
         Self {
             output_reader: BufReader::new(tempfile),
@@ -60,22 +61,9 @@ impl StreamDownload {
         let source = Source::new(tempfile.reopen().unwrap());
         let handle = source.source_handle();
 
-        if let Ok(handle) = tokio::runtime::Handle::try_current() {
-            handle.spawn(async move {
-                source.download(stream).await;
-            });
-        } else {
-            thread::spawn(move || {
-                let rt = tokio::runtime::Builder::new_current_thread()
-                    .enable_all()
-                    .build()
-                    .unwrap();
-
-                rt.block_on(async move {
-                    source.download(stream).await;
-                });
-            });
-        };
+        spawn(async move {
+            source.download(stream).await;
+        });
 
         Self {
             output_reader: BufReader::new(tempfile),
@@ -85,6 +73,70 @@ impl StreamDownload {
     }
 }
 
+/// Opens an http url. Streams of Shoutcast/Icecast servers and audio without a length are read
+/// as endless live streams, everything else is downloaded so it can be seeked. `on_title` is called with the titles
+/// announced in the ICY metadata of live streams.
+#[cfg(feature = "http")]
+pub fn open_http(
+    url: reqwest::Url,
+    on_title: http::TitleHandler,
+) -> io::Result<Box<dyn MediaSource>> {
+    let tempfile = tempfile::Builder::new().tempfile()?;
+    let source = Source::new(tempfile.reopen()?);
+    let handle = source.source_handle();
+    let (live_tx, live_rx) = tokio::sync::mpsc::channel(live::LIVE_BUFFER_CHUNKS);
+    let (is_live_tx, is_live_rx) = std::sync::mpsc::sync_channel(1);
+
+    spawn(async move {
+        let mut stream = match http::HttpStream::create(url).await {
+            Ok(stream) => stream,
+            Err(err) => {
+                is_live_tx.send(Err(err)).ok();
+                return;
+            }
+        };
+        let is_live = stream.is_live();
+        is_live_tx.send(Ok(is_live)).ok();
+        if is_live {
+            stream.set_title_handler(on_title);
+            LiveStream::receive(stream, live_tx).await;
+        } else {
+            source.download(stream).await;
+        }
+    });
+
+    match is_live_rx.recv() {
+        Ok(Ok(true)) => Ok(Box::new(LiveStream::new(live_rx))),
+        Ok(Ok(false)) => Ok(Box::new(StreamDownload {
+            output_reader: BufReader::new(tempfile),
+            handle,
+            read_position: 0,
+        })),
+        Ok(Err(err)) => Err(io::Error::new(io::ErrorKind::ConnectionAborted, err)),
+        // the runtime went away before there was a response
+        Err(_) => Err(io::Error::new(
+            io::ErrorKind::ConnectionAborted,
+            "Could not open the stream",
+        )),
+    }
+}
+
+/// Runs the download on the current tokio runtime, or on a new one if there is none
+fn spawn<F: Future<Output = ()> + Send + 'static>(download: F) {
+    if let Ok(handle) = tokio::runtime::Handle::try_current() {
+        handle.spawn(download);
+    } else {
+        thread::spawn(move || {
+            let rt = tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()
+                .unwrap();
+
+            rt.block_on(download);
+        });
+    };
+}
+
 impl Read for StreamDownload {
     fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
         debug!("Read request buf len: {}", buf.len());
@@ -110,6 +162,18 @@ impl Read for StreamDownload {
         debug!("waiting for position");
         self.handle.wait_for_requested_position();
 
+        if let Some(err) = self.handle.error() {
+            // what was downloaded before the download failed can still be read
+            let available = match self.handle.downloaded().get(&self.read_position) {
+                Some(range) => range.end - self.read_position,
+                None => return Err(err),
+            };
+            let len = buf.len().min(available as usize);
+            let read_len = self.output_reader.read(&mut buf[..len])?;
+            self.read_position += read_len as u64;
+            return Ok(read_len);
+        }
+
         debug!("reached requested position {requested_position}");
         self.output_reader.read(buf)
     }
@@ -149,6 +213,12 @@ impl Seek for StreamDownload {
         self.handle.seek(seek_pos);
         self.handle.wait_for_requested_position();
 
+        if let Some(err) = self.handle.error() {
+            if self.handle.downloaded().get(&seek_pos).is_none() {
+                return Err(err);
+            }
+        }
+
         debug!("reached seek position");
         self.output_reader.seek(pos)
     }
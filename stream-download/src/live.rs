@@ -0,0 +1,109 @@
+use bytes::{Buf, Bytes};
+use futures::StreamExt;
+use std::io::{self, Read, Seek, SeekFrom};
+use symphonia::core::io::MediaSource;
+use tokio::sync::mpsc;
+use tracing::{info, warn};
+
+use crate::source::SourceStream;
+
+// Chunks received but not read yet, this keeps a live stream from being buffered without end
+// while the player is paused
+pub(crate) const LIVE_BUFFER_CHUNKS: usize = 64;
+
+/// A stream without an end, e.g. internet radio. It is read as it arrives instead of being
+/// downloaded to a file, so it can not be seeked.
+#[derive(Debug)]
+pub struct LiveStream {
+    chunks: mpsc::Receiver<Bytes>,
+    chunk: Bytes,
+    read_position: u64,
+}
+
+impl LiveStream {
+    pub(crate) fn new(chunks: mpsc::Receiver<Bytes>) -> Self {
+        Self {
+            chunks,
+            chunk: Bytes::new(),
+            read_position: 0,
+        }
+    }
+
+    /// Passes the chunks of the stream on until it ends or the `LiveStream` is dropped
+    pub(crate) async fn receive<S: SourceStream>(mut stream: S, tx: mpsc::Sender<Bytes>) {
+        info!("Starting live stream");
+        while let Some(bytes) = stream.next().await {
+            match bytes {
+                Ok(bytes) => {
+                    if tx.send(bytes).await.is_err() {
+                        info!("Live stream closed");
+                        return;
+                    }
+                }
+                Err(err) => {
+                    warn!("Live stream failed: {}", err);
+                    return;
+                }
+            }
+        }
+        info!("Live stream finished");
+    }
+}
+
+impl Read for LiveStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.chunk.is_empty() {
+            match self.chunks.blocking_recv() {
+                Some(chunk) => self.chunk = chunk,
+                // the stream ended
+                None => return Ok(0),
+            }
+        }
+        let len = buf.len().min(self.chunk.len());
+        self.chunk.copy_to_slice(&mut buf[..len]);
+        self.read_position += len as u64;
+        Ok(len)
+    }
+}
+
+impl Seek for LiveStream {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        match pos {
+            SeekFrom::Current(0) => Ok(self.read_position),
+            SeekFrom::Start(pos) if pos == self.read_position => Ok(self.read_position),
+            _ => Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "Cannot seek in a live stream",
+            )),
+        }
+    }
+}
+
+impl MediaSource for LiveStream {
+    fn is_seekable(&self) -> bool {
+        false
+    }
+
+    fn byte_len(&self) -> Option<u64> {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_chunks_until_the_stream_ends() {
+        let (tx, rx) = mpsc::channel(LIVE_BUFFER_CHUNKS);
+        tx.try_send(Bytes::from_static(b"abc")).unwrap();
+        tx.try_send(Bytes::from_static(b"defg")).unwrap();
+        drop(tx);
+        let mut live = LiveStream::new(rx);
+        let mut read = Vec::new();
+        live.read_to_end(&mut read).unwrap();
+        assert_eq!(read, b"abcdefg");
+        assert_eq!(live.stream_position().unwrap(), 7);
+        assert!(live.seek(SeekFrom::Start(0)).is_err());
+    }
+}
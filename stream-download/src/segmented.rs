@@ -0,0 +1,239 @@
+//! Streams split into segments, e.g. by MPEG-DASH. The initialization segment and the media
+//! segments are downloaded one after the other, so the decoder reads them as one file.
+use async_trait::async_trait;
+use base64::{engine::general_purpose::STANDARD, Engine};
+use bytes::Bytes;
+use futures::{Stream, StreamExt};
+use reqwest::{Client, Url};
+use std::{
+    pin::Pin,
+    task::{self, Poll},
+};
+use tokio::{sync::mpsc, task::JoinHandle};
+use tracing::{debug, info};
+
+use crate::source::SourceStream;
+
+// Chunks downloaded ahead of the ones passed on
+const SEGMENT_BUFFER_CHUNKS: usize = 16;
+
+type Chunk = Result<(usize, Bytes), reqwest::Error>;
+
+pub struct SegmentedStream {
+    client: Client,
+    urls: Vec<Url>,
+    // where the segments start, known for all segments up to the one being received
+    offsets: Vec<u64>,
+    segment: usize,
+    // of the next byte received
+    position: u64,
+    // bytes before this position are dropped after seeking into the middle of a segment
+    skip_to: u64,
+    chunks: mpsc::Receiver<Chunk>,
+    download: JoinHandle<()>,
+}
+
+/// Downloads the segments from `first` on, tagged with their index
+fn download(
+    client: &Client,
+    urls: &[Url],
+    first: usize,
+) -> (mpsc::Receiver<Chunk>, JoinHandle<()>) {
+    let (tx, rx) = mpsc::channel(SEGMENT_BUFFER_CHUNKS);
+    let client = client.clone();
+    let urls = urls[first..].to_vec();
+    let download = tokio::spawn(async move {
+        for (segment, url) in (first..).zip(urls) {
+            debug!("Requesting segment {segment}");
+            let response = client
+                .get(url)
+                .send()
+                .await
+                .and_then(|response| response.error_for_status());
+            let mut body = match response {
+                Ok(response) => response.bytes_stream(),
+                Err(err) => {
+                    tx.send(Err(err)).await.ok();
+                    return;
+                }
+            };
+            while let Some(bytes) = body.next().await {
+                let stop = bytes.is_err();
+                if tx.send(bytes.map(|b| (segment, b))).await.is_err() || stop {
+                    return;
+                }
+            }
+        }
+    });
+    (rx, download)
+}
+
+impl Drop for SegmentedStream {
+    fn drop(&mut self) {
+        self.download.abort();
+    }
+}
+
+/// The urls of a `data:text/uri-list` url, which is how providers hand over lists of segments
+/// as a single url, see `crabidy_core::ProviderClient::get_urls_for_track`
+pub fn uri_list(url: &str) -> Option<Vec<Url>> {
+    let (media_type, data) = url.strip_prefix("data:")?.split_once(',')?;
+    let list = match media_type.strip_suffix(";base64") {
+        Some("text/uri-list") => String::from_utf8(STANDARD.decode(data).ok()?).ok()?,
+        _ => return None,
+    };
+    // lines starting with `#` are comments
+    let urls: Vec<Url> = list
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(Url::parse)
+        .collect::<Result<_, _>>()
+        .ok()?;
+    Some(urls).filter(|urls| !urls.is_empty())
+}
+
+impl Stream for SegmentedStream {
+    type Item = Result<Bytes, reqwest::Error>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut task::Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = &mut *self;
+        loop {
+            let (segment, mut bytes) = match this.chunks.poll_recv(cx) {
+                Poll::Ready(Some(Ok(chunk))) => chunk,
+                Poll::Ready(Some(Err(err))) => return Poll::Ready(Some(Err(err))),
+                Poll::Ready(None) => return Poll::Ready(None),
+                Poll::Pending => return Poll::Pending,
+            };
+            if segment != this.segment {
+                this.segment = segment;
+                if segment == this.offsets.len() {
+                    this.offsets.push(this.position);
+                }
+            }
+            let start = this.position;
+            this.position += bytes.len() as u64;
+            if this.position <= this.skip_to {
+                continue;
+            }
+            if start < this.skip_to {
+                bytes = bytes.slice((this.skip_to - start) as usize..);
+            }
+            return Poll::Ready(Some(Ok(bytes)));
+        }
+    }
+}
+
+#[async_trait]
+impl SourceStream for SegmentedStream {
+    type Url = Vec<Url>;
+    type Error = reqwest::Error;
+
+    async fn create(urls: Self::Url) -> Result<Self, Self::Error> {
+        info!("Streaming {} segments", urls.len());
+        let client = Client::new();
+        let (chunks, download) = download(&client, &urls, 0);
+        // the requests are made by the download, failed ones end the stream
+        Ok(Self {
+            client,
+            urls,
+            offsets: vec![0],
+            segment: 0,
+            position: 0,
+            skip_to: 0,
+            chunks,
+            download,
+        })
+    }
+
+    // the length of the segments is only known once they are downloaded
+    async fn content_length(&self) -> Option<u64> {
+        None
+    }
+
+    async fn seek(&mut self, pos: u64) {
+        // positions past the segments started so far are reached by downloading from the last one
+        let segment = self.offsets.partition_point(|offset| *offset <= pos) - 1;
+        info!("Seeking to segment {segment}");
+        self.segment = segment;
+        self.position = self.offsets[segment];
+        self.skip_to = pos;
+        self.download.abort();
+        (self.chunks, self.download) = download(&self.client, &self.urls, segment);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::StreamDownload;
+    use std::{io::Read, sync::mpsc, thread, time::Duration};
+    use wiremock::{matchers::path, Mock, MockServer, ResponseTemplate};
+
+    // Serves `/0`, `/1` and so on with the segments
+    async fn segment_server(segments: &[&'static str]) -> (MockServer, Vec<Url>) {
+        let server = MockServer::start().await;
+        let mut urls = Vec::new();
+        for (i, segment) in segments.iter().enumerate() {
+            Mock::given(path(format!("/{}", i)))
+                .respond_with(ResponseTemplate::new(200).set_body_string(*segment))
+                .mount(&server)
+                .await;
+            urls.push(Url::parse(&format!("{}/{}", server.uri(), i)).unwrap());
+        }
+        (server, urls)
+    }
+
+    async fn read(stream: &mut SegmentedStream, len: usize) -> Vec<u8> {
+        let mut read = Vec::new();
+        while read.len() < len {
+            read.extend_from_slice(&stream.next().await.unwrap().unwrap());
+        }
+        read
+    }
+
+    #[tokio::test]
+    async fn segments_are_concatenated_and_seeked() {
+        let (_server, urls) = segment_server(&["init", "abcdef", "ghij", "klm"]).await;
+        let mut stream = SegmentedStream::create(urls).await.unwrap();
+        assert_eq!(read(&mut stream, 17).await, b"initabcdefghijklm");
+        assert!(stream.next().await.is_none());
+
+        // into the middle of a segment that was downloaded before
+        stream.seek(12).await;
+        assert_eq!(read(&mut stream, 5).await, b"ijklm");
+        stream.seek(2).await;
+        assert_eq!(read(&mut stream, 15).await, b"itabcdefghijklm");
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn failed_segments_fail_the_read() {
+        let (server, mut urls) = segment_server(&["init"]).await;
+        // e.g. an expired signed url
+        Mock::given(path("/1"))
+            .respond_with(ResponseTemplate::new(403))
+            .mount(&server)
+            .await;
+        urls.push(Url::parse(&format!("{}/1", server.uri())).unwrap());
+
+        let mut download = StreamDownload::new_segmented(urls);
+        let (tx, rx) = mpsc::channel();
+        thread::spawn(move || {
+            let mut read = Vec::new();
+            tx.send(download.read_to_end(&mut read)).ok();
+        });
+        let read = rx
+            .recv_timeout(Duration::from_secs(10))
+            .expect("the read should not hang");
+        assert!(read.is_err());
+    }
+
+    #[test]
+    fn uri_lists_are_decoded() {
+        let list = STANDARD.encode("# segments\r\nhttps://sp/0.mp4\r\nhttps://sp/1.mp4\r\n");
+        let urls = uri_list(&format!("data:text/uri-list;base64,{}", list)).unwrap();
+        assert_eq!(urls[1].as_str(), "https://sp/1.mp4");
+        assert_eq!(uri_list("data:text/plain;base64,aGk="), None);
+        assert_eq!(uri_list("https://sp/0.mp4"), None);
+    }
+}
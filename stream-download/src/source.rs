@@ -6,14 +6,14 @@ use rangemap::RangeSet;
 use std::{
     error::Error,
     fs::File,
-    io::{BufWriter, Seek, SeekFrom, Write},
+    io::{self, BufWriter, Seek, SeekFrom, Write},
     sync::{
         atomic::{AtomicI64, Ordering},
         Arc,
     },
 };
 use tokio::sync::mpsc;
-use tracing::{debug, info, trace};
+use tracing::{debug, info, trace, warn};
 
 #[async_trait]
 pub trait SourceStream:
@@ -22,7 +22,9 @@ pub trait SourceStream:
     type Url: Send;
     type Error: Error + Send;
 
-    async fn create(url: Self::Url) -> Self;
+    async fn create(url: Self::Url) -> Result<Self, Self::Error>
+    where
+        Self: Sized;
     async fn content_length(&self) -> Option<u64>;
     async fn seek(&mut self, position: u64);
 }
@@ -63,6 +65,12 @@ impl SourceHandle {
         }
     }
 
+    /// The error the download stopped with, if it failed
+    pub fn error(&self) -> Option<io::Error> {
+        let (mutex, _) = &*self.position_reached;
+        mutex.lock().error.as_deref().map(io::Error::other)
+    }
+
     pub fn seek(&self, position: u64) {
         self.seek_tx.try_send(position).ok();
     }
@@ -86,6 +94,7 @@ impl SourceHandle {
 struct Waiter {
     position_reached: bool,
     stream_done: bool,
+    error: Option<String>,
 }
 
 pub struct Source {
@@ -137,7 +146,17 @@ impl Source {
         let mut initial_buffer = 0;
         loop {
             if let Some(bytes) = stream.next().await {
-                let bytes = bytes.unwrap();
+                let bytes = match bytes {
+                    Ok(bytes) => bytes,
+                    Err(err) => {
+                        if initial_buffer > 0 {
+                            self.position += initial_buffer;
+                            self.downloaded.write().insert(0..initial_buffer);
+                        }
+                        self.fail(err);
+                        return;
+                    }
+                };
                 self.writer.write_all(&bytes).unwrap();
                 initial_buffer += bytes.len() as u64;
                 trace!("Prefetch: {}/{} bytes", initial_buffer, PREFETCH_BYTES);
@@ -163,7 +182,13 @@ impl Source {
             tokio::select! {
                 bytes = stream.next() => {
                     if let Some(bytes) = bytes {
-                        let bytes = bytes.unwrap();
+                        let bytes = match bytes {
+                            Ok(bytes) => bytes,
+                            Err(err) => {
+                                self.fail(err);
+                                return;
+                            }
+                        };
                         let chunk_len = bytes.len() as u64;
                         self.writer.write_all(&bytes).unwrap();
                         let new_position = self.position + chunk_len;
@@ -215,6 +240,27 @@ impl Source {
         }
     }
 
+    /// Ends the download, reads past what was downloaded so far fail with `err`
+    pub(crate) fn fail<E: Error>(&mut self, err: E) {
+        warn!("Download failed: {}", err);
+        self.writer.flush().ok();
+        {
+            // when the stream could not be created, there is no length either
+            let (mutex, cvar) = &*self.content_length_retrieved;
+            let mut done = mutex.lock();
+            if !*done {
+                self.content_length.store(-1, Ordering::SeqCst);
+                *done = true;
+                cvar.notify_all();
+            }
+        }
+        let (mutex, cvar) = &*self.position_reached;
+        let mut waiter = mutex.lock();
+        waiter.error = Some(err.to_string());
+        waiter.stream_done = true;
+        cvar.notify_all();
+    }
+
     pub fn source_handle(&self) -> SourceHandle {
         SourceHandle {
             downloaded: self.downloaded.clone(),
@@ -0,0 +1,22 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Settings {
+    /// Base url of the server, e.g. `http://localhost:4533` for Navidrome
+    pub url: String,
+    pub username: String,
+    pub password: String,
+    /// Name the server sees requests coming from
+    pub client_name: String,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            url: "http://localhost:4533".to_string(),
+            username: String::new(),
+            password: String::new(),
+            client_name: "crabidy".to_string(),
+        }
+    }
+}
@@ -0,0 +1,476 @@
+//! A provider for servers speaking the Subsonic api, e.g. Navidrome, Gonic or Airsonic
+use async_trait::async_trait;
+use crabidy_core::{
+    proto::crabidy::{LibraryNode, LibraryNodeChild, SearchResult, Track},
+    ProviderError,
+};
+use rand::{distributions::Alphanumeric, Rng};
+use reqwest::{Client as HttpClient, Url};
+use tracing::{debug, instrument, warn};
+pub mod config;
+pub mod models;
+pub use models::*;
+
+const API_VERSION: &str = "1.16.1";
+const SEARCH_COUNT: usize = 50;
+
+#[derive(Debug)]
+pub struct Client {
+    http_client: HttpClient,
+    settings: config::Settings,
+    // token authentication sends md5(password + salt) instead of the password
+    salt: String,
+    token: String,
+}
+
+#[async_trait]
+impl crabidy_core::ProviderClient for Client {
+    #[instrument(skip(raw_toml_settings))]
+    async fn init(raw_toml_settings: &str) -> Result<Self, ProviderError> {
+        let settings: config::Settings = if let Ok(settings) = toml::from_str(raw_toml_settings) {
+            settings
+        } else {
+            let settings = config::Settings::default();
+            warn!(
+                "could not parse toml settings: {:#?} using default settings instead: {:#?}",
+                raw_toml_settings, settings
+            );
+            settings
+        };
+        let client = Self::new(settings)?;
+        client.ping().await?;
+        Ok(client)
+    }
+
+    #[instrument(skip(self))]
+    fn settings(&self) -> String {
+        toml::to_string_pretty(&self.settings).unwrap_or_default()
+    }
+
+    #[instrument(skip(self))]
+    async fn get_urls_for_track(&self, track_uuid: &str) -> Result<Vec<String>, ProviderError> {
+        debug!("get_urls_for_track {}", track_uuid);
+        let id = track_id(track_uuid)?;
+        Ok(vec![self.stream_url(id)?])
+    }
+
+    #[instrument(skip(self))]
+    async fn get_metadata_for_track(&self, track_uuid: &str) -> Result<Track, ProviderError> {
+        debug!("get_metadata_for_track {}", track_uuid);
+        let id = track_id(track_uuid)?;
+        let song = self
+            .request("getSong", &[("id", id.to_string())])
+            .await?
+            .song
+            .ok_or_else(|| ProviderError::not_found(track_uuid))?;
+        Ok((&song).into())
+    }
+
+    #[instrument(skip(self))]
+    fn get_lib_root(&self) -> LibraryNode {
+        debug!("get_lib_root in subsonicdy");
+        LibraryNode {
+            uuid: "node:subsonic".to_string(),
+            title: "subsonic".to_string(),
+            parent: Some(LibraryNode::new().uuid),
+            tracks: Vec::new(),
+            children: vec![
+                LibraryNodeChild::new(
+                    "node:subsonic:artists".to_string(),
+                    "artists".to_string(),
+                    false,
+                ),
+                LibraryNodeChild::new(
+                    "node:subsonic:playlists".to_string(),
+                    "playlists".to_string(),
+                    false,
+                ),
+            ],
+            is_queable: false,
+            capabilities: None,
+            artwork: Vec::new(),
+        }
+    }
+
+    #[instrument(skip(self))]
+    async fn get_lib_node(&self, uuid: &str) -> Result<LibraryNode, ProviderError> {
+        debug!("get_lib_node in subsonicdy {}", uuid);
+        let mut split = uuid.splitn(4, ':');
+        let (Some("node"), Some("subsonic"), module, id) =
+            (split.next(), split.next(), split.next(), split.next())
+        else {
+            return Err(ProviderError::malformed_uuid(uuid));
+        };
+        let node = match (module, id) {
+            (None, _) => self.get_lib_root(),
+            (Some("artists"), None) => {
+                let index = self.request("getArtists", &[]).await?.artists;
+                LibraryNode {
+                    uuid: uuid.to_string(),
+                    title: "artists".to_string(),
+                    parent: Some("node:subsonic".to_string()),
+                    tracks: Vec::new(),
+                    children: index
+                        .iter()
+                        .flat_map(|i| &i.index)
+                        .flat_map(|i| &i.artist)
+                        .map(LibraryNodeChild::from)
+                        .collect(),
+                    is_queable: false,
+                    capabilities: None,
+                    artwork: Vec::new(),
+                }
+            }
+            (Some("playlists"), None) => {
+                let playlists = self.request("getPlaylists", &[]).await?.playlists;
+                LibraryNode {
+                    uuid: uuid.to_string(),
+                    title: "playlists".to_string(),
+                    parent: Some("node:subsonic".to_string()),
+                    tracks: Vec::new(),
+                    children: playlists
+                        .iter()
+                        .flat_map(|p| &p.playlist)
+                        .map(LibraryNodeChild::from)
+                        .collect(),
+                    is_queable: false,
+                    capabilities: None,
+                    artwork: Vec::new(),
+                }
+            }
+            (Some("artist"), Some(id)) => {
+                let artist = self
+                    .request("getArtist", &[("id", id.to_string())])
+                    .await?
+                    .artist
+                    .ok_or_else(|| ProviderError::not_found(uuid))?;
+                LibraryNode {
+                    uuid: uuid.to_string(),
+                    title: artist.name,
+                    parent: Some("node:subsonic:artists".to_string()),
+                    tracks: Vec::new(),
+                    children: artist.album.iter().map(LibraryNodeChild::from).collect(),
+                    is_queable: true,
+                    capabilities: None,
+                    artwork: Vec::new(),
+                }
+            }
+            (Some("album"), Some(id)) => {
+                let album = self
+                    .request("getAlbum", &[("id", id.to_string())])
+                    .await?
+                    .album
+                    .ok_or_else(|| ProviderError::not_found(uuid))?;
+                let parent = match album.artist_id {
+                    Some(artist_id) => format!("node:subsonic:artist:{}", artist_id),
+                    None => "node:subsonic:artists".to_string(),
+                };
+                LibraryNode {
+                    uuid: uuid.to_string(),
+                    title: album.name,
+                    parent: Some(parent),
+                    tracks: album.song.iter().map(Track::from).collect(),
+                    children: Vec::new(),
+                    is_queable: true,
+                    capabilities: None,
+                    artwork: Vec::new(),
+                }
+            }
+            (Some("playlist"), Some(id)) => {
+                let playlist = self
+                    .request("getPlaylist", &[("id", id.to_string())])
+                    .await?
+                    .playlist
+                    .ok_or_else(|| ProviderError::not_found(uuid))?;
+                LibraryNode {
+                    uuid: uuid.to_string(),
+                    title: playlist.name,
+                    parent: Some("node:subsonic:playlists".to_string()),
+                    tracks: playlist.entry.iter().map(Track::from).collect(),
+                    children: Vec::new(),
+                    is_queable: true,
+                    capabilities: None,
+                    artwork: Vec::new(),
+                }
+            }
+            _ => return Err(ProviderError::malformed_uuid(uuid)),
+        };
+        Ok(node)
+    }
+
+    #[instrument(skip(self))]
+    async fn search(&self, query: &str) -> Result<SearchResult, ProviderError> {
+        debug!("search in subsonicdy {}", query);
+        let count = SEARCH_COUNT.to_string();
+        let found = self
+            .request(
+                "search3",
+                &[
+                    ("query", query.to_string()),
+                    ("artistCount", count.clone()),
+                    ("albumCount", count.clone()),
+                    ("songCount", count),
+                ],
+            )
+            .await?
+            .search_result3
+            .unwrap_or_default();
+        Ok(SearchResult {
+            tracks: found.song.iter().map(Track::from).collect(),
+            albums: found.album.iter().map(LibraryNodeChild::from).collect(),
+            artists: found.artist.iter().map(LibraryNodeChild::from).collect(),
+            playlists: Vec::new(),
+        })
+    }
+}
+
+impl Client {
+    pub fn new(settings: config::Settings) -> Result<Self, ProviderError> {
+        let http_client = HttpClient::builder()
+            .build()
+            .map_err(|err| ProviderError::InternalError(err.to_string()))?;
+        let salt: String = rand::thread_rng()
+            .sample_iter(&Alphanumeric)
+            .take(16)
+            .map(char::from)
+            .collect();
+        let token = format!(
+            "{:x}",
+            md5::compute(format!("{}{}", settings.password, salt))
+        );
+        Ok(Self {
+            http_client,
+            settings,
+            salt,
+            token,
+        })
+    }
+
+    /// Checks that the server is reachable and accepts the credentials
+    #[instrument(skip(self))]
+    pub async fn ping(&self) -> Result<(), ProviderError> {
+        self.request("ping", &[]).await.map(|_| ())
+    }
+
+    /// An url to stream the track from, it carries the credentials so it can be played without
+    /// any further requests
+    pub fn stream_url(&self, id: &str) -> Result<String, ProviderError> {
+        let mut params = self.auth_params();
+        params.push(("id", id.to_string()));
+        Url::parse_with_params(&self.endpoint_url("stream"), &params)
+            .map(String::from)
+            .map_err(|err| ProviderError::Config(format!("invalid subsonic url: {}", err)))
+    }
+
+    #[instrument(skip(self))]
+    pub async fn request(
+        &self,
+        endpoint: &str,
+        query: &[(&str, String)],
+    ) -> Result<Response, ProviderError> {
+        debug!("request {}", endpoint);
+        let mut params = self.auth_params();
+        params.push(("f", "json".to_string()));
+        params.extend(query.iter().cloned());
+        let envelope: Envelope = self
+            .http_client
+            .get(self.endpoint_url(endpoint))
+            .query(&params)
+            .send()
+            .await
+            .and_then(|r| r.error_for_status())
+            .map_err(fetch_error)?
+            .json()
+            .await
+            .map_err(fetch_error)?;
+        let response = envelope.response;
+        if response.status == "ok" {
+            return Ok(response);
+        }
+        let error = response.error.unwrap_or_default();
+        Err(match error.code {
+            // wrong username or password, token authentication not supported
+            40 | 41 => ProviderError::CouldNotLogin {
+                provider: "subsonic".to_string(),
+                cause: error.message,
+            },
+            50 => ProviderError::fetch("subsonic", Some(403), error.message),
+            70 => ProviderError::fetch("subsonic", Some(404), error.message),
+            code => ProviderError::Other(format!("subsonic: {} (error {})", error.message, code)),
+        })
+    }
+
+    fn endpoint_url(&self, endpoint: &str) -> String {
+        format!(
+            "{}/rest/{}",
+            self.settings.url.trim_end_matches('/'),
+            endpoint
+        )
+    }
+
+    fn auth_params(&self) -> Vec<(&'static str, String)> {
+        vec![
+            ("u", self.settings.username.clone()),
+            ("t", self.token.clone()),
+            ("s", self.salt.clone()),
+            ("v", API_VERSION.to_string()),
+            ("c", self.settings.client_name.clone()),
+        ]
+    }
+}
+
+fn fetch_error(err: reqwest::Error) -> ProviderError {
+    ProviderError::fetch("subsonic", err.status().map(|s| s.as_u16()), err)
+}
+
+fn track_id(uuid: &str) -> Result<&str, ProviderError> {
+    uuid.strip_prefix("track:subsonic:")
+        .ok_or_else(|| ProviderError::malformed_uuid(uuid))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crabidy_core::ProviderClient;
+    use wiremock::{
+        matchers::{any, path},
+        Mock, MockServer, ResponseTemplate,
+    };
+
+    const NOT_FOUND: &str =
+        r#"{"subsonic-response":{"status":"failed","error":{"code":70,"message":"not found"}}}"#;
+
+    /// Answers requests for `/rest/<endpoint>` with the json of the endpoint and all others with
+    /// the error subsonic servers send for unknown ids
+    async fn mock_server(routes: Vec<(&'static str, &'static str)>) -> MockServer {
+        let server = MockServer::start().await;
+        for (endpoint, body) in routes {
+            Mock::given(path(format!("/rest/{}", endpoint)))
+                .respond_with(ResponseTemplate::new(200).set_body_raw(body, "application/json"))
+                .mount(&server)
+                .await;
+        }
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(200).set_body_raw(NOT_FOUND, "application/json"))
+            .with_priority(u8::MAX)
+            .mount(&server)
+            .await;
+        server
+    }
+
+    fn client(url: String) -> Client {
+        Client::new(config::Settings {
+            url,
+            username: "crab".to_string(),
+            password: "secret".to_string(),
+            ..Default::default()
+        })
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn maps_albums_and_authenticates_with_a_token() {
+        let server = mock_server(vec![(
+            "getAlbum",
+            r#"{"subsonic-response":{"status":"ok","version":"1.16.1","album":{
+                "id":"al-1","name":"Album","artist":"Band","artistId":"ar-1","song":[
+                    {"id":"1","title":"First","artist":"Band","artistId":"ar-1","album":"Album",
+                     "albumId":"al-1","year":2001,"duration":61,"track":1,"discNumber":2,
+                     "replayGain":{"trackGain":-7.5,"albumGain":-8.1,"trackPeak":0.98}},
+                    {"id":2,"title":"Second","artist":"Band","album":"Album"}
+                ]}}}"#,
+        )])
+        .await;
+        let client = client(server.uri());
+        let node = client
+            .get_lib_node("node:subsonic:album:al-1")
+            .await
+            .unwrap();
+        assert_eq!(node.title, "Album");
+        assert_eq!(node.parent.as_deref(), Some("node:subsonic:artist:ar-1"));
+        let uuids: Vec<&str> = node.tracks.iter().map(|t| t.uuid.as_str()).collect();
+        assert_eq!(uuids, vec!["track:subsonic:1", "track:subsonic:2"]);
+        assert_eq!(node.tracks[0].duration, Some(61000));
+        assert_eq!(
+            node.tracks[0]
+                .album
+                .as_ref()
+                .unwrap()
+                .release_date
+                .as_deref(),
+            Some("2001")
+        );
+        assert_eq!(node.tracks[0].track_number, Some(1));
+        assert_eq!(node.tracks[0].volume_number, Some(2));
+        assert_eq!(node.tracks[0].album_replay_gain, Some(-8.1));
+        assert_eq!(node.tracks[1].replay_gain, None);
+        assert_eq!(
+            node.tracks[0].artists[0].uuid.as_deref(),
+            Some("node:subsonic:artist:ar-1")
+        );
+
+        let requests = server.received_requests().await.unwrap();
+        let url = &requests[0].url;
+        let token = format!("{:x}", md5::compute(format!("secret{}", client.salt)));
+        assert_eq!(url.path(), "/rest/getAlbum");
+        let query = url.query().unwrap_or_default();
+        assert!(query.contains(&format!("t={}", token)));
+        assert!(query.contains("u=crab"));
+        assert!(!query.contains("secret"));
+    }
+
+    #[tokio::test]
+    async fn stream_urls_carry_the_credentials() {
+        let client = client("http://music.local:4533/".to_string());
+        let urls = client
+            .get_urls_for_track("track:subsonic:42")
+            .await
+            .unwrap();
+        let url = Url::parse(&urls[0]).unwrap();
+        assert_eq!(url.path(), "/rest/stream");
+        let params: Vec<(String, String)> = url.query_pairs().into_owned().collect();
+        assert!(params.contains(&("id".to_string(), "42".to_string())));
+        assert!(params.contains(&("s".to_string(), client.salt.clone())));
+    }
+
+    #[tokio::test]
+    async fn api_errors_become_provider_errors() {
+        let server = mock_server(vec![(
+            "ping",
+            r#"{"subsonic-response":{"status":"failed","error":{"code":40,"message":"Wrong username or password"}}}"#,
+        )])
+        .await;
+        let client = client(server.uri());
+        assert!(matches!(
+            client.ping().await,
+            Err(ProviderError::CouldNotLogin { .. })
+        ));
+        assert_eq!(
+            http_status(client.get_lib_node("node:subsonic:artist:x").await),
+            Some(404)
+        );
+    }
+
+    fn http_status<T>(result: Result<T, ProviderError>) -> Option<u16> {
+        match result {
+            Err(ProviderError::FetchError { status, .. }) => status,
+            _ => None,
+        }
+    }
+
+    #[tokio::test]
+    async fn search_maps_all_kinds() {
+        let server = mock_server(vec![(
+            "search3",
+            r#"{"subsonic-response":{"status":"ok","searchResult3":{
+                "artist":[{"id":"ar-1","name":"Band"}],
+                "album":[{"id":"al-1","name":"Album","artist":"Band"}],
+                "song":[{"id":"1","title":"First","artist":"Band"}]}}}"#,
+        )])
+        .await;
+        let result = client(server.uri()).search("band").await.unwrap();
+        assert_eq!(result.artists[0].uuid, "node:subsonic:artist:ar-1");
+        assert_eq!(result.albums[0].uuid, "node:subsonic:album:al-1");
+        assert_eq!(result.tracks[0].title, "First");
+    }
+}
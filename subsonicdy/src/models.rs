@@ -0,0 +1,238 @@
+use crabidy_core::proto::crabidy::{
+    Album as ProtoAlbum, Artist as ProtoArtist, LibraryNodeChild, Track,
+};
+use serde::{Deserialize, Deserializer};
+
+/// Every response of the api is wrapped in this object
+#[derive(Debug, Deserialize)]
+pub struct Envelope {
+    #[serde(rename = "subsonic-response")]
+    pub response: Response,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct Response {
+    pub status: String,
+    pub error: Option<ApiError>,
+    pub artists: Option<ArtistsIndex>,
+    pub artist: Option<ArtistWithAlbums>,
+    pub album: Option<AlbumWithSongs>,
+    pub playlists: Option<Playlists>,
+    pub playlist: Option<PlaylistWithSongs>,
+    pub song: Option<Song>,
+    pub search_result3: Option<SearchResult3>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct ApiError {
+    pub code: u32,
+    pub message: String,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct ArtistsIndex {
+    pub index: Vec<Index>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct Index {
+    pub name: String,
+    pub artist: Vec<Artist>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct Artist {
+    #[serde(deserialize_with = "string_or_number")]
+    pub id: String,
+    pub name: String,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct ArtistWithAlbums {
+    #[serde(deserialize_with = "string_or_number")]
+    pub id: String,
+    pub name: String,
+    pub album: Vec<Album>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct Album {
+    #[serde(deserialize_with = "string_or_number")]
+    pub id: String,
+    pub name: String,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct AlbumWithSongs {
+    #[serde(deserialize_with = "string_or_number")]
+    pub id: String,
+    pub name: String,
+    #[serde(deserialize_with = "optional_string_or_number")]
+    pub artist_id: Option<String>,
+    pub song: Vec<Song>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct Playlists {
+    pub playlist: Vec<Playlist>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct Playlist {
+    #[serde(deserialize_with = "string_or_number")]
+    pub id: String,
+    pub name: String,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct PlaylistWithSongs {
+    #[serde(deserialize_with = "string_or_number")]
+    pub id: String,
+    pub name: String,
+    pub entry: Vec<Song>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct Song {
+    #[serde(deserialize_with = "string_or_number")]
+    pub id: String,
+    pub title: String,
+    pub artist: Option<String>,
+    #[serde(deserialize_with = "optional_string_or_number")]
+    pub artist_id: Option<String>,
+    pub album: Option<String>,
+    #[serde(deserialize_with = "optional_string_or_number")]
+    pub album_id: Option<String>,
+    pub year: Option<u32>,
+    // in seconds
+    pub duration: Option<u32>,
+    pub track: Option<u32>,
+    pub disc_number: Option<u32>,
+    // an OpenSubsonic extension
+    pub replay_gain: Option<ReplayGain>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct ReplayGain {
+    pub track_gain: Option<f32>,
+    pub track_peak: Option<f32>,
+    pub album_gain: Option<f32>,
+    pub album_peak: Option<f32>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct SearchResult3 {
+    pub artist: Vec<Artist>,
+    pub album: Vec<Album>,
+    pub song: Vec<Song>,
+}
+
+impl From<&Song> for Track {
+    fn from(song: &Song) -> Self {
+        Self {
+            uuid: format!("track:subsonic:{}", song.id),
+            title: song.title.clone(),
+            artist: song.artist.clone().unwrap_or_default(),
+            album: song.album.as_ref().map(|album| ProtoAlbum {
+                title: album.clone(),
+                release_date: song.year.map(|y| y.to_string()),
+                artwork: Vec::new(),
+                uuid: song
+                    .album_id
+                    .as_ref()
+                    .map(|id| format!("node:subsonic:album:{}", id)),
+            }),
+            duration: song.duration.map(|d| d * 1000),
+            artwork: Vec::new(),
+            artists: song
+                .artist
+                .iter()
+                .map(|name| ProtoArtist {
+                    name: name.clone(),
+                    uuid: song
+                        .artist_id
+                        .as_ref()
+                        .map(|id| format!("node:subsonic:artist:{}", id)),
+                })
+                .collect(),
+            track_number: song.track,
+            volume_number: song.disc_number,
+            replay_gain: song.replay_gain.as_ref().and_then(|g| g.track_gain),
+            peak: song.replay_gain.as_ref().and_then(|g| g.track_peak),
+            album_replay_gain: song.replay_gain.as_ref().and_then(|g| g.album_gain),
+            album_peak: song.replay_gain.as_ref().and_then(|g| g.album_peak),
+            ..Default::default()
+        }
+    }
+}
+
+impl From<&Artist> for LibraryNodeChild {
+    fn from(artist: &Artist) -> Self {
+        Self::new(
+            format!("node:subsonic:artist:{}", artist.id),
+            artist.name.clone(),
+            true,
+        )
+    }
+}
+
+impl From<&Album> for LibraryNodeChild {
+    fn from(album: &Album) -> Self {
+        Self::new(
+            format!("node:subsonic:album:{}", album.id),
+            album.name.clone(),
+            true,
+        )
+    }
+}
+
+impl From<&Playlist> for LibraryNodeChild {
+    fn from(playlist: &Playlist) -> Self {
+        Self::new(
+            format!("node:subsonic:playlist:{}", playlist.id),
+            playlist.name.clone(),
+            true,
+        )
+    }
+}
+
+// The original Subsonic server sends numeric ids while most of the newer ones send strings
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Id {
+    String(String),
+    Number(u64),
+}
+
+impl From<Id> for String {
+    fn from(id: Id) -> Self {
+        match id {
+            Id::String(id) => id,
+            Id::Number(id) => id.to_string(),
+        }
+    }
+}
+
+fn string_or_number<'de, D: Deserializer<'de>>(deserializer: D) -> Result<String, D::Error> {
+    Id::deserialize(deserializer).map(String::from)
+}
+
+fn optional_string_or_number<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<String>, D::Error> {
+    Option::<Id>::deserialize(deserializer).map(|id| id.map(String::from))
+}
@@ -75,6 +75,18 @@ pub enum AudioQuality {
     HiRes,
 }
 
+impl AudioQuality {
+    /// How the quality is requested, hi-res tracks are streamed with DASH manifests
+    pub fn as_api_str(&self) -> &'static str {
+        match self {
+            Self::Low => "LOW",
+            Self::High => "HIGH",
+            Self::Lossless => "LOSSLESS",
+            Self::HiRes => "HI_RES_LOSSLESS",
+        }
+    }
+}
+
 #[derive(Error, Debug)]
 pub enum ConfigError {
     #[error("failed to write config file")]
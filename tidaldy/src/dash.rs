@@ -0,0 +1,292 @@
+//! MPEG-DASH manifests, which the api returns for hi-res tracks instead of a list of urls. The
+//! track is split into segments, an initialization segment followed by the media segments, which
+//! are played one after the other.
+use reqwest::Url;
+use roxmltree::{Document, Node};
+
+use crate::ClientError;
+
+/// The urls of the initialization and all media segments of the audio with the highest bandwidth
+pub fn segments(mpd: &str) -> Result<Vec<String>, ClientError> {
+    let doc = Document::parse(mpd).map_err(|err| invalid(err.to_string()))?;
+    let root = doc.root_element();
+    if root.tag_name().name() != "MPD" {
+        return Err(invalid(format!("unexpected <{}>", root.tag_name().name())));
+    }
+    let period = child(root, "Period").ok_or_else(|| invalid("no period"))?;
+    let (adaptation_set, representation) = children(period, "AdaptationSet")
+        .filter(|set| is_audio(*set))
+        .flat_map(|set| children(set, "Representation").map(move |r| (set, r)))
+        .max_by_key(|(_, r)| number(r.attribute("bandwidth")).unwrap_or(0))
+        .ok_or_else(|| invalid("no audio representation"))?;
+
+    // every level can add to the base url
+    let base = [root, period, adaptation_set, representation]
+        .iter()
+        .try_fold(None, |base: Option<Url>, node| {
+            match child(*node, "BaseURL").and_then(|n| n.text()) {
+                Some(url) => resolve(base.as_ref(), url.trim()).map(Some),
+                None => Ok(base),
+            }
+        })?;
+
+    let template = child(representation, "SegmentTemplate")
+        .into_iter()
+        .chain(child(adaptation_set, "SegmentTemplate"))
+        .collect::<Vec<_>>();
+    if !template.is_empty() {
+        let duration = root
+            .attribute("mediaPresentationDuration")
+            .and_then(parse_duration);
+        let urls = template_urls(&template, representation, duration)?;
+        return urls
+            .iter()
+            .map(|url| resolve(base.as_ref(), url).map(String::from))
+            .collect();
+    }
+
+    if let Some(list) =
+        child(representation, "SegmentList").or(child(adaptation_set, "SegmentList"))
+    {
+        let init = child(list, "Initialization").and_then(|n| n.attribute("sourceURL"));
+        let media = children(list, "SegmentURL").filter_map(|n| n.attribute("media"));
+        return init
+            .into_iter()
+            .chain(media)
+            .map(|url| resolve(base.as_ref(), url).map(String::from))
+            .collect();
+    }
+
+    // the whole track in one file
+    base.map(|url| vec![url.to_string()])
+        .ok_or_else(|| invalid("no segments"))
+}
+
+/// The segments of a `SegmentTemplate`, the one of the representation overrides the attributes
+/// of the one of the adaptation set
+fn template_urls(
+    templates: &[Node],
+    representation: Node,
+    duration: Option<f64>,
+) -> Result<Vec<String>, ClientError> {
+    let attribute = |name| templates.iter().find_map(|t| t.attribute(name));
+    let media = attribute("media").ok_or_else(|| invalid("template without media"))?;
+    let start_number = number(attribute("startNumber")).unwrap_or(1);
+    let timescale = number(attribute("timescale")).unwrap_or(1).max(1);
+    let id = representation.attribute("id").unwrap_or_default();
+    let bandwidth = representation.attribute("bandwidth").unwrap_or_default();
+    let end = duration.map(|secs| (secs * timescale as f64).round() as u64);
+
+    // the start time of every segment
+    let mut times = Vec::new();
+    if let Some(timeline) = templates.iter().find_map(|t| child(*t, "SegmentTimeline")) {
+        let mut time = 0;
+        for s in children(timeline, "S") {
+            time = number(s.attribute("t")).unwrap_or(time);
+            let d = number(s.attribute("d")).ok_or_else(|| invalid("segment without duration"))?;
+            if d == 0 {
+                return Err(invalid("segment of zero duration"));
+            }
+            // -1 repeats the segment until the end of the track
+            let repeat = match s.attribute("r").map(str::parse::<i64>) {
+                Some(Ok(-1)) => {
+                    let end = end.ok_or_else(|| invalid("open repeat without duration"))?;
+                    end.saturating_sub(time).div_ceil(d).saturating_sub(1)
+                }
+                Some(Ok(r)) => r.max(0) as u64,
+                Some(Err(_)) => return Err(invalid("invalid repeat")),
+                None => 0,
+            };
+            for _ in 0..=repeat {
+                times.push(time);
+                time += d;
+            }
+        }
+    } else {
+        let d = number(attribute("duration")).ok_or_else(|| invalid("template without timing"))?;
+        let end = end.ok_or_else(|| invalid("template without duration"))?;
+        times.extend((0..end.div_ceil(d.max(1))).map(|i| i * d));
+    }
+
+    let init = attribute("initialization").map(|init| fill(init, id, bandwidth, None, None));
+    let media = times.iter().enumerate().map(|(i, time)| {
+        fill(
+            media,
+            id,
+            bandwidth,
+            Some(start_number + i as u64),
+            Some(*time),
+        )
+    });
+    Ok(init.into_iter().chain(media).collect())
+}
+
+/// Replaces the identifiers of a template like `$Number%05d$`
+fn fill(
+    template: &str,
+    id: &str,
+    bandwidth: &str,
+    number: Option<u64>,
+    time: Option<u64>,
+) -> String {
+    let mut url = String::new();
+    let mut parts = template.split('$');
+    if let Some(first) = parts.next() {
+        url.push_str(first);
+    }
+    // identifiers are every other part, `$$` is an escaped dollar sign
+    let mut is_identifier = true;
+    for part in parts {
+        if !is_identifier {
+            url.push_str(part);
+            is_identifier = true;
+            continue;
+        }
+        is_identifier = false;
+        let (name, format) = part.split_once('%').unwrap_or((part, ""));
+        let value = match name {
+            "" => Some("$".to_string()),
+            "RepresentationID" => Some(id.to_string()),
+            "Bandwidth" => Some(bandwidth.to_string()),
+            "Number" => number.map(|n| n.to_string()),
+            "Time" => time.map(|t| t.to_string()),
+            _ => None,
+        };
+        match value {
+            Some(value) => {
+                let width = format
+                    .strip_prefix('0')
+                    .and_then(|f| f.strip_suffix('d'))
+                    .and_then(|w| w.parse().ok())
+                    .unwrap_or(0);
+                url.push_str(&format!("{:0>width$}", value, width = width));
+            }
+            None => {
+                url.push('$');
+                url.push_str(part);
+                url.push('$');
+            }
+        }
+    }
+    url
+}
+
+/// Seconds of a duration like `PT3M2.230S`
+fn parse_duration(duration: &str) -> Option<f64> {
+    let time = duration
+        .strip_prefix('P')?
+        .split_once('T')
+        .map_or("", |(_, t)| t);
+    let mut secs = 0.0;
+    let mut value = String::new();
+    for c in time.chars() {
+        match c {
+            'H' => secs += value.parse::<f64>().ok()? * 3600.0,
+            'M' => secs += value.parse::<f64>().ok()? * 60.0,
+            'S' => secs += value.parse::<f64>().ok()?,
+            c => {
+                value.push(c);
+                continue;
+            }
+        }
+        value.clear();
+    }
+    Some(secs)
+}
+
+fn is_audio(adaptation_set: Node) -> bool {
+    let content_type = adaptation_set.attribute("contentType");
+    let mime_type = adaptation_set.attribute("mimeType");
+    content_type.is_none_or(|t| t == "audio") && mime_type.is_none_or(|t| t.starts_with("audio/"))
+}
+
+fn resolve(base: Option<&Url>, url: &str) -> Result<Url, ClientError> {
+    match base {
+        Some(base) => base.join(url),
+        None => Url::parse(url),
+    }
+    .map_err(|err| invalid(format!("{}: {}", url, err)))
+}
+
+fn child<'a, 'input>(node: Node<'a, 'input>, name: &str) -> Option<Node<'a, 'input>> {
+    node.children()
+        .find(|n| n.is_element() && n.tag_name().name() == name)
+}
+
+fn children<'a, 'input: 'a>(
+    node: Node<'a, 'input>,
+    name: &'a str,
+) -> impl Iterator<Item = Node<'a, 'input>> + 'a {
+    node.children()
+        .filter(move |n| n.is_element() && n.tag_name().name() == name)
+}
+
+fn number(attribute: Option<&str>) -> Option<u64> {
+    attribute?.trim().parse().ok()
+}
+
+fn invalid(cause: impl ToString) -> ClientError {
+    ClientError::DashManifestError(cause.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MPD: &str = r#"<?xml version='1.0' encoding='UTF-8'?>
+<MPD xmlns="urn:mpeg:dash:schema:mpd:2011" profiles="urn:mpeg:dash:profile:isoff-main:2011" type="static" minBufferTime="PT3.993S" mediaPresentationDuration="PT0M16.5S">
+  <Period id="0">
+    <AdaptationSet id="0" contentType="audio" mimeType="audio/mp4" segmentAlignment="true">
+      <Representation id="FLAC,44100,16" codecs="flac" bandwidth="900000" audioSamplingRate="44100">
+        <SegmentTemplate timescale="44100" initialization="https://sp.tidal.com/x/0.mp4?token=a&amp;b=1" media="https://sp.tidal.com/x/$Number$.mp4?token=a&amp;b=1" startNumber="1">
+          <SegmentTimeline>
+            <S d="176128" r="2"/>
+            <S d="199680"/>
+          </SegmentTimeline>
+        </SegmentTemplate>
+      </Representation>
+      <Representation id="FLAC,96000,24" codecs="flac" bandwidth="3000000" audioSamplingRate="96000">
+        <BaseURL>https://sp.tidal.com/hires/</BaseURL>
+        <SegmentTemplate timescale="96000" initialization="$RepresentationID$/init.mp4" media="$RepresentationID$/$Number%03d$.mp4" startNumber="0">
+          <SegmentTimeline>
+            <S t="0" d="384000" r="-1"/>
+          </SegmentTimeline>
+        </SegmentTemplate>
+      </Representation>
+    </AdaptationSet>
+  </Period>
+</MPD>"#;
+
+    #[test]
+    fn segments_of_the_best_representation_are_listed() {
+        let segments = segments(MPD).unwrap();
+        // 16.5 seconds in segments of 4 seconds
+        assert_eq!(segments.len(), 1 + 5);
+        assert_eq!(
+            segments[0],
+            "https://sp.tidal.com/hires/FLAC,96000,24/init.mp4"
+        );
+        assert_eq!(
+            segments[5],
+            "https://sp.tidal.com/hires/FLAC,96000,24/004.mp4"
+        );
+
+        let lossless = MPD.replace("3000000", "300000");
+        let segments = super::segments(&lossless).unwrap();
+        assert_eq!(segments.len(), 1 + 4);
+        assert_eq!(segments[0], "https://sp.tidal.com/x/0.mp4?token=a&b=1");
+        assert_eq!(segments[4], "https://sp.tidal.com/x/4.mp4?token=a&b=1");
+    }
+
+    #[test]
+    fn templates_and_durations_are_parsed() {
+        assert_eq!(
+            fill("$Time$-$Number%05d$$$.mp4", "a", "1", Some(7), Some(42)),
+            "42-00007$.mp4"
+        );
+        assert_eq!(fill("$Unknown$.mp4", "a", "1", None, None), "$Unknown$.mp4");
+        assert_eq!(parse_duration("PT3M2.230S"), Some(182.23));
+        assert_eq!(parse_duration("P0Y0M0DT1H0M0S"), Some(3600.0));
+        assert!(segments("<html/>").is_err());
+    }
+}
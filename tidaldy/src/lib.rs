@@ -7,6 +7,7 @@ use serde::de::DeserializeOwned;
 use tokio::time::{sleep, Duration, Instant};
 use tracing::{debug, error, info, instrument};
 pub mod config;
+mod dash;
 pub mod models;
 use async_trait::async_trait;
 pub use models::*;
@@ -36,10 +37,13 @@ impl crabidy_core::ProviderClient for Client {
         if let Ok(_) = client.login_config().await {
             return Ok(client);
         }
-        if let Ok(_) = client.login_web().await {
-            return Ok(client);
+        match client.login_web().await {
+            Ok(_) => Ok(client),
+            Err(err) => Err(crabidy_core::ProviderError::CouldNotLogin {
+                provider: "tidal".to_string(),
+                cause: err.to_string(),
+            }),
         }
-        Err(crabidy_core::ProviderError::CouldNotLogin)
     }
     #[instrument(skip(self))]
     fn settings(&self) -> String {
@@ -52,15 +56,9 @@ impl crabidy_core::ProviderClient for Client {
     ) -> Result<Vec<String>, crabidy_core::ProviderError> {
         debug!("get_urls_for_track {}", track_uuid);
         let (_, track_uuid, _) = split_uuid(track_uuid);
-        let Ok(playback) = self.get_track_playback(&track_uuid).await else {
-                  return Err(crabidy_core::ProviderError::FetchError)
-                };
+        let playback = self.get_track_playback(&track_uuid).await?;
         debug!("playback {:?}", playback);
-        let Ok(manifest) = playback.get_manifest() else {
-                  return Err(crabidy_core::ProviderError::FetchError)
-                };
-        debug!("manifest {:?}", manifest);
-        Ok(manifest.urls)
+        Ok(playback.get_urls()?)
     }
 
     #[instrument(skip(self))]
@@ -69,9 +67,7 @@ impl crabidy_core::ProviderClient for Client {
         track_uuid: &str,
     ) -> Result<crabidy_core::proto::crabidy::Track, crabidy_core::ProviderError> {
         debug!("get_metadata_for_track {}", track_uuid);
-        let Ok(track) = self.get_track(track_uuid).await else {
-                  return Err(crabidy_core::ProviderError::FetchError)
-                };
+        let track = self.get_track(track_uuid).await?;
         Ok(track.into())
     }
 
@@ -81,12 +77,12 @@ impl crabidy_core::ProviderClient for Client {
         let global_root = crabidy_core::proto::crabidy::LibraryNode::new();
         let children = vec![
             crabidy_core::proto::crabidy::LibraryNodeChild::new(
-                "node:userplaylists".to_string(),
+                "node:tidal:userplaylists".to_string(),
                 "playlists".to_string(),
                 false,
             ),
             crabidy_core::proto::crabidy::LibraryNodeChild::new(
-                "node:userartists".to_string(),
+                "node:tidal:userartists".to_string(),
                 "artists".to_string(),
                 false,
             ),
@@ -98,6 +94,35 @@ impl crabidy_core::ProviderClient for Client {
             tracks: Vec::new(),
             children,
             is_queable: false,
+            capabilities: None,
+            artwork: Vec::new(),
+        }
+    }
+
+    fn capabilities(&self) -> crabidy_core::proto::crabidy::ProviderCapabilities {
+        crabidy_core::proto::crabidy::ProviderCapabilities {
+            artwork: true,
+            lyrics: true,
+            ..crabidy_core::proto::crabidy::ProviderCapabilities::basic()
+        }
+    }
+
+    #[instrument(skip(self))]
+    async fn get_lyrics(
+        &self,
+        track_uuid: &str,
+    ) -> Result<Option<crabidy_core::proto::crabidy::Lyrics>, crabidy_core::ProviderError> {
+        debug!("get_lyrics {}", track_uuid);
+        let (_, track_id, _) = split_uuid(track_uuid);
+        match self.get_track_lyrics(&track_id).await {
+            Ok(lyrics) => Ok(lyrics.into_lyrics()),
+            // tracks without lyrics
+            Err(ClientError::HttpClientError(err))
+                if err.status() == Some(reqwest::StatusCode::NOT_FOUND) =>
+            {
+                Ok(None)
+            }
+            Err(err) => Err(err.into()),
         }
     }
 
@@ -106,106 +131,127 @@ impl crabidy_core::ProviderClient for Client {
         &self,
         uuid: &str,
     ) -> Result<crabidy_core::proto::crabidy::LibraryNode, crabidy_core::ProviderError> {
+        Ok(self.get_lib_node_page(uuid, 0, 0).await?.0)
+    }
+
+    #[instrument(skip(self))]
+    async fn get_lib_node_page(
+        &self,
+        uuid: &str,
+        offset: usize,
+        limit: usize,
+    ) -> Result<(crabidy_core::proto::crabidy::LibraryNode, usize), crabidy_core::ProviderError>
+    {
         let Some(user_id) = self.settings.login.user_id.clone() else {
-          return Err(crabidy_core::ProviderError::UnknownUser)
-    };
+            return Err(crabidy_core::ProviderError::UnknownUser {
+                provider: "tidal".to_string(),
+            });
+        };
         debug!("get_lib_node in tidaldy{}", uuid);
+        let requested = uuid;
         let (_kind, module, uuid) = split_uuid(uuid);
         error!("module:{},uuid: {}", module, uuid);
-        let node = match module.as_str() {
+        let (node, total) = match module.as_str() {
+            "" => {
+                let mut node = self.get_lib_root();
+                let total = node.paginate(offset, limit);
+                (node, total)
+            }
             "userplaylists" => {
                 let mut node = crabidy_core::proto::crabidy::LibraryNode {
-                    uuid: "node:userplaylists".to_string(),
+                    uuid: "node:tidal:userplaylists".to_string(),
                     title: "playlists".to_string(),
                     parent: Some("node:tidal".to_string()),
                     tracks: Vec::new(),
                     children: Vec::new(),
                     is_queable: false,
+                    capabilities: None,
+                    artwork: Vec::new(),
                 };
-                for playlist in self
-                    .get_users_playlists_and_favorite_playlists(&user_id)
-                    .await?
-                {
+                let (playlists, total) = self
+                    .get_users_playlists_and_favorite_playlists(&user_id, offset, limit)
+                    .await?;
+                for playlist in playlists {
                     let child = crabidy_core::proto::crabidy::LibraryNodeChild::new(
-                        format!("node:playlist:{}", playlist.playlist.uuid),
+                        format!("node:tidal:playlist:{}", playlist.playlist.uuid),
                         playlist.playlist.title,
                         true,
                     );
                     node.children.push(child);
                 }
-                node
+                (node, total)
             }
             "playlist" => {
                 let mut node: crabidy_core::proto::crabidy::LibraryNode =
                     self.get_playlist(&uuid).await?.into();
-                let tracks: Vec<crabidy_core::proto::crabidy::Track> = self
-                    .get_playlist_tracks(&uuid)
-                    .await?
-                    .iter()
-                    .map(|t| t.into())
-                    .collect();
-                node.tracks = tracks;
-                node.parent = Some("node:userplaylists".to_string());
-                node
+                let (tracks, total) = self.get_playlist_tracks(&uuid, offset, limit).await?;
+                node.tracks = tracks.iter().map(|t| t.into()).collect();
+                node.parent = Some("node:tidal:userplaylists".to_string());
+                (node, total)
             }
             "userartists" => {
                 let mut node = crabidy_core::proto::crabidy::LibraryNode {
-                    uuid: "node:userartists".to_string(),
+                    uuid: "node:tidal:userartists".to_string(),
                     title: "artists".to_string(),
                     parent: Some("node:tidal".to_string()),
                     tracks: Vec::new(),
                     children: Vec::new(),
                     is_queable: false,
+                    capabilities: None,
+                    artwork: Vec::new(),
                 };
-                for artist in self.get_users_artists(&user_id).await? {
+                let (artists, total) = self.get_users_artists(&user_id, offset, limit).await?;
+                for artist in artists {
                     let child = crabidy_core::proto::crabidy::LibraryNodeChild::new(
-                        format!("node:artist:{}", artist.item.id),
+                        format!("node:tidal:artist:{}", artist.item.id),
                         artist.item.name,
                         true,
                     );
                     node.children.push(child);
                 }
-                node
+                (node, total)
             }
             "artist" => {
                 info!("artist");
                 let mut node: crabidy_core::proto::crabidy::LibraryNode =
                     self.get_artist(&uuid).await?.into();
-                let children: Vec<crabidy_core::proto::crabidy::LibraryNodeChild> = self
-                    .get_artist_albums(&uuid)
-                    .await?
-                    .iter()
-                    .map(|t| t.into())
-                    .collect();
-                node.children = children;
-                node.parent = Some("node:userartists".to_string());
-                node
+                let (albums, total) = self.get_artist_albums(&uuid, offset, limit).await?;
+                node.children = albums.iter().map(|t| t.into()).collect();
+                node.parent = Some("node:tidal:userartists".to_string());
+                (node, total)
             }
             "album" => {
                 let album = self.get_album(&uuid).await?;
                 let artis_id = album.artist.clone().unwrap().id;
                 let mut node: crabidy_core::proto::crabidy::LibraryNode = album.into();
-                let tracks: Vec<crabidy_core::proto::crabidy::Track> = self
-                    .get_album_tracks(&uuid)
-                    .await?
-                    .iter()
-                    .map(|t| t.into())
-                    .collect();
-                node.tracks = tracks;
-                node.parent = Some(format!("node:artist:{}", artis_id));
-                node
+                let (tracks, total) = self.get_album_tracks(&uuid, offset, limit).await?;
+                node.tracks = tracks.iter().map(|t| t.into()).collect();
+                node.parent = Some(format!("node:tidal:artist:{}", artis_id));
+                (node, total)
             }
-            _ => return Err(crabidy_core::ProviderError::MalformedUuid),
+            _ => return Err(crabidy_core::ProviderError::malformed_uuid(requested)),
         };
-        Ok(node)
+        Ok((node, total))
+    }
+
+    #[instrument(skip(self))]
+    async fn search(
+        &self,
+        query: &str,
+    ) -> Result<crabidy_core::proto::crabidy::SearchResult, crabidy_core::ProviderError> {
+        debug!("search in tidaldy {}", query);
+        Ok(self.search(query).await?.into())
     }
 }
 
+/// Splits a uuid like `node:tidal:album:1234` into kind, module and id, dropping the provider
 #[instrument]
 fn split_uuid(uuid: &str) -> (String, String, String) {
-    let mut split = uuid.splitn(3, ':');
+    let mut split = uuid.splitn(4, ':');
+    let kind = split.next().unwrap_or("").to_string();
+    let _provider = split.next();
     (
-        split.next().unwrap_or("").to_string(),
+        kind,
         split.next().unwrap_or("").to_string(),
         split.next().unwrap_or("").to_string(),
     )
@@ -258,6 +304,7 @@ impl Client {
             .query(&params)
             .send()
             .await
+            .and_then(|r| r.error_for_status())
             .map_err(|e| {
                 error!("{:?}", e);
                 e
@@ -277,7 +324,20 @@ impl Client {
         uri: &str,
         query: Option<&[(&str, String)]>,
     ) -> Result<Vec<T>, ClientError> {
-        debug!("make_paginated_request {}", uri);
+        Ok(self.make_paginated_range_request(uri, query, 0, 0).await?.0)
+    }
+
+    /// Fetches `limit` items starting at `offset`, or all of them from the offset on with a limit
+    /// of 0, and returns them with the total number of items
+    #[instrument(skip(self))]
+    pub async fn make_paginated_range_request<T: DeserializeOwned>(
+        &self,
+        uri: &str,
+        query: Option<&[(&str, String)]>,
+        mut offset: usize,
+        limit: usize,
+    ) -> Result<(Vec<T>, usize), ClientError> {
+        debug!("make_paginated_range_request {}", uri);
         let Some(ref access_token) = self.settings.login.access_token.clone() else {
             return Err(ClientError::AuthError(
                 "No access token found".to_string(),
@@ -289,48 +349,29 @@ impl Client {
             ))
         };
         let country_param = ("countryCode", country_code);
-        let limit = 50;
-        let mut offset = 0;
-        let limit_param = ("limit", limit.to_string());
-        let mut params: Vec<&(&str, String)> = vec![&country_param, &limit_param];
-        if let Some(query) = query {
-            params.extend(query);
-        }
-
-        let mut response: Page<T> = self
-            .http_client
-            .get(format!("{}/{}", self.settings.hifi_url, uri))
-            .bearer_auth(access_token)
-            .query(&params)
-            .send()
-            .await
-            .map_err(|e| {
-                error!("{:?}", e);
-                e
-            })?
-            .json()
-            .await
-            .map_err(|e| {
-                error!("{:?}", e);
-                e
-            })?;
-        let mut items = Vec::with_capacity(response.total_number_of_items);
-        items.extend(response.items);
-        while response.offset + limit < response.total_number_of_items {
-            offset += limit;
+        let page_limit = 50;
+        let mut items = Vec::new();
+        loop {
+            let remaining = if limit == 0 {
+                page_limit
+            } else {
+                limit - items.len()
+            };
+            let limit_param = ("limit", remaining.min(page_limit).to_string());
             let offset_param = ("offset", offset.to_string());
             let mut params: Vec<&(&str, String)> =
                 vec![&country_param, &limit_param, &offset_param];
             if let Some(query) = query {
                 params.extend(query);
             }
-            response = self
+            let response: Page<T> = self
                 .http_client
                 .get(format!("{}/{}", self.settings.hifi_url, uri))
                 .bearer_auth(access_token)
                 .query(&params)
                 .send()
                 .await
+                .and_then(|r| r.error_for_status())
                 .map_err(|e| {
                     error!("{:?}", e);
                     e
@@ -341,9 +382,16 @@ impl Client {
                     error!("{:?}", e);
                     e
                 })?;
+            let fetched = response.items.len();
             items.extend(response.items);
+            offset += fetched;
+            if fetched == 0
+                || offset >= response.total_number_of_items
+                || (limit != 0 && items.len() >= limit)
+            {
+                return Ok((items, response.total_number_of_items));
+            }
         }
-        Ok(items)
     }
 
     #[instrument(skip(self))]
@@ -390,21 +438,29 @@ impl Client {
     }
 
     #[instrument(skip(self))]
-    pub async fn search(&self, query: &str) -> Result<(), ClientError> {
-        let query = vec![("query", query.to_string())];
-        self.make_explorer_request(&format!("search/artists"), Some(&query))
-            .await?;
-        Ok(())
+    pub async fn search(&self, query: &str) -> Result<SearchResults, ClientError> {
+        let query = vec![
+            ("query", query.to_string()),
+            ("types", "ARTISTS,ALBUMS,TRACKS,PLAYLISTS".to_string()),
+            ("limit", "50".to_string()),
+        ];
+        self.make_request("search", Some(&query)).await
     }
 
     #[instrument(skip(self))]
     pub async fn get_playlist_tracks(
         &self,
         playlist_uuid: &str,
-    ) -> Result<Vec<Track>, ClientError> {
-        Ok(self
-            .make_paginated_request(&format!("playlists/{}/tracks", playlist_uuid), None)
-            .await?)
+        offset: usize,
+        limit: usize,
+    ) -> Result<(Vec<Track>, usize), ClientError> {
+        self.make_paginated_range_request(
+            &format!("playlists/{}/tracks", playlist_uuid),
+            None,
+            offset,
+            limit,
+        )
+        .await
     }
 
     #[instrument(skip(self))]
@@ -422,10 +478,19 @@ impl Client {
     }
 
     #[instrument(skip(self))]
-    pub async fn get_artist_albums(&self, artist_uuid: &str) -> Result<Vec<Album>, ClientError> {
-        Ok(self
-            .make_paginated_request(&format!("artists/{}/albums", artist_uuid), None)
-            .await?)
+    pub async fn get_artist_albums(
+        &self,
+        artist_uuid: &str,
+        offset: usize,
+        limit: usize,
+    ) -> Result<(Vec<Album>, usize), ClientError> {
+        self.make_paginated_range_request(
+            &format!("artists/{}/albums", artist_uuid),
+            None,
+            offset,
+            limit,
+        )
+        .await
     }
 
     #[instrument(skip(self))]
@@ -439,13 +504,16 @@ impl Client {
     pub async fn get_users_playlists_and_favorite_playlists(
         &self,
         user_id: &str,
-    ) -> Result<Vec<PlaylistAndFavorite>, ClientError> {
-        Ok(self
-            .make_paginated_request(
-                &format!("users/{}/playlistsAndFavoritePlaylists", user_id),
-                None,
-            )
-            .await?)
+        offset: usize,
+        limit: usize,
+    ) -> Result<(Vec<PlaylistAndFavorite>, usize), ClientError> {
+        self.make_paginated_range_request(
+            &format!("users/{}/playlistsAndFavoritePlaylists", user_id),
+            None,
+            offset,
+            limit,
+        )
+        .await
     }
 
     #[instrument(skip(self))]
@@ -460,14 +528,19 @@ impl Client {
     }
 
     #[instrument(skip(self))]
-    pub async fn get_users_artists(&self, user_id: &str) -> Result<Vec<ArtistItem>, ClientError> {
-        Ok(self
-            .make_paginated_request(
-                &format!("users/{}/favorites/artists", user_id),
-                None,
-                // Some(&query),
-            )
-            .await?)
+    pub async fn get_users_artists(
+        &self,
+        user_id: &str,
+        offset: usize,
+        limit: usize,
+    ) -> Result<(Vec<ArtistItem>, usize), ClientError> {
+        self.make_paginated_range_request(
+            &format!("users/{}/favorites/artists", user_id),
+            None,
+            offset,
+            limit,
+        )
+        .await
     }
 
     #[instrument(skip(self))]
@@ -488,15 +561,28 @@ impl Client {
     }
 
     #[instrument(skip(self))]
-    pub async fn get_album_tracks(&self, album_id: &str) -> Result<Vec<Track>, ClientError> {
-        self.make_paginated_request(&format!("albums/{}/tracks", album_id), None)
-            .await
+    pub async fn get_album_tracks(
+        &self,
+        album_id: &str,
+        offset: usize,
+        limit: usize,
+    ) -> Result<(Vec<Track>, usize), ClientError> {
+        self.make_paginated_range_request(
+            &format!("albums/{}/tracks", album_id),
+            None,
+            offset,
+            limit,
+        )
+        .await
     }
 
     #[instrument(skip(self))]
     pub async fn get_track_playback(&self, track_id: &str) -> Result<TrackPlayback, ClientError> {
         let query = vec![
-            ("audioquality", "LOSSLESS".to_string()),
+            (
+                "audioquality",
+                self.settings.audio_quality.as_api_str().to_string(),
+            ),
             ("playbackmode", "STREAM".to_string()),
             ("assetpresentation", "FULL".to_string()),
         ];
@@ -514,6 +600,12 @@ impl Client {
             .await
     }
 
+    #[instrument(skip(self))]
+    pub async fn get_track_lyrics(&self, track_id: &str) -> Result<TrackLyrics, ClientError> {
+        self.make_request(&format!("tracks/{}/lyrics", track_id), None)
+            .await
+    }
+
     #[instrument(skip(self))]
     pub async fn login_web(&mut self) -> Result<(), ClientError> {
         let code_response = self.get_device_code().await?;
@@ -704,7 +796,7 @@ mod tests {
     async fn test() {
         let client = setup().await;
         let user = client.settings.login.user_id.clone().unwrap();
-        let result = client.get_users_artists(&user).await.unwrap();
+        let result = client.get_users_artists(&user, 0, 0).await.unwrap();
         println!("{:?}", result);
         let result = client.get_artist("5293333").await.unwrap();
         println!("{:?}", result);
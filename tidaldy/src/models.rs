@@ -1,10 +1,36 @@
 use std::{str::FromStr, string::FromUtf8Error};
 
-use crabidy_core::proto::crabidy::{LibraryNode, LibraryNodeChild};
+use base64::{engine::general_purpose::STANDARD, Engine};
+
+use crabidy_core::proto::crabidy::{Image, LibraryNode, LibraryNodeChild, Lyrics};
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use thiserror::Error;
 
+// the sizes the image service scales the square covers of albums and playlists and the pictures
+// of artists to
+const ALBUM_COVER_SIZES: [u32; 5] = [80, 160, 320, 640, 1280];
+const PLAYLIST_IMAGE_SIZES: [u32; 6] = [160, 320, 480, 640, 750, 1080];
+const ARTIST_PICTURE_SIZES: [u32; 4] = [160, 320, 480, 750];
+
+// the manifests of hi-res tracks, all others are `application/vnd.tidal.bts`
+const DASH_MIME_TYPE: &str = "application/dash+xml";
+
+/// The urls of an image of the given id, e.g. `2fa21a6e-2ba5-4ca5-9b5e-2a6e0cbd1d5a`
+fn images(id: &str, sizes: &[u32]) -> Vec<Image> {
+    let path = id.replace('-', "/");
+    sizes
+        .iter()
+        .map(|size| {
+            let url = format!(
+                "https://resources.tidal.com/images/{}/{}x{}.jpg",
+                path, size, size
+            );
+            Image::new(url, *size, *size)
+        })
+        .collect()
+}
+
 #[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct Page<T> {
@@ -24,12 +50,14 @@ pub struct ArtistItem {
 impl From<ArtistItem> for LibraryNode {
     fn from(item: ArtistItem) -> Self {
         Self {
-            uuid: format!("artist:{}", item.item.id),
+            uuid: format!("node:tidal:artist:{}", item.item.id),
             title: item.item.name,
             children: Vec::new(),
             parent: None,
             tracks: Vec::new(),
             is_queable: true,
+            capabilities: None,
+            artwork: Vec::new(),
         }
     }
 }
@@ -47,15 +75,35 @@ pub struct Artist {
     pub mixes: Option<ArtistMixes>,
 }
 
+impl Artist {
+    pub fn artwork(&self) -> Vec<Image> {
+        match &self.picture {
+            Some(Value::String(id)) => images(id, &ARTIST_PICTURE_SIZES),
+            _ => Vec::new(),
+        }
+    }
+}
+
 impl From<Artist> for LibraryNode {
     fn from(artist: Artist) -> Self {
         Self {
-            uuid: format!("node:artist:{}", artist.id),
+            uuid: format!("node:tidal:artist:{}", artist.id),
+            artwork: artist.artwork(),
             title: artist.name,
             children: Vec::new(),
             parent: None,
             tracks: Vec::new(),
             is_queable: true,
+            capabilities: None,
+        }
+    }
+}
+
+impl From<&Artist> for crabidy_core::proto::crabidy::Artist {
+    fn from(artist: &Artist) -> Self {
+        Self {
+            name: artist.name.clone(),
+            uuid: Some(format!("node:tidal:artist:{}", artist.id)),
         }
     }
 }
@@ -63,7 +111,7 @@ impl From<Artist> for LibraryNode {
 impl From<Artist> for LibraryNodeChild {
     fn from(artist: Artist) -> Self {
         Self {
-            uuid: format!("node:artist:{}", artist.id),
+            uuid: format!("node:tidal:artist:{}", artist.id),
             title: artist.name,
             is_queable: true,
         }
@@ -93,15 +141,23 @@ pub enum ClientError {
     Utf8DecodeError(#[from] FromUtf8Error),
     #[error("json decoding failed")]
     JsonDecodeError(#[from] serde_json::Error),
+    #[error("invalid DASH manifest: {0}")]
+    DashManifestError(String),
 }
 
 impl From<ClientError> for crabidy_core::ProviderError {
     fn from(err: ClientError) -> Self {
         match err {
-            ClientError::ConnectionError => Self::FetchError,
-            ClientError::HttpClientError(_) => Self::FetchError,
-            ClientError::SerdeUrlError(_) => Self::FetchError,
-            _ => Self::Other,
+            ClientError::ConnectionError => Self::fetch("tidal", None, err),
+            ClientError::HttpClientError(err) => {
+                Self::fetch("tidal", err.status().map(|s| s.as_u16()), err)
+            }
+            ClientError::SerdeUrlError(err) => Self::fetch("tidal", None, err),
+            ClientError::AuthError(cause) => Self::CouldNotLogin {
+                provider: "tidal".to_string(),
+                cause,
+            },
+            err => Self::Other(format!("tidal: {}", err)),
         }
     }
 }
@@ -148,6 +204,28 @@ pub struct UserResponse {
     pub country_code: String,
 }
 
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TrackLyrics {
+    pub track_id: Option<u64>,
+    pub lyrics_provider: Option<String>,
+    pub lyrics: Option<String>,
+    // the same lyrics in the LRC format, only if they are synced
+    pub subtitles: Option<String>,
+    pub is_right_to_left: Option<bool>,
+}
+
+impl TrackLyrics {
+    pub fn into_lyrics(self) -> Option<Lyrics> {
+        let lyrics = match (self.subtitles, self.lyrics) {
+            (Some(lrc), _) if !lrc.trim().is_empty() => Lyrics::parse(&lrc),
+            (_, Some(text)) => Lyrics::plain(&text),
+            _ => return None,
+        };
+        Some(lyrics).filter(|lyrics| !lyrics.lines.is_empty())
+    }
+}
+
 #[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct TrackPlayback {
@@ -168,6 +246,23 @@ impl TrackPlayback {
     pub fn get_manifest(&self) -> Result<PlaybackManifest, ClientError> {
         PlaybackManifest::from_str(&self.manifest)
     }
+
+    /// The urls to play. The segments of DASH manifests are played as one stream, so they are
+    /// handed over as a single `text/uri-list` data url, see
+    /// `ProviderClient::get_urls_for_track` for the format.
+    pub fn get_urls(&self) -> Result<Vec<String>, ClientError> {
+        if self.manifest_mime_type != DASH_MIME_TYPE {
+            return Ok(self.get_manifest()?.urls);
+        }
+        let mpd = String::from_utf8(STANDARD.decode(&self.manifest)?)?;
+        let segments = crate::dash::segments(&mpd)?;
+        // lines of a uri list end with CRLF
+        let list: String = segments.iter().map(|url| format!("{}\r\n", url)).collect();
+        Ok(vec![format!(
+            "data:text/uri-list;base64,{}",
+            STANDARD.encode(list)
+        )])
+    }
 }
 
 // #[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
@@ -231,23 +326,20 @@ pub struct Track {
 }
 impl From<Track> for crabidy_core::proto::crabidy::Track {
     fn from(track: Track) -> Self {
-        Self {
-            uuid: format!("track:{}", track.id),
-            title: track.title,
-            artist: match track.artist {
-                Some(a) => a.name.clone(),
-                None => "".to_string(),
-            },
-            album: track.album.map(|a| a.into()),
-            duration: track.duration.map(|d| d as u32 * 1000),
-        }
+        (&track).into()
     }
 }
 
 impl From<&Track> for crabidy_core::proto::crabidy::Track {
     fn from(track: &Track) -> Self {
+        // the main artist is listed first, older responses only have the main one
+        let artists = match (&track.artists, &track.artist) {
+            (Some(artists), _) if !artists.is_empty() => artists.iter().map(Into::into).collect(),
+            (_, Some(artist)) => vec![artist.into()],
+            _ => Vec::new(),
+        };
         Self {
-            uuid: format!("track:{}", track.id),
+            uuid: format!("track:tidal:{}", track.id),
             title: track.title.clone(),
             artist: match track.artist.as_ref() {
                 Some(a) => a.name.clone(),
@@ -255,6 +347,18 @@ impl From<&Track> for crabidy_core::proto::crabidy::Track {
             },
             album: track.album.clone().map(|a| a.into()),
             duration: track.duration.map(|d| d as u32 * 1000),
+            artwork: Vec::new(),
+            artists,
+            track_number: track.track_number.map(|n| n as u32),
+            volume_number: track.volume_number.map(|n| n as u32),
+            isrc: track.isrc.clone(),
+            explicit: track.explicit.unwrap_or(false),
+            audio_quality: track.audio_quality.clone(),
+            replay_gain: track.replay_gain.map(|gain| gain as f32),
+            peak: track.peak.map(|peak| peak as f32),
+            // only in the playback info, album mode falls back to the track gain
+            album_replay_gain: None,
+            album_peak: None,
         }
     }
 }
@@ -355,15 +459,26 @@ pub struct Album {
     pub artists: Option<Vec<Artist>>,
 }
 
+impl Album {
+    pub fn artwork(&self) -> Vec<Image> {
+        self.cover
+            .as_ref()
+            .map(|id| images(id, &ALBUM_COVER_SIZES))
+            .unwrap_or_default()
+    }
+}
+
 impl From<Album> for crabidy_core::proto::crabidy::LibraryNode {
     fn from(album: Album) -> Self {
         Self {
-            uuid: format!("node:album:{}", album.id),
+            uuid: format!("node:tidal:album:{}", album.id),
+            artwork: album.artwork(),
             title: album.title,
             children: Vec::new(),
             parent: None,
             tracks: Vec::new(),
             is_queable: true,
+            capabilities: None,
         }
     }
 }
@@ -371,7 +486,7 @@ impl From<Album> for crabidy_core::proto::crabidy::LibraryNode {
 impl From<Album> for crabidy_core::proto::crabidy::LibraryNodeChild {
     fn from(album: Album) -> Self {
         Self {
-            uuid: format!("node:album:{}", album.id),
+            uuid: format!("node:tidal:album:{}", album.id),
             title: album.title,
             is_queable: true,
         }
@@ -381,7 +496,7 @@ impl From<Album> for crabidy_core::proto::crabidy::LibraryNodeChild {
 impl From<&Album> for crabidy_core::proto::crabidy::LibraryNodeChild {
     fn from(album: &Album) -> Self {
         Self {
-            uuid: format!("node:album:{}", album.id),
+            uuid: format!("node:tidal:album:{}", album.id),
             title: album.title.clone(),
             is_queable: true,
         }
@@ -391,6 +506,8 @@ impl From<&Album> for crabidy_core::proto::crabidy::LibraryNodeChild {
 impl From<Album> for crabidy_core::proto::crabidy::Album {
     fn from(album: Album) -> Self {
         Self {
+            artwork: album.artwork(),
+            uuid: Some(format!("node:tidal:album:{}", album.id)),
             title: album.title,
             release_date: album.release_date,
         }
@@ -509,12 +626,61 @@ pub struct Playlist {
 impl From<Playlist> for crabidy_core::proto::crabidy::LibraryNode {
     fn from(a: Playlist) -> Self {
         crabidy_core::proto::crabidy::LibraryNode {
+            // the other image is not square
+            artwork: a
+                .square_image
+                .as_ref()
+                .map(|id| images(id, &PLAYLIST_IMAGE_SIZES))
+                .unwrap_or_default(),
             title: a.title,
-            uuid: format!("node:playlist:{}", a.uuid),
+            uuid: format!("node:tidal:playlist:{}", a.uuid),
             tracks: Vec::new(),
             parent: None,
             children: Vec::new(),
             is_queable: true,
+            capabilities: None,
+        }
+    }
+}
+
+impl From<&Playlist> for crabidy_core::proto::crabidy::LibraryNodeChild {
+    fn from(a: &Playlist) -> Self {
+        Self {
+            uuid: format!("node:tidal:playlist:{}", a.uuid),
+            title: a.title.clone(),
+            is_queable: true,
+        }
+    }
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SearchResults {
+    pub artists: Option<Page<Artist>>,
+    pub albums: Option<Page<Album>>,
+    pub playlists: Option<Page<Playlist>>,
+    pub tracks: Option<Page<Track>>,
+}
+
+impl From<SearchResults> for crabidy_core::proto::crabidy::SearchResult {
+    fn from(results: SearchResults) -> Self {
+        Self {
+            tracks: results
+                .tracks
+                .map(|p| p.items.iter().map(|t| t.into()).collect())
+                .unwrap_or_default(),
+            albums: results
+                .albums
+                .map(|p| p.items.iter().map(|a| a.into()).collect())
+                .unwrap_or_default(),
+            artists: results
+                .artists
+                .map(|p| p.items.into_iter().map(|a| a.into()).collect())
+                .unwrap_or_default(),
+            playlists: results
+                .playlists
+                .map(|p| p.items.iter().map(|p| p.into()).collect())
+                .unwrap_or_default(),
         }
     }
 }
@@ -557,3 +723,93 @@ pub struct PlaylistTrack {
     pub index: i64,
     pub item_uuid: String,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn covers_are_offered_in_all_sizes() {
+        let album = Album {
+            cover: Some("2fa21a6e-2ba5-4ca5-9b5e-2a6e0cbd1d5a".to_string()),
+            ..Default::default()
+        };
+        let artwork = crabidy_core::proto::crabidy::Album::from(album).artwork;
+        assert_eq!(artwork.len(), ALBUM_COVER_SIZES.len());
+        assert_eq!(
+            artwork[2].url,
+            "https://resources.tidal.com/images/2fa21a6e/2ba5/4ca5/9b5e/2a6e0cbd1d5a/320x320.jpg"
+        );
+        assert_eq!((artwork[2].width, artwork[2].height), (320, 320));
+        assert!(LibraryNode::from(Artist::default()).artwork.is_empty());
+    }
+
+    #[test]
+    fn tracks_keep_their_metadata() {
+        let track: Track = serde_json::from_str(
+            r#"{"id":58990516,"title":"Crab Rave","duration":170,"replayGain":-9.37,"peak":0.988,
+                "trackNumber":3,"volumeNumber":2,"isrc":"USQX91802301","explicit":true,
+                "audioQuality":"LOSSLESS","artist":{"id":1,"name":"Noisestorm"},
+                "artists":[{"id":1,"name":"Noisestorm"},{"id":2,"name":"Crabs"}],
+                "album":{"id":58990515,"title":"Crab Rave"}}"#,
+        )
+        .unwrap();
+        let track = crabidy_core::proto::crabidy::Track::from(track);
+        assert_eq!(
+            (track.volume_number, track.track_number),
+            (Some(2), Some(3))
+        );
+        assert_eq!(track.isrc.as_deref(), Some("USQX91802301"));
+        assert!(track.explicit);
+        assert_eq!(track.audio_quality.as_deref(), Some("LOSSLESS"));
+        assert_eq!(track.replay_gain, Some(-9.37));
+        assert_eq!(track.all_artists(), "Noisestorm, Crabs");
+        assert_eq!(
+            track.artists[1].uuid.as_deref(),
+            Some("node:tidal:artist:2")
+        );
+        assert_eq!(
+            track.album.unwrap().uuid.as_deref(),
+            Some("node:tidal:album:58990515")
+        );
+    }
+
+    #[test]
+    fn dash_segments_are_handed_over_as_one_url() {
+        let mpd = r#"<MPD mediaPresentationDuration="PT8S"><Period><AdaptationSet>
+            <Representation id="r" bandwidth="1"><SegmentTemplate timescale="1"
+                initialization="https://sp/0.mp4" media="https://sp/$Number$.mp4">
+                <SegmentTimeline><S d="4" r="1"/></SegmentTimeline>
+            </SegmentTemplate></Representation>
+        </AdaptationSet></Period></MPD>"#;
+        let playback = TrackPlayback {
+            manifest_mime_type: DASH_MIME_TYPE.to_string(),
+            manifest: STANDARD.encode(mpd),
+            ..Default::default()
+        };
+        let urls = playback.get_urls().unwrap();
+        let list = urls[0].strip_prefix("data:text/uri-list;base64,").unwrap();
+        assert_eq!(
+            String::from_utf8(STANDARD.decode(list).unwrap()).unwrap(),
+            "https://sp/0.mp4\r\nhttps://sp/1.mp4\r\nhttps://sp/2.mp4\r\n"
+        );
+    }
+
+    #[test]
+    fn synced_lyrics_are_preferred() {
+        let lyrics: TrackLyrics = serde_json::from_str(
+            r#"{"trackId":1,"lyrics":"First\nSecond","subtitles":"[00:01.00] First\n[00:02.50] Second","isRightToLeft":false}"#,
+        )
+        .unwrap();
+        let synced = lyrics.clone().into_lyrics().unwrap();
+        assert!(synced.synced);
+        assert_eq!(synced.lines[1].start, 2500);
+
+        let plain = TrackLyrics {
+            subtitles: None,
+            ..lyrics
+        };
+        assert!(!plain.into_lyrics().unwrap().synced);
+        assert_eq!(TrackLyrics::default().into_lyrics(), None);
+    }
+}